@@ -0,0 +1,82 @@
+use std::{env, fs, path::Path};
+
+/// Embed Atari side drivers found in `drivers/` folder and files of the
+/// tools drive found in `tools/` folder.
+///
+/// Drivers must be named `serdisk-v<protocol version>.prg`.
+fn main() {
+    embed_drivers();
+    embed_tools();
+}
+
+fn embed_drivers() {
+    let drivers_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("drivers");
+    println!("cargo:rerun-if-changed={}", drivers_dir.display());
+
+    let mut drivers = Vec::new();
+    if let Ok(entries) = fs::read_dir(&drivers_dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let version = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.to_lowercase())
+                .and_then(|name| {
+                    name.strip_prefix("serdisk-v")?
+                        .strip_suffix(".prg")?
+                        .parse::<u8>()
+                        .ok()
+                });
+
+            if let Some(version) = version {
+                println!("cargo:rerun-if-changed={}", path.display());
+                drivers.push((version, path));
+            }
+        }
+    }
+    drivers.sort();
+
+    let mut output = String::from("pub static DRIVERS: &[(u8, &[u8])] = &[\n");
+    for (version, path) in drivers {
+        output.push_str(&format!(
+            "    ({}, include_bytes!({:?})),\n",
+            version,
+            path.display().to_string()
+        ));
+    }
+    output.push_str("];\n");
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("drivers.rs");
+    fs::write(out_path, output).unwrap();
+}
+
+fn embed_tools() {
+    let tools_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("tools");
+    println!("cargo:rerun-if-changed={}", tools_dir.display());
+
+    let mut tools = Vec::new();
+    if let Ok(entries) = fs::read_dir(&tools_dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let name = path.file_name().and_then(|name| name.to_str());
+            if let (true, Some(name)) = (path.is_file(), name) {
+                println!("cargo:rerun-if-changed={}", path.display());
+                tools.push((name.to_uppercase(), path.clone()));
+            }
+        }
+    }
+    tools.sort();
+
+    let mut output = String::from("pub static TOOLS: &[(&str, &[u8])] = &[\n");
+    for (name, path) in tools {
+        output.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            path.display().to_string()
+        ));
+    }
+    output.push_str("];\n");
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("tools.rs");
+    fs::write(out_path, output).unwrap();
+}
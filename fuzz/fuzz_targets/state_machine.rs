@@ -0,0 +1,36 @@
+//! Feed arbitrary bytes to the state machine, as if sent by Atari.
+//!
+//! Run with `cargo +nightly fuzz run state_machine`.
+
+#![no_main]
+
+use std::sync::{Arc, Mutex};
+
+use ataridisk::{
+    layout::DiskLayout,
+    persistence::Persistence,
+    state_machine,
+    storage::{DiskStorage, ROOT_INDEX},
+    transport::MemoryTransport,
+};
+use chrono::NaiveDateTime;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Small disk with a few entries, so FAT and directories have content
+    let mtime = NaiveDateTime::from_timestamp(0, 0);
+    let mut storage = DiskStorage::new(DiskLayout::default());
+    let dir = storage
+        .add_virtual_directory("DIR", "", mtime, ROOT_INDEX)
+        .unwrap();
+    storage
+        .add_virtual_file("FILE", "TXT", mtime, b"hello atari", dir)
+        .unwrap();
+
+    let storage = Arc::new(Mutex::new(storage));
+    let mut serial = MemoryTransport::new(data);
+    let mut persistence = Persistence::new(None, None);
+
+    // Errors are expected, only panics are bugs
+    let _ = state_machine::run(storage, &mut serial, &mut persistence);
+});
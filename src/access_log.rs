@@ -0,0 +1,137 @@
+//! Files Atari reads and writes, resolved from sector accesses.
+//!
+//! Each access is logged under the `ataridisk::access` target (ex: `Atari
+//! read GAMES\DUNG.PRG`) and given to transaction subscribers. Mapping
+//! sectors to files walks the whole disk, so it is only done when someone
+//! is interested, and kept until Atari changes FAT or directories.
+
+use crate::{
+    backend::SectorBackend,
+    reverse::{ReverseMapper, SectorMap},
+    storage::DiskStorage,
+    transaction::{self, Transaction, TransactionKind},
+};
+
+const LOG_TARGET: &str = "ataridisk::access";
+
+#[derive(Debug, Default)]
+pub struct AccessLog {
+    map: Option<SectorMap>,
+}
+
+impl AccessLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled() -> bool {
+        log::log_enabled!(target: LOG_TARGET, log::Level::Info) || transaction::has_subscribers()
+    }
+
+    /// Files using some of the accessed sectors, in disk order.
+    pub fn files<B>(&mut self, storage: &DiskStorage<B>, index: u16, count: u16) -> Vec<String>
+    where
+        B: SectorBackend,
+    {
+        if self.map.is_none() {
+            match ReverseMapper::new(storage).sector_map() {
+                Ok(map) => self.map = Some(map),
+                Err(error) => {
+                    log::warn!("Cannot map sectors to files (error: {})", error);
+                    return Vec::new();
+                }
+            }
+        }
+        let map = self.map.as_ref().expect("Sector map is built");
+
+        let mut files: Vec<String> = Vec::new();
+        for sector_index in index..index.saturating_add(count) {
+            if let Some(entry) = map.owner(sector_index).filter(|e| !e.file_info.is_dir()) {
+                if files.last() != Some(&entry.disk_path) {
+                    files.push(entry.disk_path.clone());
+                }
+            }
+        }
+        files
+    }
+
+    /// Log files read or written by a transaction.
+    ///
+    /// Written sectors must already be applied to `storage`.
+    pub fn record<B>(&mut self, storage: &DiskStorage<B>, txn: &Transaction, index: u16, count: u16)
+    where
+        B: SectorBackend,
+    {
+        if !Self::is_enabled() {
+            // Map may be outdated once enabled again
+            self.map = None;
+            return;
+        }
+
+        if txn.kind() == TransactionKind::Write && self.changes_tree(storage, index, count) {
+            self.map = None;
+        }
+
+        let files = self.files(storage, index, count);
+        if files.is_empty() {
+            return;
+        }
+
+        let verb = match txn.kind() {
+            TransactionKind::Write => "wrote",
+            _ => "read",
+        };
+        for path in &files {
+            log::info!(target: LOG_TARGET, "Atari {} {}", verb, path);
+        }
+        txn.file_access(files);
+    }
+
+    /// Check if a write may change files of the disk: FAT, root directory
+    /// or sub directory sectors.
+    fn changes_tree<B>(&self, storage: &DiskStorage<B>, index: u16, count: u16) -> bool
+    where
+        B: SectorBackend,
+    {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return true,
+        };
+
+        index < storage.disk_layout.first_free_sector()
+            || (index..index.saturating_add(count))
+                .any(|i| map.owner(i).is_some_and(|e| e.file_info.is_dir()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::{layout::DiskLayout, storage::ROOT_INDEX};
+
+    #[test]
+    fn test_files() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let games = storage
+            .add_virtual_directory("GAMES", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("DUNG", "PRG", mtime, &[1; 2048], games)
+            .unwrap();
+        storage
+            .add_virtual_file("README", "TXT", mtime, b"hello", ROOT_INDEX)
+            .unwrap();
+
+        let first_data = storage.disk_layout.first_free_sector();
+        let mut access_log = AccessLog::new();
+        // Directory sectors, both clusters of DUNG.PRG, then README.TXT
+        assert_eq!(
+            access_log.files(&storage, first_data, 8),
+            ["GAMES\\DUNG.PRG", "README.TXT"]
+        );
+        assert!(access_log.files(&storage, 0, first_data).is_empty());
+    }
+}
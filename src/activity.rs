@@ -0,0 +1,225 @@
+//! Disk activity light, for hosts hidden in the Atari case (ex: Raspberry
+//! Pi driving a LED on a GPIO pin).
+//!
+//! Light is switched on when Atari starts reading or writing sectors, and
+//! off `HOLD` after the transfer finished, unless another one started
+//! meanwhile, so short bursts are still visible. Sysfs GPIO pins and LEDs (including keyboard LEDs) are behind
+//! the `gpio` feature, other platforms implement `ActivityIndicator`.
+
+use std::{
+    io,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::transaction::{self, Notice, TransactionKind};
+
+/// Time light stays on after a transfer.
+pub const HOLD: Duration = Duration::from_millis(50);
+
+/// Something showing disk activity.
+pub trait ActivityIndicator: Send {
+    fn set(&mut self, active: bool) -> io::Result<()>;
+}
+
+/// Activity light given in config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityLight {
+    /// Sysfs GPIO pin number (ex: `17`)
+    Gpio(u32),
+    /// Sysfs LED name (ex: `input3::scrolllock`)
+    Led(String),
+}
+
+impl ActivityLight {
+    /// Open light, if built with `gpio` feature.
+    #[cfg(feature = "gpio")]
+    pub fn open(&self) -> io::Result<Box<dyn ActivityIndicator>> {
+        Ok(Box::new(match self {
+            Self::Gpio(pin) => sysfs::SysfsIndicator::gpio(*pin)?,
+            Self::Led(name) => sysfs::SysfsIndicator::led(name)?,
+        }))
+    }
+
+    #[cfg(not(feature = "gpio"))]
+    pub fn open(&self) -> io::Result<Box<dyn ActivityIndicator>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "built without gpio feature",
+        ))
+    }
+}
+
+#[cfg(feature = "gpio")]
+mod sysfs {
+    use std::{fs, io, path::PathBuf};
+
+    use super::ActivityIndicator;
+
+    /// Light driven by writing to a sysfs file.
+    #[derive(Debug)]
+    pub struct SysfsIndicator {
+        path: PathBuf,
+        on: Vec<u8>,
+    }
+
+    impl SysfsIndicator {
+        /// Export GPIO `pin` if needed and make it an output.
+        pub fn gpio(pin: u32) -> io::Result<Self> {
+            let dir = PathBuf::from(format!("/sys/class/gpio/gpio{}", pin));
+            if !dir.exists() {
+                fs::write("/sys/class/gpio/export", pin.to_string())?;
+            }
+            fs::write(dir.join("direction"), "out")?;
+            Ok(Self {
+                path: dir.join("value"),
+                on: b"1".to_vec(),
+            })
+        }
+
+        /// LED named `name`, switched on at its maximum brightness.
+        pub fn led(name: &str) -> io::Result<Self> {
+            let dir = PathBuf::from("/sys/class/leds").join(name);
+            let max = fs::read_to_string(dir.join("max_brightness"))?;
+            Ok(Self {
+                path: dir.join("brightness"),
+                on: max.trim().as_bytes().to_vec(),
+            })
+        }
+    }
+
+    impl ActivityIndicator for SysfsIndicator {
+        fn set(&mut self, active: bool) -> io::Result<()> {
+            fs::write(&self.path, if active { &self.on[..] } else { b"0" })
+        }
+    }
+}
+
+/// Light state, from transfers started and finished.
+struct Activity {
+    indicator: Box<dyn ActivityIndicator>,
+    /// When to switch light off, when it is on
+    off_at: Option<Instant>,
+    lit: bool,
+}
+
+impl Activity {
+    fn new(indicator: Box<dyn ActivityIndicator>) -> Self {
+        Self {
+            indicator,
+            off_at: None,
+            lit: false,
+        }
+    }
+
+    fn started(&mut self) {
+        self.off_at = None;
+        self.light(true);
+    }
+
+    fn finished(&mut self, now: Instant) {
+        self.off_at = Some(now + HOLD);
+    }
+
+    /// Switch light off if it is time, and return how long to wait for it
+    /// otherwise.
+    fn update(&mut self, now: Instant) -> Option<Duration> {
+        let off_at = self.off_at?;
+        if now < off_at {
+            return Some(off_at - now);
+        }
+        self.off_at = None;
+        self.light(false);
+        None
+    }
+
+    fn light(&mut self, on: bool) {
+        if self.lit == on {
+            return;
+        }
+        self.lit = on;
+        if let Err(e) = self.indicator.set(on) {
+            log::warn!("Cannot switch activity light (error: {})", e);
+        }
+    }
+}
+
+/// Drive `indicator` from sectors transfers until the app stops.
+pub fn spawn(indicator: Box<dyn ActivityIndicator>) {
+    let (sender, receiver) = mpsc::channel();
+    transaction::subscribe(move |notice| {
+        let transfer = match notice {
+            Notice::Started { kind, .. } => Some((*kind, true)),
+            Notice::Finished(record) => Some((record.kind, false)),
+            _ => None,
+        };
+        if let Some((TransactionKind::Read | TransactionKind::Write, started)) = transfer {
+            let _ = sender.send(started);
+        }
+    });
+
+    thread::spawn(move || {
+        let mut activity = Activity::new(indicator);
+        let mut timeout = None;
+        loop {
+            let started = match timeout {
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(started) => Some(started),
+                    Err(mpsc::RecvTimeoutError::Timeout) => None,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                },
+                None => match receiver.recv() {
+                    Ok(started) => Some(started),
+                    Err(_) => return,
+                },
+            };
+            match started {
+                Some(true) => activity.started(),
+                Some(false) => activity.finished(Instant::now()),
+                None => {}
+            }
+            timeout = activity.update(Instant::now());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Recorder(Arc<Mutex<Vec<bool>>>);
+
+    impl ActivityIndicator for Recorder {
+        fn set(&mut self, active: bool) -> io::Result<()> {
+            self.0.lock().unwrap().push(active);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_activity() {
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let mut activity = Activity::new(Box::new(Recorder(states.clone())));
+        let now = Instant::now();
+
+        assert_eq!(activity.update(now), None);
+        activity.started();
+        activity.started();
+        assert_eq!(activity.update(now), None);
+        activity.finished(now);
+        assert_eq!(activity.update(now), Some(HOLD));
+
+        // Next transfer starts before light is off
+        activity.started();
+        activity.finished(now + HOLD);
+        assert_eq!(activity.update(now + HOLD), Some(HOLD));
+        assert_eq!(activity.update(now + HOLD * 2), None);
+        assert_eq!(*states.lock().unwrap(), [true, false]);
+    }
+}
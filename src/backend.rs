@@ -0,0 +1,701 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fmt::{self, Debug},
+    fs::{File, OpenOptions},
+    io::{self, BufReader},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+};
+
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+
+use crate::{
+    entries::DirectoryContent,
+    error::{self, SerialDiskError},
+    journal::{self, WriteJournal},
+    layout::DiskLayout,
+};
+
+/// Sectors kept in RAM by `JournaledBackend` before they are written to
+/// its image.
+pub const CHECKPOINT_SECTORS: usize = 1024;
+
+/// Where sectors content of a virtual disk is stored.
+pub trait SectorBackend {
+    /// Get sector content, or `None` if sector has never been initialized.
+    fn sector(&self, index: u16) -> io::Result<Option<Cow<'_, [u8]>>>;
+
+    /// Replace sector content.
+    fn set_sector(&mut self, index: u16, data: Vec<u8>);
+
+    /// Make sure every written sector reached stable storage.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Tell if content of this backend survives app restart.
+    ///
+    /// When it does, FAT and root directory sectors are also stored
+    /// in the backend so disk can be reopened later.
+    fn is_persistent(&self) -> bool {
+        false
+    }
+
+    /// Hint that sectors `index..index + count` will be read soon, for
+    /// backends loading host data on demand.
+    fn prefetch(&self, _index: u16, _count: u16) {}
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) enum DiskBloc {
+    Data(Vec<u8>),
+    Entries(DirectoryContent),
+    /// LZ4 compressed data, size prepended. Only made with the
+    /// `compression` feature, but always known so dumps can be read by any
+    /// build.
+    Compressed(Vec<u8>),
+}
+
+impl DiskBloc {
+    /// Bloc holding `data`, compressed when it saves memory.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    fn new(data: Vec<u8>, compress: bool) -> Self {
+        #[cfg(feature = "compression")]
+        if compress {
+            let compressed = lz4_flex::compress_prepend_size(&data);
+            if compressed.len() < data.len() {
+                return Self::Compressed(compressed);
+            }
+        }
+        Self::Data(data)
+    }
+
+    fn as_raw(&self) -> io::Result<Cow<'_, [u8]>> {
+        match self {
+            Self::Data(data) => Ok(Cow::Borrowed(data)),
+            Self::Entries(entries) => Ok(Cow::Borrowed(entries.as_raw())),
+            Self::Compressed(data) => decompress(data).map(Cow::Owned),
+        }
+    }
+
+    /// Length of bloc content, without decompressing it.
+    fn raw_len(&self) -> usize {
+        match self {
+            Self::Compressed(data) => data.get(..4).map_or(0, |size| {
+                u32::from_le_bytes([size[0], size[1], size[2], size[3]])
+            }) as usize,
+            _ => self.stored_len(),
+        }
+    }
+
+    /// Bytes used to store bloc content.
+    fn stored_len(&self) -> usize {
+        match self {
+            Self::Data(data) | Self::Compressed(data) => data.len(),
+            Self::Entries(entries) => entries.as_raw().len(),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    lz4_flex::decompress_size_prepended(data).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupted compressed sector: {}", e),
+        )
+    })
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::other(
+        "compressed sector, built without compression feature",
+    ))
+}
+
+/// Sectors stored in RAM, only allocated when they are used.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct MemoryBackend {
+    sectors: HashMap<u16, DiskBloc>,
+    /// Keep sectors LZ4 compressed, decompressing them on each read
+    #[serde(skip)]
+    compress: bool,
+}
+
+impl MemoryBackend {
+    /// Keep sectors compressed from now on, existing ones included.
+    ///
+    /// Only available with the `compression` feature, ignored otherwise.
+    pub fn set_compression(&mut self, compress: bool) {
+        if compress && cfg!(not(feature = "compression")) {
+            log::warn!("Built without compression feature, sectors are kept uncompressed");
+            return;
+        }
+        self.compress = compress;
+        for (index, bloc) in self.sectors.iter_mut() {
+            match bloc.as_raw() {
+                Ok(data) => *bloc = DiskBloc::new(data.into_owned(), compress),
+                Err(e) => log::warn!("Sector {:#x} left as is (error: {})", index, e),
+            }
+        }
+    }
+
+    /// Sector bytes of the disk and bytes actually used to store them.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        self.sectors.values().fold((0, 0), |(raw, stored), bloc| {
+            (raw + bloc.raw_len(), stored + bloc.stored_len())
+        })
+    }
+}
+
+impl SectorBackend for MemoryBackend {
+    fn sector(&self, index: u16) -> io::Result<Option<Cow<'_, [u8]>>> {
+        self.sectors.get(&index).map(DiskBloc::as_raw).transpose()
+    }
+
+    fn set_sector(&mut self, index: u16, data: Vec<u8>) {
+        self.sectors
+            .insert(index, DiskBloc::new(data, self.compress));
+    }
+}
+
+/// Sectors stored in a raw disk image mapped in memory.
+///
+/// OS only loads in RAM the pages the Atari is using and write them back
+/// to the image file, so huge disks can be served with little memory.
+pub struct MmapBackend {
+    file: File,
+    data: *mut u8,
+    len: usize,
+    bytes_per_sector: usize,
+    prefetcher: Option<Prefetcher>,
+}
+
+/// Thread loading pages of the mapping before Atari reads them, so serial
+/// link does not wait for host disk.
+struct Prefetcher {
+    /// Byte ranges of the mapping to load
+    sender: Sender<(usize, usize)>,
+    thread: JoinHandle<()>,
+}
+
+impl Prefetcher {
+    fn spawn(data: *mut u8) -> Self {
+        let (sender, receiver) = mpsc::channel::<(usize, usize)>();
+        let address = data as usize;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as usize;
+
+        let thread = thread::spawn(move || {
+            for (start, len) in receiver {
+                let start = start - start % page_size;
+                unsafe {
+                    let page = (address + start) as *mut libc::c_void;
+                    libc::madvise(page, len, libc::MADV_WILLNEED);
+                    // Touch every page, readahead advice may be ignored
+                    for offset in (start..start + len).step_by(page_size) {
+                        ptr::read_volatile((address + offset) as *const u8);
+                    }
+                }
+            }
+        });
+
+        Self { sender, thread }
+    }
+}
+
+// Mapping is owned by the backend and only accessed through `&self` / `&mut self`.
+unsafe impl Send for MmapBackend {}
+
+impl MmapBackend {
+    /// Open (or create) a raw disk image matching disk layout.
+    pub fn open<P>(path: P, disk_layout: &DiskLayout) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes_per_sector = disk_layout.bytes_per_sector() as usize;
+        let len = disk_layout.sector_count() as usize * bytes_per_sector;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < len as u64 {
+            file.set_len(len as u64)?;
+        }
+
+        let data = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            file,
+            data: data as *mut u8,
+            len,
+            bytes_per_sector,
+            prefetcher: Some(Prefetcher::spawn(data as *mut u8)),
+        })
+    }
+
+    fn range(&self, index: u16) -> Option<(usize, usize)> {
+        let start = index as usize * self.bytes_per_sector;
+        let end = start + self.bytes_per_sector;
+
+        if end <= self.len {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.data, self.len) }
+    }
+
+    /// Sectors holding something else than zeros.
+    fn used_sectors(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.as_slice()
+            .chunks(self.bytes_per_sector)
+            .enumerate()
+            .filter(|(_, data)| data.iter().any(|b| *b != 0))
+            .map(|(index, data)| (index as u16, data))
+    }
+}
+
+impl SectorBackend for MmapBackend {
+    fn sector(&self, index: u16) -> io::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self
+            .range(index)
+            .map(|(start, end)| Cow::Borrowed(&self.as_slice()[start..end])))
+    }
+
+    fn set_sector(&mut self, index: u16, data: Vec<u8>) {
+        let (start, end) = self.range(index).expect("Out of range sector");
+        self.as_mut_slice()[start..end].copy_from_slice(&data);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result =
+            unsafe { libc::msync(self.data as *mut libc::c_void, self.len, libc::MS_SYNC) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.file.sync_all()
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    fn prefetch(&self, index: u16, count: u16) {
+        let start = index as usize * self.bytes_per_sector;
+        let end = (start + count as usize * self.bytes_per_sector).min(self.len);
+        if let (Some(prefetcher), true) = (&self.prefetcher, start < end) {
+            let _ = prefetcher.sender.send((start, end - start));
+        }
+    }
+}
+
+impl Drop for MmapBackend {
+    fn drop(&mut self) {
+        // Prefetcher must not touch pages once they are unmapped
+        if let Some(Prefetcher { sender, thread }) = self.prefetcher.take() {
+            drop(sender);
+            let _ = thread.join();
+        }
+        unsafe {
+            libc::munmap(self.data as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+impl Debug for MmapBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapBackend")
+            .field("len", &self.len)
+            .field("bytes_per_sector", &self.bytes_per_sector)
+            .finish()
+    }
+}
+
+/// Image backed disks are dumped as a RAM disk containing only used sectors,
+/// so dumps made from them can still be extracted.
+impl Serialize for MmapBackend {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_sectors(serializer, self.used_sectors().collect())
+    }
+}
+
+fn serialize_sectors<S>(serializer: S, sectors: Vec<(u16, &[u8])>) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(sectors.len()))?;
+    for (index, data) in sectors {
+        map.serialize_entry(&index, &DiskBloc::Data(data.to_vec()))?;
+    }
+    map.end()
+}
+
+/// Raw disk image whose writes go through a journal file next to it.
+///
+/// Written sectors are appended to the journal and kept in RAM, image only
+/// gets them on checkpoints, once the journal has reached stable storage.
+/// A crash never leaves a half updated image: sectors journaled since last
+/// checkpoint are applied again when image is opened.
+#[derive(Debug)]
+pub struct JournaledBackend {
+    image: MmapBackend,
+    journal: WriteJournal,
+    /// Sectors written since last checkpoint
+    pending: HashMap<u16, Vec<u8>>,
+    /// First journal error, reported on next flush
+    error: Option<io::Error>,
+}
+
+impl JournaledBackend {
+    /// Open (or create) a raw disk image and its journal, applying sectors
+    /// left in the journal by a previous run.
+    pub fn open<P>(path: P, disk_layout: &DiskLayout) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut image = MmapBackend::open(&path, disk_layout)?;
+        let journal_path = Self::journal_path(path.as_ref());
+
+        if journal_path.exists() {
+            let mut reader = BufReader::new(File::open(&journal_path)?);
+            let writes = journal::replay_sectors(&mut reader, |index, data| {
+                if image.range(index).is_some() && data.len() == image.bytes_per_sector {
+                    image.set_sector(index, data.to_vec());
+                }
+            })?;
+            if writes > 0 {
+                log::info!("Applied {} journaled writes to {:?}", writes, path.as_ref());
+                image.flush()?;
+            }
+        }
+
+        let mut journal = WriteJournal::open(&journal_path)?;
+        journal.truncate()?;
+        Ok(Self {
+            image,
+            journal,
+            pending: HashMap::new(),
+            error: None,
+        })
+    }
+
+    /// Journal of image at `path`, `<path>.journal`.
+    pub fn journal_path(path: &Path) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".journal");
+        PathBuf::from(name)
+    }
+
+    /// Write pending sectors to image, then forget them in journal.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        for (index, data) in self.pending.drain() {
+            self.image.set_sector(index, data);
+        }
+        self.image.flush()?;
+        self.journal.truncate().map_err(into_io_error)
+    }
+}
+
+fn into_io_error(error: SerialDiskError) -> io::Error {
+    match error {
+        SerialDiskError::IO(error) => error,
+        error => io::Error::other(error.to_string()),
+    }
+}
+
+impl SectorBackend for JournaledBackend {
+    fn sector(&self, index: u16) -> io::Result<Option<Cow<'_, [u8]>>> {
+        match self.pending.get(&index) {
+            Some(data) => Ok(Some(Cow::Borrowed(data))),
+            None => self.image.sector(index),
+        }
+    }
+
+    fn set_sector(&mut self, index: u16, data: Vec<u8>) {
+        assert!(self.image.range(index).is_some(), "Out of range sector");
+        if let Err(error) = self.journal.append(index, 1, &data) {
+            log::warn!("Cannot journal sector {:#04x} (error: {})", index, error);
+            self.error.get_or_insert(into_io_error(error));
+        }
+        self.pending.insert(index, data);
+    }
+
+    /// Sync journal, and update image once enough sectors are pending.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.journal.sync().map_err(into_io_error)?;
+        if self.pending.len() >= CHECKPOINT_SECTORS {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    fn prefetch(&self, index: u16, count: u16) {
+        self.image.prefetch(index, count);
+    }
+}
+
+impl Drop for JournaledBackend {
+    fn drop(&mut self) {
+        // Journal still holds pending sectors if this fails
+        if self.error.is_none() {
+            if let Err(error) = self.checkpoint() {
+                log::warn!("Cannot update disk image (error: {})", error);
+            }
+        }
+    }
+}
+
+/// Dumped as a RAM disk containing only used sectors, like `MmapBackend`.
+impl Serialize for JournaledBackend {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut sectors: BTreeMap<u16, &[u8]> = self.image.used_sectors().collect();
+        for (index, data) in &self.pending {
+            if data.iter().any(|b| *b != 0) {
+                sectors.insert(*index, data);
+            } else {
+                sectors.remove(index);
+            }
+        }
+        serialize_sectors(serializer, sectors.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        fixture::Fixture,
+        layout::{PartitionType, Tos},
+        storage::DiskStorage,
+    };
+
+    #[test]
+    fn test_memory_backend() {
+        let mut backend = MemoryBackend::default();
+        assert_eq!(backend.sector(0x10).unwrap(), None);
+
+        backend.set_sector(0x10, vec![0xAA; 4]);
+        assert_eq!(
+            backend.sector(0x10).unwrap().as_deref(),
+            Some(&[0xAA; 4][..])
+        );
+
+        // Corrupted compressed bloc, or compressed by a build with the
+        // compression feature
+        backend.sectors.insert(
+            0x11,
+            DiskBloc::Compressed(vec![0x00, 0x02, 0x00, 0x00, 0xFF]),
+        );
+        assert!(backend.sector(0x11).is_err());
+        assert!(!backend.is_persistent());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_memory_backend() {
+        let mut backend = MemoryBackend::default();
+        let text = b"Atari ST ".repeat(57);
+        backend.set_sector(0x10, text.clone());
+        backend.set_compression(true);
+        let noise: Vec<u8> = (0..512u32).map(|i| (i * 7919 % 251) as u8).collect();
+        backend.set_sector(0x11, noise.clone());
+
+        assert_eq!(backend.sector(0x10).unwrap().as_deref(), Some(&text[..]));
+        assert_eq!(backend.sector(0x11).unwrap().as_deref(), Some(&noise[..]));
+        let (raw, stored) = backend.memory_usage();
+        assert_eq!(raw, text.len() + noise.len());
+        assert!(stored < raw);
+
+        backend.set_compression(false);
+        assert_eq!(backend.memory_usage(), (raw, raw));
+    }
+
+    /// Run with `cargo test --release bench_ -- --ignored --nocapture`.
+    #[cfg(feature = "compression")]
+    #[test]
+    #[ignore]
+    fn bench_compressed_reads() {
+        let layout = DiskLayout::default();
+        let sector_size = layout.bytes_per_sector() as usize;
+        for compress in [false, true] {
+            let mut backend = MemoryBackend::default();
+            backend.set_compression(compress);
+            for index in 0..4096u16 {
+                let content = format!("line {} of some text file\r\n", index).repeat(400);
+                backend.set_sector(index, content.into_bytes()[..sector_size].to_vec());
+            }
+
+            let start = std::time::Instant::now();
+            let mut total = 0;
+            for _ in 0..16 {
+                for index in 0..4096u16 {
+                    total += backend.sector(index).unwrap().unwrap().len();
+                }
+            }
+            let elapsed = start.elapsed();
+            let (raw, stored) = backend.memory_usage();
+            println!(
+                "compress={}: {} bytes stored for {}, {:.1} MB/s",
+                compress,
+                stored,
+                raw,
+                total as f64 / elapsed.as_secs_f64() / 1e6
+            );
+        }
+    }
+
+    #[test]
+    fn test_mmap_backend() {
+        let fixture = Fixture::empty("img").unwrap();
+        let path = fixture.join("test.img");
+        let layout = DiskLayout::new(Tos::V100, PartitionType::Gem, 8);
+        let sector = vec![0x42; layout.bytes_per_sector() as usize];
+
+        {
+            let mut backend = MmapBackend::open(&path, &layout).unwrap();
+            assert_eq!(
+                backend.sector(0x100).unwrap().as_deref(),
+                Some(&vec![0; sector.len()][..])
+            );
+
+            backend.set_sector(0x100, sector.clone());
+            backend.flush().unwrap();
+        }
+
+        // Content survive reopening image
+        let backend = MmapBackend::open(&path, &layout).unwrap();
+        backend.prefetch(0x100, 64);
+        backend.prefetch((layout.sector_count() - 1) as u16, 64);
+        assert_eq!(backend.sector(0x100).unwrap().as_deref(), Some(&sector[..]));
+        assert!(backend.is_persistent());
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            layout.sector_count() as u64 * layout.bytes_per_sector() as u64
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journaled_backend() {
+        let fixture = Fixture::empty("journaled-img").unwrap();
+        let path = fixture.join("test.img");
+        let journal_path = JournaledBackend::journal_path(&path);
+        let layout = DiskLayout::new(Tos::V100, PartitionType::Gem, 8);
+        let sector_len = layout.bytes_per_sector() as usize;
+
+        // Journal is synced, image is left as is until a checkpoint
+        let mut backend = JournaledBackend::open(&path, &layout).unwrap();
+        backend.set_sector(0x100, vec![0x42; sector_len]);
+        backend.flush().unwrap();
+        assert_eq!(
+            backend.sector(0x100).unwrap().as_deref(),
+            Some(&vec![0x42; sector_len][..])
+        );
+        assert!(fs::metadata(&journal_path).unwrap().len() > 0);
+        let image = MmapBackend::open(&path, &layout).unwrap();
+        assert_eq!(image.sector(0x100).unwrap().unwrap()[0], 0);
+
+        // Crash before checkpoint: journal is applied on reopening
+        std::mem::forget(backend);
+        let backend = JournaledBackend::open(&path, &layout).unwrap();
+        assert_eq!(fs::metadata(&journal_path).unwrap().len(), 0);
+        assert_eq!(image.sector(0x100).unwrap().unwrap()[0], 0x42);
+
+        // Torn record at end of journal is left out
+        drop(backend);
+        let mut journal = WriteJournal::open(&journal_path).unwrap();
+        journal.append(0x101, 1, &vec![0x43; sector_len]).unwrap();
+        journal.sync().unwrap();
+        let torn = fs::read(&journal_path).unwrap();
+        fs::write(&journal_path, &torn[..torn.len() - 2]).unwrap();
+        let mut backend = JournaledBackend::open(&path, &layout).unwrap();
+        assert_eq!(backend.sector(0x101).unwrap().unwrap()[0], 0);
+
+        // Enough pending sectors, or closing, update image
+        for index in 0..CHECKPOINT_SECTORS as u16 {
+            backend.set_sector(0x200 + index, vec![0x44; sector_len]);
+        }
+        backend.flush().unwrap();
+        assert_eq!(fs::metadata(&journal_path).unwrap().len(), 0);
+        assert_eq!(image.sector(0x200).unwrap().unwrap()[0], 0x44);
+        backend.set_sector(0x102, vec![0x45; sector_len]);
+        drop(backend);
+        assert_eq!(image.sector(0x102).unwrap().unwrap()[0], 0x45);
+    }
+
+    #[test]
+    fn test_mmap_storage_reopen() {
+        let sample = Fixture::sample("mmap-reopen").unwrap();
+        let fixture = Fixture::empty("fs-img").unwrap();
+        let path = fixture.join("fs.img");
+        let layout = DiskLayout::new(Tos::V100, PartitionType::Gem, 8);
+
+        {
+            let backend = MmapBackend::open(&path, &layout).unwrap();
+            let (mut storage, loaded) = DiskStorage::open_backend(layout.clone(), backend).unwrap();
+            assert!(!loaded);
+
+            storage.import_path(sample.path()).unwrap();
+            storage.flush().unwrap();
+        }
+
+        let backend = MmapBackend::open(&path, &layout).unwrap();
+        let (storage, loaded) = DiskStorage::open_backend(layout, backend).unwrap();
+        assert!(loaded);
+
+        let files = storage.list_root_file_infos();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            storage.read_file(&files[0]).unwrap(),
+            std::fs::read(sample.join("TEST.TXT")).unwrap()
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! Rotated copies of the dump, so a bad session cannot clobber the only good
+//! one.
+//!
+//! Before serving, the dump left by the previous session becomes generation
+//! 1 (`ramdisk.dump.1`), generation 1 becomes 2 and so on, up to the count
+//! to keep. Generations 2 and older can be LZ4 compressed (`.lz4` appended
+//! to their name), they are read back as any dump. LZ4 is used rather than
+//! zstd, as for dumps.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::error;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackupConfig {
+    /// Generations to keep
+    pub count: usize,
+
+    /// LZ4 compress generations 2 and older (needs `compression` feature)
+    #[serde(default)]
+    pub lz4: bool,
+}
+
+/// Path of a generation of the dump at `dump_path`.
+pub fn generation_path(dump_path: &Path, generation: usize, compressed: bool) -> PathBuf {
+    let mut path = dump_path.as_os_str().to_owned();
+    path.push(format!(".{}", generation));
+    if compressed {
+        path.push(".lz4");
+    }
+    PathBuf::from(path)
+}
+
+/// Existing file of a generation, compressed or not.
+fn find_generation(dump_path: &Path, generation: usize) -> Option<PathBuf> {
+    [false, true]
+        .iter()
+        .map(|&compressed| generation_path(dump_path, generation, compressed))
+        .find(|path| path.exists())
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Move generation file `from` to `generation`, compressing it if asked.
+fn shift(dump_path: &Path, from: &Path, generation: usize, compress: bool) -> error::Result<()> {
+    let compressed = from.extension().is_some_and(|ext| ext == "lz4");
+    if compressed || !compress {
+        fs::rename(from, generation_path(dump_path, generation, compressed))?;
+        return Ok(());
+    }
+
+    // Dumps written with `lz4_dump` are only renamed
+    if crate::dump::is_compressed(from)? {
+        fs::rename(from, generation_path(dump_path, generation, true))?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "compression")]
+    {
+        let to = generation_path(dump_path, generation, true);
+        fs::write(&to, crate::dump::compress_dump(&fs::read(from)?))?;
+        File::open(&to)?.sync_all()?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Keep a copy of the dump at `dump_path` as generation 1, shifting older
+/// ones, and tell if a copy was made.
+///
+/// Nothing is done if the dump is missing or has not changed since it was
+/// last copied.
+pub fn rotate(dump_path: &Path, config: &BackupConfig) -> error::Result<bool> {
+    if config.count == 0 || !dump_path.exists() {
+        return Ok(false);
+    }
+    let first_path = generation_path(dump_path, 1, false);
+    if first_path.exists() && fs::read(&first_path)? == fs::read(dump_path)? {
+        return Ok(false);
+    }
+
+    let compress = config.lz4 && cfg!(feature = "compression");
+    if config.lz4 && !compress {
+        log::warn!("Built without compression feature, backups are kept uncompressed");
+    }
+
+    for compressed in [false, true] {
+        remove_if_exists(&generation_path(dump_path, config.count, compressed))?;
+    }
+    for generation in (1..config.count).rev() {
+        if let Some(from) = find_generation(dump_path, generation) {
+            shift(dump_path, &from, generation + 1, compress)?;
+        }
+    }
+
+    // Copy is renamed once complete, as dumps are
+    let mut tmp_path = first_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::copy(dump_path, &tmp_path)?;
+    File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, &first_path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn test_rotate() {
+        let fixture = Fixture::empty("backup").unwrap();
+        let dir = fixture.path();
+        let dump_path = dir.join("ramdisk.dump");
+        let config = BackupConfig {
+            count: 2,
+            lz4: false,
+        };
+
+        assert!(!rotate(&dump_path, &config).unwrap());
+        for session in [b"first", b"other", b"third"] {
+            fs::write(&dump_path, session).unwrap();
+            assert!(rotate(&dump_path, &config).unwrap());
+        }
+        // Server restarted without Atari writing anything
+        assert!(!rotate(&dump_path, &config).unwrap());
+
+        let read = |generation| fs::read(generation_path(&dump_path, generation, false)).unwrap();
+        assert_eq!(read(1), b"third");
+        assert_eq!(read(2), b"other");
+        assert!(find_generation(&dump_path, 3).is_none());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_rotate_compressed() {
+        use crate::{
+            dump,
+            layout::DiskLayout,
+            storage::{DiskStorage, ROOT_INDEX},
+        };
+
+        let fixture = Fixture::empty("backupz").unwrap();
+        let dir = fixture.path();
+        let dump_path = dir.join("ramdisk.dump");
+        let config = BackupConfig {
+            count: 3,
+            lz4: true,
+        };
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        for name in ["FIRST", "SECOND"] {
+            storage
+                .add_virtual_file(
+                    name,
+                    "TXT",
+                    chrono::Local::now().naive_local(),
+                    b"",
+                    ROOT_INDEX,
+                )
+                .unwrap();
+            dump::write_dump_file(&dump_path, &storage, false).unwrap();
+            assert!(rotate(&dump_path, &config).unwrap());
+        }
+
+        assert!(generation_path(&dump_path, 1, false).exists());
+        let older = generation_path(&dump_path, 2, true);
+        let mut reader = io::BufReader::new(File::open(older).unwrap());
+        let restored = dump::read_dump(&mut reader).unwrap();
+        assert!(restored.find_entry("FIRST.TXT").unwrap().is_some());
+        assert!(restored.find_entry("SECOND.TXT").unwrap().is_none());
+    }
+}
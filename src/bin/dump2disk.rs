@@ -5,7 +5,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use ataridisk::{entries::FileInfo, storage::DiskStorage};
+use ataridisk::{dump, entries::FileInfo, shared_root::SharedRoot, storage::DiskStorage};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -16,6 +16,27 @@ struct Opt {
     /// Folder to dump data to
     #[structopt(default_value = "out")]
     dst_folder: PathBuf,
+
+    /// Prefix hidden files with a dot
+    #[structopt(long)]
+    hidden_as_dotfiles: bool,
+
+    /// Keep write permission on read-only files
+    #[structopt(long)]
+    ignore_read_only: bool,
+}
+
+impl Opt {
+    /// Name to give on host FS to a disk entry.
+    fn host_filename(&self, file_info: &FileInfo) -> anyhow::Result<String> {
+        let filename = file_info.filename()?;
+
+        if self.hidden_as_dotfiles && file_info.is_hidden() {
+            Ok(format!(".{}", filename))
+        } else {
+            Ok(filename)
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -23,48 +44,128 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     log::info!("Reading dump file from {:?}", opt.src_filename);
-    let src_file = File::open(opt.src_filename)?;
-    let reader = BufReader::new(src_file);
-    let disk: DiskStorage = bincode::deserialize_from(reader)?;
+    let src_file = File::open(&opt.src_filename)?;
+    let mut reader = BufReader::new(src_file);
+    let disk = dump::read_dump(&mut reader)?;
+    log::info!(
+        "Disk geometry: {:?} / {:?} ({} bytes per sector)",
+        disk.disk_layout.tos(),
+        disk.disk_layout.partition_type(),
+        disk.disk_layout.bytes_per_sector()
+    );
 
     log::info!("Dumping disk content to: {:?}", opt.dst_folder);
     fs::create_dir_all(&opt.dst_folder)?;
 
+    // Names written by Atari must not escape destination folder
+    let root = SharedRoot::new(&opt.dst_folder)?;
     for file_info in disk.list_root_file_infos() {
         if file_info.is_dir() {
-            dump_dir(&disk, &file_info, &opt.dst_folder)?;
+            dump_dir(&opt, &root, &disk, &file_info, root.path())?;
         } else {
-            dump_file(&disk, &file_info, &opt.dst_folder)?;
+            dump_file(&opt, &root, &disk, &file_info, root.path())?;
         }
     }
     Ok(())
 }
 
-fn dump_file<P>(disk: &DiskStorage, file_info: &FileInfo, out_dir: P) -> anyhow::Result<()>
+fn dump_file<P>(
+    opt: &Opt,
+    root: &SharedRoot,
+    disk: &DiskStorage,
+    file_info: &FileInfo,
+    out_dir: P,
+) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
-    let file_name = out_dir.as_ref().join(&file_info.filename()?);
+    let file_name = root.join(out_dir, &opt.host_filename(file_info)?)?;
 
     log::info!("Dumping: {:?}", file_name);
     let content = disk.read_file(file_info)?;
-    fs::write(file_name, content)?;
+    fs::write(&file_name, content)?;
+
+    if file_info.is_read_only() && !opt.ignore_read_only {
+        let mut permissions = fs::metadata(&file_name)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file_name, permissions)?;
+    }
     Ok(())
 }
 
-fn dump_dir<P>(disk: &DiskStorage, file_info: &FileInfo, out_dir: P) -> anyhow::Result<()>
+fn dump_dir<P>(
+    opt: &Opt,
+    root: &SharedRoot,
+    disk: &DiskStorage,
+    file_info: &FileInfo,
+    out_dir: P,
+) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
-    let out_dir = out_dir.as_ref().join(file_info.filename()?);
+    let out_dir = root.join(out_dir, &opt.host_filename(file_info)?)?;
     fs::create_dir_all(&out_dir)?;
 
-    for entry in disk.read_dir(file_info)?.iter().skip(2) {
-        if entry.is_dir() {
-            dump_dir(disk, entry, &out_dir)?;
-        } else {
-            dump_file(disk, entry, &out_dir)?;
+    // Large directories span several clusters, `.` and `..` only start the first one
+    for (i, cluster_index) in disk.dir_chain(file_info).enumerate() {
+        let entries = disk.read_dir_cluster(cluster_index)?;
+        let skipped = if i == 0 { 2 } else { 0 };
+
+        for entry in entries.iter().skip(skipped) {
+            if entry.is_deleted() || entry.is_unused() {
+                continue;
+            }
+            if entry.is_dir() {
+                dump_dir(opt, root, disk, entry, &out_dir)?;
+            } else {
+                dump_file(opt, root, disk, entry, &out_dir)?;
+            }
         }
     }
     Ok(())
 }
+
+// Binaries only see `fixture` with its feature: `cargo test --features fixture`
+#[cfg(all(test, feature = "fixture"))]
+mod tests {
+    use ataridisk::{
+        fixture::Fixture,
+        layout::{DiskLayout, PartitionType, Tos},
+        storage::ROOT_INDEX,
+    };
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_large_dir() {
+        let fixture = Fixture::empty("dump2disk").unwrap();
+        let out = fixture.path();
+
+        // 32 entries per cluster: 100 files need 4 clusters
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut disk = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Gem, 8));
+        let dir = disk
+            .add_virtual_directory("BIG", "", mtime, ROOT_INDEX)
+            .unwrap();
+        for i in 0..100 {
+            disk.add_virtual_file(&format!("F{}", i), "TXT", mtime, b"x", dir)
+                .unwrap();
+        }
+        let dir_info = disk.list_root_file_infos()[0].clone();
+        assert!(disk.dir_chain(&dir_info).count() > 1);
+
+        let opt = Opt {
+            src_filename: PathBuf::new(),
+            dst_folder: out.to_path_buf(),
+            hidden_as_dotfiles: false,
+            ignore_read_only: false,
+        };
+        let root = SharedRoot::new(out).unwrap();
+        dump_dir(&opt, &root, &disk, &dir_info, root.path()).unwrap();
+
+        let dumped = fs::read_dir(root.path().join("BIG")).unwrap().count();
+        assert_eq!(dumped, 100);
+        assert!(root.path().join("BIG").join("F99.TXT").exists());
+    }
+}
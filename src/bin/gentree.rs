@@ -0,0 +1,63 @@
+use std::{fs, path::PathBuf};
+
+use ataridisk::fixture::{self, TreeSpec};
+use structopt::StructOpt;
+
+/// Generate a host directory tree to import, to test or benchmark the server
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Folder to generate tree in
+    #[structopt(default_value = "tree")]
+    dst_folder: PathBuf,
+
+    /// Files in each directory
+    #[structopt(long, default_value = "4")]
+    files: usize,
+
+    /// Sub directories in each directory
+    #[structopt(long, default_value = "2")]
+    dirs: usize,
+
+    /// Directory levels below root
+    #[structopt(long, default_value = "2")]
+    depth: usize,
+
+    /// Smallest file size, in bytes
+    #[structopt(long, default_value = "0")]
+    min_size: usize,
+
+    /// Largest file size, in bytes
+    #[structopt(long, default_value = "4096")]
+    max_size: usize,
+
+    /// Give some entries names TOS cannot use as is
+    #[structopt(long)]
+    weird_names: bool,
+
+    /// Seed of names and content, same seed giving same tree
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::from_args();
+    env_logger::init();
+
+    if opt.dst_folder.exists() && fs::read_dir(&opt.dst_folder)?.next().is_some() {
+        anyhow::bail!("{:?} is not empty", opt.dst_folder);
+    }
+    fs::create_dir_all(&opt.dst_folder)?;
+
+    let spec = TreeSpec {
+        files: opt.files,
+        dirs: opt.dirs,
+        depth: opt.depth,
+        min_size: opt.min_size,
+        max_size: opt.max_size,
+        weird_names: opt.weird_names,
+        seed: opt.seed,
+    };
+    let files = fixture::write_tree(&opt.dst_folder, &spec)?;
+    println!("{} files generated in {:?}", files.len(), opt.dst_folder);
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Executable boot sectors.
+//!
+//! TOS runs the boot sector of a disk only if the sum of its first 256 big
+//! endian words is `0x1234`. The last of these words is free, and set to
+//! reach this value.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::error::{self, SerialDiskError};
+
+/// Checksum of an executable boot sector.
+pub const EXECUTABLE_CHECKSUM: u16 = 0x1234;
+
+/// Bytes covered by the checksum, whatever the sector size.
+pub const CHECKSUM_LEN: usize = 512;
+
+/// BPB fields of the boot sector, kept when injecting boot code.
+const BPB_RANGE: std::ops::Range<usize> = 0x0B..0x1E;
+
+/// Sum of the words covered by the checksum.
+pub fn checksum(sector: &[u8]) -> u16 {
+    sector[..CHECKSUM_LEN]
+        .chunks_exact(2)
+        .fold(0u16, |sum, word| {
+            sum.wrapping_add(BigEndian::read_u16(word))
+        })
+}
+
+pub fn is_executable(sector: &[u8]) -> bool {
+    sector.len() >= CHECKSUM_LEN && checksum(sector) == EXECUTABLE_CHECKSUM
+}
+
+/// Set the last checksummed word so the sector is executable.
+pub fn fix_checksum(sector: &mut [u8]) {
+    let last = CHECKSUM_LEN - 2;
+    BigEndian::write_u16(&mut sector[last..], 0);
+    let word = EXECUTABLE_CHECKSUM.wrapping_sub(checksum(sector));
+    BigEndian::write_u16(&mut sector[last..], word);
+}
+
+/// Put boot code of a user provided boot sector in `sector`, keeping the BPB
+/// of `sector`, and make it executable.
+pub fn inject(sector: &mut [u8], boot: &[u8]) -> error::Result<()> {
+    if boot.len() != CHECKSUM_LEN {
+        return Err(SerialDiskError::InvalidImage(format!(
+            "boot sector must be {} bytes, got {}",
+            CHECKSUM_LEN,
+            boot.len()
+        )));
+    }
+    if sector.len() < CHECKSUM_LEN {
+        return Err(SerialDiskError::InvalidImage(format!(
+            "cannot inject boot code in {} bytes sector",
+            sector.len()
+        )));
+    }
+
+    let bpb = sector[BPB_RANGE].to_vec();
+    sector[..CHECKSUM_LEN].copy_from_slice(boot);
+    sector[BPB_RANGE].copy_from_slice(&bpb);
+    fix_checksum(sector);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject() {
+        let mut sector = vec![0; 8192];
+        sector[0x0B..0x0D].copy_from_slice(&[0x00, 0x20]);
+        assert!(!is_executable(&sector));
+
+        let mut boot = vec![0x4E; CHECKSUM_LEN];
+        boot[0x0B] = 0xFF;
+        inject(&mut sector, &boot).unwrap();
+        assert!(is_executable(&sector));
+        assert_eq!(&sector[0x0B..0x0D], [0x00, 0x20]);
+        assert_eq!(sector[0x1E], 0x4E);
+        assert_eq!(sector[CHECKSUM_LEN], 0);
+
+        // Fixing again changes nothing
+        let fixed = sector.clone();
+        fix_checksum(&mut sector);
+        assert_eq!(sector, fixed);
+
+        assert!(inject(&mut sector, &boot[..100]).is_err());
+    }
+}
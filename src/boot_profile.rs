@@ -0,0 +1,189 @@
+//! What Atari reads while booting (AUTO programs, ACC files...), to serve
+//! it faster on next boots.
+//!
+//! A profile is recorded with `--record-boot`: read commands received during
+//! `BOOT_WINDOW` from the first read of a session, and files they read. Once
+//! given in config, its reads are compressed when serving starts and kept in
+//! memory, and `export` lays its files in clusters following each other.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{access_log::AccessLog, backend::SectorBackend, error, storage::DiskStorage};
+
+/// Time from first read during which reads are part of the boot.
+pub const BOOT_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BootProfile {
+    /// Sector index and count of read commands, in order
+    pub reads: Vec<(u16, u16)>,
+    /// `\` separated paths of files read, in order
+    pub files: Vec<String>,
+}
+
+impl BootProfile {
+    pub fn load<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content).map_err(io::Error::from)?)
+    }
+
+    pub fn save<P>(&self, path: P) -> error::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let content = serde_json::to_string_pretty(self).map_err(io::Error::from)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// Profile of the boot of a session, saved as it grows.
+#[derive(Debug)]
+pub struct BootRecorder {
+    path: PathBuf,
+    started: Option<Instant>,
+    profile: BootProfile,
+    access_log: AccessLog,
+}
+
+impl BootRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            started: None,
+            profile: BootProfile::default(),
+            access_log: AccessLog::new(),
+        }
+    }
+
+    /// Add a read command to the profile, if boot is not over.
+    pub fn record<B>(&mut self, storage: &DiskStorage<B>, index: u16, count: u16, now: Instant)
+    where
+        B: SectorBackend,
+    {
+        let started = *self.started.get_or_insert(now);
+        if now.duration_since(started) >= BOOT_WINDOW
+            || self.profile.reads.contains(&(index, count))
+        {
+            return;
+        }
+
+        self.profile.reads.push((index, count));
+        for file in self.access_log.files(storage, index, count) {
+            if !self.profile.files.contains(&file) {
+                self.profile.files.push(file);
+            }
+        }
+
+        if let Err(e) = self.profile.save(&self.path) {
+            log::warn!("Cannot save boot profile {:?} (error: {})", self.path, e);
+        }
+    }
+
+    pub fn profile(&self) -> &BootProfile {
+        &self.profile
+    }
+}
+
+/// Read answers of a boot profile, ready to send.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PinnedReads {
+    /// Sectors content and answer frame, by sector index and count
+    frames: HashMap<(u16, u16), (Vec<u8>, Vec<u8>)>,
+}
+
+impl PinnedReads {
+    /// Build answers of profile reads from current disk content, with
+    /// `encode` giving the frame of some sectors content.
+    pub fn new<B, F>(profile: &BootProfile, storage: &DiskStorage<B>, mut encode: F) -> Self
+    where
+        B: SectorBackend,
+        F: FnMut(&[u8]) -> error::Result<Vec<u8>>,
+    {
+        let mut frames = HashMap::new();
+        for &(index, count) in &profile.reads {
+            let mut data = Vec::new();
+            let frame = storage
+                .read_sectors(&mut data, index, count)
+                .map_err(error::SerialDiskError::from)
+                .and_then(|_| encode(&data));
+            match frame {
+                Ok(frame) => {
+                    frames.insert((index, count), (data, frame));
+                }
+                Err(e) => log::warn!("Cannot pin boot read {:#x} (error: {})", index, e),
+            }
+        }
+        Self { frames }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Answer frame of a read, if sectors still have their pinned content.
+    pub fn frame(&self, index: u16, count: u16, data: &[u8]) -> Option<&[u8]> {
+        match self.frames.get(&(index, count)) {
+            Some((pinned, frame)) if pinned == data => Some(frame),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout, storage::ROOT_INDEX};
+
+    #[test]
+    fn test_record() {
+        let fixture = Fixture::empty("boot").unwrap();
+        let path = fixture.join("test.boot");
+        let mtime = chrono::Local::now().naive_local();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage
+            .add_virtual_file("DESK", "ACC", mtime, &[1; 2048], ROOT_INDEX)
+            .unwrap();
+        let entry = storage.find_entry("DESK.ACC").unwrap().unwrap();
+        let sector = storage
+            .disk_layout
+            .convert_cluster_to_sector(entry.cluster_index);
+
+        let mut recorder = BootRecorder::new(path.clone());
+        let now = Instant::now();
+        recorder.record(&storage, 0, 2, now);
+        recorder.record(&storage, sector, 4, now);
+        recorder.record(&storage, sector, 4, now);
+        recorder.record(&storage, 0x100, 1, now + BOOT_WINDOW);
+
+        let expected = BootProfile {
+            reads: vec![(0, 2), (sector, 4)],
+            files: vec!["DESK.ACC".to_string()],
+        };
+        assert_eq!(recorder.profile(), &expected);
+        assert_eq!(BootProfile::load(&path).unwrap(), expected);
+
+        let pinned = PinnedReads::new(&expected, &storage, |data| Ok(data.to_vec()));
+        assert_eq!(pinned.len(), 2);
+        let mut data = Vec::new();
+        storage.read_sectors(&mut data, sector, 4).unwrap();
+        assert_eq!(pinned.frame(sector, 4, &data), Some(&data[..]));
+        data[0] = 0;
+        assert_eq!(pinned.frame(sector, 4, &data), None);
+        assert_eq!(pinned.frame(sector, 2, &data[..1024]), None);
+    }
+}
@@ -0,0 +1,72 @@
+//! Changes made to a disk, for subsystems following its content.
+//!
+//! Every subscriber gets its own channel, so it can handle events on its own
+//! thread without slowing down the serial link. Subscribers dropping their
+//! receiver are forgotten on next event.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageEvent {
+    /// Atari has written sectors
+    SectorWritten { index: u16, count: u16 },
+    /// A file has been added or its entry has been updated (size, time...)
+    FileWritten { path: String, size: usize },
+    /// A directory entry has been added, changed or deleted
+    DirEntryChanged { path: String, deleted: bool },
+}
+
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Vec<Sender<StorageEvent>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self) -> Receiver<StorageEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Check if anyone listens, to skip computing events otherwise.
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    pub fn emit(&mut self, event: StorageEvent) {
+        match self.subscribers.as_slice() {
+            [] => {}
+            [subscriber] => {
+                if subscriber.send(event).is_err() {
+                    self.subscribers.clear();
+                }
+            }
+            _ => self
+                .subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit() {
+        let mut bus = EventBus::default();
+        assert!(!bus.has_subscribers());
+
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        let event = StorageEvent::SectorWritten { index: 4, count: 1 };
+        bus.emit(event.clone());
+        assert_eq!(first.try_recv(), Ok(event.clone()));
+        assert_eq!(second.try_recv(), Ok(event.clone()));
+
+        drop(first);
+        bus.emit(event.clone());
+        assert_eq!(second.try_recv(), Ok(event));
+        assert_eq!(bus.subscribers.len(), 1);
+    }
+}
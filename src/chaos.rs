@@ -0,0 +1,269 @@
+//! Fault injection on the link with Atari, for driver development.
+//!
+//! Faults are given as `key=value` pairs separated by commas (ex:
+//! `drop=1000,flip=0.001,delay=200,seed=7`):
+//!
+//! - `drop=N`: drop every N-th byte, in each direction,
+//! - `flip=P`: flip a random bit of a byte with probability P,
+//! - `delay=MS`: wait up to MS milliseconds when the link turns around
+//!   (first transfer after the other side has spoken),
+//! - `seed=S`: seed of the random faults, same seed gives same faults.
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Write},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use crate::{error, rng::Rng, transport::Transport};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Chaos {
+    pub drop_every: Option<u64>,
+    pub flip_probability: f64,
+    pub max_delay_ms: u64,
+    pub seed: u64,
+}
+
+impl FromStr for Chaos {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Self::default();
+
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", pair))?;
+            let invalid = || format!("invalid value for {}: {:?}", key, value);
+
+            match key {
+                "drop" => {
+                    let every: u64 = value.parse().map_err(|_| invalid())?;
+                    chaos.drop_every = (every > 0).then_some(every);
+                }
+                "flip" => {
+                    chaos.flip_probability = value.parse().map_err(|_| invalid())?;
+                    if !(0.0..=1.0).contains(&chaos.flip_probability) {
+                        return Err(format!("flip probability must be in 0..1, got {}", value));
+                    }
+                }
+                "delay" => chaos.max_delay_ms = value.parse().map_err(|_| invalid())?,
+                "seed" => chaos.seed = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown fault {:?}", key)),
+            }
+        }
+
+        Ok(chaos)
+    }
+}
+
+impl Display for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "drop={},flip={},delay={},seed={}",
+            self.drop_every.unwrap_or_default(),
+            self.flip_probability,
+            self.max_delay_ms,
+            self.seed
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Read,
+    Write,
+}
+
+/// Transport injecting faults in the bytes going through it.
+#[derive(Debug)]
+pub struct ChaosTransport<T> {
+    inner: T,
+    chaos: Chaos,
+    rng: Rng,
+    read_count: u64,
+    write_count: u64,
+    last_direction: Option<Direction>,
+}
+
+impl<T> ChaosTransport<T> {
+    pub fn new(inner: T, chaos: Chaos) -> Self {
+        log::warn!("Injecting faults on link with Atari ({})", chaos);
+        Self {
+            inner,
+            chaos,
+            rng: Rng::new(chaos.seed),
+            read_count: 0,
+            write_count: 0,
+            last_direction: None,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn turn_around(&mut self, direction: Direction) {
+        if self.last_direction == Some(direction) {
+            return;
+        }
+        self.last_direction = Some(direction);
+
+        if self.chaos.max_delay_ms > 0 {
+            let delay = self.rng.below(self.chaos.max_delay_ms as usize + 1);
+            log::debug!("Chaos: delaying {:?} by {}ms", direction, delay);
+            thread::sleep(Duration::from_millis(delay as u64));
+        }
+    }
+
+    /// Apply faults to `data` and return bytes left.
+    fn corrupt(&mut self, data: &[u8], direction: Direction) -> Vec<u8> {
+        let mut kept = Vec::with_capacity(data.len());
+
+        for &byte in data {
+            let count = match direction {
+                Direction::Read => &mut self.read_count,
+                Direction::Write => &mut self.write_count,
+            };
+            *count += 1;
+
+            if self
+                .chaos
+                .drop_every
+                .is_some_and(|every| *count % every == 0)
+            {
+                log::debug!("Chaos: dropping {:?} byte {}", direction, count);
+                continue;
+            }
+
+            if self.chaos.flip_probability > 0.0 && self.rng.chance(self.chaos.flip_probability) {
+                let bit = self.rng.below(8);
+                log::debug!(
+                    "Chaos: flipping bit {} of {:?} byte {}",
+                    bit,
+                    direction,
+                    count
+                );
+                kept.push(byte ^ (1 << bit));
+            } else {
+                kept.push(byte);
+            }
+        }
+
+        kept
+    }
+}
+
+impl<T> Read for ChaosTransport<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.turn_around(Direction::Read);
+
+        loop {
+            let count = self.inner.read(buf)?;
+            if count == 0 {
+                return Ok(0);
+            }
+
+            // Every byte may have been dropped, read again
+            let kept = self.corrupt(&buf[..count], Direction::Read);
+            if !kept.is_empty() {
+                buf[..kept.len()].copy_from_slice(&kept);
+                return Ok(kept.len());
+            }
+        }
+    }
+}
+
+impl<T> Write for ChaosTransport<T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.turn_around(Direction::Write);
+
+        let kept = self.corrupt(buf, Direction::Write);
+        self.inner.write_all(&kept)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T> Transport for ChaosTransport<T>
+where
+    T: Transport,
+{
+    fn discard_pending(&mut self) -> error::Result<()> {
+        self.inner.discard_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    #[test]
+    fn test_parse() {
+        let chaos: Chaos = "drop=1000, flip=0.001,delay=20,seed=7".parse().unwrap();
+        assert_eq!(
+            chaos,
+            Chaos {
+                drop_every: Some(1000),
+                flip_probability: 0.001,
+                max_delay_ms: 20,
+                seed: 7,
+            }
+        );
+        assert_eq!(chaos.to_string().parse::<Chaos>(), Ok(chaos));
+
+        assert!("flip=2".parse::<Chaos>().is_err());
+        assert!("latency=5".parse::<Chaos>().is_err());
+        assert!("drop".parse::<Chaos>().is_err());
+    }
+
+    #[test]
+    fn test_drop() {
+        let chaos = Chaos {
+            drop_every: Some(3),
+            ..Default::default()
+        };
+        let mut transport =
+            ChaosTransport::new(MemoryTransport::new(&[1, 2, 3, 4, 5, 6, 7]), chaos);
+
+        let mut received = Vec::new();
+        transport.read_to_end(&mut received).unwrap_err();
+        assert_eq!(received, [1, 2, 4, 5, 7]);
+
+        transport.write_all(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(transport.into_inner().output(), [1, 2, 4]);
+    }
+
+    #[test]
+    fn test_flip_is_seeded() {
+        let chaos = Chaos {
+            flip_probability: 0.5,
+            seed: 42,
+            ..Default::default()
+        };
+        let sent = |chaos| {
+            let mut transport = ChaosTransport::new(MemoryTransport::default(), chaos);
+            transport.write_all(&[0; 64]).unwrap();
+            transport.into_inner().output().to_vec()
+        };
+
+        let output = sent(chaos);
+        assert_eq!(output, sent(chaos));
+        assert!(output.iter().all(|b| b.count_ones() <= 1));
+        assert!(output.iter().any(|b| *b != 0));
+    }
+}
@@ -2,19 +2,21 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::error;
 
+/// Compute a CRC32 POSIX value for a given payload.
+pub fn crc32(buf: &[u8]) -> u32 {
+    let mut crc = crc_any::CRC::crc32posix();
+    crc.digest(buf);
+    crc.get_crc() as u32
+}
+
 /// Compute a CRC32 POSIX value for a given payload
 /// to send, then write it to input writer.
 pub fn write_crc32<W>(writer: &mut W, buf: &[u8]) -> error::Result<()>
 where
     W: WriteBytesExt,
 {
-    // Compute hash
-    let mut crc = crc_any::CRC::crc32posix();
-    crc.digest(buf);
-    let val = crc.get_crc();
-
     // Encode hash with correct endianess
-    writer.write_u32::<BigEndian>(val as u32)?;
+    writer.write_u32::<BigEndian>(crc32(buf))?;
 
     Ok(())
 }
@@ -26,12 +28,7 @@ where
     // Read hash with correct endianess
     let expected = reader.read_u32::<BigEndian>()? as u64;
 
-    // Compute hash
-    let mut crc = crc_any::CRC::crc32posix();
-    crc.digest(buf);
-    let val = crc.get_crc();
-
-    Ok(val == expected)
+    Ok(crc32(buf) as u64 == expected)
 }
 
 #[cfg(test)]
@@ -0,0 +1,143 @@
+//! Length of the chunks of chunked frames, tuned along a session.
+//!
+//! Chunks only hold whole sectors, so a driver can read again the sectors of
+//! a chunk received with an invalid CRC instead of the whole frame: small
+//! chunks cost less on a noisy link, large ones have less overhead on a
+//! clean one. Atari checks the CRC of what it reads on its side, so errors
+//! are seen on writes, which go through the same link.
+
+use std::{collections::BTreeMap, time::Duration};
+
+pub const MIN_CHUNK_LEN: usize = 512;
+/// Chunk length is sent in a word.
+pub const MAX_CHUNK_LEN: usize = 32 * 1024;
+pub const INITIAL_CHUNK_LEN: usize = 8 * 1024;
+
+/// Frames sent without CRC error before trying another length.
+const FRAMES_PER_TRY: u32 = 4;
+
+#[derive(Debug)]
+pub struct ChunkSize {
+    len: usize,
+    /// Frames sent with current length since last change or error
+    clean_frames: u32,
+    sent_bytes: usize,
+    sent_time: Duration,
+    /// Last throughput measured with each length, in bytes per second
+    throughput: BTreeMap<usize, f64>,
+}
+
+impl Default for ChunkSize {
+    fn default() -> Self {
+        Self {
+            len: INITIAL_CHUNK_LEN,
+            clean_frames: 0,
+            sent_bytes: 0,
+            sent_time: Duration::ZERO,
+            throughput: BTreeMap::new(),
+        }
+    }
+}
+
+impl ChunkSize {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current chunk length, a power of two.
+    pub fn chunk_len(&self) -> usize {
+        self.len
+    }
+
+    /// Halve chunk length after a CRC error.
+    pub fn record_crc_error(&mut self) {
+        self.set_len((self.len / 2).max(MIN_CHUNK_LEN));
+    }
+
+    /// Record the time taken to send a chunked frame of `bytes`.
+    ///
+    /// Every few clean frames, length doubles unless the doubled one has
+    /// already been measured slower, and halves if the halved one has been
+    /// measured faster.
+    pub fn record_frame(&mut self, bytes: usize, elapsed: Duration) {
+        self.clean_frames += 1;
+        self.sent_bytes += bytes;
+        self.sent_time += elapsed;
+        if self.clean_frames < FRAMES_PER_TRY {
+            return;
+        }
+
+        let throughput = self.sent_bytes as f64 / self.sent_time.as_secs_f64().max(1e-6);
+        self.throughput.insert(self.len, throughput);
+        let measured = |len: usize| self.throughput.get(&len).copied();
+
+        if self.len > MIN_CHUNK_LEN && measured(self.len / 2).is_some_and(|t| t > throughput) {
+            self.set_len(self.len / 2);
+        } else if self.len < MAX_CHUNK_LEN && measured(self.len * 2).is_none_or(|t| t >= throughput)
+        {
+            self.set_len(self.len * 2);
+        } else {
+            self.set_len(self.len);
+        }
+    }
+
+    fn set_len(&mut self, len: usize) {
+        if len != self.len {
+            log::debug!("Chunk length set to {} bytes", len);
+        }
+        self.len = len;
+        self.clean_frames = 0;
+        self.sent_bytes = 0;
+        self.sent_time = Duration::ZERO;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send frames at a throughput depending on chunk length.
+    fn send_frames<F>(chunk_size: &mut ChunkSize, frames: usize, throughput: F)
+    where
+        F: Fn(usize) -> f64,
+    {
+        for _ in 0..frames {
+            let bytes = 64 * 1024;
+            let secs = bytes as f64 / throughput(chunk_size.chunk_len());
+            chunk_size.record_frame(bytes, Duration::from_secs_f64(secs));
+        }
+    }
+
+    #[test]
+    fn test_grow_on_clean_link() {
+        let mut chunk_size = ChunkSize::new();
+        send_frames(&mut chunk_size, 100, |len| 10_000.0 + len as f64);
+        assert_eq!(chunk_size.chunk_len(), MAX_CHUNK_LEN);
+    }
+
+    #[test]
+    fn test_shrink_on_errors() {
+        let mut chunk_size = ChunkSize::new();
+        for _ in 0..10 {
+            chunk_size.record_crc_error();
+        }
+        assert_eq!(chunk_size.chunk_len(), MIN_CHUNK_LEN);
+
+        // Clean link again
+        send_frames(&mut chunk_size, 100, |_| 10_000.0);
+        assert_eq!(chunk_size.chunk_len(), MAX_CHUNK_LEN);
+    }
+
+    #[test]
+    fn test_converge_to_fastest() {
+        // Turnaround of the driver makes chunks larger than 8 KiB slower
+        let throughput = |len: usize| if len > 8 * 1024 { 5_000.0 } else { len as f64 };
+        let mut chunk_size = ChunkSize::new();
+        send_frames(&mut chunk_size, 100, throughput);
+        assert_eq!(chunk_size.chunk_len(), 8 * 1024);
+
+        chunk_size.record_crc_error();
+        send_frames(&mut chunk_size, 100, throughput);
+        assert_eq!(chunk_size.chunk_len(), 8 * 1024);
+    }
+}
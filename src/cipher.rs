@@ -0,0 +1,300 @@
+//! ChaCha20 stream cipher (RFC 8439), to encrypt links crossing untrusted
+//! networks.
+//!
+//! The 32 bytes key is derived from a passphrase shared with Atari side with
+//! PBKDF2 (RFC 8018) over HMAC-SHA-1, `ITERATIONS` times, salted with 8
+//! random bytes drawn when server starts.
+//!
+//! Each connection starts with the server sending in clear this salt, then 8
+//! random bytes of session nonce. Atari side only derives key again when
+//! salt changes. Each direction has its own nonce: a direction byte (`0x00`
+//! from Atari, `0x01` to Atari), 3 zero bytes, then the session nonce. Block
+//! counter starts at 0. Data is encrypted only, not authenticated.
+
+use std::{fs::File, io::Read};
+
+use crate::hash::Sha1;
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 8;
+pub const SESSION_NONCE_LEN: usize = 8;
+
+/// Slows down guessing passphrases, while Atari side still derives a key in
+/// a few seconds.
+pub const ITERATIONS: u32 = 1024;
+
+const HMAC_BLOCK_LEN: usize = 64;
+
+const BLOCK_LEN: usize = 64;
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646E, 0x7962_2D32, 0x6B20_6574];
+
+/// Direction byte of the nonce of data sent by Atari.
+pub const FROM_ATARI: u8 = 0x00;
+/// Direction byte of the nonce of data sent to Atari.
+pub const TO_ATARI: u8 = 0x01;
+
+/// Key shared with Atari side, with the salt it was derived with.
+#[derive(Debug, Clone)]
+pub struct LinkKey {
+    pub salt: [u8; SALT_LEN],
+    pub key: [u8; KEY_LEN],
+}
+
+impl LinkKey {
+    /// Derive key from `passphrase` with a new random salt.
+    pub fn new(passphrase: &str) -> std::io::Result<Self> {
+        let salt = random_bytes()?;
+        Ok(Self {
+            salt,
+            key: derive_key(passphrase, &salt),
+        })
+    }
+}
+
+/// Key derived from `passphrase` and `salt`.
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0; KEY_LEN];
+    pbkdf2_sha1(passphrase.as_bytes(), salt, ITERATIONS, &mut key);
+    key
+}
+
+/// Fill `output` with PBKDF2-HMAC-SHA-1 of `password` and `salt`.
+fn pbkdf2_sha1(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    let hmac = Hmac::new(password);
+    for (index, chunk) in output.chunks_mut(20).enumerate() {
+        let mut content = salt.to_vec();
+        content.extend_from_slice(&(index as u32 + 1).to_be_bytes());
+        let mut u = hmac.digest(&content);
+        let mut block = u;
+        for _ in 1..iterations {
+            u = hmac.digest(&u);
+            block.iter_mut().zip(&u).for_each(|(b, u)| *b ^= u);
+        }
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+/// HMAC-SHA-1 (RFC 2104), with padded key hashed once for every message.
+struct Hmac {
+    inner: Sha1,
+    outer: Sha1,
+}
+
+impl Hmac {
+    fn new(key: &[u8]) -> Self {
+        let mut padded = [0; HMAC_BLOCK_LEN];
+        if key.len() > HMAC_BLOCK_LEN {
+            padded[..20].copy_from_slice(&Sha1::digest(key));
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+
+        let hasher = |pad: u8| {
+            let mut sha1 = Sha1::default();
+            sha1.update(&padded.map(|byte| byte ^ pad));
+            sha1
+        };
+        Self {
+            inner: hasher(0x36),
+            outer: hasher(0x5C),
+        }
+    }
+
+    fn digest(&self, content: &[u8]) -> [u8; 20] {
+        let mut inner = self.inner.clone();
+        inner.update(content);
+        let mut outer = self.outer.clone();
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+}
+
+/// Random bytes starting a connection, read from the OS.
+pub fn session_nonce() -> std::io::Result<[u8; SESSION_NONCE_LEN]> {
+    random_bytes()
+}
+
+fn random_bytes<const N: usize>() -> std::io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; 12]) -> [u8; BLOCK_LEN] {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&CONSTANTS);
+    for (i, chunk) in key.chunks_exact(4).enumerate() {
+        initial[4 + i] = word(chunk);
+    }
+    initial[12] = counter;
+    for (i, chunk) in nonce.chunks_exact(4).enumerate() {
+        initial[13 + i] = word(chunk);
+    }
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut output = [0; BLOCK_LEN];
+    for (i, chunk) in output.chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&state[i].wrapping_add(initial[i]).to_le_bytes());
+    }
+    output
+}
+
+/// Key stream of one direction of a connection.
+#[derive(Debug, Clone)]
+pub struct KeyStream {
+    key: [u8; KEY_LEN],
+    nonce: [u8; 12],
+    counter: u32,
+    block: [u8; BLOCK_LEN],
+    used: usize,
+}
+
+impl KeyStream {
+    pub fn new(
+        key: &[u8; KEY_LEN],
+        direction: u8,
+        session_nonce: &[u8; SESSION_NONCE_LEN],
+    ) -> Self {
+        let mut nonce = [0; 12];
+        nonce[0] = direction;
+        nonce[4..].copy_from_slice(session_nonce);
+        Self::with_nonce(key, nonce, 0)
+    }
+
+    fn with_nonce(key: &[u8; KEY_LEN], nonce: [u8; 12], counter: u32) -> Self {
+        Self {
+            key: *key,
+            nonce,
+            counter,
+            block: [0; BLOCK_LEN],
+            used: BLOCK_LEN,
+        }
+    }
+
+    /// Encrypt or decrypt `data` in place.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.used == BLOCK_LEN {
+                self.block = block(&self.key, self.counter, &self.nonce);
+                self.counter = self.counter.wrapping_add(1);
+                self.used = 0;
+            }
+            *byte ^= self.block[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc_key() -> [u8; KEY_LEN] {
+        let mut key = [0; KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        key
+    }
+
+    #[test]
+    fn test_block() {
+        // RFC 8439, 2.3.2
+        let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4A, 0, 0, 0, 0];
+        let output = block(&rfc_key(), 1, &nonce);
+        assert_eq!(
+            output[..16],
+            [
+                0x10, 0xF1, 0xE7, 0xE4, 0xD1, 0x3B, 0x59, 0x15, 0x50, 0x0F, 0xDD, 0x1F, 0xA3, 0x20,
+                0x71, 0xC4
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_stream() {
+        // RFC 8439, 2.4.2
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4A, 0, 0, 0, 0];
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".to_vec();
+        let plain = data.clone();
+
+        // Applied in uneven pieces, across block boundaries
+        let mut stream = KeyStream::with_nonce(&rfc_key(), nonce, 1);
+        let (first, second) = data.split_at_mut(70);
+        stream.apply(first);
+        stream.apply(second);
+        assert_eq!(data[..8], [0x6E, 0x2E, 0x35, 0x9A, 0x25, 0x68, 0xF9, 0x80]);
+        assert_eq!(data[data.len() - 2..], [0x87, 0x4D]);
+
+        KeyStream::with_nonce(&rfc_key(), nonce, 1).apply(&mut data);
+        assert_eq!(data, plain);
+
+        // Directions do not share their key stream
+        let key = derive_key("secret", &[1; SALT_LEN]);
+        let mut to_atari = [0; 16];
+        let mut from_atari = [0; 16];
+        KeyStream::new(&key, TO_ATARI, &[7; 8]).apply(&mut to_atari);
+        KeyStream::new(&key, FROM_ATARI, &[7; 8]).apply(&mut from_atari);
+        assert_ne!(to_atari, from_atari);
+        assert_ne!(key, derive_key("Secret", &[1; SALT_LEN]));
+        assert_ne!(key, derive_key("secret", &[2; SALT_LEN]));
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        // RFC 6070
+        let pbkdf2 = |password: &[u8], salt: &[u8], iterations, len| {
+            let mut output = vec![0; len];
+            pbkdf2_sha1(password, salt, iterations, &mut output);
+            crate::hash::to_hex(&output)
+        };
+        assert_eq!(
+            pbkdf2(b"password", b"salt", 1, 20),
+            "0c60c80f961f0e71f3a9b524af6012062fe037a6"
+        );
+        assert_eq!(
+            pbkdf2(b"password", b"salt", 4096, 20),
+            "4b007901b765489abead49d926f721d065a429c1"
+        );
+        assert_eq!(
+            pbkdf2(
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                25
+            ),
+            "3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038"
+        );
+
+        // Keys longer than a block are hashed first
+        let long = [b'k'; 100];
+        assert_eq!(
+            Hmac::new(&long).digest(b"data"),
+            Hmac::new(&Sha1::digest(&long)).digest(b"data")
+        );
+    }
+}
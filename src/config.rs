@@ -1,6 +1,28 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
 use serde::Deserialize;
 
-use crate::layout::{PartitionType, Tos};
+use crate::{
+    activity::ActivityLight,
+    backup::BackupConfig,
+    hooks::Hooks,
+    host_exec::HostCommands,
+    idle::IdleConfig,
+    image::BootFields,
+    layout::{PartitionType, Tos},
+    overlay::OverlayConfig,
+    persistence::WritePolicy,
+    quota::Quota,
+    state_machine::ClockSource,
+    storage::{FormatPolicy, UninitializedReadPolicy},
+    tos::{ExecutablePolicy, NamePolicy},
+    transport::TcpKeepalive,
+};
 
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
@@ -15,6 +37,220 @@ pub struct Config {
     /// Number of sector to reserve for root directory
     #[serde(default)]
     root_directory_sectors: Option<u16>,
+
+    /// Generate an `INDEX.TXT` file listing disk content at root
+    #[serde(default)]
+    pub generate_index: bool,
+
+    /// What to do with host files TOS cannot handle (`warn`, `skip` or `rename`)
+    #[serde(default)]
+    pub name_policy: NamePolicy,
+
+    /// Disk extensions of imported files, by host extension (ex: `tosprg` to `PRG`)
+    #[serde(default)]
+    pub extension_map: BTreeMap<String, String>,
+
+    /// What to do with programs without a runnable extension (`ignore`, `warn` or `rename`)
+    #[serde(default)]
+    pub executable_policy: ExecutablePolicy,
+
+    /// Host files larger than this many bytes are imported as numbered parts
+    #[serde(default)]
+    pub split_size: Option<usize>,
+
+    /// Deepest directory level created on import, deeper files are moved up
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// Keep RAM disk sectors LZ4 compressed, trading CPU for memory
+    #[serde(default)]
+    pub compress_sectors: bool,
+
+    /// Write dump files LZ4 compressed, read back either way
+    #[serde(default)]
+    pub lz4_dump: bool,
+
+    /// Floppy images (`.ST`, `.MSA` or `.STX`) to copy in virtual sub directories
+    #[serde(default)]
+    pub floppy_images: Vec<FloppyImport>,
+
+    /// Host directories merged in the disk, after `load_path`
+    #[serde(default)]
+    pub sources: Vec<Source>,
+
+    /// Host directories imported in disk directories, by `/` separated disk path
+    #[serde(default)]
+    pub mounts: BTreeMap<String, PathBuf>,
+
+    /// Refuse Atari writes of directory entries with invalid names, clusters or sizes
+    #[serde(default)]
+    pub strict_entries: bool,
+
+    /// What to do when Atari formats the disk (`refuse` or `allow`)
+    #[serde(default)]
+    pub format_policy: FormatPolicy,
+
+    /// What Atari reads from sectors never written (`zeros`, `warn`, `error` or `fill_e5`)
+    #[serde(default)]
+    pub uninitialized_reads: UninitializedReadPolicy,
+
+    /// Limits on clusters used by sub directories
+    #[serde(default)]
+    pub quotas: Vec<Quota>,
+
+    /// Host folder where content of files deleted from Atari is kept
+    #[serde(default)]
+    pub trash_dir: Option<PathBuf>,
+
+    /// When Atari writes are persisted (`write_through`, `write_back` or `ram_only`)
+    #[serde(default)]
+    pub write_policy: WritePolicy,
+
+    /// File where statistics of the sessions of each port are kept
+    #[serde(default)]
+    pub stats_file: Option<PathBuf>,
+
+    /// File where read and write counts of each sector are kept
+    #[serde(default)]
+    pub heat_map_file: Option<PathBuf>,
+
+    /// Shell commands to run on key events
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    /// Media descriptor, OEM name and serial number of exported boot sectors
+    #[serde(default)]
+    pub boot_sector: BootFields,
+
+    /// Keepalive probes of TCP connections with Atari
+    #[serde(default)]
+    pub tcp_keepalive: TcpKeepalive,
+
+    /// Secret Atari must authenticate with before reading or writing over TCP
+    #[serde(default)]
+    pub tcp_secret: Option<String>,
+
+    /// Passphrase of the key encrypting TCP connections with Atari
+    #[serde(default)]
+    pub tcp_passphrase: Option<String>,
+
+    /// Clock Atari driver can set the ST clock from
+    #[serde(default)]
+    pub clock: ClockSource,
+
+    /// Shell commands Atari can run by name, for cross development
+    #[serde(default)]
+    pub host_commands: HostCommands,
+
+    /// Host file where what Atari appends to `DEBUG.LOG` is copied
+    #[serde(default)]
+    pub debug_log: Option<PathBuf>,
+
+    /// Shell commands generating content of disk files, by disk path
+    #[serde(default)]
+    pub generated_files: BTreeMap<String, String>,
+
+    /// File keeping server generation, to tell Atari about server restarts
+    #[serde(default)]
+    pub session_file: Option<PathBuf>,
+
+    /// Delays of the polls for stop signal and TCP connections
+    #[serde(default)]
+    pub idle: IdleConfig,
+
+    /// GPIO pin or LED lit while Atari reads or writes (needs `gpio` feature)
+    #[serde(default)]
+    pub activity_light: Option<ActivityLight>,
+
+    /// Seconds between background checks of FAT chains and directories
+    #[serde(default)]
+    pub scrub_interval_secs: Option<u64>,
+
+    /// Boot profile recorded with `--record-boot`, to speed boots up
+    #[serde(default)]
+    pub boot_profile: Option<PathBuf>,
+
+    /// Keep Atari writes apart from served content, in a delta layer
+    #[serde(default)]
+    pub overlay: Option<OverlayConfig>,
+
+    /// Copies of the dump left by previous sessions
+    #[serde(default)]
+    pub backups: Option<BackupConfig>,
+
+    /// Settings of each machine served from this host, selected by name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Settings of a single machine, overriding main ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Profile {
+    /// Port the machine is connected to
+    #[serde(default)]
+    pub port: Option<String>,
+
+    #[serde(default)]
+    pub tos: Option<Tos>,
+
+    #[serde(default)]
+    pub partition_type: Option<PartitionType>,
+
+    #[serde(default)]
+    pub root_directory_sectors: Option<u16>,
+
+    /// Path to import as virtual disk content
+    #[serde(default)]
+    pub load_path: Option<PathBuf>,
+
+    /// RAM disk dump filename
+    #[serde(default)]
+    pub dump: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FloppyImport {
+    /// Image file on host FS
+    pub image: PathBuf,
+
+    /// Directory where to copy floppy content (ex: `GAMES/DISK1`)
+    pub target: String,
+}
+
+/// Host directory merged with other ones in the disk.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Source {
+    /// Directory on host FS
+    pub path: PathBuf,
+
+    /// Directory where to import content (ex: `GAMES`), disk root by default
+    #[serde(default)]
+    pub target: String,
+
+    /// Ignore Atari writes changing what this source has imported
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    /// Parse `<path>[:<target>][:ro]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, read_only) = match s.strip_suffix(":ro") {
+            Some(source) => (source, true),
+            None => (s, false),
+        };
+        let (path, target) = source.rsplit_once(':').unwrap_or((source, ""));
+        if path.is_empty() {
+            return Err(format!("expected <path>[:<target>][:ro], got {:?}", s));
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            target: target.to_string(),
+            read_only,
+        })
+    }
 }
 
 impl Config {
@@ -22,4 +258,106 @@ impl Config {
     pub fn root_directory_sectors(&self) -> u16 {
         self.root_directory_sectors.unwrap_or(8)
     }
+
+    /// Apply disk layout of profile `name` and return the profile.
+    pub fn select_profile(&mut self, name: &str) -> Option<Profile> {
+        let profile = self.profiles.get(name)?.clone();
+
+        if let Some(tos) = &profile.tos {
+            self.tos = tos.clone();
+        }
+        if let Some(partition_type) = &profile.partition_type {
+            self.partition_type = partition_type.clone();
+        }
+        if profile.root_directory_sectors.is_some() {
+            self.root_directory_sectors = profile.root_directory_sectors;
+        }
+
+        Some(profile)
+    }
+
+    /// Sources importing `mounts`, parent directories first.
+    pub fn mount_sources(&self) -> Vec<Source> {
+        self.mounts
+            .iter()
+            .map(|(target, path)| Source {
+                path: expand_home(path),
+                target: target.trim_matches('/').to_string(),
+                read_only: false,
+            })
+            .collect()
+    }
+}
+
+/// Replace a leading `~` with home directory, when known.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mounts() {
+        let config: Config = serde_json::from_str(
+            r#"{ "mounts": { "WORK": "~/atari/work", "GAMES/RPG": "/srv/rpg", "GAMES": "/srv/games" } }"#,
+        )
+        .unwrap();
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        let targets: Vec<(PathBuf, String)> = config
+            .mount_sources()
+            .into_iter()
+            .map(|source| (source.path, source.target))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (PathBuf::from("/srv/games"), "GAMES".to_string()),
+                (PathBuf::from("/srv/rpg"), "GAMES/RPG".to_string()),
+                (home.join("atari/work"), "WORK".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sources() {
+        let config: Config = serde_json::from_str(
+            r#"{ "sources": [{ "path": "games", "target": "GAMES", "read_only": true }, { "path": "work" }] }"#,
+        )
+        .unwrap();
+        assert_eq!(config.sources[1].target, "");
+        assert!(!config.sources[1].read_only);
+        assert_eq!("games:GAMES:ro".parse(), Ok(config.sources[0].clone()));
+        assert_eq!("work".parse(), Ok(config.sources[1].clone()));
+        assert!("games:ro".parse::<Source>().unwrap().read_only);
+        assert!(":GAMES".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "tos": "V104",
+                "root_directory_sectors": 4,
+                "profiles": {
+                    "st520": { "port": "/dev/ttyUSB1", "tos": "V100", "load_path": "st520" },
+                    "megaste": { "partition_type": "GEM" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(config.select_profile("falcon").is_none());
+
+        let profile = config.select_profile("st520").unwrap();
+        assert_eq!(profile.port.as_deref(), Some("/dev/ttyUSB1"));
+        assert_eq!(profile.load_path, Some(PathBuf::from("st520")));
+        assert_eq!(config.tos, Tos::V100);
+        assert_eq!(config.partition_type, PartitionType::Bgm);
+        assert_eq!(config.root_directory_sectors(), 4);
+    }
 }
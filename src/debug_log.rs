@@ -0,0 +1,166 @@
+//! Crude printf channel for Atari programs: what they append to `DEBUG.LOG`
+//! at disk root is copied to a host log file and to the console.
+//!
+//! Bytes are copied when GEMDOS updates the file entry, so on `Fclose` (or
+//! when the file gets a new cluster). File content is still stored on disk,
+//! for GEMDOS to read back what it has written.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use chrono::Local;
+
+use crate::{
+    backend::SectorBackend,
+    bus::StorageEvent,
+    entries::FileInfo,
+    error,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+pub const DEBUG_LOG_NAME: &str = "DEBUG.LOG";
+
+/// Host side of `DEBUG.LOG`, with the part of the file already copied.
+#[derive(Debug)]
+struct Sink<W> {
+    host: W,
+    cluster_index: u16,
+    copied: usize,
+}
+
+impl<W> Sink<W>
+where
+    W: Write,
+{
+    /// Copy what has been appended since previous call, and return it.
+    fn forward<'a>(&mut self, entry: &FileInfo, content: &'a [u8]) -> io::Result<&'a [u8]> {
+        // Created again or truncated by Atari: copy from start
+        if entry.cluster_index != self.cluster_index || content.len() < self.copied {
+            self.cluster_index = entry.cluster_index;
+            self.copied = 0;
+        }
+
+        let appended = &content[self.copied..];
+        self.host.write_all(appended)?;
+        self.host.flush()?;
+        self.copied = content.len();
+        Ok(appended)
+    }
+}
+
+fn read_log<B>(storage: &DiskStorage<B>) -> error::Result<Option<(FileInfo, Vec<u8>)>>
+where
+    B: SectorBackend,
+{
+    match storage.find_entry(DEBUG_LOG_NAME)? {
+        Some(entry) if !entry.is_dir() => {
+            let content = storage.read_file(&entry)?;
+            Ok(Some((entry, content)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Add `DEBUG.LOG` at disk root if missing, and append what Atari writes to
+/// it to `host_path`.
+pub fn spawn<B>(host_path: &Path, storage: Arc<Mutex<DiskStorage<B>>>) -> error::Result<()>
+where
+    B: SectorBackend + Send + 'static,
+{
+    let host = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(host_path)?;
+
+    let (mut sink, events) = {
+        let mut storage = storage.lock().unwrap();
+        let sink = match storage.find_entry(DEBUG_LOG_NAME)? {
+            // Content of an imported file is not copied again
+            Some(entry) => Sink {
+                host,
+                cluster_index: entry.cluster_index,
+                copied: entry.size(),
+            },
+            None => {
+                let mtime = Local::now().naive_local();
+                storage.add_virtual_file("DEBUG", "LOG", mtime, &[], ROOT_INDEX)?;
+                Sink {
+                    host,
+                    cluster_index: 0,
+                    copied: 0,
+                }
+            }
+        };
+        (sink, storage.subscribe())
+    };
+    log::info!("Atari writes to {} go to {:?}", DEBUG_LOG_NAME, host_path);
+
+    thread::spawn(move || {
+        for event in events.iter() {
+            match event {
+                StorageEvent::FileWritten { path, .. }
+                    if path.eq_ignore_ascii_case(DEBUG_LOG_NAME) => {}
+                _ => continue,
+            }
+
+            let log = read_log(&storage.lock().unwrap());
+            let (entry, content) = match log {
+                Ok(Some(log)) => log,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::warn!("Cannot read {} (error: {})", DEBUG_LOG_NAME, e);
+                    continue;
+                }
+            };
+
+            match sink.forward(&entry, &content) {
+                Ok(appended) => {
+                    for line in String::from_utf8_lossy(appended).lines() {
+                        log::info!("Atari: {}", line);
+                    }
+                }
+                Err(e) => log::warn!("Cannot copy {} to host (error: {})", DEBUG_LOG_NAME, e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::DiskLayout;
+
+    #[test]
+    fn test_forward() {
+        let mtime = Local::now().naive_local();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage
+            .add_virtual_file("DEBUG", "LOG", mtime, b"boot\r\n", ROOT_INDEX)
+            .unwrap();
+        let (entry, content) = read_log(&storage).unwrap().unwrap();
+
+        let mut sink = Sink {
+            host: Vec::new(),
+            cluster_index: entry.cluster_index,
+            copied: 3,
+        };
+        assert_eq!(sink.forward(&entry, &content).unwrap(), b"t\r\n");
+        assert_eq!(sink.forward(&entry, &content).unwrap(), b"");
+        assert_eq!(
+            sink.forward(&entry, b"boot\r\nx=1\r\n").unwrap(),
+            b"x=1\r\n"
+        );
+
+        // File created again
+        let mut recreated = entry.clone();
+        recreated.cluster_index += 1;
+        assert_eq!(sink.forward(&recreated, b"new").unwrap(), b"new");
+        assert_eq!(sink.host, b"t\r\nx=1\r\nnew");
+    }
+}
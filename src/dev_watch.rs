@@ -0,0 +1,255 @@
+//! Keep a disk folder in sync with a host build output directory, so each
+//! build can be run on Atari without restarting the server.
+//!
+//! Only files at the top of the host directory are followed, by polling. A
+//! change is applied once file size and modification time are the same for
+//! two scans, to skip files still being written by the build. Atari polls the
+//! count of changes (command `0x08`) to know when to read the disk again.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{backend::SectorBackend, error, storage::DiskStorage};
+
+/// Delay between two scans of host directory.
+pub const SCAN_DELAY: Duration = Duration::from_secs(1);
+
+/// Host directory followed, and disk folder where its files are copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevWatch {
+    pub host_dir: PathBuf,
+    /// `\` separated path from disk root, created when missing
+    pub disk_dir: String,
+}
+
+impl FromStr for DevWatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once(':') {
+            Some((host_dir, disk_dir)) if !host_dir.is_empty() => Ok(Self {
+                host_dir: PathBuf::from(host_dir),
+                disk_dir: disk_dir.replace('/', "\\"),
+            }),
+            _ => Err(format!("expected <host_dir>:<disk_dir>, got {:?}", s)),
+        }
+    }
+}
+
+/// Size and modification time of a file.
+type Stamp = (u64, SystemTime);
+
+/// Files at the top of a directory.
+fn scan(dir: &Path) -> io::Result<BTreeMap<PathBuf, Stamp>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // Follow symlinks, build tools often link their outputs
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        files.insert(path, (metadata.len(), metadata.modified()?));
+    }
+    Ok(files)
+}
+
+/// Changes seen in host directory and not applied yet.
+#[derive(Debug)]
+struct Watcher {
+    watch: DevWatch,
+    /// Files as they have been copied on disk
+    applied: BTreeMap<PathBuf, Stamp>,
+    /// Files found by previous scan
+    previous: BTreeMap<PathBuf, Stamp>,
+}
+
+impl Watcher {
+    fn new(watch: DevWatch) -> Self {
+        Self {
+            watch,
+            applied: BTreeMap::new(),
+            previous: BTreeMap::new(),
+        }
+    }
+
+    /// Apply what has not changed since previous scan, and return the
+    /// number of files changed on disk.
+    fn sync<B>(
+        &mut self,
+        storage: &mut DiskStorage<B>,
+        current: BTreeMap<PathBuf, Stamp>,
+    ) -> error::Result<usize>
+    where
+        B: SectorBackend,
+    {
+        let changed: Vec<_> = current
+            .iter()
+            .filter(|(path, stamp)| {
+                self.previous.get(*path) == Some(stamp) && self.applied.get(*path) != Some(stamp)
+            })
+            .map(|(path, stamp)| (path.clone(), *stamp))
+            .collect();
+        let removed: Vec<_> = self
+            .applied
+            .keys()
+            .filter(|path| !current.contains_key(*path) && !self.previous.contains_key(*path))
+            .cloned()
+            .collect();
+        self.previous = current;
+
+        if changed.is_empty() && removed.is_empty() {
+            return Ok(0);
+        }
+
+        let dir = self.disk_dir_index(storage)?;
+        for (path, stamp) in &changed {
+            // Kept as applied on failure, it is tried again on next change
+            self.applied.insert(path.clone(), *stamp);
+            if let Err(e) = put_file(storage, path, stamp.1, dir) {
+                log::warn!("Cannot copy {:?} on disk (error: {})", path, e);
+            } else {
+                log::info!("Updated {:?} on disk", path);
+            }
+        }
+        for path in &removed {
+            self.applied.remove(path);
+            let (name, ext) = storage.file_components(path)?;
+            storage.remove_file(&filename(&name, &ext), dir)?;
+            log::info!("Removed {:?} from disk", path);
+        }
+
+        Ok(changed.len() + removed.len())
+    }
+
+    /// First cluster of disk folder, created again if Atari removed it.
+    fn disk_dir_index<B>(&self, storage: &mut DiskStorage<B>) -> error::Result<u16>
+    where
+        B: SectorBackend,
+    {
+        let mtime = naive_time(SystemTime::now());
+        storage.find_or_add_virtual_path(&self.watch.disk_dir, mtime)
+    }
+}
+
+fn put_file<B>(
+    storage: &mut DiskStorage<B>,
+    path: &Path,
+    mtime: SystemTime,
+    dir: u16,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    let (name, ext) = storage.file_components(path)?;
+    let content = fs::read(path)?;
+    let (name, ext) = storage.executable_components(name, ext, &content);
+    storage.put_virtual_file(&name, &ext, naive_time(mtime), &content, dir)
+}
+
+fn filename(name: &str, ext: &str) -> String {
+    if ext.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, ext)
+    }
+}
+
+fn naive_time(time: SystemTime) -> NaiveDateTime {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    NaiveDateTime::from_timestamp(secs as i64, 0)
+}
+
+/// Follow host directory of `watch` until the app stops.
+pub fn spawn<B>(watch: DevWatch, storage: Arc<Mutex<DiskStorage<B>>>)
+where
+    B: SectorBackend + Send + 'static,
+{
+    log::info!(
+        "Following {:?} in disk folder {:?}",
+        watch.host_dir,
+        watch.disk_dir
+    );
+
+    thread::spawn(move || {
+        let mut watcher = Watcher::new(watch);
+        loop {
+            // Directory may be missing for a while, ex: on clean builds
+            match scan(&watcher.watch.host_dir) {
+                Ok(current) => {
+                    let mut storage = storage.lock().unwrap();
+                    if let Err(e) = watcher.sync(&mut storage, current) {
+                        log::warn!("Cannot update disk folder (error: {})", e);
+                    }
+                }
+                Err(e) => log::debug!("Cannot scan {:?} (error: {})", watcher.watch.host_dir, e),
+            }
+            thread::sleep(SCAN_DELAY);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "build/out:DEV/BIN".parse(),
+            Ok(DevWatch {
+                host_dir: PathBuf::from("build/out"),
+                disk_dir: "DEV\\BIN".to_string(),
+            })
+        );
+        assert!("build".parse::<DevWatch>().is_err());
+        assert!(":DEV".parse::<DevWatch>().is_err());
+    }
+
+    #[test]
+    fn test_sync() {
+        let fixture = Fixture::empty("dev-watch").unwrap();
+        let dir = fixture.path();
+        fs::write(dir.join("GAME.PRG"), b"v1").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let mut watcher = Watcher::new(DevWatch {
+            host_dir: dir.to_path_buf(),
+            disk_dir: "DEV\\BIN".to_string(),
+        });
+        let mut sync = |storage: &mut DiskStorage| {
+            let current = scan(dir).unwrap();
+            watcher.sync(storage, current).unwrap()
+        };
+
+        // Copied once stable
+        assert_eq!(sync(&mut storage), 0);
+        assert!(storage.find_entry("DEV").unwrap().is_none());
+        assert_eq!(sync(&mut storage), 1);
+        let entry = storage.find_entry("DEV\\BIN\\GAME.PRG").unwrap().unwrap();
+        assert_eq!(storage.read_file(&entry).unwrap(), b"v1");
+        assert_eq!(sync(&mut storage), 0);
+
+        fs::write(dir.join("GAME.PRG"), b"version 2").unwrap();
+        sync(&mut storage);
+        sync(&mut storage);
+        let entry = storage.find_entry("DEV\\BIN\\GAME.PRG").unwrap().unwrap();
+        assert_eq!(storage.read_file(&entry).unwrap(), b"version 2");
+
+        fs::remove_file(dir.join("GAME.PRG")).unwrap();
+        assert_eq!(sync(&mut storage), 0);
+        assert_eq!(sync(&mut storage), 1);
+        assert!(storage.find_entry("DEV\\BIN\\GAME.PRG").unwrap().is_none());
+        assert_eq!(storage.host_changes(), 3);
+    }
+}
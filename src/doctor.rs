@@ -0,0 +1,248 @@
+//! Serial port access diagnostics.
+//!
+//! Opening a port most often fails because the user is not in the group
+//! owning it (`dialout` on most Linux distributions, `uucp` on some), or
+//! joined it after logging in. `permission_hint` tells which case it is and
+//! how to fix it, `report` collects what a bug report about ports needs.
+
+use std::{
+    ffi::CString,
+    fmt::Write,
+    fs,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+/// Programs known to open serial ports on their own.
+const PORT_GRABBERS: [(&str, &str); 2] = [
+    (
+        "ModemManager",
+        "probes new serial ports, disable it or tag the adapter with ID_MM_DEVICE_IGNORE",
+    ),
+    (
+        "brltty",
+        "claims some USB serial adapters (ex: CH341), uninstall it if no braille display is used",
+    ),
+];
+
+/// Line of `/etc/group`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// Parse `/etc/group` content, skipping invalid lines.
+pub fn parse_groups(content: &str) -> Vec<Group> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let gid = fields.nth(1)?.parse().ok()?;
+            let members = fields
+                .next()
+                .unwrap_or("")
+                .split(',')
+                .filter(|member| !member.is_empty())
+                .map(str::to_string)
+                .collect();
+            Some(Group {
+                name: name.to_string(),
+                gid,
+                members,
+            })
+        })
+        .collect()
+}
+
+fn system_groups() -> Vec<Group> {
+    fs::read_to_string("/etc/group")
+        .map(|content| parse_groups(&content))
+        .unwrap_or_default()
+}
+
+/// Name of the user running the server.
+fn user_name() -> String {
+    let uid = unsafe { libc::getuid() };
+    let from_passwd = fs::read_to_string("/etc/passwd").ok().and_then(|content| {
+        content.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                [name, _, id, ..] if id.parse() == Ok(uid) => Some(name.to_string()),
+                _ => None,
+            }
+        })
+    });
+    from_passwd.unwrap_or_else(|| uid.to_string())
+}
+
+/// Groups of this process, as opposed to the ones of the user in
+/// `/etc/group` which only apply from next login.
+fn process_groups() -> Vec<u32> {
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups.push(unsafe { libc::getegid() });
+    groups
+}
+
+/// How to get access to a port owned by group `port_gid`.
+fn remediation(user: &str, port_gid: u32, group: Option<&Group>, process_groups: &[u32]) -> String {
+    let name = group.map_or_else(|| port_gid.to_string(), |group| group.name.clone());
+    if process_groups.contains(&port_gid) {
+        format!(
+            "group {} cannot read and write the port, check udev rules setting its mode",
+            name
+        )
+    } else if group.is_some_and(|group| group.members.iter().any(|member| member == user)) {
+        format!(
+            "{} joined group {} after logging in, log out and in again (or run `newgrp {}`)",
+            user, name, name
+        )
+    } else {
+        format!(
+            "add {} to group {} with `sudo usermod -aG {} {}`, then log out and in again",
+            user, name, name, user
+        )
+    }
+}
+
+/// What to do when opening `port` is denied, if its owner is known.
+pub fn permission_hint(port: &str) -> Option<String> {
+    let gid = fs::metadata(port).ok()?.gid();
+    let groups = system_groups();
+    let group = groups.iter().find(|group| group.gid == gid);
+    Some(remediation(&user_name(), gid, group, &process_groups()))
+}
+
+fn is_accessible(path: &Path) -> bool {
+    CString::new(path.as_os_str().as_bytes())
+        .is_ok_and(|path| unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) } == 0)
+}
+
+/// Names of running processes.
+fn running_programs() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| fs::read_to_string(entry.ok()?.path().join("comm")).ok())
+        .map(|comm| comm.trim().to_string())
+        .collect()
+}
+
+/// Environment details to join to a bug report about serial ports.
+pub fn report() -> String {
+    let mut text = String::new();
+    let groups = system_groups();
+    let group_name = |gid: u32| {
+        groups
+            .iter()
+            .find(|group| group.gid == gid)
+            .map_or_else(|| gid.to_string(), |group| group.name.clone())
+    };
+
+    let _ = writeln!(text, "ataridisk {}", env!("CARGO_PKG_VERSION"));
+    let kernel = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    let _ = writeln!(
+        text,
+        "System: {} {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        kernel.trim()
+    );
+    let session_groups: Vec<String> = process_groups().into_iter().map(group_name).collect();
+    let _ = writeln!(
+        text,
+        "User: {} (groups: {})",
+        user_name(),
+        session_groups.join(", ")
+    );
+
+    let _ = writeln!(text, "Ports:");
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            let _ = writeln!(text, "  cannot list ports ({})", e);
+            Vec::new()
+        }
+    };
+    if ports.is_empty() {
+        let _ = writeln!(text, "  none found, check adapter is plugged (see `dmesg`)");
+    }
+    let mut usb_ids = Vec::new();
+    for port in &ports {
+        let _ = write!(text, "- {}", port.port_name);
+        if let serialport::SerialPortType::UsbPort(usb) = &port.port_type {
+            let _ = write!(text, " (USB {:04x}:{:04x}", usb.vid, usb.pid);
+            for detail in [&usb.manufacturer, &usb.product].iter().copied().flatten() {
+                let _ = write!(text, " {}", detail);
+            }
+            let _ = write!(text, ")");
+            usb_ids.push((usb.vid, usb.pid));
+        }
+        match fs::metadata(&port.port_name) {
+            Ok(metadata) => {
+                let _ = write!(
+                    text,
+                    ", group {}, mode {:o}",
+                    group_name(metadata.gid()),
+                    metadata.mode() & 0o777
+                );
+            }
+            Err(e) => {
+                let _ = write!(text, ", {}", e);
+            }
+        }
+        if is_accessible(Path::new(&port.port_name)) {
+            let _ = writeln!(text, ", accessible");
+        } else {
+            let hint = permission_hint(&port.port_name).unwrap_or_default();
+            let _ = writeln!(text, ", not accessible: {}", hint);
+        }
+    }
+
+    let running = running_programs();
+    let grabbers: Vec<_> = PORT_GRABBERS
+        .iter()
+        .filter(|(name, _)| running.iter().any(|program| program == name))
+        .collect();
+    if !grabbers.is_empty() || !usb_ids.is_empty() {
+        let _ = writeln!(text, "Hints:");
+    }
+    for (name, hint) in grabbers {
+        let _ = writeln!(text, "- {} is running: it {}", name, hint);
+    }
+    for (vid, pid) in usb_ids {
+        let _ = writeln!(
+            text,
+            "- stable name and access for {:04x}:{:04x}, in /etc/udev/rules.d/99-atari.rules:\n  \
+             SUBSYSTEM==\"tty\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", \
+             GROUP=\"dialout\", MODE=\"0660\", SYMLINK+=\"atari\"",
+            vid, pid, vid, pid
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remediation() {
+        let groups = parse_groups("root:x:0:\ndialout:x:20:alice,bob\ninvalid\nuucp:x:14:\n");
+        assert_eq!(groups.len(), 3);
+        let dialout = groups.iter().find(|g| g.gid == 20);
+        assert_eq!(dialout.unwrap().members, ["alice", "bob"]);
+
+        assert!(remediation("carol", 20, dialout, &[100])
+            .starts_with("add carol to group dialout with `sudo usermod -aG dialout carol`"));
+        assert!(remediation("alice", 20, dialout, &[100]).contains("log out and in again"));
+        assert!(remediation("alice", 20, dialout, &[20, 100]).contains("udev rules"));
+        assert!(remediation("alice", 42, None, &[]).starts_with("add alice to group 42"));
+    }
+}
@@ -0,0 +1,29 @@
+use std::{fs, path::Path};
+
+use crate::error::{self, SerialDiskError};
+
+include!(concat!(env!("OUT_DIR"), "/drivers.rs"));
+
+/// List protocol versions of drivers bundled with this binary.
+pub fn bundled_versions() -> Vec<u8> {
+    DRIVERS.iter().map(|(version, _)| *version).collect()
+}
+
+/// Get driver content for a given protocol version.
+pub fn find(protocol_version: u8) -> Option<&'static [u8]> {
+    DRIVERS
+        .iter()
+        .find(|(version, _)| *version == protocol_version)
+        .map(|(_, content)| *content)
+}
+
+/// Write driver for a given protocol version to a file.
+pub fn write_driver<P>(protocol_version: u8, path: P) -> error::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let content = find(protocol_version).ok_or(SerialDiskError::MissingDriver(protocol_version))?;
+    fs::write(path, content)?;
+
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufWriter, Read, Write},
+    path::Path,
+};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use serde::Serialize;
+
+use crate::{
+    error::{self, SerialDiskError},
+    layout::DiskLayout,
+    storage::DiskStorage,
+};
+
+const DUMP_MAGIC: [u8; 4] = *b"ATDK";
+const DUMP_VERSION: u8 = 1;
+/// Start of a whole dump compressed with LZ4, size prepended.
+const COMPRESSED_MAGIC: [u8; 4] = *b"ATL4";
+
+/// Write RAM disk to a dump.
+///
+/// Disk layout is written in a small header before disk content so it can be
+/// checked before trying to serve or extract the dump.
+pub fn write_dump<W, B>(writer: &mut W, storage: &DiskStorage<B>) -> error::Result<()>
+where
+    W: Write,
+    B: Serialize,
+{
+    writer.write_all(&DUMP_MAGIC)?;
+    writer.write_u8(DUMP_VERSION)?;
+    bincode::serialize_into(&mut *writer, &storage.disk_layout)?;
+    bincode::serialize_into(&mut *writer, storage)?;
+
+    Ok(())
+}
+
+/// Write RAM disk to a dump file and make sure it reach stable storage.
+///
+/// Dump is first written next to the target then renamed, so a crash
+/// while dumping never clobber the previous dump. With `compress`, it is
+/// LZ4 compressed as a whole (only with the `compression` feature).
+pub fn write_dump_file<P, B>(path: P, storage: &DiskStorage<B>, compress: bool) -> error::Result<()>
+where
+    P: AsRef<Path>,
+    B: Serialize,
+{
+    let path = path.as_ref();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    if compress {
+        write_compressed_dump(&mut writer, storage)?;
+    } else {
+        write_dump(&mut writer, storage)?;
+    }
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Compress a whole dump, as rotated backups are.
+#[cfg(feature = "compression")]
+pub fn compress_dump(dump: &[u8]) -> Vec<u8> {
+    let mut compressed = COMPRESSED_MAGIC.to_vec();
+    compressed.extend(lz4_flex::compress_prepend_size(dump));
+    compressed
+}
+
+#[cfg(feature = "compression")]
+fn write_compressed_dump<W, B>(writer: &mut W, storage: &DiskStorage<B>) -> error::Result<()>
+where
+    W: Write,
+    B: Serialize,
+{
+    let mut dump = vec![];
+    write_dump(&mut dump, storage)?;
+    writer.write_all(&compress_dump(&dump))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "compression"))]
+fn write_compressed_dump<W, B>(writer: &mut W, storage: &DiskStorage<B>) -> error::Result<()>
+where
+    W: Write,
+    B: Serialize,
+{
+    log::warn!("Built without compression feature, dump is written uncompressed");
+    write_dump(writer, storage)
+}
+
+/// Tell if dump file at `path` is compressed.
+pub fn is_compressed<P>(path: P) -> error::Result<bool>
+where
+    P: AsRef<Path>,
+{
+    let mut magic = [0; COMPRESSED_MAGIC.len()];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == COMPRESSED_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn decompress_dump<R>(reader: &mut R) -> error::Result<Vec<u8>>
+where
+    R: Read,
+{
+    let mut compressed = vec![];
+    reader.read_to_end(&mut compressed)?;
+
+    #[cfg(feature = "compression")]
+    return lz4_flex::decompress_size_prepended(&compressed[COMPRESSED_MAGIC.len()..])
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()).into());
+    #[cfg(not(feature = "compression"))]
+    Err(SerialDiskError::IncompatibleLayout(
+        "compressed dump needs compression feature".to_string(),
+    ))
+}
+
+/// Read RAM disk from a dump.
+///
+/// Dumps without header (made by previous versions) and compressed ones are
+/// still accepted.
+pub fn read_dump<R>(reader: &mut R) -> error::Result<DiskStorage>
+where
+    R: BufRead,
+{
+    if reader.fill_buf()?.starts_with(&COMPRESSED_MAGIC) {
+        let dump = decompress_dump(reader)?;
+        return read_dump(&mut dump.as_slice());
+    }
+
+    let header_layout = read_header(reader)?;
+    let storage: DiskStorage = bincode::deserialize_from(&mut *reader)?;
+
+    if let Some(layout) = header_layout {
+        layout.ensure_compatible(&storage.disk_layout)?;
+    }
+
+    Ok(storage)
+}
+
+/// Read RAM disk from a dump and check it match the expected layout.
+pub fn read_dump_with_layout<R>(reader: &mut R, expected: &DiskLayout) -> error::Result<DiskStorage>
+where
+    R: BufRead,
+{
+    let storage = read_dump(reader)?;
+    expected.ensure_compatible(&storage.disk_layout)?;
+
+    Ok(storage)
+}
+
+/// Read dump header if any and return the layout it contains.
+fn read_header<R>(reader: &mut R) -> error::Result<Option<DiskLayout>>
+where
+    R: BufRead,
+{
+    if !reader.fill_buf()?.starts_with(&DUMP_MAGIC) {
+        return Ok(None);
+    }
+    reader.consume(DUMP_MAGIC.len());
+
+    let version = reader.read_u8()?;
+    if version != DUMP_VERSION {
+        return Err(SerialDiskError::IncompatibleLayout(format!(
+            "unsupported dump version {}",
+            version
+        )));
+    }
+
+    Ok(Some(bincode::deserialize_from(&mut *reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::Fixture,
+        layout::{PartitionType, Tos},
+    };
+
+    fn dump(storage: &DiskStorage) -> Vec<u8> {
+        let mut buf = vec![];
+        write_dump(&mut buf, storage).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut storage = DiskStorage::new(DiskLayout::new(Tos::V100, PartitionType::Gem, 4));
+        let sample = Fixture::sample("dump").unwrap();
+        storage.import_path(sample.path()).unwrap();
+
+        let buf = dump(&storage);
+        assert!(buf.starts_with(&DUMP_MAGIC));
+
+        let restored = read_dump(&mut buf.as_slice()).unwrap();
+        assert_eq!(restored.disk_layout, storage.disk_layout);
+        assert_eq!(
+            restored.list_root_file_infos(),
+            storage.list_root_file_infos()
+        );
+    }
+
+    #[test]
+    fn test_write_dump_file() {
+        let fixture = Fixture::empty("dump-file").unwrap();
+        let path = fixture.join("ramdisk.dump");
+        let storage = DiskStorage::new(DiskLayout::default());
+
+        for compress in [false, cfg!(feature = "compression")] {
+            write_dump_file(&path, &storage, compress).unwrap();
+            assert_eq!(is_compressed(&path).unwrap(), compress);
+            let mut reader = std::io::BufReader::new(File::open(&path).unwrap());
+            assert!(read_dump(&mut reader).is_ok());
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_dump() {
+        let storage = DiskStorage::new(DiskLayout::new(Tos::V100, PartitionType::Gem, 4));
+        let compressed = compress_dump(&dump(&storage));
+        assert!(compressed.starts_with(&COMPRESSED_MAGIC));
+
+        let restored = read_dump(&mut compressed.as_slice()).unwrap();
+        assert_eq!(restored.disk_layout, storage.disk_layout);
+    }
+
+    #[test]
+    fn test_legacy_dump() {
+        let storage = DiskStorage::new(DiskLayout::default());
+        let buf = bincode::serialize(&storage).unwrap();
+
+        let restored = read_dump(&mut buf.as_slice()).unwrap();
+        assert_eq!(restored.disk_layout, DiskLayout::default());
+    }
+
+    #[test]
+    fn test_layout_mismatch() {
+        let storage = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Bgm, 8));
+        let buf = dump(&storage);
+
+        assert!(read_dump_with_layout(
+            &mut buf.as_slice(),
+            &DiskLayout::new(Tos::V104, PartitionType::Bgm, 8)
+        )
+        .is_ok());
+        assert!(matches!(
+            read_dump_with_layout(
+                &mut buf.as_slice(),
+                &DiskLayout::new(Tos::V104, PartitionType::Gem, 8)
+            ),
+            Err(SerialDiskError::IncompatibleLayout(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_version() {
+        let mut buf = dump(&DiskStorage::new(DiskLayout::default()));
+        buf[DUMP_MAGIC.len()] = 0xFF;
+
+        assert!(matches!(
+            read_dump(&mut buf.as_slice()),
+            Err(SerialDiskError::IncompatibleLayout(_))
+        ));
+    }
+}
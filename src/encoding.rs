@@ -0,0 +1,267 @@
+//! Encodings of the bytes exchanged with Atari, for links not passing raw
+//! binary (ex: terminal emulators eating control characters).
+//!
+//! Both sides must use the same encoding. Encoded bytes are only printable
+//! ASCII, at the cost of speed:
+//!
+//! - `base16`: each byte is sent as two upper case hex digits, anything else
+//!   received (line breaks added by terminals) is ignored,
+//! - `kermit`: printable bytes are sent as is, control bytes are prefixed
+//!   with `#` and XORed with `0x40`, bytes with high bit set are prefixed with
+//!   `&` and sent without it (quoting is the one of KERMIT packets).
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use crate::{error, transport::Transport};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+const CONTROL_PREFIX: u8 = b'#';
+const HIGH_BIT_PREFIX: u8 = b'&';
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Bytes are sent as is
+    #[default]
+    Raw,
+    Base16,
+    Kermit,
+}
+
+impl Encoding {
+    /// Append the encoding of `data` to `out`.
+    pub fn encode(&self, data: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Self::Raw => out.extend_from_slice(data),
+            Self::Base16 => {
+                for byte in data {
+                    out.push(HEX_DIGITS[(byte >> 4) as usize]);
+                    out.push(HEX_DIGITS[(byte & 0x0F) as usize]);
+                }
+            }
+            Self::Kermit => {
+                for &byte in data {
+                    if byte & 0x80 != 0 {
+                        out.push(HIGH_BIT_PREFIX);
+                    }
+                    let low = byte & 0x7F;
+                    if low < 0x20 || low == 0x7F {
+                        out.extend_from_slice(&[CONTROL_PREFIX, low ^ 0x40]);
+                    } else if low == CONTROL_PREFIX || low == HIGH_BIT_PREFIX {
+                        out.extend_from_slice(&[CONTROL_PREFIX, low]);
+                    } else {
+                        out.push(low);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "base16" => Ok(Self::Base16),
+            "kermit" => Ok(Self::Kermit),
+            _ => Err(format!(
+                "unknown encoding {:?} (expected raw, base16 or kermit)",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Raw => "raw",
+            Self::Base16 => "base16",
+            Self::Kermit => "kermit",
+        })
+    }
+}
+
+/// Decoding state kept between reads, as an encoded byte may be split.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Decoder {
+    high_nibble: Option<u8>,
+    quoted: bool,
+    high_bit: bool,
+}
+
+impl Decoder {
+    fn decode(&mut self, encoding: Encoding, data: &[u8], out: &mut Vec<u8>) {
+        match encoding {
+            Encoding::Raw => out.extend_from_slice(data),
+            Encoding::Base16 => {
+                for &c in data {
+                    let Some(digit) = (c as char).to_digit(16) else {
+                        continue;
+                    };
+                    match self.high_nibble.take() {
+                        Some(high) => out.push(high << 4 | digit as u8),
+                        None => self.high_nibble = Some(digit as u8),
+                    }
+                }
+            }
+            Encoding::Kermit => {
+                for &c in data {
+                    let low = if self.quoted {
+                        self.quoted = false;
+                        match c {
+                            CONTROL_PREFIX | HIGH_BIT_PREFIX => c,
+                            _ => c ^ 0x40,
+                        }
+                    } else if c == CONTROL_PREFIX {
+                        self.quoted = true;
+                        continue;
+                    } else if c == HIGH_BIT_PREFIX {
+                        self.high_bit = true;
+                        continue;
+                    } else {
+                        c
+                    };
+
+                    let high = if self.high_bit { 0x80 } else { 0 };
+                    self.high_bit = false;
+                    out.push(high | low);
+                }
+            }
+        }
+    }
+}
+
+/// Transport encoding what is sent to Atari, and decoding what it receives.
+#[derive(Debug)]
+pub struct EncodedTransport<T> {
+    inner: T,
+    encoding: Encoding,
+    decoder: Decoder,
+    /// Decoded bytes not read yet
+    pending: Vec<u8>,
+}
+
+impl<T> EncodedTransport<T> {
+    pub fn new(inner: T, encoding: Encoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            decoder: Decoder::default(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<T> Read for EncodedTransport<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Raw {
+            return self.inner.read(buf);
+        }
+
+        // Some encoded bytes (prefixes, ignored chars) give nothing
+        let mut encoded = [0; 256];
+        while self.pending.is_empty() && !buf.is_empty() {
+            let len = self.inner.read(&mut encoded)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            self.decoder
+                .decode(self.encoding, &encoded[..len], &mut self.pending);
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<T> Write for EncodedTransport<T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Raw {
+            return self.inner.write(buf);
+        }
+
+        let mut encoded = Vec::with_capacity(buf.len() * 2);
+        self.encoding.encode(buf, &mut encoded);
+        self.inner.write_all(&encoded)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T> Transport for EncodedTransport<T>
+where
+    T: Transport,
+{
+    fn discard_pending(&mut self) -> error::Result<()> {
+        self.inner.discard_pending()?;
+        self.decoder = Decoder::default();
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    fn round_trip(encoding: Encoding) {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut encoded = Vec::new();
+        encoding.encode(&data, &mut encoded);
+        if encoding != Encoding::Raw {
+            assert!(encoded.iter().all(|c| (0x20..0x7F).contains(c)));
+        }
+
+        // Decoded one byte at a time, to split encoded bytes
+        let mut decoder = Decoder::default();
+        let mut decoded = Vec::new();
+        for c in encoded.chunks(1) {
+            decoder.decode(encoding, c, &mut decoded);
+        }
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        round_trip(Encoding::Raw);
+        round_trip(Encoding::Base16);
+        round_trip(Encoding::Kermit);
+        assert_eq!("kermit".parse(), Ok(Encoding::Kermit));
+        assert!("hex".parse::<Encoding>().is_err());
+
+        let mut encoded = Vec::new();
+        Encoding::Kermit.encode(b"A\r#\x8D", &mut encoded);
+        assert_eq!(encoded, b"A#M##&#M");
+    }
+
+    #[test]
+    fn test_encoded_transport() {
+        let mut transport =
+            EncodedTransport::new(MemoryTransport::new(b"41\r\n42 4\n3"), Encoding::Base16);
+        let mut buf = [0; 3];
+        transport.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ABC");
+        assert!(transport.read(&mut buf).is_err());
+
+        transport.write_all(&[0x00, 0xFF]).unwrap();
+        assert_eq!(transport.inner.output(), b"00FF");
+    }
+}
@@ -1,4 +1,4 @@
-use std::{io, mem, os::unix::prelude::MetadataExt, path::Path, slice, time::UNIX_EPOCH};
+use std::{io, mem, path::Path, slice, time::UNIX_EPOCH};
 
 use byteorder::{NativeEndian, ReadBytesExt};
 use chrono::prelude::*;
@@ -7,12 +7,14 @@ use serde::{Deserialize, Serialize};
 use crate::{
     dos,
     error::{self, SerialDiskError},
+    name_key::NameKey,
+    tos,
 };
 
 macro_rules! as_static_str {
     ($input:expr, $size:expr) => {{
-        let mut result = [' ' as u8; $size];
-        for (i, b) in $input.bytes().into_iter().enumerate() {
+        let mut result = [b' '; $size];
+        for (i, b) in $input.bytes().enumerate() {
             assert!(i < result.len());
             result[i] = b;
         }
@@ -22,7 +24,7 @@ macro_rules! as_static_str {
 
 macro_rules! from_reader_static {
     ($reader:expr, $size:expr) => {{
-        let mut result = [' ' as u8; $size];
+        let mut result = [b' '; $size];
         for i in 0..$size {
             result[i] = $reader.read_u8()?;
         }
@@ -30,21 +32,75 @@ macro_rules! from_reader_static {
     }};
 }
 
-fn format_datetime_to_atari(dt: NaiveDateTime) -> (u16, u16) {
+/// Encode a DOS / GEMDOS time + date pair.
+pub fn format_datetime_to_atari(dt: NaiveDateTime) -> (u16, u16) {
     let time = (dt.second() / 2) as u16 | (dt.minute() << 5) as u16 | (dt.hour() << 11) as u16;
     let date = dt.day() as u16 | (dt.month() << 5) as u16 | ((dt.year() - 1980) << 9) as u16;
 
     (time, date)
 }
 
+/// Decode a DOS / GEMDOS time + date pair.
+pub fn parse_datetime_from_atari(time: u16, date: u16) -> Option<NaiveDateTime> {
+    let date = NaiveDate::from_ymd_opt(
+        (date >> 9) as i32 + 1980,
+        ((date >> 5) & 0x0F) as u32,
+        (date & 0x1F) as u32,
+    )?;
+    let time = NaiveTime::from_hms_opt(
+        (time >> 11) as u32,
+        ((time >> 5) & 0x3F) as u32,
+        (time & 0x1F) as u32 * 2,
+    )?;
+
+    Some(NaiveDateTime::new(date, time))
+}
+
+/// First name byte of deleted entries.
+const DELETED_MARK: u8 = 0xE5;
+
 /// Attribute that can be apply to file.
 #[derive(Debug)]
 #[repr(u8)]
 enum FileAttr {
     None = 0x00,
+    ReadOnly = 0x01,
+    Hidden = 0x02,
+    Volume = 0x08,
     Directory = 0x10,
 }
 
+/// What an entry tells about the file it describes, wherever the file
+/// comes from (host FS, ZIP archive, floppy image, generated content...).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub is_dir: bool,
+    pub modified: NaiveDateTime,
+    /// Content size, 0 for directories
+    pub size: u32,
+}
+
+impl EntryMetadata {
+    /// Metadata of a host file or directory.
+    pub fn from_host<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let metadata = path.as_ref().metadata()?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+
+        Ok(Self {
+            is_dir: metadata.is_dir(),
+            modified: NaiveDateTime::from_timestamp(mtime as i64, 0),
+            size: if metadata.is_dir() {
+                0
+            } else {
+                metadata.len() as u32
+            },
+        })
+    }
+}
+
 /// Item as it is dump on disk
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[repr(C)]
@@ -136,32 +192,55 @@ impl FileInfo {
         Self::new(name, ext, attr, mtime_naive, cluster_index, size)
     }
 
-    /// Create a new file from path
-    pub fn try_from_path_and_index<P>(path: P, cluster_index: u16) -> error::Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        let path = path.as_ref();
-        assert!(path.exists());
-
-        let (name, ext) = dos::as_valid_file_components(&path)?;
-
-        let name = as_static_str!(name, 8);
-        let ext = as_static_str!(ext, 3);
+    /// Create new file from static information
+    pub fn from_static_file_info(
+        filename: &str,
+        extension: &str,
+        mtime_naive: NaiveDateTime,
+        cluster_index: u16,
+        size: u32,
+    ) -> Self {
+        let metadata = EntryMetadata {
+            is_dir: false,
+            modified: mtime_naive,
+            size,
+        };
+        Self::from_metadata(filename, extension, &metadata, cluster_index)
+    }
 
-        let attr = if path.is_dir() {
+    /// Create new entry named `filename.extension` from file metadata.
+    pub fn from_metadata(
+        filename: &str,
+        extension: &str,
+        metadata: &EntryMetadata,
+        cluster_index: u16,
+    ) -> Self {
+        let name = as_static_str!(filename, 8);
+        let ext = as_static_str!(extension, 3);
+        let attr = if metadata.is_dir {
             FileAttr::Directory
         } else {
             FileAttr::None
         } as u8;
 
-        let metadata = path.metadata()?;
-        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
-        let mtime_naive = NaiveDateTime::from_timestamp(mtime as i64, 0);
-
-        let size = metadata.size() as u32;
+        Self::new(
+            name,
+            ext,
+            attr,
+            metadata.modified,
+            cluster_index,
+            metadata.size,
+        )
+    }
 
-        Ok(Self::new(name, ext, attr, mtime_naive, cluster_index, size))
+    /// Create a new file from path
+    pub fn try_from_path_and_index<P>(path: P, cluster_index: u16) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let (name, ext) = dos::as_valid_file_components(&path)?;
+        let metadata = EntryMetadata::from_host(&path)?;
+        Ok(Self::from_metadata(&name, &ext, &metadata, cluster_index))
     }
 
     /// Create an file from any reader trait (vec, serial port, etc).
@@ -200,13 +279,221 @@ impl FileInfo {
         }
     }
 
+    /// Atari may set other attributes (ex: archive) beside the directory one.
     pub fn is_dir(&self) -> bool {
-        self.attr == FileAttr::Directory as u8
+        self.attr & FileAttr::Directory as u8 != 0
+    }
+
+    /// Entry of a file deleted by Atari.
+    pub fn is_deleted(&self) -> bool {
+        self.name[0] == DELETED_MARK
+    }
+
+    /// Entry which has never been used, nor any entry after it.
+    pub fn is_unused(&self) -> bool {
+        self.name[0] == 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attr & FileAttr::ReadOnly as u8 != 0
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.attr & FileAttr::Hidden as u8 != 0
     }
 
     pub fn size(&self) -> usize {
         self.size as usize
     }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+
+    /// Mark entry as deleted, as GEMDOS does.
+    pub fn mark_deleted(&mut self) {
+        self.name[0] = DELETED_MARK;
+    }
+
+    /// Change file name and extension.
+    pub fn rename(&mut self, filename: &str, extension: &str) {
+        self.name = as_static_str!(filename, 8);
+        self.ext = as_static_str!(extension, 3);
+    }
+
+    /// Change last modification date.
+    pub fn set_modified(&mut self, mtime_naive: NaiveDateTime) {
+        let (mtime, mdate) = format_datetime_to_atari(mtime_naive);
+        self.mtime = mtime;
+        self.mdate = mdate;
+    }
+
+    /// Last modification date, if valid.
+    pub fn modified(&self) -> Option<NaiveDateTime> {
+        parse_datetime_from_atari(self.mtime, self.mdate)
+    }
+
+    /// Check a used entry could have been written by GEMDOS, on a disk with
+    /// `cluster_count` FAT entries of `bytes_per_cluster` bytes each.
+    pub fn check(&self, cluster_count: usize, bytes_per_cluster: usize) -> error::Result<()> {
+        if self.is_deleted() || self.is_unused() {
+            return Ok(());
+        }
+        let shown =
+            String::from_utf8_lossy(&[&self.name[..], b".", &self.ext].concat()).replace(' ', "");
+        let invalid = |what: String| Err(SerialDiskError::InvalidEntry(what));
+
+        // Names are padded with spaces, labels may hold some
+        let trimmed =
+            |part: &[u8]| part.len() - part.iter().rev().take_while(|&&b| b == b' ').count();
+        let valid_part = |part: &[u8]| {
+            part[..trimmed(part)]
+                .iter()
+                .all(|&b| tos::is_valid_char(b as char))
+        };
+        let is_dot = self.is_dir() && (&self.name == b".       " || &self.name == b"..      ");
+        let valid_name = if self.attr & FileAttr::Volume as u8 != 0 {
+            self.name
+                .iter()
+                .chain(&self.ext)
+                .all(|b| (0x20..0x7F).contains(b))
+        } else {
+            is_dot || (trimmed(&self.name) > 0 && valid_part(&self.name) && valid_part(&self.ext))
+        };
+        if !valid_name {
+            return invalid(format!("{:?} has chars not allowed by TOS", shown));
+        }
+
+        let cluster = self.cluster_index as usize;
+        if cluster == 0 {
+            // `..` of a root sub directory points to root
+            if self.is_dir() && !is_dot {
+                return invalid(format!("directory {:?} has no cluster", shown));
+            }
+            if !self.is_dir() && self.size > 0 {
+                return invalid(format!(
+                    "{:?} holds {} bytes without cluster",
+                    shown, self.size
+                ));
+            }
+        } else if cluster < 2 || cluster >= cluster_count {
+            return invalid(format!(
+                "{:?} starts at cluster {:#06x}, out of FAT",
+                shown, cluster
+            ));
+        }
+
+        if !self.is_dir() && self.size() > cluster_count * bytes_per_cluster {
+            return invalid(format!(
+                "{:?} holds {} bytes, more than the disk",
+                shown, self.size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Build an entry checked against TOS constraints, without any host file
+/// (ex: generated test disks or virtual files).
+#[derive(Debug, Clone)]
+pub struct FileInfoBuilder {
+    name: String,
+    ext: String,
+    attr: u8,
+    created: Option<NaiveDateTime>,
+    modified: NaiveDateTime,
+    cluster_index: u16,
+    size: u32,
+}
+
+impl FileInfoBuilder {
+    /// File named `name.ext`, modified now, empty and without cluster.
+    pub fn new(name: &str, ext: &str) -> Self {
+        Self {
+            name: name.to_ascii_uppercase(),
+            ext: ext.to_ascii_uppercase(),
+            attr: FileAttr::None as u8,
+            created: None,
+            modified: Local::now().naive_local(),
+            cluster_index: 0,
+            size: 0,
+        }
+    }
+
+    fn with_attr(mut self, attr: FileAttr, set: bool) -> Self {
+        if set {
+            self.attr |= attr as u8;
+        } else {
+            self.attr &= !(attr as u8);
+        }
+        self
+    }
+
+    pub fn directory(self, directory: bool) -> Self {
+        self.with_attr(FileAttr::Directory, directory)
+    }
+
+    pub fn read_only(self, read_only: bool) -> Self {
+        self.with_attr(FileAttr::ReadOnly, read_only)
+    }
+
+    pub fn hidden(self, hidden: bool) -> Self {
+        self.with_attr(FileAttr::Hidden, hidden)
+    }
+
+    pub fn created(mut self, created: NaiveDateTime) -> Self {
+        self.created = Some(created);
+        self
+    }
+
+    pub fn modified(mut self, modified: NaiveDateTime) -> Self {
+        self.modified = modified;
+        self
+    }
+
+    /// First cluster of the content.
+    pub fn cluster(mut self, cluster_index: u16) -> Self {
+        self.cluster_index = cluster_index;
+        self
+    }
+
+    pub fn size(mut self, size: u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn build(self) -> error::Result<FileInfo> {
+        if self.name.len() > 8 || self.ext.len() > 3 {
+            return Err(SerialDiskError::InvalidFilename);
+        }
+        if let Some(issue) = tos::check_name("", &self.name, &self.ext).first() {
+            return Err(SerialDiskError::RejectedName(format!(
+                "{}.{}: {}",
+                self.name, self.ext, issue
+            )));
+        }
+        if self.attr & FileAttr::Directory as u8 != 0 && self.size != 0 {
+            return Err(SerialDiskError::InvalidAttr);
+        }
+        for date in self.created.iter().chain([&self.modified]) {
+            if !(1980..=2107).contains(&date.year()) {
+                return Err(SerialDiskError::InvalidDate(date.to_string()));
+            }
+        }
+
+        let mut file_info = FileInfo::new(
+            as_static_str!(self.name, 8),
+            as_static_str!(self.ext, 3),
+            self.attr,
+            self.modified,
+            self.cluster_index,
+            self.size,
+        );
+        if let Some(created) = self.created {
+            (file_info.ctime, file_info.cdate) = format_datetime_to_atari(created);
+        }
+        Ok(file_info)
+    }
 }
 
 /// List of all file contains on the disk.
@@ -218,12 +505,38 @@ pub struct DirectoryContent {
 
 impl DirectoryContent {
     /// Create table with a given number of entries.
-    pub fn new(count: usize) -> Self {
-        assert!(count > 0);
+    pub fn new(count: usize) -> error::Result<Self> {
+        if count == 0 {
+            return Err(SerialDiskError::EmptyTable);
+        }
 
-        Self {
+        Ok(Self {
             file_infos: vec![FileInfo::EMPTY; count],
-        }
+        })
+    }
+
+    /// Number of entries used, deleted ones included.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of entries table can hold.
+    pub fn capacity(&self) -> usize {
+        self.file_infos.len()
+    }
+
+    /// Tell if next `push` fails.
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Used entries, deleted ones included.
+    pub fn iter(&self) -> impl Iterator<Item = &FileInfo> {
+        self.file_infos.iter().filter(|e| **e != FileInfo::EMPTY)
     }
 
     /// Create table from reader trait (ex: serial port)
@@ -266,18 +579,28 @@ impl DirectoryContent {
             .ok_or(SerialDiskError::FolderFull)
     }
 
+    /// File in use named `filename`, comparing names by `NameKey`.
+    pub fn find_file_mut(&mut self, filename: &str) -> Option<&mut FileInfo> {
+        let key = NameKey::new(filename);
+        self.file_infos.iter_mut().find(|e| {
+            !e.is_deleted()
+                && !e.is_unused()
+                && !e.is_dir()
+                && e.filename().is_ok_and(|name| NameKey::new(&name) == key)
+        })
+    }
+
     pub fn as_vec(&self) -> Vec<FileInfo> {
-        self.file_infos
-            .iter()
-            .filter(|e| **e != FileInfo::EMPTY)
-            .cloned()
-            .collect()
+        self.iter().cloned().collect()
     }
 }
 
 #[cfg(test)]
+// Expected raw entries keep their original char cast notation
+#[allow(clippy::char_lit_as_u8, clippy::identity_op)]
 mod tests {
     use super::*;
+    use crate::fixture::Fixture;
 
     const EXPECTED_FILE_INFO_SIZE: usize = 0x20;
 
@@ -288,32 +611,112 @@ mod tests {
 
     #[test]
     fn test_empty_init() {
-        let table = DirectoryContent::new(3);
+        let table = DirectoryContent::new(3).unwrap();
         assert_eq!(table.as_raw(), [0; EXPECTED_FILE_INFO_SIZE * 3]);
     }
 
+    #[test]
+    fn test_from_metadata() {
+        let sample = Fixture::sample("entries-metadata").unwrap();
+        let metadata = EntryMetadata::from_host(sample.join("TEST.TXT")).unwrap();
+        assert!(!metadata.is_dir);
+        assert_eq!(
+            FileInfo::from_metadata("TEST", "TXT", &metadata, 0x1234),
+            FileInfo::try_from_path_and_index(sample.join("TEST.TXT"), 0x1234).unwrap()
+        );
+
+        let modified = NaiveDate::from_ymd(1990, 1, 2).and_hms(3, 4, 6);
+        let metadata = EntryMetadata {
+            is_dir: true,
+            modified,
+            size: 0,
+        };
+        let file_info = FileInfo::from_metadata("GAMES", "", &metadata, 0x12);
+        assert!(file_info.is_dir());
+        assert_eq!(file_info.modified(), Some(modified));
+        assert_eq!(file_info.filename().unwrap(), "GAMES");
+    }
+
+    #[test]
+    fn test_builder() {
+        let date = NaiveDate::from_ymd(1992, 3, 4).and_hms(10, 20, 30);
+        let file_info = FileInfoBuilder::new("readme", "txt")
+            .read_only(true)
+            .hidden(true)
+            .created(date)
+            .modified(date)
+            .cluster(0x12)
+            .size(42)
+            .build()
+            .unwrap();
+        assert_eq!(file_info.filename().unwrap(), "README.TXT");
+        assert!(file_info.is_read_only() && file_info.is_hidden() && !file_info.is_dir());
+        assert_eq!((file_info.cluster_index, file_info.size()), (0x12, 42));
+        assert_eq!(file_info.modified(), Some(date));
+        assert_eq!(
+            parse_datetime_from_atari(file_info.ctime, file_info.cdate),
+            Some(date)
+        );
+
+        let dir = FileInfoBuilder::new("GAMES", "").directory(true).build();
+        assert!(dir.unwrap().is_dir());
+
+        assert_eq!(
+            FileInfoBuilder::new("TOOLONGNAME", "").build(),
+            Err(SerialDiskError::InvalidFilename)
+        );
+        assert!(matches!(
+            FileInfoBuilder::new("A B", "TXT").build(),
+            Err(SerialDiskError::RejectedName(_))
+        ));
+        assert_eq!(
+            FileInfoBuilder::new("GAMES", "")
+                .directory(true)
+                .size(1)
+                .build(),
+            Err(SerialDiskError::InvalidAttr)
+        );
+        let old = NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0);
+        assert!(matches!(
+            FileInfoBuilder::new("OLD", "").modified(old).build(),
+            Err(SerialDiskError::InvalidDate(_))
+        ));
+    }
+
     #[test]
     fn test_full() {
-        let mut table = DirectoryContent::new(3);
-        let file_info = FileInfo::try_from_path_and_index("./data/TEST.TXT", 0x1234).unwrap();
+        let sample = Fixture::sample("entries-full").unwrap();
+        let mut table = DirectoryContent::new(3).unwrap();
+        let file_info = FileInfo::try_from_path_and_index(sample.join("TEST.TXT"), 0x1234).unwrap();
 
         // Check add success and fail the check emptyness
+        assert!(table.is_empty());
         assert_eq!(table.push(file_info.clone()), Ok(()));
         assert_eq!(table.push(file_info.clone()), Ok(()));
+        assert_eq!((table.len(), table.capacity()), (2, 3));
+        assert!(!table.is_full());
         assert_eq!(table.push(file_info.clone()), Ok(()));
+        assert!(table.is_full());
+        assert_eq!(table.iter().count(), 3);
         assert_eq!(
             table.push(file_info.clone()),
             Err(SerialDiskError::FolderFull)
         );
+
+        assert_eq!(
+            DirectoryContent::new(0).map(|_| ()),
+            Err(SerialDiskError::EmptyTable)
+        );
     }
 
     #[test]
     fn test_content() {
-        let mut table = DirectoryContent::new(1);
+        let sample = Fixture::sample("entries-content").unwrap();
+        let mut table = DirectoryContent::new(1).unwrap();
         assert_eq!(table.as_raw(), [0; EXPECTED_FILE_INFO_SIZE * 1]);
 
         assert_eq!(
-            table.push(FileInfo::try_from_path_and_index("./data/TEST.TXT", 0x1234).unwrap()),
+            table.push(FileInfo::try_from_path_and_index(sample.join("TEST.TXT"), 0x1234).unwrap()),
             Ok(()),
         );
         assert_eq!(
@@ -334,7 +737,7 @@ mod tests {
 
     #[test]
     fn test_from_static_dir_info() {
-        let mut table = DirectoryContent::new(1);
+        let mut table = DirectoryContent::new(1).unwrap();
         assert_eq!(table.as_raw(), [0; EXPECTED_FILE_INFO_SIZE * 1]);
 
         assert_eq!(
@@ -357,6 +760,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_check() {
+        let file = |name: &str, ext: &str, cluster: u16, size: u32| {
+            FileInfo::new(
+                as_static_str!(name, 8),
+                as_static_str!(ext, 3),
+                FileAttr::None as u8,
+                NaiveDateTime::from_timestamp(0, 0),
+                cluster,
+                size,
+            )
+        };
+        let check = |file_info: &FileInfo| file_info.check(0x100, 1024).is_ok();
+
+        assert!(check(&file("DUNG", "PRG", 2, 2000)));
+        assert!(check(&file("EMPTY", "", 0, 0)));
+        assert!(check(&FileInfo::from_static_dir_info("..", "", 0)));
+        assert!(!check(&file("DU*G", "PRG", 2, 2000)));
+        assert!(!check(&file("DU NG", "PRG", 2, 2000)));
+        assert!(!check(&file("", "PRG", 2, 2000)));
+        assert!(!check(&file("DUNG", "PRG", 1, 2000)));
+        assert!(!check(&file("DUNG", "PRG", 0x100, 2000)));
+        assert!(!check(&file("DUNG", "PRG", 0, 2000)));
+        assert!(!check(&file("DUNG", "PRG", 2, 0x100 * 1024 + 1)));
+        assert!(!check(&FileInfo::from_static_dir_info("GAMES", "", 0)));
+
+        // Deleted entries are not checked
+        let mut deleted = file("DUNG", "PRG", 0xFFFF, 0);
+        deleted.mark_deleted();
+        assert!(check(&deleted));
+    }
+
     #[test]
     fn test_reader_fail() {
         let empty: Vec<u8> = vec![];
@@ -394,11 +829,47 @@ mod tests {
         assert_eq!(file_info.size(), 0);
     }
 
+    #[test]
+    fn test_attributes() {
+        let mut data = vec![0x20; 32];
+        data[..11].copy_from_slice(b"TEST    TXT");
+
+        // Read-only + archive
+        data[11] = 0x21;
+        let file_info = FileInfo::try_from_reader(&mut data.as_slice()).unwrap();
+        assert!(!file_info.is_dir());
+        assert!(file_info.is_read_only());
+        assert!(!file_info.is_hidden());
+
+        // Directory + hidden + archive
+        data[11] = 0x32;
+        let file_info = FileInfo::try_from_reader(&mut data.as_slice()).unwrap();
+        assert!(file_info.is_dir());
+        assert!(!file_info.is_read_only());
+        assert!(file_info.is_hidden());
+    }
+
+    #[test]
+    fn test_modified() {
+        let mtime = NaiveDateTime::new(
+            NaiveDate::from_ymd(2021, 8, 1),
+            NaiveTime::from_hms(14, 30, 12),
+        );
+        let file_info = FileInfo::from_static_file_info("TEST", "TXT", mtime, 0x1234, 20);
+
+        assert!(!file_info.is_dir());
+        assert_eq!(file_info.filename().unwrap(), "TEST.TXT");
+        assert_eq!(file_info.size(), 20);
+        assert_eq!(file_info.modified(), Some(mtime));
+        assert_eq!(FileInfo::EMPTY.modified(), None);
+    }
+
     #[test]
     fn test_list() {
+        let sample = Fixture::sample("entries-list").unwrap();
         // Prepare a table with a lot of space in it
-        let mut table = DirectoryContent::new(2096);
-        let file_info = FileInfo::try_from_path_and_index("./data/TEST.TXT", 0x1234).unwrap();
+        let mut table = DirectoryContent::new(2096).unwrap();
+        let file_info = FileInfo::try_from_path_and_index(sample.join("TEST.TXT"), 0x1234).unwrap();
         assert_eq!(table.push(file_info.clone()), Ok(()));
         assert_eq!(table.push(file_info.clone()), Ok(()));
         assert_eq!(table.push(file_info.clone()), Ok(()));
@@ -1,4 +1,4 @@
-use std::{io, time};
+use std::{fmt, io, time};
 
 use thiserror::Error;
 
@@ -23,6 +23,9 @@ pub enum SerialDiskError {
     #[error("folder is full")]
     FolderFull,
 
+    #[error("directory table without entries")]
+    EmptyTable,
+
     #[error("invalid time: {0}")]
     InvalidTime(#[from] time::SystemTimeError),
 
@@ -31,23 +34,190 @@ pub enum SerialDiskError {
 
     #[error("invalid attributes")]
     InvalidAttr,
+
+    #[error("date not supported by TOS: {0}")]
+    InvalidDate(String),
+
+    #[error("incompatible disk layout: {0}")]
+    IncompatibleLayout(String),
+
+    #[error("invalid dump: {0}")]
+    Dump(#[from] bincode::Error),
+
+    #[error("no driver bundled for protocol version {0}")]
+    MissingDriver(u8),
+
+    #[error("invalid archive: {0}")]
+    InvalidArchive(String),
+
+    #[error("invalid floppy image: {0}")]
+    InvalidImage(String),
+
+    #[error("name rejected by TOS: {0}")]
+    RejectedName(String),
+
+    #[error("unknown user or group: {0}")]
+    UnknownAccount(String),
+
+    #[error("path is outside shared directory: {0}")]
+    OutsideSharedRoot(String),
+
+    #[error("quota of {0} exceeded")]
+    QuotaExceeded(String),
+
+    #[error("invalid trace: {0}")]
+    InvalidTrace(String),
+
+    #[error("invalid split file: {0}")]
+    InvalidSplit(String),
+
+    #[error("invalid directory entry: {0}")]
+    InvalidEntry(String),
+
+    /// Error raised while working on some disk entry or sector.
+    #[error("{source} ({context})")]
+    Context {
+        context: ErrorContext,
+        source: Box<SerialDiskError>,
+    },
+}
+
+/// Where on disk an error happened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// `\` separated disk path of the entry
+    pub path: Option<String>,
+    /// First cluster of the directory of the entry
+    pub parent_cluster: Option<u16>,
+    pub sector_index: Option<u16>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(path) = &self.path {
+            parts.push(format!("path: {}", path));
+        }
+        if let Some(cluster) = self.parent_cluster {
+            parts.push(format!("parent cluster: {:#x}", cluster));
+        }
+        if let Some(sector) = self.sector_index {
+            parts.push(format!("sector: {:#x}", sector));
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+impl SerialDiskError {
+    /// Error without its context.
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Add context to error, keeping what is already known.
+    fn with_context<F>(self, update: F) -> Self
+    where
+        F: FnOnce(&mut ErrorContext),
+    {
+        let (mut context, source) = match self {
+            Self::Context { context, source } => (context, source),
+            error => (ErrorContext::default(), Box::new(error)),
+        };
+        update(&mut context);
+        Self::Context { context, source }
+    }
+
+    pub fn with_path(self, path: String) -> Self {
+        self.with_context(|context| {
+            context.path.get_or_insert(path);
+        })
+    }
+
+    pub fn in_folder(self, cluster_index: u16) -> Self {
+        self.with_context(|context| {
+            context.parent_cluster.get_or_insert(cluster_index);
+        })
+    }
+
+    pub fn at_sector(self, sector_index: u16) -> Self {
+        self.with_context(|context| {
+            context.sector_index.get_or_insert(sector_index);
+        })
+    }
 }
 
 impl PartialEq for SerialDiskError {
     fn eq(&self, other: &Self) -> bool {
         matches!(
-            (&*self, other),
+            (self, other),
             (Self::Serial(_), Self::Serial(_))
                 | (Self::IO(_), &Self::IO(_))
                 | (Self::DiskFull, Self::DiskFull)
                 | (Self::InvalidFilename, Self::InvalidFilename)
                 | (Self::InvalidChars, Self::InvalidChars)
                 | (Self::FolderFull, Self::FolderFull)
+                | (Self::EmptyTable, Self::EmptyTable)
                 | (Self::InvalidTime(_), &Self::InvalidTime(_))
                 | (Self::StringParse(_), &Self::StringParse(_))
                 | (Self::InvalidAttr, Self::InvalidAttr)
+                | (Self::InvalidDate(_), Self::InvalidDate(_))
+                | (Self::IncompatibleLayout(_), Self::IncompatibleLayout(_))
+                | (Self::Dump(_), Self::Dump(_))
+                | (Self::MissingDriver(_), Self::MissingDriver(_))
+                | (Self::InvalidArchive(_), Self::InvalidArchive(_))
+                | (Self::InvalidImage(_), Self::InvalidImage(_))
+                | (Self::RejectedName(_), Self::RejectedName(_))
+                | (Self::UnknownAccount(_), Self::UnknownAccount(_))
+                | (Self::OutsideSharedRoot(_), Self::OutsideSharedRoot(_))
+                | (Self::QuotaExceeded(_), Self::QuotaExceeded(_))
+                | (Self::InvalidTrace(_), Self::InvalidTrace(_))
+                | (Self::InvalidSplit(_), Self::InvalidSplit(_))
+        ) || matches!(
+            (self, other),
+            (
+                Self::Context { context, source },
+                Self::Context { context: other_context, source: other_source },
+            ) if context == other_context && source == other_source
         )
     }
 }
 
 pub type Result<T> = std::result::Result<T, SerialDiskError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let error = SerialDiskError::FolderFull
+            .in_folder(0x12)
+            .with_path("GAMES\\DUNG.PRG".to_string())
+            .in_folder(0x34);
+
+        assert_eq!(error.root(), &SerialDiskError::FolderFull);
+        assert_eq!(
+            error.context(),
+            Some(&ErrorContext {
+                path: Some("GAMES\\DUNG.PRG".to_string()),
+                parent_cluster: Some(0x12),
+                sector_index: None,
+            })
+        );
+        assert_ne!(error, SerialDiskError::FolderFull);
+        assert_eq!(
+            error.to_string(),
+            "folder is full (path: GAMES\\DUNG.PRG, parent cluster: 0x12)"
+        );
+    }
+}
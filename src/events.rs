@@ -0,0 +1,250 @@
+//! Machine readable stream of server events.
+//!
+//! Events are printed on stdout as newline delimited JSON, so wrapper
+//! scripts and GUIs can follow what the server is doing.
+
+use std::{
+    io::{self, Write},
+    sync::mpsc::Receiver,
+    thread,
+};
+
+use serde::Serialize;
+
+use crate::{
+    bus::StorageEvent,
+    import_report::SkippedEntry,
+    transaction::{self, Notice, TransactionKind, TransactionRecord},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ImportFinished {
+        files: usize,
+        bytes: usize,
+        duration_ms: u128,
+    },
+    ImportSkipped {
+        path: String,
+        kind: String,
+        reason: String,
+    },
+    Ready {
+        port: String,
+    },
+    Read {
+        txn: u64,
+        sector: u16,
+        count: u16,
+        bytes: usize,
+        duration_ms: u128,
+        success: bool,
+    },
+    Write {
+        txn: u64,
+        sector: u16,
+        count: u16,
+        bytes: usize,
+        duration_ms: u128,
+        success: bool,
+    },
+    Commit {
+        txn: u64,
+        duration_ms: u128,
+        success: bool,
+    },
+    CrcError {
+        txn: u64,
+        sector: u16,
+        count: u16,
+    },
+    DiskFull {
+        txn: u64,
+    },
+    FormatAttempt {
+        txn: u64,
+        allowed: bool,
+    },
+    FileAccess {
+        txn: u64,
+        access: String,
+        paths: Vec<String>,
+    },
+    FileWritten {
+        path: String,
+        size: usize,
+    },
+    DirEntryChanged {
+        path: String,
+        deleted: bool,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Event {
+    /// Convert a finished transaction to an event, if it is worth one.
+    pub fn from_transaction(record: &TransactionRecord) -> Option<Self> {
+        let (sector, count) = record.sectors.unwrap_or_default();
+        let txn = record.id;
+        let bytes = record.bytes;
+        let duration_ms = record.duration.as_millis();
+        let success = record.success;
+
+        match record.kind {
+            TransactionKind::Read => Some(Self::Read {
+                txn,
+                sector,
+                count,
+                bytes,
+                duration_ms,
+                success,
+            }),
+            TransactionKind::Write => Some(Self::Write {
+                txn,
+                sector,
+                count,
+                bytes,
+                duration_ms,
+                success,
+            }),
+            TransactionKind::Commit => Some(Self::Commit {
+                txn,
+                duration_ms,
+                success,
+            }),
+            TransactionKind::BiosParameterBlock
+            | TransactionKind::SpeedTest
+            | TransactionKind::Auth
+            | TransactionKind::Clock
+            | TransactionKind::HostCommand
+            | TransactionKind::HostChanges
+            | TransactionKind::Capabilities => None,
+        }
+    }
+
+    pub fn from_skipped(entry: &SkippedEntry) -> Self {
+        Self::ImportSkipped {
+            path: entry.path.display().to_string(),
+            kind: entry.reason.kind().to_string(),
+            reason: entry.reason.to_string(),
+        }
+    }
+
+    /// Convert a transaction notice to an event, if it is worth one.
+    pub fn from_notice(notice: &Notice) -> Option<Self> {
+        match notice {
+            Notice::Started { .. } => None,
+            Notice::Finished(record) => Self::from_transaction(record),
+            Notice::CrcError { id, sectors } => {
+                let (sector, count) = sectors.unwrap_or_default();
+                Some(Self::CrcError {
+                    txn: *id,
+                    sector,
+                    count,
+                })
+            }
+            Notice::DiskFull { id } => Some(Self::DiskFull { txn: *id }),
+            Notice::FormatAttempt { id, allowed } => Some(Self::FormatAttempt {
+                txn: *id,
+                allowed: *allowed,
+            }),
+            Notice::FileAccess { id, kind, paths } => Some(Self::FileAccess {
+                txn: *id,
+                access: kind.to_string(),
+                paths: paths.clone(),
+            }),
+        }
+    }
+
+    /// Convert a disk change to an event, if it is not already reported by
+    /// transactions.
+    pub fn from_storage(event: &StorageEvent) -> Option<Self> {
+        match event {
+            StorageEvent::SectorWritten { .. } => None,
+            StorageEvent::FileWritten { path, size } => Some(Self::FileWritten {
+                path: path.clone(),
+                size: *size,
+            }),
+            StorageEvent::DirEntryChanged { path, deleted } => Some(Self::DirEntryChanged {
+                path: path.clone(),
+                deleted: *deleted,
+            }),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Events are always serializable")
+    }
+
+    /// Print event as a single JSON line on stdout.
+    pub fn emit(&self) {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        // Nobody to report to if stdout is closed
+        let _ = writeln!(stdout, "{}", self.to_json());
+        let _ = stdout.flush();
+    }
+}
+
+/// Emit an event for every transaction served to Atari.
+pub fn emit_transactions() {
+    transaction::subscribe(|notice| {
+        if let Some(event) = Event::from_notice(notice) {
+            event.emit();
+        }
+    });
+}
+
+/// Emit an event for every disk change received from `receiver`.
+pub fn emit_storage_events(receiver: Receiver<StorageEvent>) {
+    thread::spawn(move || {
+        for event in receiver.iter().filter_map(|e| Event::from_storage(&e)) {
+            event.emit();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let event = Event::ImportFinished {
+            files: 3,
+            bytes: 1024,
+            duration_ms: 12,
+        };
+        assert_eq!(
+            event.to_json(),
+            r#"{"event":"import_finished","files":3,"bytes":1024,"duration_ms":12}"#
+        );
+    }
+
+    #[test]
+    fn test_from_transaction() {
+        let record = TransactionRecord {
+            id: 7,
+            kind: TransactionKind::Write,
+            sectors: Some((0x104, 2)),
+            bytes: 1024,
+            duration: Duration::from_millis(30),
+            success: true,
+        };
+        assert_eq!(
+            Event::from_transaction(&record).unwrap().to_json(),
+            r#"{"event":"write","txn":7,"sector":260,"count":2,"bytes":1024,"duration_ms":30,"success":true}"#
+        );
+
+        let record = TransactionRecord {
+            kind: TransactionKind::BiosParameterBlock,
+            ..record
+        };
+        assert_eq!(Event::from_transaction(&record), None);
+    }
+}
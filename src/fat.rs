@@ -1,4 +1,4 @@
-use std::{io, mem, slice};
+use std::{collections::HashSet, io, mem, slice};
 
 use byteorder::{NativeEndian, ReadBytesExt};
 use serde::{Deserialize, Serialize};
@@ -11,7 +11,18 @@ enum ClusterValue {
     EndOfClusterChain = 0xFFFF,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Where walking a chain stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainEnd {
+    /// End of chain mark
+    End,
+    /// Free, reserved or out of range cluster reached
+    Broken(u16),
+    /// Cluster reached a second time
+    Loop(u16),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[repr(C)]
 pub struct FileAllocationTable {
     entries: Vec<u16>,
@@ -39,6 +50,31 @@ impl FileAllocationTable {
         }
     }
 
+    /// Number of entries, reserved ones included.
+    pub fn cluster_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of clusters which can still be reserved.
+    pub fn free_cluster_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|x| **x == ClusterValue::Free as u16)
+            .count()
+    }
+
+    /// Check if a cluster can be reserved.
+    pub fn is_free(&self, cluster_index: u16) -> bool {
+        self.entries.get(cluster_index as usize) == Some(&(ClusterValue::Free as u16))
+    }
+
+    /// Check if no cluster is used.
+    pub fn is_empty(&self) -> bool {
+        self.entries[2..]
+            .iter()
+            .all(|x| *x == ClusterValue::Free as u16)
+    }
+
     /// Get new empty cluster
     pub fn reserve_cluster(&mut self) -> Option<u16> {
         self.entries
@@ -59,57 +95,167 @@ impl FileAllocationTable {
             existing_index,
         );
 
-        self.reserve_cluster().map(|next_index| {
+        self.reserve_cluster().inspect(|&next_index| {
             self.entries[existing_index as usize] = next_index;
-            next_index
         })
     }
 
+    /// Reserve a chain of each length in `lens` (non zero), following each
+    /// other in the first free run long enough, and return their first
+    /// clusters.
+    pub fn reserve_runs(&mut self, lens: &[usize]) -> Option<Vec<u16>> {
+        let total: usize = lens.iter().sum();
+        let mut start = 2;
+        let mut run = 0;
+        for (i, entry) in self.entries.iter().enumerate().skip(2) {
+            if run == total {
+                break;
+            }
+            if *entry == ClusterValue::Free as u16 {
+                run += 1;
+            } else {
+                start = i + 1;
+                run = 0;
+            }
+        }
+        if run < total {
+            return None;
+        }
+
+        let mut firsts = Vec::with_capacity(lens.len());
+        for len in lens {
+            firsts.push(start as u16);
+            for i in start..start + len - 1 {
+                self.entries[i] = i as u16 + 1;
+            }
+            self.entries[start + len - 1] = ClusterValue::EndOfClusterChain as u16;
+            start += len;
+        }
+        Some(firsts)
+    }
+
+    /// Free every cluster of a chain and return them.
+    pub fn free_chain(&mut self, start_block: u16) -> Vec<u16> {
+        self.truncate_chain(start_block, 0)
+    }
+
+    /// Keep the first `len` clusters of a chain, free the others and return
+    /// them.
+    pub fn truncate_chain(&mut self, start_block: u16, len: usize) -> Vec<u16> {
+        let mut chain = self.list_chain(start_block);
+        let freed = chain.split_off(len.min(chain.len()));
+
+        if let (Some(&last), false) = (chain.last(), freed.is_empty()) {
+            self.entries[last as usize] = ClusterValue::EndOfClusterChain as u16;
+        }
+        for &cluster_index in &freed {
+            self.entries[cluster_index as usize] = ClusterValue::Free as u16;
+        }
+        freed
+    }
+
+    /// Overwrite entries starting at `entry_index` with raw FAT data.
+    ///
+    /// Entries past the end of the table (padding of last FAT sector)
+    /// are read and dropped.
     pub fn merge_data<R>(
         &mut self,
         reader: &mut R,
-        bytes_index: usize,
+        entry_index: usize,
         bytes_count: usize,
     ) -> io::Result<()>
     where
         R: ReadBytesExt,
     {
-        assert_eq!(bytes_index % 2, 0, "Bytes index must be odd");
-        assert_eq!(bytes_count % 2, 0, "Bytes count must be odd");
+        assert_eq!(bytes_count % 2, 0, "Bytes count must be even");
 
         for i in 0..(bytes_count / 2) {
-            self.entries[bytes_index + i] = reader.read_u16::<NativeEndian>()?;
+            let value = reader.read_u16::<NativeEndian>()?;
+            if let Some(entry) = self.entries.get_mut(entry_index + i) {
+                *entry = value;
+            }
         }
 
         Ok(())
     }
 
-    pub fn list_chain(&self, start_block: u16) -> Vec<u16> {
-        if start_block <= ClusterValue::Reserved as u16 {
-            return Vec::new();
-        }
+    /// Clusters used by files or directories.
+    pub fn allocated_clusters(&self) -> impl Iterator<Item = u16> + '_ {
+        (2..self.entries.len())
+            .filter(move |index| self.entries[*index] != ClusterValue::Free as u16)
+            .map(|index| index as u16)
+    }
 
-        let mut chain = Vec::new();
+    /// Clusters of a chain up to where it stops, without trusting the FAT
+    /// (unlike `chain`, which does not tell why it stopped).
+    pub fn checked_chain(&self, start_block: u16) -> (Vec<u16>, ChainEnd) {
+        let mut clusters = Vec::new();
+        let mut visited = HashSet::new();
         let mut block = start_block;
+        loop {
+            if block == ClusterValue::EndOfClusterChain as u16 {
+                return (clusters, ChainEnd::End);
+            }
+            if block <= ClusterValue::Reserved as u16 || block as usize >= self.entries.len() {
+                return (clusters, ChainEnd::Broken(block));
+            }
+            if !visited.insert(block) {
+                return (clusters, ChainEnd::Loop(block));
+            }
+            clusters.push(block);
+            block = self.entries[block as usize];
+        }
+    }
 
-        while block != ClusterValue::EndOfClusterChain as u16 {
-            chain.push(block);
+    pub fn list_chain(&self, start_block: u16) -> Vec<u16> {
+        self.chain(start_block).collect()
+    }
 
-            assert!(
-                (block as usize) < self.entries.len(),
-                "Out of range block: {:#04x}",
-                block
-            );
-            block = self.entries[block as usize];
+    /// Iterate over clusters of a chain, without allocating it.
+    pub fn chain(&self, start_block: u16) -> ClusterChain<'_> {
+        ClusterChain {
+            entries: &self.entries,
+            block: start_block,
+            remaining: self.entries.len(),
         }
+    }
+}
+
+/// Clusters of a chain, in order.
+///
+/// A chain looping on itself (corrupted FAT) stops once every cluster has
+/// been given, one going out of range stops before the invalid cluster.
+#[derive(Debug, Clone)]
+pub struct ClusterChain<'a> {
+    entries: &'a [u16],
+    block: u16,
+    remaining: usize,
+}
 
-        chain
+impl Iterator for ClusterChain<'_> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        // Free, reserved or out of range: broken chain (ex: freed by Atari)
+        if self.block == ClusterValue::EndOfClusterChain as u16
+            || self.block <= ClusterValue::Reserved as u16
+            || self.block as usize >= self.entries.len()
+            || self.remaining == 0
+        {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let block = self.block;
+        self.block = self.entries[block as usize];
+        Some(block)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prop;
 
     #[test]
     fn test_reserve() {
@@ -140,10 +286,13 @@ mod tests {
             ]
         );
 
+        assert_eq!(fat.free_cluster_count(), 3);
+
         assert_eq!(fat.reserve_cluster(), Some(0x0003));
         assert_eq!(fat.reserve_cluster(), Some(0x0004));
         assert_eq!(fat.reserve_cluster(), Some(0x0005));
         assert_eq!(fat.reserve_cluster(), None);
+        assert_eq!(fat.free_cluster_count(), 0);
         assert_eq!(
             fat.as_raw(),
             [
@@ -195,6 +344,42 @@ mod tests {
         assert_eq!(fat.as_raw(), expected);
     }
 
+    #[test]
+    fn test_free_chain() {
+        let mut fat = FileAllocationTable::new(6);
+        fat.reserve_cluster();
+        fat.extend_cluster(0x0002);
+        fat.reserve_cluster();
+
+        assert_eq!(fat.free_chain(0x0002), [0x0002, 0x0003]);
+        assert!(fat.is_free(0x0002));
+        assert!(fat.is_free(0x0003));
+        assert!(!fat.is_free(0x0004));
+        assert_eq!(fat.reserve_cluster(), Some(0x0002));
+
+        fat.extend_cluster(0x0002);
+        fat.extend_cluster(0x0003);
+        assert_eq!(fat.truncate_chain(0x0002, 1), [0x0003, 0x0005]);
+        assert_eq!(fat.list_chain(0x0002), [0x0002]);
+        assert!(fat.truncate_chain(0x0002, 1).is_empty());
+    }
+
+    #[test]
+    fn test_reserve_runs() {
+        let mut fat = FileAllocationTable::new(10);
+        fat.reserve_cluster();
+        fat.reserve_cluster();
+        fat.reserve_cluster();
+        fat.free_chain(0x0003);
+
+        // Hole at 0x0003 is too small
+        assert_eq!(fat.reserve_runs(&[2, 1]), Some(vec![0x0005, 0x0007]));
+        assert_eq!(fat.list_chain(0x0005), [0x0005, 0x0006]);
+        assert_eq!(fat.list_chain(0x0007), [0x0007]);
+        assert_eq!(fat.reserve_runs(&[3]), None);
+        assert_eq!(fat.reserve_runs(&[2]), Some(vec![0x0008]));
+    }
+
     #[test]
     #[should_panic(expected = "Existing cluster index is not an ending index.")]
     fn test_extend_panic() {
@@ -263,6 +448,87 @@ mod tests {
         assert_ne!(fat.list_chain(0x00_02).len(), 0);
     }
 
+    #[test]
+    fn test_reserve_extend_invariants() {
+        prop::check(prop::CASES, |rng| {
+            let count = rng.between(2, 64);
+            let mut fat = FileAllocationTable::new(count);
+            let mut chains: Vec<Vec<u16>> = Vec::new();
+
+            for _ in 0..rng.below(100) {
+                let expected_free = count - 2 - chains.iter().map(Vec::len).sum::<usize>();
+                assert_eq!(fat.free_cluster_count(), expected_free);
+
+                let new_cluster = if chains.is_empty() || rng.bool() {
+                    let cluster = fat.reserve_cluster();
+                    if let Some(cluster) = cluster {
+                        chains.push(vec![cluster]);
+                    }
+                    cluster
+                } else {
+                    let chain_index = rng.below(chains.len());
+                    let chain = &mut chains[chain_index];
+                    let cluster = fat.extend_cluster(*chain.last().unwrap());
+                    if let Some(cluster) = cluster {
+                        chain.push(cluster);
+                    }
+                    cluster
+                };
+                assert_eq!(new_cluster.is_none(), expected_free == 0);
+            }
+
+            // Chains are stored as built and never share a cluster
+            let mut used: Vec<u16> = chains.iter().flatten().copied().collect();
+            for chain in &chains {
+                assert_eq!(&fat.list_chain(chain[0]), chain);
+            }
+            used.sort_unstable();
+            used.dedup();
+            assert_eq!(used.len(), count - 2 - fat.free_cluster_count());
+            assert!(used.iter().all(|c| *c >= 2));
+        });
+    }
+
+    #[test]
+    fn test_merge_raw_round_trip() {
+        prop::check(prop::CASES, |rng| {
+            let count = rng.between(2, 64);
+            let mut fat = FileAllocationTable::new(count);
+            for cluster in 2..count {
+                fat.entries[cluster] = rng.next_u64() as u16;
+            }
+
+            // Copy table by chunks, as Atari does with FAT sectors
+            let raw = fat.as_raw().to_vec();
+            let chunk_entries = rng.between(1, 8);
+            let mut copy = FileAllocationTable::new(count);
+            for (i, chunk) in raw.chunks(chunk_entries * 2).enumerate() {
+                copy.merge_data(&mut &chunk[..], i * chunk_entries, chunk.len())
+                    .unwrap();
+            }
+            assert_eq!(copy.as_raw(), &raw[..]);
+        });
+    }
+
+    #[test]
+    fn test_checked_chain() {
+        let mut fat = FileAllocationTable::new(8);
+        let first = fat.reserve_cluster().unwrap();
+        let second = fat.extend_cluster(first).unwrap();
+        assert_eq!(fat.checked_chain(first), (vec![2, 3], ChainEnd::End));
+        assert_eq!(fat.allocated_clusters().collect::<Vec<_>>(), [2, 3]);
+
+        fat.entries[second as usize] = 0x40;
+        assert_eq!(
+            fat.checked_chain(first),
+            (vec![2, 3], ChainEnd::Broken(0x40))
+        );
+        fat.entries[second as usize] = first;
+        assert_eq!(fat.checked_chain(first), (vec![2, 3], ChainEnd::Loop(2)));
+        assert_eq!(fat.checked_chain(5), (vec![5], ChainEnd::Broken(0)));
+        assert_eq!(fat.checked_chain(1), (vec![], ChainEnd::Broken(1)));
+    }
+
     #[test]
     fn test_list() {
         // Prepare FAT
@@ -276,5 +542,15 @@ mod tests {
         // List chains
         assert_eq!(fat.list_chain(0x00_02), vec![0x00_02, 0x00_03, 0x00_04]);
         assert_eq!(fat.list_chain(0x00_05), vec![0x00_05, 0x00_06]);
+        assert_eq!(fat.chain(0x00_02).nth(1), Some(0x00_03));
+
+        // Corrupted FAT looping on itself
+        fat.entries[0x0004] = 0x0002;
+        assert_eq!(fat.chain(0x00_02).count(), 10);
+
+        // Corrupted FAT going out of range
+        fat.entries[0x0003] = 0x0042;
+        assert_eq!(fat.list_chain(0x00_02), vec![0x00_02, 0x00_03]);
+        assert!(fat.list_chain(0x0042).is_empty());
     }
 }
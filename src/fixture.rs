@@ -0,0 +1,246 @@
+//! Host directory trees generated for tests and benchmarks.
+//!
+//! Names, sizes and content of a tree are drawn from the seed of its
+//! `TreeSpec`, so the same spec always gives the same tree. With
+//! `weird_names`, some names need the import policies to fit TOS (long, lower
+//! case, spaces, accents, several dots or clashing once shortened).
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use crate::rng::Rng;
+
+/// Content of `TEST.TXT` in `Fixture::sample`.
+pub const SAMPLE_CONTENT: &[u8] = b"This is a test file\n";
+
+/// Modification time of generated files, in seconds (2021-08-01 14:30 UTC).
+pub const MTIME_SECS: u64 = 1_627_828_200;
+
+/// Names TOS cannot use as is, `{}` being replaced by a unique part. Long
+/// ones all clash once shortened.
+const WEIRD_NAMES: [&str; 5] = [
+    "{} with spaces.txt",
+    "lower_{}.doc",
+    "Été {}.dat",
+    "archive.{}.tar.gz",
+    "VERY_LONG_NAME_{}.TXT",
+];
+
+/// Shape of a generated tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeSpec {
+    /// Files in each directory
+    pub files: usize,
+    /// Sub directories in each directory but the deepest ones
+    pub dirs: usize,
+    /// Directory levels below root
+    pub depth: usize,
+    /// Smallest file size, in bytes
+    pub min_size: usize,
+    /// Largest file size, in bytes
+    pub max_size: usize,
+    /// Give some entries names TOS cannot use as is
+    pub weird_names: bool,
+    pub seed: u64,
+}
+
+impl Default for TreeSpec {
+    fn default() -> Self {
+        Self {
+            files: 4,
+            dirs: 2,
+            depth: 2,
+            min_size: 0,
+            max_size: 4096,
+            weird_names: false,
+            seed: 0,
+        }
+    }
+}
+
+/// Name of the generated entry `index` of its directory.
+///
+/// Random letters follow the index, so names of different indexes differ.
+fn entry_name(rng: &mut Rng, spec: &TreeSpec, index: usize, is_dir: bool) -> String {
+    let letters: String = (0..rng.between(1, 4))
+        .map(|_| (b'A' + rng.below(26) as u8) as char)
+        .collect();
+    let unique = format!("{}{}", index, letters);
+    if spec.weird_names && rng.chance(0.5) {
+        WEIRD_NAMES[rng.below(WEIRD_NAMES.len())].replace("{}", &unique)
+    } else if is_dir {
+        format!("D{}", unique)
+    } else {
+        format!("F{}.BIN", unique)
+    }
+}
+
+fn write_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    fs::write(path, content)?;
+    fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(MTIME_SECS))
+}
+
+/// Generate tree described by `spec` in existing directory `root` and return
+/// paths of the files, relative to it.
+pub fn write_tree(root: &Path, spec: &TreeSpec) -> io::Result<Vec<PathBuf>> {
+    let mut rng = Rng::new(spec.seed);
+    let mut files = Vec::new();
+    let mut pending = vec![(PathBuf::new(), 0)];
+
+    while let Some((dir, level)) = pending.pop() {
+        for index in 0..spec.files {
+            let path = dir.join(entry_name(&mut rng, spec, index, false));
+            let size = rng.between(spec.min_size, spec.max_size.max(spec.min_size));
+            write_file(&root.join(&path), &rng.bytes(size))?;
+            files.push(path);
+        }
+        if level < spec.depth {
+            for index in spec.files..spec.files + spec.dirs {
+                let path = dir.join(entry_name(&mut rng, spec, index, true));
+                fs::create_dir(root.join(&path))?;
+                pending.push((path, level + 1));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Generated tree in the temporary directory, removed when dropped.
+#[derive(Debug)]
+pub struct Fixture {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl Fixture {
+    /// Generate tree described by `spec`, `name` telling apart the trees of
+    /// the tests running at the same time.
+    pub fn generate(name: &str, spec: &TreeSpec) -> io::Result<Self> {
+        let root = Self::create_root(name)?;
+        let files = write_tree(&root, spec)?;
+        Ok(Self { root, files })
+    }
+
+    /// Empty directory, for tests writing their own files.
+    pub fn empty(name: &str) -> io::Result<Self> {
+        Ok(Self {
+            root: Self::create_root(name)?,
+            files: Vec::new(),
+        })
+    }
+
+    /// Directory holding a single `TEST.TXT` file of `SAMPLE_CONTENT`.
+    pub fn sample(name: &str) -> io::Result<Self> {
+        let root = Self::create_root(name)?;
+        write_file(&root.join("TEST.TXT"), SAMPLE_CONTENT)?;
+        Ok(Self {
+            root,
+            files: vec![PathBuf::from("TEST.TXT")],
+        })
+    }
+
+    fn create_root(name: &str) -> io::Result<PathBuf> {
+        let root = std::env::temp_dir().join(format!("ataridisk-{}-{}", name, process::id()));
+        match fs::remove_dir_all(&root) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        fs::create_dir_all(&root)?;
+        Ok(root)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path of a file of the tree.
+    pub fn join<P>(&self, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        self.root.join(path)
+    }
+
+    /// Generated files, relative to root.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Total size of the files.
+    pub fn size(&self) -> io::Result<usize> {
+        self.files.iter().try_fold(0, |size, file| {
+            Ok(size + fs::metadata(self.join(file))?.len() as usize)
+        })
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{layout::DiskLayout, storage::DiskStorage};
+
+    #[test]
+    fn test_generate() {
+        let spec = TreeSpec {
+            files: 3,
+            dirs: 2,
+            depth: 2,
+            min_size: 10,
+            max_size: 2000,
+            weird_names: false,
+            seed: 42,
+        };
+        let fixture = Fixture::generate("fixture", &spec).unwrap();
+        // 1 + 2 + 4 directories
+        assert_eq!(fixture.files().len(), 3 * 7);
+        for file in fixture.files() {
+            let size = fs::metadata(fixture.join(file)).unwrap().len() as usize;
+            assert!((10..=2000).contains(&size), "{:?}", file);
+        }
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(fixture.path()).unwrap();
+        assert_eq!(
+            storage.count_files().unwrap(),
+            (3 * 7, fixture.size().unwrap())
+        );
+
+        // Same spec gives the same tree
+        let other = Fixture::generate("fixture-again", &spec).unwrap();
+        assert_eq!(other.files(), fixture.files());
+        let first = &fixture.files()[0];
+        assert_eq!(
+            fs::read(other.join(first)).unwrap(),
+            fs::read(fixture.join(first)).unwrap()
+        );
+
+        let weird = Fixture::generate(
+            "fixture-weird",
+            &TreeSpec {
+                weird_names: true,
+                ..spec
+            },
+        )
+        .unwrap();
+        assert_eq!(weird.files().len(), 3 * 7);
+        assert!(weird
+            .files()
+            .iter()
+            .any(|file| !file.to_str().unwrap().is_ascii()));
+    }
+}
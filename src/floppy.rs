@@ -0,0 +1,694 @@
+//! Read Atari floppy images (`.ST`, `.MSA` and `.STX`) and copy their files
+//! in the virtual disk.
+//!
+//! Images are first decoded as a raw sector dump, then the FAT12 file system
+//! it contains is walked.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+
+use crate::{
+    backend::SectorBackend,
+    entries::parse_datetime_from_atari,
+    error::{self, SerialDiskError},
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+
+// Largest floppy geometry (over formatted high density disks)
+const MAX_TRACKS: usize = 86;
+const MAX_SECTORS_PER_TRACK: usize = 22;
+
+const MSA_MAGIC: u16 = 0x0E0F;
+const MSA_RLE_MARKER: u8 = 0xE5;
+
+const STX_MAGIC: [u8; 4] = *b"RSY\0";
+const STX_TRACK_HAS_SECTORS: u16 = 0x0001;
+const STX_SECTOR_NOT_FOUND: u8 = 0x10;
+
+const ATTR_VOLUME: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+
+macro_rules! invalid {
+    ($msg:expr) => {
+        SerialDiskError::InvalidImage($msg.to_string())
+    };
+}
+
+/// Decode any supported floppy image to raw sectors.
+///
+/// Format is detected from its content, files without known header are
+/// considered as raw `.ST` images.
+pub fn decode_image(data: &[u8]) -> error::Result<Vec<u8>> {
+    if data.starts_with(&STX_MAGIC) {
+        decode_stx(data)
+    } else if data.len() >= 2 && data[..2] == MSA_MAGIC.to_be_bytes() {
+        decode_msa(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+/// Decode a Magic Shadow Archiver image.
+pub fn decode_msa(data: &[u8]) -> error::Result<Vec<u8>> {
+    let mut reader = data;
+    if reader.read_u16::<BigEndian>()? != MSA_MAGIC {
+        return Err(invalid!("invalid MSA header"));
+    }
+
+    let sectors_per_track = reader.read_u16::<BigEndian>()? as usize;
+    let sides = reader.read_u16::<BigEndian>()? as usize + 1;
+    let start_track = reader.read_u16::<BigEndian>()? as usize;
+    let end_track = reader.read_u16::<BigEndian>()? as usize;
+    if sides > 2
+        || start_track > end_track
+        || end_track >= MAX_TRACKS
+        || sectors_per_track == 0
+        || sectors_per_track > MAX_SECTORS_PER_TRACK
+    {
+        return Err(invalid!("invalid MSA geometry"));
+    }
+
+    let track_size = sectors_per_track * SECTOR_SIZE;
+    let mut image = vec![0; (end_track + 1) * sides * track_size];
+
+    for track in start_track..=end_track {
+        for side in 0..sides {
+            let len = reader.read_u16::<BigEndian>()? as usize;
+            if reader.len() < len {
+                return Err(invalid!("truncated MSA track"));
+            }
+            let (packed, remaining) = reader.split_at(len);
+            reader = remaining;
+
+            let offset = (track * sides + side) * track_size;
+            let output = &mut image[offset..offset + track_size];
+            if len == track_size {
+                output.copy_from_slice(packed);
+            } else {
+                unpack_msa_track(packed, output)?;
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+fn unpack_msa_track(mut packed: &[u8], output: &mut [u8]) -> error::Result<()> {
+    let mut pos = 0;
+
+    while !packed.is_empty() {
+        let byte = packed.read_u8()?;
+        let (value, count) = if byte == MSA_RLE_MARKER {
+            let value = packed.read_u8()?;
+            (value, packed.read_u16::<BigEndian>()? as usize)
+        } else {
+            (byte, 1)
+        };
+
+        if pos + count > output.len() {
+            return Err(invalid!("MSA track overflow"));
+        }
+        output[pos..pos + count].iter_mut().for_each(|b| *b = value);
+        pos += count;
+    }
+
+    if pos != output.len() {
+        return Err(invalid!("truncated MSA track content"));
+    }
+
+    Ok(())
+}
+
+/// Decode a Pasti image.
+///
+/// Only sector content is kept: copy protections relying on timings or
+/// fuzzy bits are lost, which is fine to extract files.
+pub fn decode_stx(data: &[u8]) -> error::Result<Vec<u8>> {
+    if !data.starts_with(&STX_MAGIC) || data.len() < 16 {
+        return Err(invalid!("invalid STX header"));
+    }
+    let track_count = data[10] as usize;
+
+    // (track, side, sector number) -> content
+    let mut sectors = BTreeMap::new();
+    let mut offset = 16;
+
+    for _ in 0..track_count {
+        let mut descriptor = data
+            .get(offset..offset + 16)
+            .ok_or_else(|| invalid!("truncated STX track"))?;
+        let record_size = descriptor.read_u32::<LittleEndian>()? as usize;
+        let fuzzy_count = descriptor.read_u32::<LittleEndian>()? as usize;
+        let sector_count = descriptor.read_u16::<LittleEndian>()? as usize;
+        let flags = descriptor.read_u16::<LittleEndian>()?;
+        let _track_length = descriptor.read_u16::<LittleEndian>()?;
+        let track_number = descriptor.read_u8()?;
+
+        let record = data
+            .get(offset..offset + record_size)
+            .ok_or_else(|| invalid!("truncated STX track"))?;
+        let track = track_number & 0x7F;
+        let side = track_number >> 7;
+        if sector_count > MAX_SECTORS_PER_TRACK {
+            return Err(invalid!("invalid STX geometry"));
+        }
+
+        if flags & STX_TRACK_HAS_SECTORS == 0 {
+            // Standard track: sectors are stored one after the other
+            for index in 0..sector_count {
+                let start = 16 + index * SECTOR_SIZE;
+                let content = record
+                    .get(start..start + SECTOR_SIZE)
+                    .ok_or_else(|| invalid!("truncated STX sector"))?;
+                sectors.insert((track, side, index as u8 + 1), content.to_vec());
+            }
+        } else {
+            let data_start = 16 + sector_count * 16 + fuzzy_count;
+
+            for index in 0..sector_count {
+                let mut sector = record
+                    .get(16 + index * 16..16 + (index + 1) * 16)
+                    .ok_or_else(|| invalid!("truncated STX track"))?;
+                let data_offset = sector.read_u32::<LittleEndian>()? as usize;
+                let _bit_position = sector.read_u16::<LittleEndian>()?;
+                let _read_time = sector.read_u16::<LittleEndian>()?;
+                let _id_track = sector.read_u8()?;
+                let _id_head = sector.read_u8()?;
+                let id_number = sector.read_u8()?;
+                let id_size = sector.read_u8()?;
+                let _id_crc = sector.read_u16::<LittleEndian>()?;
+                let fdc_status = sector.read_u8()?;
+
+                if fdc_status & STX_SECTOR_NOT_FOUND != 0 {
+                    continue;
+                }
+
+                let size = 128 << (id_size & 0x03);
+                let start = data_start + data_offset;
+                let content = record
+                    .get(start..start + size)
+                    .ok_or_else(|| invalid!("truncated STX sector"))?;
+                sectors.insert((track, side, id_number), content.to_vec());
+            }
+        }
+
+        offset += record_size;
+    }
+
+    // Rebuild a raw image from sectors found
+    let sides = sectors.keys().map(|(_, side, _)| *side).max().unwrap_or(0) as usize + 1;
+    let sectors_per_track = sectors.keys().map(|(_, _, n)| *n).max().unwrap_or(0) as usize;
+    let tracks = sectors.keys().map(|(t, _, _)| *t).max().unwrap_or(0) as usize + 1;
+
+    let mut image = vec![0; tracks * sides * sectors_per_track * SECTOR_SIZE];
+    for ((track, side, number), content) in sectors {
+        if number == 0 || content.len() != SECTOR_SIZE {
+            continue;
+        }
+        let index =
+            (track as usize * sides + side as usize) * sectors_per_track + number as usize - 1;
+        image[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE].copy_from_slice(&content);
+    }
+
+    Ok(image)
+}
+
+/// File or directory found on a floppy.
+#[derive(Debug, Clone)]
+pub struct FloppyEntry {
+    pub name: String,
+    pub extension: String,
+    pub is_dir: bool,
+    pub mtime: NaiveDateTime,
+    cluster_index: u16,
+    size: usize,
+}
+
+/// FAT12 file system of a decoded floppy image.
+#[derive(Debug)]
+pub struct FloppyFs {
+    image: Vec<u8>,
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    fat_offset: usize,
+    root_sector: usize,
+    root_entries: usize,
+    data_sector: usize,
+}
+
+impl FloppyFs {
+    /// Read boot sector of a raw image.
+    pub fn new(image: Vec<u8>) -> error::Result<Self> {
+        if image.len() < SECTOR_SIZE {
+            return Err(invalid!("image too small"));
+        }
+
+        let mut bpb = &image[0x0B..];
+        let bytes_per_sector = bpb.read_u16::<LittleEndian>()? as usize;
+        let sectors_per_cluster = bpb.read_u8()? as usize;
+        let reserved_sectors = bpb.read_u16::<LittleEndian>()? as usize;
+        let fat_count = bpb.read_u8()? as usize;
+        let root_entries = bpb.read_u16::<LittleEndian>()? as usize;
+        let _sector_count = bpb.read_u16::<LittleEndian>()?;
+        let _media = bpb.read_u8()?;
+        let sectors_per_fat = bpb.read_u16::<LittleEndian>()? as usize;
+
+        if bytes_per_sector == 0 || !bytes_per_sector.is_multiple_of(DIR_ENTRY_SIZE) {
+            return Err(invalid!("invalid bytes per sector"));
+        }
+        if sectors_per_cluster == 0 || fat_count == 0 || sectors_per_fat == 0 {
+            return Err(invalid!("invalid boot sector"));
+        }
+
+        let root_sector = reserved_sectors + fat_count * sectors_per_fat;
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE).div_ceil(bytes_per_sector);
+
+        Ok(Self {
+            image,
+            bytes_per_sector,
+            sectors_per_cluster,
+            fat_offset: reserved_sectors * bytes_per_sector,
+            root_sector,
+            root_entries,
+            data_sector: root_sector + root_sectors,
+        })
+    }
+
+    pub fn root_entries(&self) -> error::Result<Vec<FloppyEntry>> {
+        let start = self.root_sector * self.bytes_per_sector;
+        let raw = self
+            .image
+            .get(start..start + self.root_entries * DIR_ENTRY_SIZE)
+            .ok_or_else(|| invalid!("root directory out of image"))?;
+
+        Ok(parse_entries(raw))
+    }
+
+    pub fn read_dir(&self, entry: &FloppyEntry) -> error::Result<Vec<FloppyEntry>> {
+        assert!(entry.is_dir, "Cannot read file as a dir");
+        Ok(parse_entries(&self.read_chain(entry.cluster_index)?))
+    }
+
+    pub fn read_file(&self, entry: &FloppyEntry) -> error::Result<Vec<u8>> {
+        assert!(!entry.is_dir, "Cannot read dir as a file");
+        if entry.size == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut content = self.read_chain(entry.cluster_index)?;
+        if content.len() < entry.size {
+            return Err(invalid!(format!("truncated file {}", entry.name)));
+        }
+        content.truncate(entry.size);
+
+        Ok(content)
+    }
+
+    fn fat_entry(&self, cluster_index: u16) -> error::Result<u16> {
+        let offset = self.fat_offset + cluster_index as usize * 3 / 2;
+        let bytes = self
+            .image
+            .get(offset..offset + 2)
+            .ok_or_else(|| invalid!("FAT out of image"))?;
+        let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+
+        Ok(if cluster_index.is_multiple_of(2) {
+            value & 0x0FFF
+        } else {
+            value >> 4
+        })
+    }
+
+    fn read_chain(&self, first_cluster: u16) -> error::Result<Vec<u8>> {
+        let cluster_size = self.sectors_per_cluster * self.bytes_per_sector;
+        let max_clusters = self.image.len() / cluster_size;
+
+        let mut content = Vec::new();
+        let mut cluster_index = first_cluster;
+
+        while (2..0xFF0).contains(&cluster_index) {
+            if content.len() / cluster_size > max_clusters {
+                return Err(invalid!("looping cluster chain"));
+            }
+
+            let sector = self.data_sector + (cluster_index as usize - 2) * self.sectors_per_cluster;
+            let start = sector * self.bytes_per_sector;
+            let cluster = self
+                .image
+                .get(start..start + cluster_size)
+                .ok_or_else(|| invalid!("cluster out of image"))?;
+            content.extend_from_slice(cluster);
+
+            cluster_index = self.fat_entry(cluster_index)?;
+        }
+
+        Ok(content)
+    }
+}
+
+fn parse_entries(raw: &[u8]) -> Vec<FloppyEntry> {
+    // DOS epoch, used when entries have no valid date
+    let default_mtime = NaiveDate::from_ymd(1980, 1, 1).and_hms(0, 0, 0);
+
+    raw.chunks_exact(DIR_ENTRY_SIZE)
+        .take_while(|entry| entry[0] != ENTRY_END)
+        .filter(|entry| entry[0] != ENTRY_DELETED && entry[0] != b'.')
+        .filter(|entry| entry[11] & ATTR_VOLUME == 0)
+        .map(|entry| {
+            let field = |range: std::ops::Range<usize>| {
+                String::from_utf8_lossy(&entry[range]).trim().to_string()
+            };
+            let u16_at = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+
+            FloppyEntry {
+                name: field(0..8),
+                extension: field(8..11),
+                is_dir: entry[11] & ATTR_DIRECTORY != 0,
+                mtime: parse_datetime_from_atari(u16_at(22), u16_at(24)).unwrap_or(default_mtime),
+                cluster_index: u16_at(26),
+                size: u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]]) as usize,
+            }
+        })
+        .collect()
+}
+
+/// Copy every file of a floppy image in a virtual disk sub directory.
+///
+/// Target is a `/` separated path from disk root, missing directories are
+/// created so several disks can share the same parent.
+pub fn import_floppy<P, B>(storage: &mut DiskStorage<B>, path: P, target: &str) -> error::Result<()>
+where
+    P: AsRef<Path>,
+    B: SectorBackend,
+{
+    let image = decode_image(&fs::read(path)?)?;
+    import_floppy_image(storage, image, target)
+}
+
+pub fn import_floppy_image<B>(
+    storage: &mut DiskStorage<B>,
+    image: Vec<u8>,
+    target: &str,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    let floppy = FloppyFs::new(image)?;
+    let now = Local::now().naive_local();
+
+    let mut parent_index = ROOT_INDEX;
+    for component in target.split(['/', '\\']) {
+        if component.is_empty() {
+            continue;
+        }
+        let (name, ext) = crate::dos::as_valid_file_components(component)?;
+        parent_index = storage.find_or_add_virtual_directory(&name, &ext, now, parent_index)?;
+    }
+
+    copy_entries(storage, &floppy, &floppy.root_entries()?, parent_index);
+    Ok(())
+}
+
+fn copy_entries<B>(
+    storage: &mut DiskStorage<B>,
+    floppy: &FloppyFs,
+    entries: &[FloppyEntry],
+    parent_index: u16,
+) where
+    B: SectorBackend,
+{
+    for entry in entries {
+        let result = if entry.is_dir {
+            storage
+                .add_virtual_directory(&entry.name, &entry.extension, entry.mtime, parent_index)
+                .and_then(|index| {
+                    copy_entries(storage, floppy, &floppy.read_dir(entry)?, index);
+                    Ok(())
+                })
+        } else {
+            floppy.read_file(entry).and_then(|content| {
+                storage.add_virtual_file(
+                    &entry.name,
+                    &entry.extension,
+                    entry.mtime,
+                    &content,
+                    parent_index,
+                )
+            })
+        };
+
+        if let Err(e) = result {
+            log::warn!(
+                "Cannot copy {}.{} from floppy (error: {})",
+                entry.name,
+                entry.extension,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::DiskLayout;
+
+    const SECTORS_PER_TRACK: usize = 9;
+    const SIDES: usize = 2;
+    const TRACKS: usize = 2;
+
+    fn set_fat12(image: &mut [u8], cluster_index: usize, value: u16) {
+        let offset = SECTOR_SIZE + cluster_index * 3 / 2;
+        if cluster_index.is_multiple_of(2) {
+            image[offset] = value as u8;
+            image[offset + 1] = (image[offset + 1] & 0xF0) | (value >> 8) as u8;
+        } else {
+            image[offset] = (image[offset] & 0x0F) | ((value << 4) as u8);
+            image[offset + 1] = (value >> 4) as u8;
+        }
+    }
+
+    fn set_entry(
+        image: &mut [u8],
+        sector: usize,
+        index: usize,
+        name: &[u8; 11],
+        attr: u8,
+        cluster: u16,
+        size: u32,
+    ) {
+        let offset = sector * SECTOR_SIZE + index * DIR_ENTRY_SIZE;
+        let entry = &mut image[offset..offset + DIR_ENTRY_SIZE];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attr;
+        // 2021-08-01 12:00:00
+        entry[22..24].copy_from_slice(&(12u16 << 11).to_le_bytes());
+        entry[24..26].copy_from_slice(&((41u16 << 9) | (8 << 5) | 1).to_le_bytes());
+        entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Tiny floppy containing `README.TXT` and `GAMES\DUNG.PRG`.
+    ///
+    /// 1 reserved sector, 2 FATs of 1 sector, 1 root sector, 2 sectors per cluster.
+    fn test_image() -> Vec<u8> {
+        let mut image = vec![0; TRACKS * SIDES * SECTORS_PER_TRACK * SECTOR_SIZE];
+        image[0x0B..0x0D].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        image[0x0D] = 2;
+        image[0x0E..0x10].copy_from_slice(&1u16.to_le_bytes());
+        image[0x10] = 2;
+        image[0x11..0x13].copy_from_slice(&16u16.to_le_bytes());
+        let sector_count = (image.len() / SECTOR_SIZE) as u16;
+        image[0x13..0x15].copy_from_slice(&sector_count.to_le_bytes());
+        image[0x15] = 0xF9;
+        image[0x16..0x18].copy_from_slice(&1u16.to_le_bytes());
+
+        set_fat12(&mut image, 2, 0xFFF);
+        set_fat12(&mut image, 3, 0xFFF);
+        set_fat12(&mut image, 4, 5);
+        set_fat12(&mut image, 5, 0xFFF);
+
+        set_entry(&mut image, 3, 0, b"BACKUP     ", ATTR_VOLUME, 0, 0);
+        set_entry(&mut image, 3, 1, b"GAMES      ", ATTR_DIRECTORY, 2, 0);
+        set_entry(&mut image, 3, 2, b"README  TXT", 0x20, 3, 11);
+        set_entry(&mut image, 3, 3, b"\xE5ELETED TXT", 0, 0, 0);
+
+        set_entry(&mut image, 4, 0, b".          ", ATTR_DIRECTORY, 2, 0);
+        set_entry(&mut image, 4, 1, b"..         ", ATTR_DIRECTORY, 0, 0);
+        set_entry(&mut image, 4, 2, b"DUNG    PRG", 0, 4, 1500);
+
+        // Data starts at sector 4, cluster `n` is at sector 4 + (n - 2) * 2
+        image[6 * SECTOR_SIZE..6 * SECTOR_SIZE + 11].copy_from_slice(b"Hello Atari");
+        for (i, b) in image[8 * SECTOR_SIZE..8 * SECTOR_SIZE + 1500]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = i as u8;
+        }
+
+        image
+    }
+
+    fn encode_msa(image: &[u8]) -> Vec<u8> {
+        let mut msa = vec![];
+        for value in [
+            MSA_MAGIC,
+            SECTORS_PER_TRACK as u16,
+            SIDES as u16 - 1,
+            0,
+            TRACKS as u16 - 1,
+        ] {
+            msa.extend_from_slice(&value.to_be_bytes());
+        }
+
+        for track in image.chunks(SECTORS_PER_TRACK * SECTOR_SIZE) {
+            let mut packed = vec![];
+            for run in track.chunk_by(|a, b| a == b) {
+                if run.len() > 4 || run[0] == MSA_RLE_MARKER {
+                    packed.push(MSA_RLE_MARKER);
+                    packed.push(run[0]);
+                    packed.extend_from_slice(&(run.len() as u16).to_be_bytes());
+                } else {
+                    packed.extend_from_slice(run);
+                }
+            }
+
+            let track = if packed.len() < track.len() {
+                &packed[..]
+            } else {
+                track
+            };
+            msa.extend_from_slice(&(track.len() as u16).to_be_bytes());
+            msa.extend_from_slice(track);
+        }
+
+        msa
+    }
+
+    fn encode_stx(image: &[u8]) -> Vec<u8> {
+        let mut stx = STX_MAGIC.to_vec();
+        stx.extend_from_slice(&[3, 0, 1, 0, 0, 0]);
+        stx.push((TRACKS * SIDES) as u8);
+        stx.extend_from_slice(&[2, 0, 0, 0, 0]);
+
+        for (index, track) in image.chunks(SECTORS_PER_TRACK * SECTOR_SIZE).enumerate() {
+            let track_number = ((index / SIDES) | ((index % SIDES) << 7)) as u8;
+
+            // First track use sector descriptors (with sectors stored in reverse order),
+            // other ones are standard tracks
+            let with_descriptors = index == 0;
+            let mut record = vec![];
+            if with_descriptors {
+                for number in 0..SECTORS_PER_TRACK {
+                    let data_offset = (SECTORS_PER_TRACK - 1 - number) * SECTOR_SIZE;
+                    record.extend_from_slice(&(data_offset as u32).to_le_bytes());
+                    record.extend_from_slice(&[0; 4]);
+                    record.extend_from_slice(&[0, 0, number as u8 + 1, 2, 0, 0, 0, 0]);
+                }
+                for sector in track.chunks(SECTOR_SIZE).rev() {
+                    record.extend_from_slice(sector);
+                }
+            } else {
+                record.extend_from_slice(track);
+            }
+
+            stx.extend_from_slice(&(record.len() as u32 + 16).to_le_bytes());
+            stx.extend_from_slice(&0u32.to_le_bytes());
+            stx.extend_from_slice(&(SECTORS_PER_TRACK as u16).to_le_bytes());
+            stx.extend_from_slice(&(with_descriptors as u16).to_le_bytes());
+            stx.extend_from_slice(&6250u16.to_le_bytes());
+            stx.push(track_number);
+            stx.push(0);
+            stx.extend_from_slice(&record);
+        }
+
+        stx
+    }
+
+    #[test]
+    fn test_decode_msa() {
+        let image = test_image();
+        let msa = encode_msa(&image);
+        assert!(msa.len() < image.len());
+        assert_eq!(decode_image(&msa).unwrap(), image);
+    }
+
+    #[test]
+    fn test_decode_stx() {
+        let image = test_image();
+        assert_eq!(decode_image(&encode_stx(&image)).unwrap(), image);
+    }
+
+    #[test]
+    fn test_invalid_image() {
+        assert!(decode_msa(&[0x0E, 0x0F, 0x00]).is_err());
+        assert!(decode_stx(b"RSY\0").is_err());
+
+        // Geometry too large for a floppy
+        let mut msa = encode_msa(&test_image());
+        msa[2..4].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        assert!(decode_msa(&msa).is_err());
+        let mut msa = encode_msa(&test_image());
+        msa[8..10].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        assert!(decode_msa(&msa).is_err());
+        let mut stx = encode_stx(&test_image());
+        stx[24..26].copy_from_slice(&300u16.to_le_bytes());
+        assert!(decode_stx(&stx).is_err());
+
+        // Sector descriptors out of track record
+        let mut stx = encode_stx(&test_image());
+        stx[16..20].copy_from_slice(&32u32.to_le_bytes());
+        assert!(decode_stx(&stx).is_err());
+        assert!(FloppyFs::new(vec![0; SECTOR_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_floppy_fs() {
+        let floppy = FloppyFs::new(test_image()).unwrap();
+
+        let root = floppy.root_entries().unwrap();
+        let names: Vec<&str> = root.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["GAMES", "README"]);
+        assert!(root[0].is_dir);
+        assert_eq!(floppy.read_file(&root[1]).unwrap(), b"Hello Atari");
+
+        let games = floppy.read_dir(&root[0]).unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].extension, "PRG");
+        let content = floppy.read_file(&games[0]).unwrap();
+        assert_eq!(content.len(), 1500);
+        assert_eq!(content[1499], (1499 % 256) as u8);
+    }
+
+    #[test]
+    fn test_import_floppy() {
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        import_floppy_image(&mut storage, test_image(), "GAMES/DISK1").unwrap();
+        import_floppy_image(&mut storage, test_image(), "GAMES/DISK2/").unwrap();
+
+        // Both disks share the same parent
+        let root = storage.list_root_file_infos();
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].filename().unwrap(), "GAMES");
+
+        let disks = storage.read_dir(&root[0]).unwrap();
+        let names: Vec<String> = disks.iter().map(|f| f.filename().unwrap()).collect();
+        assert_eq!(names, [".", "..", "DISK1", "DISK2"]);
+
+        let disk1 = storage.read_dir(&disks[2]).unwrap();
+        assert_eq!(disk1[2].filename().unwrap(), "GAMES");
+        assert_eq!(disk1[3].filename().unwrap(), "README.TXT");
+        assert_eq!(storage.read_file(&disk1[3]).unwrap(), b"Hello Atari");
+
+        let games = storage.read_dir(&disk1[2]).unwrap();
+        assert_eq!(games[2].filename().unwrap(), "DUNG.PRG");
+        assert_eq!(storage.read_file(&games[2]).unwrap().len(), 1500);
+    }
+}
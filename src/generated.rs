@@ -0,0 +1,118 @@
+//! Files whose content is the output of a host command (ex: `NEWS.TXT` from
+//! a `curl` script), making the disk a gateway to the host.
+//!
+//! Commands are run with `sh -c` when serving starts, then again when Atari
+//! reads the directory of their file, at most once per `REFRESH_DELAY`.
+//! Content is `stdout` of the command, as is.
+
+use std::{
+    collections::BTreeMap,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// Minimum delay between two runs of the command of a file, as GEMDOS reads
+/// a directory several times to open a single file.
+pub const REFRESH_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct GeneratedFile {
+    /// `\` separated path from disk root
+    pub disk_path: String,
+    /// Shell command line
+    pub command: String,
+    last_run: Option<Instant>,
+    /// Content served since last run
+    content: Option<Vec<u8>>,
+}
+
+impl GeneratedFile {
+    pub fn new(disk_path: &str, command: &str) -> Self {
+        Self {
+            disk_path: disk_path.replace('/', "\\"),
+            command: command.to_string(),
+            last_run: None,
+            content: None,
+        }
+    }
+
+    /// Files of config, by disk path.
+    pub fn from_config(files: &BTreeMap<String, String>) -> Vec<Self> {
+        files
+            .iter()
+            .map(|(disk_path, command)| Self::new(disk_path, command))
+            .collect()
+    }
+
+    /// Directory path and file name.
+    pub fn split_path(&self) -> (&str, &str) {
+        self.disk_path
+            .rsplit_once('\\')
+            .unwrap_or(("", &self.disk_path))
+    }
+
+    /// Check if command can be run again.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.last_run
+            .is_none_or(|last_run| now.duration_since(last_run) >= REFRESH_DELAY)
+    }
+
+    /// Run command and return its output, if it has changed since previous
+    /// run.
+    ///
+    /// Failures are logged and keep the content already served.
+    pub fn generate(&mut self, now: Instant) -> Option<Vec<u8>> {
+        self.last_run = Some(now);
+        log::debug!("Generating {} ({:?})", self.disk_path, self.command);
+
+        let output = match Command::new("sh").arg("-c").arg(&self.command).output() {
+            Ok(output) => output,
+            Err(e) => {
+                log::warn!("Cannot run command of {} (error: {})", self.disk_path, e);
+                return None;
+            }
+        };
+        if !output.status.success() {
+            log::warn!(
+                "Command of {} failed ({}): {}",
+                self.disk_path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+
+        if self.content.as_ref() == Some(&output.stdout) {
+            return None;
+        }
+        self.content = Some(output.stdout.clone());
+        Some(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let mut file = GeneratedFile::new("INFO/NEWS.TXT", "echo hello");
+        assert_eq!(file.split_path(), ("INFO", "NEWS.TXT"));
+        assert_eq!(
+            GeneratedFile::new("NEWS.TXT", "").split_path(),
+            ("", "NEWS.TXT")
+        );
+
+        let now = Instant::now();
+        assert!(file.is_due(now));
+        assert_eq!(file.generate(now), Some(b"hello\n".to_vec()));
+        assert!(!file.is_due(now + Duration::from_secs(1)));
+        assert!(file.is_due(now + REFRESH_DELAY));
+
+        // Same output, nothing to change
+        assert_eq!(file.generate(now + REFRESH_DELAY), None);
+
+        file.command = "exit 1".to_string();
+        assert_eq!(file.generate(now), None);
+    }
+}
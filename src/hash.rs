@@ -0,0 +1,282 @@
+//! Content hashes of the files stored on a virtual disk.
+
+use std::fmt::Write;
+
+use crate::{backend::SectorBackend, entries::FileInfo, error, storage::DiskStorage};
+
+/// Minimal SHA-1 implementation (FIPS 180-1).
+///
+/// SHA-1 is only used to identify content, not for security.
+#[derive(Debug, Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self {
+            state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+}
+
+impl Sha1 {
+    pub fn digest(content: &[u8]) -> [u8; 20] {
+        let mut sha1 = Self::default();
+        sha1.update(content);
+        sha1.finish()
+    }
+
+    pub fn update(&mut self, mut content: &[u8]) {
+        self.length += content.len() as u64;
+
+        if !self.buffer.is_empty() {
+            let missing = (64 - self.buffer.len()).min(content.len());
+            self.buffer.extend_from_slice(&content[..missing]);
+            content = &content[missing..];
+
+            if self.buffer.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.buffer);
+            self.process(&block);
+        }
+
+        let mut blocks = content.chunks_exact(64);
+        for block in &mut blocks {
+            self.process(block);
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bit_length = self.length.wrapping_mul(8);
+
+        let mut padding = vec![0x80];
+        padding.resize((119 - (self.length % 64) as usize) % 64 + 1, 0);
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+        self.update(&padding);
+        debug_assert!(self.buffer.is_empty());
+
+        let mut result = [0; 20];
+        for (chunk, value) in result.chunks_mut(4).zip(&self.state) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+        result
+    }
+
+    fn process(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// Format bytes as lowercase hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(output, "{:02x}", b).unwrap();
+    }
+    output
+}
+
+/// Compare secrets in constant time, to not tell how close a guess is.
+///
+/// Only a length mismatch returns early.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Hashes of a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    /// `\` separated path from disk root
+    pub path: String,
+    pub size: usize,
+    pub sha1: [u8; 20],
+    pub crc32: u32,
+}
+
+/// Hashes of every file stored on a disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashReport {
+    /// Files sorted by path
+    pub files: Vec<FileHash>,
+    /// Hash of every file path and content
+    pub disk_sha1: [u8; 20],
+}
+
+impl<B> DiskStorage<B>
+where
+    B: SectorBackend,
+{
+    /// Compute hashes of every file stored on the disk.
+    ///
+    /// Disk hash only depends on file paths and content, so disks built
+    /// with different layouts or import orders give the same hash.
+    pub fn hash_report(&self) -> error::Result<HashReport> {
+        let mut files = Vec::new();
+        hash_files(self, &self.list_root_file_infos(), "", &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut disk_sha1 = Sha1::default();
+        for file in &files {
+            disk_sha1.update(file.path.as_bytes());
+            disk_sha1.update(&[0]);
+            disk_sha1.update(&file.sha1);
+        }
+
+        Ok(HashReport {
+            files,
+            disk_sha1: disk_sha1.finish(),
+        })
+    }
+}
+
+fn hash_files<B>(
+    storage: &DiskStorage<B>,
+    file_infos: &[FileInfo],
+    prefix: &str,
+    files: &mut Vec<FileHash>,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    for file_info in file_infos {
+        let path = format!("{}{}", prefix, file_info.filename()?);
+
+        if file_info.is_dir() {
+            // Skip `.` and `..`
+            let children: Vec<FileInfo> =
+                storage.read_dir(file_info)?.into_iter().skip(2).collect();
+            hash_files(storage, &children, &format!("{}\\", path), files)?;
+        } else {
+            let content = storage.read_file(file_info)?;
+            let mut crc = crc_any::CRC::crc32();
+            crc.digest(&content);
+
+            files.push(FileHash {
+                path,
+                size: content.len(),
+                sha1: Sha1::digest(&content),
+                crc32: crc.get_crc() as u32,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout, storage::ROOT_INDEX};
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            to_hex(&Sha1::digest(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            to_hex(&Sha1::digest(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            to_hex(&Sha1::digest(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(!constant_time_eq(b"s3cret", b"s3creT"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cre"));
+    }
+
+    #[test]
+    fn test_sha1_update() {
+        let content = vec![b'a'; 1000];
+        let mut sha1 = Sha1::default();
+        for chunk in content.chunks(7) {
+            sha1.update(chunk);
+        }
+        assert_eq!(sha1.finish(), Sha1::digest(&content));
+    }
+
+    #[test]
+    fn test_hash_report() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let sample = Fixture::sample("hash").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(sample.path()).unwrap();
+        let dir = storage
+            .add_virtual_directory("SUB", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("ABC", "", mtime, b"abc", dir)
+            .unwrap();
+
+        let report = storage.hash_report().unwrap();
+        let paths: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["SUB\\ABC", "TEST.TXT"]);
+        assert_eq!(
+            to_hex(&report.files[0].sha1),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(report.files[0].crc32, 0x352441C2);
+
+        // Same content imported in another order give the same disk hash
+        let mut other = DiskStorage::new(DiskLayout::default());
+        let dir = other
+            .add_virtual_directory("SUB", "", mtime, ROOT_INDEX)
+            .unwrap();
+        other
+            .add_virtual_file("ABC", "", mtime, b"abc", dir)
+            .unwrap();
+        other.import_path(sample.path()).unwrap();
+        assert_eq!(other.hash_report().unwrap(), report);
+    }
+}
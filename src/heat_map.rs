@@ -0,0 +1,232 @@
+//! Reads and writes of each sector, kept across runs, to see what Atari
+//! actually touches (ex: which sectors are worth prefetching or pinning).
+//!
+//! Counts come from finished read and write transactions. They are exported
+//! as CSV, or as a PNG image with one pixel per sector, in disk order from
+//! top left: green for reads, red for writes, brighter when more frequent.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::{self, Notice, TransactionKind};
+
+/// Sectors on each line of the image.
+pub const PNG_WIDTH: usize = 256;
+
+/// Color of sectors never touched.
+const UNTOUCHED: [u8; 3] = [0x20, 0x20, 0x20];
+
+/// PNG file signature.
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Largest stored deflate block.
+const MAX_STORED_LEN: usize = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SectorCounts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HeatMap {
+    /// Counts of touched sectors, by index
+    pub sectors: BTreeMap<u16, SectorCounts>,
+}
+
+impl HeatMap {
+    /// Load counts, starting from scratch if file is missing or invalid.
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::warn!("Cannot read heat map {:?} (error: {})", path, e);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            log::warn!("Ignoring invalid heat map {:?} (error: {})", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let content = serde_json::to_string(self)?;
+        fs::write(path, content)
+    }
+
+    /// Count sectors of the read and write transactions from now on.
+    pub fn record_session(heat_map: &Arc<Mutex<Self>>) {
+        let heat_map = heat_map.clone();
+        transaction::subscribe(move |notice| heat_map.lock().unwrap().record(notice));
+    }
+
+    fn record(&mut self, notice: &Notice) {
+        let record = match notice {
+            Notice::Finished(record) if record.success => record,
+            _ => return,
+        };
+        let (index, count) = match record.sectors {
+            Some(sectors) => sectors,
+            None => return,
+        };
+
+        for sector in index..index.saturating_add(count) {
+            let counts = self.sectors.entry(sector).or_default();
+            match record.kind {
+                TransactionKind::Read => counts.reads += 1,
+                TransactionKind::Write => counts.writes += 1,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("sector,reads,writes\n");
+        for (sector, counts) in &self.sectors {
+            let _ = writeln!(csv, "{},{},{}", sector, counts.reads, counts.writes);
+        }
+        csv
+    }
+
+    /// Image of the sectors up to the last touched one.
+    pub fn to_png(&self) -> Vec<u8> {
+        let sector_count = self
+            .sectors
+            .keys()
+            .last()
+            .map_or(0, |&last| last as usize + 1);
+        let height = sector_count.div_ceil(PNG_WIDTH).max(1);
+        let max_reads = self.sectors.values().map(|c| c.reads).max().unwrap_or(0);
+        let max_writes = self.sectors.values().map(|c| c.writes).max().unwrap_or(0);
+
+        let mut pixels = vec![UNTOUCHED; PNG_WIDTH * height];
+        for (&sector, counts) in &self.sectors {
+            pixels[sector as usize] = [
+                intensity(counts.writes, max_writes),
+                intensity(counts.reads, max_reads),
+                0x00,
+            ];
+        }
+        encode_png(PNG_WIDTH, height, &pixels)
+    }
+}
+
+/// Channel value of a count, on a log scale so rare accesses still show.
+fn intensity(count: u64, max: u64) -> u8 {
+    if count == 0 {
+        return 0x00;
+    }
+    let scale = (count as f64).ln_1p() / (max as f64).ln_1p();
+    (0x40 as f64 + scale * (0xFF - 0x40) as f64) as u8
+}
+
+/// Encode RGB pixels as a PNG image, with uncompressed deflate blocks.
+fn encode_png(width: usize, height: usize, pixels: &[[u8; 3]]) -> Vec<u8> {
+    // Each line starts with its filter type (none)
+    let mut raw = Vec::with_capacity(height * (1 + width * 3));
+    for line in pixels.chunks(width) {
+        raw.push(0x00);
+        raw.extend(line.iter().flatten());
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(MAX_STORED_LEN).count();
+    for (i, block) in raw.chunks(MAX_STORED_LEN).enumerate() {
+        let len = block.len() as u16;
+        zlib.push((i + 1 == blocks) as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits RGB, default compression, filtering and no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    for (kind, data) in [(b"IHDR", &header), (b"IDAT", &zlib), (b"IEND", &Vec::new())] {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let mut crc = crc_any::CRC::crc32();
+        crc.digest(kind);
+        crc.digest(data);
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        png.extend_from_slice(&(crc.get_crc() as u32).to_be_bytes());
+    }
+    png
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{inflate, transaction::TransactionRecord};
+
+    #[test]
+    fn test_record_and_export() {
+        let finished = |kind, sectors, success| {
+            Notice::Finished(TransactionRecord {
+                id: 0,
+                kind,
+                sectors: Some(sectors),
+                bytes: 0,
+                duration: Duration::ZERO,
+                success,
+            })
+        };
+
+        let mut heat_map = HeatMap::default();
+        heat_map.record(&finished(TransactionKind::Read, (2, 2), true));
+        heat_map.record(&finished(TransactionKind::Read, (3, 1), true));
+        heat_map.record(&finished(TransactionKind::Write, (3, 1), true));
+        heat_map.record(&finished(TransactionKind::Write, (9, 1), false));
+        assert_eq!(heat_map.to_csv(), "sector,reads,writes\n2,1,0\n3,2,1\n");
+
+        let reloaded: HeatMap =
+            serde_json::from_str(&serde_json::to_string(&heat_map).unwrap()).unwrap();
+        assert_eq!(reloaded, heat_map);
+
+        // Image data inflates back to filtered lines of RGB pixels
+        let png = heat_map.to_png();
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(png[12..16], *b"IHDR");
+        let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(png[37..41], *b"IDAT");
+        let zlib = &png[41..41 + idat_len];
+        let raw = inflate::inflate(&zlib[2..zlib.len() - 4], 1 + PNG_WIDTH * 3).unwrap();
+        assert_eq!(raw.len(), 1 + PNG_WIDTH * 3);
+        assert_eq!(raw[1..4], UNTOUCHED);
+        assert_eq!(raw[1 + 3 * 3..][..3], [0xFF, 0xFF, 0x00]);
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&raw).to_be_bytes());
+    }
+}
@@ -0,0 +1,227 @@
+//! Shell commands run on key server events.
+//!
+//! Commands are run with `sh -c` and get event details from `ATARIDISK_*`
+//! environment variables, so they can send desktop notifications, play a
+//! sound, start a backup...
+
+use std::{
+    process::Command,
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::transaction::{self, Notice, TransactionKind, TransactionRecord};
+
+/// Time without writes after which `on_write_complete` runs, so saving a
+/// file runs it once rather than for every write command.
+pub const WRITE_IDLE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Hooks {
+    /// Command to run when Atari has finished writing sectors, once writes
+    /// have been idle for `WRITE_IDLE`
+    #[serde(default)]
+    pub on_write_complete: Option<String>,
+
+    /// Command to run when data received from Atari has an invalid CRC
+    #[serde(default)]
+    pub on_crc_error: Option<String>,
+
+    /// Command to run when disk has no free cluster left
+    #[serde(default)]
+    pub on_disk_full: Option<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.on_write_complete.is_none()
+            && self.on_crc_error.is_none()
+            && self.on_disk_full.is_none()
+    }
+
+    /// Run hooks on matching transaction notices.
+    pub fn install(&self) {
+        if self.is_empty() {
+            return;
+        }
+
+        let writes = self.on_write_complete.clone().map(spawn_write_hook);
+        let hooks = self.clone();
+        transaction::subscribe(move |notice| hooks.on_notice(notice, writes.as_ref()));
+    }
+
+    fn on_notice(&self, notice: &Notice, writes: Option<&Sender<TransactionRecord>>) {
+        match notice {
+            Notice::Finished(record) if record.kind == TransactionKind::Write && record.success => {
+                if let Some(writes) = writes {
+                    let _ = writes.send(record.clone());
+                }
+            }
+            Notice::CrcError { id, sectors } => {
+                if let Some(command) = &self.on_crc_error {
+                    let (sector, count) = sectors.unwrap_or_default();
+                    run(
+                        command,
+                        vec![
+                            ("ATARIDISK_EVENT", "crc_error".to_string()),
+                            ("ATARIDISK_TXN", id.to_string()),
+                            ("ATARIDISK_SECTOR", sector.to_string()),
+                            ("ATARIDISK_COUNT", count.to_string()),
+                        ],
+                    );
+                }
+            }
+            Notice::DiskFull { id } => {
+                if let Some(command) = &self.on_disk_full {
+                    run(
+                        command,
+                        vec![
+                            ("ATARIDISK_EVENT", "disk_full".to_string()),
+                            ("ATARIDISK_TXN", id.to_string()),
+                        ],
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes received since `on_write_complete` last ran.
+#[derive(Debug, Default)]
+struct WriteBatch {
+    /// Last write transaction
+    last_id: u64,
+    /// Lowest written sector
+    first_sector: Option<u16>,
+    writes: usize,
+    sectors: usize,
+    bytes: usize,
+    idle_at: Option<Instant>,
+}
+
+impl WriteBatch {
+    fn add(&mut self, record: &TransactionRecord, now: Instant) {
+        self.last_id = record.id;
+        if let Some((sector, count)) = record.sectors {
+            self.first_sector = Some(self.first_sector.map_or(sector, |s| s.min(sector)));
+            self.sectors += count as usize;
+        }
+        self.writes += 1;
+        self.bytes += record.bytes;
+        self.idle_at = Some(now + WRITE_IDLE);
+    }
+
+    /// Time left before batch must be reported, `None` if it is empty.
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.idle_at
+            .map(|idle_at| idle_at.saturating_duration_since(now))
+    }
+
+    /// Hook environment for batched writes, leaving batch empty.
+    fn take_env(&mut self) -> Vec<(&'static str, String)> {
+        let batch = std::mem::take(self);
+        vec![
+            ("ATARIDISK_EVENT", "write_complete".to_string()),
+            ("ATARIDISK_TXN", batch.last_id.to_string()),
+            (
+                "ATARIDISK_SECTOR",
+                batch.first_sector.unwrap_or_default().to_string(),
+            ),
+            ("ATARIDISK_COUNT", batch.sectors.to_string()),
+            ("ATARIDISK_BYTES", batch.bytes.to_string()),
+            ("ATARIDISK_WRITES", batch.writes.to_string()),
+        ]
+    }
+}
+
+/// Run `command` once each burst of writes sent to returned channel is over.
+fn spawn_write_hook(command: String) -> Sender<TransactionRecord> {
+    let (sender, receiver) = mpsc::channel::<TransactionRecord>();
+
+    thread::spawn(move || {
+        let mut batch = WriteBatch::default();
+        loop {
+            let record = match batch.remaining(Instant::now()) {
+                Some(remaining) if remaining.is_zero() => {
+                    run(&command, batch.take_env());
+                    continue;
+                }
+                Some(remaining) => match receiver.recv_timeout(remaining) {
+                    Ok(record) => record,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                },
+                None => match receiver.recv() {
+                    Ok(record) => record,
+                    Err(_) => return,
+                },
+            };
+            batch.add(&record, Instant::now());
+        }
+    });
+
+    sender
+}
+
+/// Start `command` without waiting for it, so Atari is never slowed down.
+fn run(command: &str, env: Vec<(&'static str, String)>) {
+    log::debug!("Running hook {:?}", command);
+
+    let child = Command::new("sh").arg("-c").arg(command).envs(env).spawn();
+
+    match child {
+        // Wait from another thread to not leave zombie processes
+        Ok(mut child) => {
+            let command = command.to_string();
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => {
+                    log::warn!("Hook {:?} exited with {}", command, status)
+                }
+                Ok(_) => {}
+                Err(err) => log::warn!("Failed to wait hook {:?}: {}", command, err),
+            });
+        }
+        Err(err) => log::warn!("Failed to run hook {:?}: {}", command, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(id: u64, sector: u16, count: u16) -> TransactionRecord {
+        TransactionRecord {
+            id,
+            kind: TransactionKind::Write,
+            sectors: Some((sector, count)),
+            bytes: count as usize * 512,
+            duration: Duration::default(),
+            success: true,
+        }
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let mut batch = WriteBatch::default();
+        let now = Instant::now();
+        assert_eq!(batch.remaining(now), None);
+
+        batch.add(&write(3, 0x20, 2), now);
+        batch.add(&write(4, 0x10, 4), now + WRITE_IDLE / 2);
+        assert_eq!(batch.remaining(now + WRITE_IDLE), Some(WRITE_IDLE / 2));
+        assert_eq!(batch.remaining(now + WRITE_IDLE * 2), Some(Duration::ZERO));
+
+        let env = batch.take_env();
+        let value = |key: &str| env.iter().find(|(k, _)| *k == key).unwrap().1.clone();
+        assert_eq!(value("ATARIDISK_TXN"), "4");
+        assert_eq!(value("ATARIDISK_SECTOR"), "16");
+        assert_eq!(value("ATARIDISK_COUNT"), "6");
+        assert_eq!(value("ATARIDISK_BYTES"), "3072");
+        assert_eq!(value("ATARIDISK_WRITES"), "2");
+        assert_eq!(batch.remaining(now), None);
+    }
+}
@@ -0,0 +1,157 @@
+//! Host commands Atari can run, for cross development workflows (ex: build
+//! and copy latest PRG on the disk).
+//!
+//! Atari only sends the name of a command: shell command lines are given in
+//! config, nothing else can be run. Commands are run with `sh -c`, and Atari
+//! waits for them to exit, at most `TIMEOUT`.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Longest command name Atari can send.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Output given back to Atari is truncated to this length.
+pub const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+/// Commands still running after this time are killed, with the processes
+/// they started.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Shell command lines by name.
+pub type HostCommands = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// Exit code, none if command has been killed by a signal
+    pub exit_code: Option<i32>,
+    /// `stdout` then `stderr`, with TOS line endings
+    pub output: Vec<u8>,
+}
+
+/// Run command named `name`, if any.
+pub fn run(commands: &HostCommands, name: &str) -> Option<io::Result<CommandOutput>> {
+    let command = commands.get(name)?;
+    log::info!("Atari runs host command {:?} ({:?})", name, command);
+
+    Some(run_shell(command, TIMEOUT))
+}
+
+fn run_shell(command: &str, timeout: Duration) -> io::Result<CommandOutput> {
+    // Own process group, so processes started by the command are killed too
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    let stdout = child.stdout.take().map(read_capped);
+    let stderr = child.stderr.take().map(read_capped);
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            log::warn!("Host command {:?} timed out, killing it", command);
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            break child.wait()?;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let mut text = Vec::new();
+    for reader in stdout.into_iter().chain(stderr) {
+        text.extend(reader.join().unwrap_or_default());
+    }
+    Ok(CommandOutput {
+        exit_code: status.code(),
+        output: tos_text(&text),
+    })
+}
+
+/// Read a pipe until closed on another thread, keeping its first
+/// `MAX_OUTPUT_LEN` bytes only.
+fn read_capped<R>(mut reader: R) -> JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut kept = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return kept,
+                Ok(len) => {
+                    let room = MAX_OUTPUT_LEN - kept.len();
+                    kept.extend_from_slice(&buffer[..len.min(room)]);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return kept,
+            }
+        }
+    })
+}
+
+/// Convert line endings to CR LF and truncate to `MAX_OUTPUT_LEN` bytes.
+fn tos_text(text: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(text.len());
+    for &c in text {
+        if c == b'\n' && converted.last() != Some(&b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(c);
+    }
+    converted.truncate(MAX_OUTPUT_LEN);
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut commands = HostCommands::new();
+        commands.insert(
+            "build".to_string(),
+            "echo built; echo oops >&2; exit 3".to_string(),
+        );
+
+        let result = run(&commands, "build").unwrap().unwrap();
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.output, b"built\r\noops\r\n");
+
+        assert!(run(&commands, "rm -rf /").is_none());
+        assert_eq!(tos_text(b"a\r\nb\n"), b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_run_limits() {
+        // Killed with the processes it started
+        let start = Instant::now();
+        let result = run_shell(
+            "echo started; sleep 30 & sleep 30",
+            Duration::from_millis(200),
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(result.exit_code, None);
+        assert_eq!(result.output, b"started\r\n");
+
+        let result = run_shell("yes | head -c 1000000", TIMEOUT).unwrap();
+        assert_eq!(result.exit_code, Some(0));
+        assert_eq!(result.output.len(), MAX_OUTPUT_LEN);
+    }
+}
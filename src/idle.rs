@@ -0,0 +1,101 @@
+//! Idle backoff of the loops polling for a stop signal or a TCP connection,
+//! so an always-on server does not keep waking a small host up.
+//!
+//! Polls are `min_poll_ms` apart while Atari has sent a command in the last
+//! `idle_after_secs`, then each delay doubles up to `max_poll_ms`. Atari
+//! data is never polled for: link reads block until it comes.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+static LAST_ACTIVITY: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Delay between polls while Atari is active
+    pub min_poll_ms: u64,
+    /// Longest delay between polls once idle
+    pub max_poll_ms: u64,
+    /// Time without command before delays grow
+    pub idle_after_secs: u64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            min_poll_ms: 100,
+            max_poll_ms: 2000,
+            idle_after_secs: 30,
+        }
+    }
+}
+
+/// Get back to short polls, called on each Atari command.
+pub fn mark_active() {
+    *LAST_ACTIVITY.lock().unwrap() = Some(Instant::now());
+}
+
+#[derive(Debug)]
+pub struct IdleBackoff {
+    config: IdleConfig,
+    delay: Duration,
+    started: Instant,
+}
+
+impl IdleBackoff {
+    pub fn new(config: IdleConfig) -> Self {
+        Self {
+            config,
+            delay: Duration::from_millis(config.min_poll_ms),
+            started: Instant::now(),
+        }
+    }
+
+    /// Delay before next poll.
+    pub fn next_delay(&mut self) -> Duration {
+        let last_activity = LAST_ACTIVITY.lock().unwrap().unwrap_or(self.started);
+        self.delay_after(last_activity, Instant::now())
+    }
+
+    fn delay_after(&mut self, last_activity: Instant, now: Instant) -> Duration {
+        let min = Duration::from_millis(self.config.min_poll_ms);
+        let max = Duration::from_millis(self.config.max_poll_ms).max(min);
+        let idle_after = Duration::from_secs(self.config.idle_after_secs);
+
+        self.delay = if now.saturating_duration_since(last_activity) < idle_after {
+            min
+        } else {
+            (self.delay * 2).clamp(min, max)
+        };
+        self.delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = IdleBackoff::new(IdleConfig::default());
+        let start = Instant::now();
+        let idle = start + Duration::from_secs(30);
+        let ms = Duration::from_millis;
+
+        assert_eq!(backoff.delay_after(start, start), ms(100));
+        assert_eq!(backoff.delay_after(start, idle), ms(200));
+        assert_eq!(backoff.delay_after(start, idle), ms(400));
+        for _ in 0..4 {
+            backoff.delay_after(start, idle);
+        }
+        assert_eq!(backoff.delay_after(start, idle), ms(2000));
+
+        // Activity wakes polls up at once
+        assert_eq!(backoff.delay_after(idle, idle), ms(100));
+    }
+}
@@ -0,0 +1,363 @@
+//! Export a disk as an image usable outside of the serial link.
+//!
+//! Atari never sees the boot sector of the served disk: the driver gives
+//! GEMDOS its BPB directly, and sector 0 is the first FAT sector. Exported
+//! partitions put a boot sector describing the layout in front of them, so
+//! hard disk drivers can mount them.
+//!
+//! Partitions can be prepended with an AHDI root sector, giving a whole disk
+//! image to write as is to a CF/SD card used with an ACSI adapter. The root
+//! sector has no bad sector list nor boot code.
+//!
+//! Boot code of a user provided boot sector can be put in the first
+//! partition, to boot from it.
+
+use std::{convert::TryFrom, io};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use serde::Deserialize;
+
+use crate::{
+    backend::SectorBackend,
+    boot,
+    error::{self, SerialDiskError},
+    layout::{DiskLayout, PartitionType},
+    storage::DiskStorage,
+};
+
+/// Physical sector size, partition table counts in such sectors.
+pub const PHYSICAL_SECTOR_SIZE: usize = 512;
+
+/// Number of partitions an AHDI root sector can describe.
+pub const AHDI_MAX_PARTITIONS: usize = 4;
+
+const AHDI_DISK_SIZE_OFFSET: usize = 0x1C2;
+const AHDI_PARTITIONS_OFFSET: usize = 0x1C6;
+const AHDI_PARTITION_SIZE: usize = 12;
+const AHDI_PARTITION_EXISTS: u8 = 0x01;
+
+const BOOT_RESERVED_SECTORS: u16 = 1;
+const BOOT_FAT_COUNT: u8 = 2;
+const BOOT_OEM_NAME_LEN: usize = 6;
+const BOOT_MAX_SERIAL_NUMBER: u32 = 0xFF_FFFF;
+const DIR_ENTRY_SIZE: u16 = 32;
+
+/// Sectors written per read from storage.
+const EXPORT_CHUNK_SECTORS: u16 = 64;
+
+/// Boot sector fields some disk tools look at, without effect on GEMDOS.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BootFields {
+    /// Media descriptor byte
+    pub media_descriptor: u8,
+    /// Up to 6 ASCII characters, padded with spaces
+    pub oem_name: String,
+    /// 24 bits volume serial number
+    pub serial_number: u32,
+}
+
+impl Default for BootFields {
+    fn default() -> Self {
+        Self {
+            media_descriptor: 0xF8,
+            oem_name: "ATARID".to_string(),
+            serial_number: 0,
+        }
+    }
+}
+
+/// Partition entry of an AHDI root sector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AhdiPartition {
+    /// `GEM` or `BGM`
+    pub id: [u8; 3],
+    /// First physical sector
+    pub start: u32,
+    /// Size in physical sectors
+    pub size: u32,
+}
+
+impl AhdiPartition {
+    /// Partition holding a disk with `layout`, starting at `start`.
+    pub fn new(layout: &DiskLayout, start: u32) -> error::Result<Self> {
+        let id = match layout.partition_type() {
+            PartitionType::Gem => *b"GEM",
+            PartitionType::Bgm => *b"BGM",
+        };
+        Ok(Self {
+            id,
+            start,
+            size: partition_size(layout)?,
+        })
+    }
+}
+
+/// Number of logical sectors of an exported partition, boot sector included.
+fn logical_sector_count(layout: &DiskLayout) -> error::Result<u16> {
+    u16::try_from(layout.sector_count() + BOOT_RESERVED_SECTORS as u32).map_err(|_| {
+        SerialDiskError::InvalidImage(format!(
+            "{} sectors do not fit in a boot sector",
+            layout.sector_count()
+        ))
+    })
+}
+
+/// Size of an exported partition in physical sectors.
+pub fn partition_size(layout: &DiskLayout) -> error::Result<u32> {
+    let physical_per_logical = layout.bytes_per_sector() as u32 / PHYSICAL_SECTOR_SIZE as u32;
+    Ok(logical_sector_count(layout)? as u32 * physical_per_logical)
+}
+
+/// Build the boot sector of an exported partition.
+///
+/// Its BPB describes the layout served to Atari, with the FATs moved one
+/// sector further to make room for the boot sector itself.
+pub fn boot_sector(layout: &DiskLayout, fields: &BootFields) -> error::Result<Vec<u8>> {
+    if !fields.oem_name.is_ascii() || fields.oem_name.len() > BOOT_OEM_NAME_LEN {
+        return Err(SerialDiskError::InvalidImage(format!(
+            "OEM name must be at most {} ASCII characters, got {:?}",
+            BOOT_OEM_NAME_LEN, fields.oem_name
+        )));
+    }
+    if fields.serial_number > BOOT_MAX_SERIAL_NUMBER {
+        return Err(SerialDiskError::InvalidImage(format!(
+            "serial number {:#x} does not fit in 24 bits",
+            fields.serial_number
+        )));
+    }
+
+    let mut sector = vec![0; layout.bytes_per_sector() as usize];
+
+    // `BRA.S` over the BPB, as written by TOS
+    sector[0] = 0x60;
+    sector[1] = 0x38;
+    let oem_name = format!("{:<1$}", fields.oem_name, BOOT_OEM_NAME_LEN);
+    sector[0x02..0x08].copy_from_slice(oem_name.as_bytes());
+    sector[0x08..0x0B].copy_from_slice(&fields.serial_number.to_le_bytes()[..3]);
+
+    LittleEndian::write_u16(&mut sector[0x0B..], layout.bytes_per_sector());
+    sector[0x0D] = layout.sectors_per_cluster() as u8;
+    LittleEndian::write_u16(&mut sector[0x0E..], BOOT_RESERVED_SECTORS);
+    sector[0x10] = BOOT_FAT_COUNT;
+    LittleEndian::write_u16(
+        &mut sector[0x11..],
+        layout.root_directory_sectors() * (layout.bytes_per_sector() / DIR_ENTRY_SIZE),
+    );
+    LittleEndian::write_u16(&mut sector[0x13..], logical_sector_count(layout)?);
+    sector[0x15] = fields.media_descriptor;
+    LittleEndian::write_u16(&mut sector[0x16..], layout.count_1fat_sectors());
+
+    Ok(sector)
+}
+
+/// Build an AHDI root sector describing `partitions`.
+pub fn ahdi_root_sector(partitions: &[AhdiPartition]) -> error::Result<[u8; PHYSICAL_SECTOR_SIZE]> {
+    if partitions.len() > AHDI_MAX_PARTITIONS {
+        return Err(SerialDiskError::InvalidImage(format!(
+            "{} partitions given, AHDI root sector holds {}",
+            partitions.len(),
+            AHDI_MAX_PARTITIONS
+        )));
+    }
+
+    let mut sector = [0; PHYSICAL_SECTOR_SIZE];
+    let disk_size = partitions
+        .iter()
+        .map(|p| p.start + p.size)
+        .max()
+        .unwrap_or(1);
+    BigEndian::write_u32(&mut sector[AHDI_DISK_SIZE_OFFSET..], disk_size);
+
+    for (i, partition) in partitions.iter().enumerate() {
+        let entry = &mut sector[AHDI_PARTITIONS_OFFSET + i * AHDI_PARTITION_SIZE..];
+        entry[0] = AHDI_PARTITION_EXISTS;
+        entry[1..4].copy_from_slice(&partition.id);
+        BigEndian::write_u32(&mut entry[4..], partition.start);
+        BigEndian::write_u32(&mut entry[8..], partition.size);
+    }
+
+    Ok(sector)
+}
+
+/// Write `storage` as a partition: boot sector then every disk sector.
+///
+/// Boot sector is made executable with `boot_code` if given.
+pub fn write_partition<B, W>(
+    storage: &DiskStorage<B>,
+    fields: &BootFields,
+    boot_code: Option<&[u8]>,
+    writer: &mut W,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+    W: io::Write,
+{
+    let layout = &storage.disk_layout;
+    let mut boot_sector = boot_sector(layout, fields)?;
+    if let Some(boot_code) = boot_code {
+        boot::inject(&mut boot_sector, boot_code)?;
+    }
+    writer.write_all(&boot_sector)?;
+
+    let sector_count = logical_sector_count(layout)? - BOOT_RESERVED_SECTORS;
+    let mut index = 0;
+    while index < sector_count {
+        let count = EXPORT_CHUNK_SECTORS.min(sector_count - index);
+        storage.read_sectors(writer, index, count)?;
+        index += count;
+    }
+
+    Ok(())
+}
+
+/// Write `storage` as a raw image, as a whole AHDI disk if `ahdi` is set.
+pub fn export<B, W>(storage: &DiskStorage<B>, writer: &mut W, ahdi: bool) -> error::Result<()>
+where
+    B: SectorBackend,
+    W: io::Write,
+{
+    if ahdi {
+        write_ahdi_disk(&[storage], &BootFields::default(), None, writer)
+    } else {
+        write_partition(storage, &BootFields::default(), None, writer)
+    }
+}
+
+/// Write an AHDI disk with a partition for each storage, in order (first one
+/// is `C:`, then `D:`...). `boot_code` goes to first partition.
+pub fn write_ahdi_disk<B, W>(
+    storages: &[&DiskStorage<B>],
+    fields: &BootFields,
+    boot_code: Option<&[u8]>,
+    writer: &mut W,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+    W: io::Write,
+{
+    // Partitions follow each other right after root sector
+    let mut partitions = Vec::with_capacity(storages.len());
+    let mut start = 1;
+    for storage in storages {
+        let partition = AhdiPartition::new(&storage.disk_layout, start)?;
+        start += partition.size;
+        partitions.push(partition);
+    }
+
+    writer.write_all(&ahdi_root_sector(&partitions)?)?;
+    for (i, storage) in storages.iter().enumerate() {
+        write_partition(storage, fields, boot_code.filter(|_| i == 0), writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::{layout::Tos, storage::ROOT_INDEX};
+
+    #[test]
+    fn test_export_ahdi() {
+        let layout = DiskLayout::new(Tos::V104, PartitionType::Gem, 8);
+        let mut storage = DiskStorage::new(layout.clone());
+        storage
+            .add_virtual_file(
+                "README",
+                "TXT",
+                NaiveDateTime::from_timestamp(0, 0),
+                b"hello",
+                ROOT_INDEX,
+            )
+            .unwrap();
+
+        let mut image = Vec::new();
+        export(&storage, &mut image, true).unwrap();
+        let size = partition_size(&layout).unwrap();
+        assert_eq!(image.len(), (1 + size as usize) * PHYSICAL_SECTOR_SIZE);
+
+        // Root sector
+        assert_eq!(BigEndian::read_u32(&image[0x1C2..]), 1 + size);
+        assert_eq!(&image[0x1C6..0x1CA], b"\x01GEM");
+        assert_eq!(BigEndian::read_u32(&image[0x1CA..]), 1);
+        assert_eq!(BigEndian::read_u32(&image[0x1CE..]), size);
+        assert_eq!(image[0x1D2], 0);
+
+        // Boot sector, then FAT and root directory
+        let partition = &image[PHYSICAL_SECTOR_SIZE..];
+        assert_eq!(LittleEndian::read_u16(&partition[0x0B..]), 512);
+        assert_eq!(
+            LittleEndian::read_u16(&partition[0x16..]),
+            layout.count_1fat_sectors()
+        );
+        let root = &partition[(1 + layout.count_fat_sectors() as usize) * 512..];
+        assert_eq!(&root[..11], b"README  TXT");
+    }
+
+    #[test]
+    fn test_write_ahdi_disk() {
+        let gem = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Gem, 8));
+        let bgm = DiskStorage::new(DiskLayout::default());
+        let gem_size = partition_size(&gem.disk_layout).unwrap();
+        let bgm_size = partition_size(&bgm.disk_layout).unwrap();
+
+        let mut image = Vec::new();
+        let boot_code = [0x4E; boot::CHECKSUM_LEN];
+        write_ahdi_disk(
+            &[&gem, &bgm, &gem],
+            &BootFields::default(),
+            Some(&boot_code),
+            &mut image,
+        )
+        .unwrap();
+        let disk_size = 1 + 2 * gem_size + bgm_size;
+        assert_eq!(image.len(), disk_size as usize * PHYSICAL_SECTOR_SIZE);
+        assert_eq!(BigEndian::read_u32(&image[0x1C2..]), disk_size);
+
+        let entry = |i: usize| &image[0x1C6 + i * 12..0x1C6 + (i + 1) * 12];
+        assert_eq!(&entry(1)[..4], b"\x01BGM");
+        assert_eq!(BigEndian::read_u32(&entry(1)[4..]), 1 + gem_size);
+        assert_eq!(BigEndian::read_u32(&entry(2)[4..]), 1 + gem_size + bgm_size);
+        assert_eq!(entry(3)[0], 0);
+
+        // Only first partition boots
+        assert!(boot::is_executable(&image[PHYSICAL_SECTOR_SIZE..]));
+        let third = &image[(1 + gem_size + bgm_size) as usize * PHYSICAL_SECTOR_SIZE..];
+        assert_eq!(LittleEndian::read_u16(&third[0x0B..]), 512);
+        assert!(!boot::is_executable(third));
+    }
+
+    #[test]
+    fn test_boot_fields() {
+        let layout = DiskLayout::default();
+        let fields = BootFields {
+            media_descriptor: 0xF0,
+            oem_name: "TOS".to_string(),
+            serial_number: 0x123456,
+        };
+        let sector = boot_sector(&layout, &fields).unwrap();
+        assert_eq!(&sector[0x02..0x0B], b"TOS   \x56\x34\x12");
+        assert_eq!(sector[0x15], 0xF0);
+
+        let too_long = BootFields {
+            oem_name: "ATARIDISK".to_string(),
+            ..Default::default()
+        };
+        assert!(boot_sector(&layout, &too_long).is_err());
+        let too_large = BootFields {
+            serial_number: 0x100_0000,
+            ..Default::default()
+        };
+        assert!(boot_sector(&layout, &too_large).is_err());
+    }
+
+    #[test]
+    fn test_root_sector_limit() {
+        let partition = AhdiPartition::new(&DiskLayout::default(), 1).unwrap();
+        assert_eq!(&partition.id, b"BGM");
+        assert_eq!(partition.size % 16, 0);
+        assert!(ahdi_root_sector(&vec![partition; 5]).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! What happened to host entries while importing them.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+use crate::error::SerialDiskError;
+
+/// Why a host entry has not been imported.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    InvalidChars,
+    InvalidName,
+    /// Name rejected by name policy
+    RejectedName,
+    DiskFull,
+    FolderFull,
+    QuotaExceeded,
+    /// Symlink to outside of shared directory
+    OutsideSharedRoot,
+    SymlinkLoop,
+    /// Neither a file nor a directory (ex: socket, device)
+    UnsupportedType,
+    /// Name already used on disk, by another source
+    AlreadyExists,
+    /// Any other error
+    Error(String),
+}
+
+impl SkipReason {
+    /// Short name of the reason, used to group skipped entries.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidChars => "invalid_chars",
+            Self::InvalidName => "invalid_name",
+            Self::RejectedName => "rejected_name",
+            Self::DiskFull => "disk_full",
+            Self::FolderFull => "folder_full",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::OutsideSharedRoot => "outside_shared_root",
+            Self::SymlinkLoop => "symlink_loop",
+            Self::UnsupportedType => "unsupported_type",
+            Self::AlreadyExists => "already_exists",
+            Self::Error(_) => "error",
+        }
+    }
+}
+
+impl From<&SerialDiskError> for SkipReason {
+    fn from(error: &SerialDiskError) -> Self {
+        match error.root() {
+            SerialDiskError::InvalidChars => Self::InvalidChars,
+            SerialDiskError::InvalidFilename => Self::InvalidName,
+            SerialDiskError::RejectedName(_) => Self::RejectedName,
+            SerialDiskError::DiskFull => Self::DiskFull,
+            SerialDiskError::FolderFull => Self::FolderFull,
+            SerialDiskError::QuotaExceeded(_) => Self::QuotaExceeded,
+            SerialDiskError::OutsideSharedRoot(_) => Self::OutsideSharedRoot,
+            error => Self::Error(error.to_string()),
+        }
+    }
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidChars => write!(f, "invalid chars"),
+            Self::InvalidName => write!(f, "invalid name"),
+            Self::RejectedName => write!(f, "name rejected by TOS"),
+            Self::DiskFull => write!(f, "disk full"),
+            Self::FolderFull => write!(f, "folder full"),
+            Self::QuotaExceeded => write!(f, "quota exceeded"),
+            Self::OutsideSharedRoot => write!(f, "outside shared directory"),
+            Self::SymlinkLoop => write!(f, "symlink loop"),
+            Self::UnsupportedType => write!(f, "unsupported file type"),
+            Self::AlreadyExists => write!(f, "already on disk"),
+            Self::Error(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Host entry which has not been imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Host file imported in an ancestor of its directory, too deep for TOS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlattenedEntry {
+    pub path: PathBuf,
+    /// `\` separated path it has been given on disk
+    pub disk_path: String,
+}
+
+/// Outcome of a host directory import.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub files: usize,
+    pub directories: usize,
+    /// Size of imported files
+    pub bytes: usize,
+    /// Clusters reserved for imported files and directories
+    pub clusters: usize,
+    pub skipped: Vec<SkippedEntry>,
+    pub flattened: Vec<FlattenedEntry>,
+}
+
+impl ImportReport {
+    pub fn skip(&mut self, path: PathBuf, reason: SkipReason) {
+        log::warn!("Skipping: {:?} ({})", path, reason);
+        self.skipped.push(SkippedEntry { path, reason });
+    }
+
+    pub fn flatten(&mut self, path: PathBuf, disk_path: String) {
+        log::info!("Flattening: {:?} imported as {}", path, disk_path);
+        self.flattened.push(FlattenedEntry { path, disk_path });
+    }
+
+    /// Skip an entry which failed to import, logging where it failed.
+    pub fn skip_error(&mut self, path: PathBuf, error: &SerialDiskError) {
+        log::warn!("Skipping: {:?} ({})", path, error);
+        self.skipped.push(SkippedEntry {
+            path,
+            reason: error.into(),
+        });
+    }
+
+    /// Number of skipped entries for each kind of reason.
+    pub fn skipped_by_kind(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in &self.skipped {
+            *counts.entry(entry.reason.kind()).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// Import totals, flattened files, then skipped entries grouped by reason.
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files and {} directories imported ({} bytes, {} clusters)",
+            self.files, self.directories, self.bytes, self.clusters
+        )?;
+        if !self.flattened.is_empty() {
+            write!(
+                f,
+                "\n{} files moved up from too deep directories:",
+                self.flattened.len()
+            )?;
+            for entry in &self.flattened {
+                write!(f, "\n  {} -> {}", entry.path.display(), entry.disk_path)?;
+            }
+        }
+        if self.skipped.is_empty() {
+            return Ok(());
+        }
+
+        let mut groups: BTreeMap<String, Vec<&SkippedEntry>> = BTreeMap::new();
+        for entry in &self.skipped {
+            let label = match &entry.reason {
+                SkipReason::Error(_) => "other error".to_string(),
+                reason => reason.to_string(),
+            };
+            groups.entry(label).or_default().push(entry);
+        }
+
+        write!(f, "\n{} entries skipped:", self.skipped.len())?;
+        for (label, entries) in groups {
+            write!(f, "\n  {} {}:", entries.len(), label)?;
+            for entry in entries {
+                write!(f, "\n    {}", entry.path.display())?;
+                if let SkipReason::Error(message) = &entry.reason {
+                    write!(f, " ({})", message)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut report = ImportReport {
+            files: 3,
+            directories: 1,
+            bytes: 1024,
+            clusters: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            report.to_string(),
+            "3 files and 1 directories imported (1024 bytes, 4 clusters)"
+        );
+
+        report.skip("a/CAFÉ.TXT".into(), SkipReason::InvalidChars);
+        report.skip("a/NOËL.TXT".into(), SkipReason::InvalidChars);
+        report.skip("a/fifo".into(), SkipReason::UnsupportedType);
+        report.skip_error(
+            "a/BIG.BIN".into(),
+            &SerialDiskError::DiskFull.with_path("BIG.BIN".to_string()),
+        );
+
+        assert_eq!(report.skipped_by_kind()["invalid_chars"], 2);
+        assert_eq!(
+            report.to_string(),
+            "3 files and 1 directories imported (1024 bytes, 4 clusters)\n\
+             4 entries skipped:\n  \
+             1 disk full:\n    a/BIG.BIN\n  \
+             2 invalid chars:\n    a/CAFÉ.TXT\n    a/NOËL.TXT\n  \
+             1 unsupported file type:\n    a/fifo"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::Write;
+
+use chrono::Local;
+
+use crate::{
+    backend::SectorBackend,
+    entries::FileInfo,
+    error,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+const INDEX_NAME: &str = "INDEX";
+const INDEX_EXT: &str = "TXT";
+
+/// Build a text listing of every file served on the disk.
+///
+/// Atari text files use CRLF line endings.
+pub fn build_index<B>(storage: &DiskStorage<B>) -> error::Result<String>
+where
+    B: SectorBackend,
+{
+    let mut lines = Vec::new();
+    let mut file_count = 0;
+    let mut total_size = 0;
+
+    walk(
+        storage,
+        &storage.list_root_file_infos(),
+        "",
+        &mut lines,
+        &mut file_count,
+        &mut total_size,
+    )?;
+
+    let mut index = String::new();
+    write_line(&mut index, "Index of virtual disk");
+    write_line(&mut index, "");
+    for line in lines {
+        write_line(&mut index, &line);
+    }
+    write_line(&mut index, "");
+    write_line(
+        &mut index,
+        &format!("{} files, {} bytes", file_count, total_size),
+    );
+
+    Ok(index)
+}
+
+/// Add an `INDEX.TXT` file at disk root listing every file served.
+pub fn add_index_file<B>(storage: &mut DiskStorage<B>) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    let filename = format!("{}.{}", INDEX_NAME, INDEX_EXT);
+    for file_info in storage.list_root_file_infos() {
+        if file_info.filename()? == filename {
+            log::warn!(
+                "{} already exists on disk, skipping index generation",
+                filename
+            );
+            return Ok(());
+        }
+    }
+
+    let index = build_index(storage)?;
+    let mtime = Local::now().naive_local();
+    storage.add_virtual_file(INDEX_NAME, INDEX_EXT, mtime, index.as_bytes(), ROOT_INDEX)
+}
+
+fn write_line(output: &mut String, line: &str) {
+    output.push_str(line);
+    output.push_str("\r\n");
+}
+
+fn walk<B>(
+    storage: &DiskStorage<B>,
+    file_infos: &[FileInfo],
+    prefix: &str,
+    lines: &mut Vec<String>,
+    file_count: &mut usize,
+    total_size: &mut usize,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    for file_info in file_infos {
+        let path = format!("{}{}", prefix, file_info.filename()?);
+        let date = file_info
+            .modified()
+            .map(|dt| dt.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+
+        if file_info.is_dir() {
+            let mut line = String::new();
+            write!(
+                line,
+                "{:<40} {:>10}  {}",
+                format!("{}\\", path),
+                "<DIR>",
+                date
+            )
+            .unwrap();
+            lines.push(line);
+
+            // Skip `.` and `..`
+            let children: Vec<FileInfo> =
+                storage.read_dir(file_info)?.into_iter().skip(2).collect();
+            walk(
+                storage,
+                &children,
+                &format!("{}\\", path),
+                lines,
+                file_count,
+                total_size,
+            )?;
+        } else {
+            let mut line = String::new();
+            write!(line, "{:<40} {:>10}  {}", path, file_info.size(), date).unwrap();
+            lines.push(line);
+
+            *file_count += 1;
+            *total_size += file_info.size();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout};
+
+    #[test]
+    fn test_index() {
+        let sample = Fixture::sample("index").unwrap();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(sample.path()).unwrap();
+
+        let index = build_index(&storage).unwrap();
+        assert!(index.contains("TEST.TXT"));
+        assert!(index.contains("1 files, 20 bytes\r\n"));
+    }
+
+    #[test]
+    fn test_add_index_file() {
+        let sample = Fixture::sample("index-file").unwrap();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(sample.path()).unwrap();
+        add_index_file(&mut storage).unwrap();
+        add_index_file(&mut storage).unwrap();
+
+        let index_files: Vec<_> = storage
+            .list_root_file_infos()
+            .into_iter()
+            .filter(|f| f.filename().unwrap() == "INDEX.TXT")
+            .collect();
+        assert_eq!(index_files.len(), 1);
+
+        let content = String::from_utf8(storage.read_file(&index_files[0]).unwrap()).unwrap();
+        assert!(content.starts_with("Index of virtual disk\r\n"));
+        assert!(content.contains("TEST.TXT"));
+        assert!(content.ends_with("1 files, 20 bytes\r\n"));
+    }
+}
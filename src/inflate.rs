@@ -0,0 +1,347 @@
+//! Minimal DEFLATE (RFC 1951) decoder used to read compressed archives.
+//!
+//! This follows the structure of zlib `puff.c`: canonical Huffman codes
+//! are decoded bit by bit, which is slow but small and good enough for
+//! the size of Atari files.
+
+use crate::error::{self, SerialDiskError};
+
+const MAX_BITS: usize = 15;
+const MAX_LITERAL_CODES: usize = 286;
+const MAX_DISTANCE_CODES: usize = 30;
+const FIXED_LITERAL_CODES: usize = 288;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+macro_rules! invalid {
+    ($msg:expr) => {
+        SerialDiskError::InvalidArchive(format!("deflate: {}", $msg))
+    };
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> error::Result<u32> {
+        let mut value = self.bit_buf;
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid!("unexpected end of stream"))?;
+            self.pos += 1;
+            value |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        self.bit_buf = if count == 32 { 0 } else { value >> count };
+        self.bit_count -= count;
+        Ok(value & ((1u64 << count) - 1) as u32)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// Canonical Huffman code description.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> error::Result<Self> {
+        let mut counts = [0; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+
+        // Check code is not over-subscribed
+        let mut left: i32 = 1;
+        for count in counts.iter().skip(1) {
+            left <<= 1;
+            left -= *count as i32;
+            if left < 0 {
+                return Err(invalid!("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> error::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(invalid!("invalid huffman code"))
+    }
+}
+
+/// Decompress a raw DEFLATE stream, failing once output would be larger
+/// than `limit` bytes.
+pub fn inflate(data: &[u8], limit: usize) -> error::Result<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::with_capacity((data.len() * 2).min(limit));
+
+    loop {
+        let last = reader.bits(1)?;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut output, limit)?,
+            1 => {
+                let (literals, distances) = fixed_codes()?;
+                inflate_codes(&mut reader, &mut output, limit, &literals, &distances)?
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, &mut output, limit, &literals, &distances)?
+            }
+            _ => return Err(invalid!("invalid block type")),
+        }
+
+        if last == 1 {
+            return Ok(output);
+        }
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>, limit: usize) -> error::Result<()> {
+    reader.align_to_byte();
+
+    let header = reader
+        .data
+        .get(reader.pos..reader.pos + 4)
+        .ok_or_else(|| invalid!("unexpected end of stream"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(invalid!("invalid stored block length"));
+    }
+    reader.pos += 4;
+
+    let content = reader
+        .data
+        .get(reader.pos..reader.pos + len as usize)
+        .ok_or_else(|| invalid!("unexpected end of stream"))?;
+    if output.len() + content.len() > limit {
+        return Err(invalid!("output too large"));
+    }
+    output.extend_from_slice(content);
+    reader.pos += len as usize;
+
+    Ok(())
+}
+
+fn fixed_codes() -> error::Result<(Huffman, Huffman)> {
+    let mut lengths = [0; FIXED_LITERAL_CODES];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    Ok((
+        Huffman::new(&lengths)?,
+        Huffman::new(&[5; MAX_DISTANCE_CODES])?,
+    ))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> error::Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+    if literal_count > MAX_LITERAL_CODES || distance_count > MAX_DISTANCE_CODES {
+        return Err(invalid!("too many codes"));
+    }
+
+    let mut code_lengths = [0; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_huffman = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_huffman.decode(reader)?;
+        if symbol < 16 {
+            lengths[index] = symbol as u8;
+            index += 1;
+            continue;
+        }
+
+        let (value, repeat) = match symbol {
+            16 => {
+                let previous = *index
+                    .checked_sub(1)
+                    .and_then(|i| lengths.get(i))
+                    .ok_or_else(|| invalid!("repeat without previous length"))?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(invalid!("too many lengths"));
+        }
+        for length in lengths.iter_mut().skip(index).take(repeat) {
+            *length = value;
+        }
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(invalid!("missing end of block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> error::Result<()> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 if output.len() >= limit => return Err(invalid!("output too large")),
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(invalid!("invalid length code"));
+                }
+                let length = LENGTH_BASE[symbol] as usize
+                    + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = distances.decode(reader)? as usize;
+                if symbol >= DISTANCE_BASE.len() {
+                    return Err(invalid!("invalid distance code"));
+                }
+                let distance = DISTANCE_BASE[symbol] as usize
+                    + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(invalid!("distance too far back"));
+                }
+                if output.len() + length > limit {
+                    return Err(invalid!("output too large"));
+                }
+
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored() {
+        // Final stored block containing "abc"
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data, 3).unwrap(), b"abc");
+        assert!(inflate(&data, 2).is_err());
+    }
+
+    #[test]
+    fn test_fixed() {
+        // Raw deflate of "hello hello hello" (fixed codes + back reference)
+        let data = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x00];
+        assert_eq!(inflate(&data, 17).unwrap(), b"hello hello hello");
+        // Back reference going over limit
+        assert!(inflate(&data, 10).is_err());
+    }
+
+    #[test]
+    fn test_dynamic() {
+        // Raw deflate with dynamic huffman codes
+        let data = [
+            0x05, 0xC1, 0x09, 0x01, 0x80, 0x40, 0x10, 0x00, 0xA1, 0x4A, 0xFE, 0xA7, 0x71, 0x98,
+            0xED, 0xDF, 0x41, 0x00, 0xA0, 0xAA, 0x19, 0x00, 0xAA, 0x9A, 0x01, 0xA0, 0xAA, 0x19,
+            0x00, 0xAA, 0x9A, 0xD9, 0xF6, 0xE3, 0xBC, 0xEE, 0x67, 0xBD, 0xDF, 0xB6, 0x1F, 0xE7,
+            0x75, 0x3F, 0xEB, 0xFD, 0x7E,
+        ];
+        let expected = [b"aaaaaaaaaabbbbbcc".repeat(4), b"0123456789".repeat(2)].concat();
+        assert_eq!(inflate(&data, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(inflate(&[], 16).is_err());
+        assert!(inflate(&[0x07], 16).is_err());
+        assert!(inflate(&[0x01, 0x03, 0x00, 0x00, 0x00], 16).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{backend::SectorBackend, checksum, error, storage::DiskStorage};
+
+/// Append-only journal of every write command received from the Atari.
+///
+/// Each record is stored as: sector index, sector count, data length,
+/// data and a CRC32 of all previous fields. A record that has been
+/// partially written (crash, power loss) is detected on replay thanks
+/// to the CRC and everything after it is ignored.
+///
+/// Every write is preceded by a time mark: a record of no sector whose data
+/// is the UNIX time in milliseconds, so a session can be replayed up to any
+/// moment. Older journals have no mark and replay the same.
+#[derive(Debug)]
+pub struct WriteJournal {
+    writer: BufWriter<File>,
+}
+
+impl WriteJournal {
+    /// Open journal file for append, creating it if needed.
+    pub fn open<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Append a write command to the journal and flush it to stable storage.
+    pub fn record(
+        &mut self,
+        sector_index: u16,
+        sector_count: u16,
+        data: &[u8],
+    ) -> error::Result<()> {
+        let mut record = encode_time_mark(Utc::now())?;
+        record.extend(encode_record(sector_index, sector_count, data)?);
+
+        self.writer.write_all(&record)?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+
+        Ok(())
+    }
+
+    /// Append a write command without time mark, leaving it to `sync` to
+    /// reach stable storage.
+    pub fn append(
+        &mut self,
+        sector_index: u16,
+        sector_count: u16,
+        data: &[u8],
+    ) -> error::Result<()> {
+        self.writer
+            .write_all(&encode_record(sector_index, sector_count, data)?)?;
+
+        Ok(())
+    }
+
+    /// Make sure every recorded command reached stable storage.
+    pub fn sync(&mut self) -> error::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+
+        Ok(())
+    }
+
+    /// Forget every recorded command, once they are all stored elsewhere.
+    pub fn truncate(&mut self) -> error::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().set_len(0)?;
+        self.writer.get_ref().sync_all()?;
+
+        Ok(())
+    }
+}
+
+fn encode_record(sector_index: u16, sector_count: u16, data: &[u8]) -> error::Result<Vec<u8>> {
+    let mut record = Vec::with_capacity(data.len() + 12);
+    record.write_u16::<BigEndian>(sector_index)?;
+    record.write_u16::<BigEndian>(sector_count)?;
+    record.write_u32::<BigEndian>(data.len() as u32)?;
+    record.extend_from_slice(data);
+
+    let mut crc = vec![];
+    checksum::write_crc32(&mut crc, &record)?;
+    record.extend(crc);
+
+    Ok(record)
+}
+
+fn encode_time_mark(time: DateTime<Utc>) -> error::Result<Vec<u8>> {
+    encode_record(0, 0, &time.timestamp_millis().to_be_bytes())
+}
+
+/// Time of a record written by `encode_time_mark`.
+fn time_mark(sector_count: u16, data: &[u8]) -> Option<DateTime<Utc>> {
+    match (sector_count, <&[u8; 8]>::try_from(data)) {
+        (0, Ok(millis)) => Some(Utc.timestamp_millis(i64::from_be_bytes(*millis))),
+        _ => None,
+    }
+}
+
+/// Outcome of `replay_until`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Replayed {
+    /// Write commands applied
+    pub writes: usize,
+    /// Time of the last applied write, if journal has time marks
+    pub last_time: Option<DateTime<Utc>>,
+    /// Whether writes after `until` have been left out
+    pub stopped: bool,
+}
+
+/// Read next valid record from journal.
+///
+/// Return `None` on end of journal or if the record is corrupted.
+fn read_record<R>(reader: &mut R) -> error::Result<Option<(u16, u16, Vec<u8>)>>
+where
+    R: Read,
+{
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut header_reader = &header[..];
+    let sector_index = header_reader.read_u16::<BigEndian>()?;
+    let sector_count = header_reader.read_u16::<BigEndian>()?;
+    let data_len = header_reader.read_u32::<BigEndian>()? as usize;
+
+    let mut data = vec![0; data_len];
+    let mut crc = [0; 4];
+    if reader.read_exact(&mut data).is_err() || reader.read_exact(&mut crc).is_err() {
+        log::warn!("Truncated journal record, stopping replay");
+        return Ok(None);
+    }
+
+    let mut record = header.to_vec();
+    record.extend_from_slice(&data);
+    if !checksum::check_crc32(&mut &crc[..], &record)? {
+        log::warn!("Corrupted journal record, stopping replay");
+        return Ok(None);
+    }
+
+    Ok(Some((sector_index, sector_count, data)))
+}
+
+/// Apply all valid journal records on top of storage and return how many
+/// write commands have been replayed.
+pub fn replay<R, B>(reader: &mut R, storage: &mut DiskStorage<B>) -> error::Result<usize>
+where
+    R: Read,
+    B: SectorBackend,
+{
+    Ok(replay_until(reader, storage, None)?.writes)
+}
+
+/// Apply journal records on top of storage, leaving out writes received
+/// after `until`.
+pub fn replay_until<R, B>(
+    reader: &mut R,
+    storage: &mut DiskStorage<B>,
+    until: Option<DateTime<Utc>>,
+) -> error::Result<Replayed>
+where
+    R: Read,
+    B: SectorBackend,
+{
+    let mut replayed = Replayed::default();
+    let mut time = None;
+
+    while let Some((sector_index, sector_count, data)) = read_record(reader)? {
+        if let Some(mark) = time_mark(sector_count, &data) {
+            if until.is_some_and(|until| mark > until) {
+                replayed.stopped = true;
+                break;
+            }
+            time = Some(mark);
+            continue;
+        }
+
+        log::debug!(
+            "Replaying write: index={:#04x}, count={:#04x}",
+            sector_index,
+            sector_count
+        );
+        storage.write_sectors(&mut data.as_slice(), sector_index, sector_count)?;
+        replayed.writes += 1;
+        replayed.last_time = time;
+    }
+
+    Ok(replayed)
+}
+
+/// Give sectors of every valid write record to `apply`, without a disk
+/// storage, and return how many write commands have been replayed.
+pub fn replay_sectors<R, F>(reader: &mut R, mut apply: F) -> error::Result<usize>
+where
+    R: Read,
+    F: FnMut(u16, &[u8]),
+{
+    let mut writes = 0;
+    while let Some((sector_index, sector_count, data)) = read_record(reader)? {
+        if sector_count == 0 || data.len() < sector_count as usize {
+            continue;
+        }
+        let sector_len = data.len() / sector_count as usize;
+        for (offset, sector) in data.chunks_exact(sector_len).enumerate() {
+            apply(sector_index.wrapping_add(offset as u16), sector);
+        }
+        writes += 1;
+    }
+
+    Ok(writes)
+}
+
+/// Replay journal file on top of storage.
+pub fn replay_file<P, B>(path: P, storage: &mut DiskStorage<B>) -> error::Result<usize>
+where
+    P: AsRef<Path>,
+    B: SectorBackend,
+{
+    let mut reader = io::BufReader::new(File::open(path)?);
+    replay(&mut reader, storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::DiskLayout;
+
+    fn read_back(storage: &DiskStorage, sector_index: u16) -> Vec<u8> {
+        let mut buf = vec![];
+        storage.read_sector(&mut buf, sector_index).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_replay() {
+        let layout = DiskLayout::default();
+        let sector_size = layout.bytes_per_sector() as usize;
+        let sector_index = layout.first_free_sector() + 10;
+
+        let mut journal = vec![];
+        journal.extend(encode_record(sector_index, 1, &vec![0xAA; sector_size]).unwrap());
+        journal.extend(encode_record(sector_index + 1, 1, &vec![0xBB; sector_size]).unwrap());
+
+        let mut storage = DiskStorage::new(layout);
+        assert_eq!(replay(&mut journal.as_slice(), &mut storage), Ok(2));
+        assert_eq!(read_back(&storage, sector_index), vec![0xAA; sector_size]);
+        assert_eq!(
+            read_back(&storage, sector_index + 1),
+            vec![0xBB; sector_size]
+        );
+    }
+
+    #[test]
+    fn test_replay_truncated() {
+        let layout = DiskLayout::default();
+        let sector_size = layout.bytes_per_sector() as usize;
+        let sector_index = layout.first_free_sector() + 10;
+
+        let mut journal = vec![];
+        journal.extend(encode_record(sector_index, 1, &vec![0xAA; sector_size]).unwrap());
+        let second = encode_record(sector_index + 1, 1, &vec![0xBB; sector_size]).unwrap();
+        journal.extend(&second[..second.len() / 2]);
+
+        let mut storage = DiskStorage::new(layout);
+        assert_eq!(replay(&mut journal.as_slice(), &mut storage), Ok(1));
+        assert_eq!(read_back(&storage, sector_index + 1), vec![0; sector_size]);
+    }
+
+    #[test]
+    fn test_replay_corrupted() {
+        let layout = DiskLayout::default();
+        let sector_size = layout.bytes_per_sector() as usize;
+        let sector_index = layout.first_free_sector() + 10;
+
+        let mut journal = encode_record(sector_index, 1, &vec![0xAA; sector_size]).unwrap();
+        journal[20] ^= 0xFF;
+
+        let mut storage = DiskStorage::new(layout);
+        assert_eq!(replay(&mut journal.as_slice(), &mut storage), Ok(0));
+    }
+
+    #[test]
+    fn test_replay_until() {
+        let layout = DiskLayout::default();
+        let sector_size = layout.bytes_per_sector() as usize;
+        let sector_index = layout.first_free_sector() + 10;
+        let at = |seconds: i64| Utc.timestamp(1_700_000_000 + seconds, 0);
+
+        // Atari saves a file, then overwrites it a minute later
+        let mut journal = vec![];
+        for (seconds, byte) in [(0, 0xAA), (60, 0xBB)] {
+            journal.extend(encode_time_mark(at(seconds)).unwrap());
+            journal.extend(encode_record(sector_index, 1, &vec![byte; sector_size]).unwrap());
+        }
+
+        let mut storage = DiskStorage::new(layout.clone());
+        let replayed = replay_until(&mut journal.as_slice(), &mut storage, Some(at(30))).unwrap();
+        assert_eq!(
+            replayed,
+            Replayed {
+                writes: 1,
+                last_time: Some(at(0)),
+                stopped: true,
+            }
+        );
+        assert_eq!(read_back(&storage, sector_index), vec![0xAA; sector_size]);
+
+        // Time marks are not counted as writes
+        let mut storage = DiskStorage::new(layout);
+        assert_eq!(replay(&mut journal.as_slice(), &mut storage), Ok(2));
+        assert_eq!(read_back(&storage, sector_index), vec![0xBB; sector_size]);
+    }
+}
@@ -3,12 +3,13 @@ use std::mem::size_of;
 use byteorder::{BigEndian, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 
-use crate::error;
+use crate::error::{self, SerialDiskError};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum PartitionType {
     Gem,
+    #[default]
     Bgm,
 }
 
@@ -18,44 +19,33 @@ impl PartitionType {
     /// NB. This should be configurable for BGM but we prefer always
     /// use max size.
     pub fn bytes_per_sector(&self) -> u16 {
-        match &*self {
+        match self {
             Self::Gem => 512,
             Self::Bgm => 8192,
         }
     }
 }
 
-impl Default for PartitionType {
-    fn default() -> Self {
-        Self::Bgm
-    }
-}
-
 //. TOS supported versions.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Tos {
     V100,
+    #[default]
     V104,
 }
 
 impl Tos {
     #[inline]
     pub fn cluster_count(&self) -> u16 {
-        match &*self {
+        match self {
             Self::V100 => 0x3FFF, // 14 bits
             Self::V104 => 0x7FFF, // 15 bits
         }
     }
 }
 
-impl Default for Tos {
-    fn default() -> Self {
-        Tos::V104
-    }
-}
-
 /// Helper to represent FAT12 / FAT16 disk layout.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct DiskLayout {
     tos: Tos,
     partition_type: PartitionType,
@@ -72,6 +62,33 @@ impl DiskLayout {
         }
     }
 
+    /// TOS version this layout has been built for.
+    #[inline]
+    pub fn tos(&self) -> &Tos {
+        &self.tos
+    }
+
+    /// Partition type this layout has been built for.
+    #[inline]
+    pub fn partition_type(&self) -> &PartitionType {
+        &self.partition_type
+    }
+
+    /// Check another layout describe the same disk geometry.
+    ///
+    /// Serving a disk with a different geometry than the one it has been
+    /// built with would make the Atari read garbage, so we refuse it.
+    pub fn ensure_compatible(&self, other: &Self) -> error::Result<()> {
+        if self == other {
+            Ok(())
+        } else {
+            Err(SerialDiskError::IncompatibleLayout(format!(
+                "expected {:?}, found {:?}",
+                self, other
+            )))
+        }
+    }
+
     /// Number of sectors for root directory.
     #[inline]
     pub fn root_directory_sectors(&self) -> u16 {
@@ -134,6 +151,19 @@ impl DiskLayout {
         self.first_free_sector() / self.sectors_per_cluster()
     }
 
+    /// Number of entries in one FAT.
+    #[inline]
+    pub fn fat_entries_count(&self) -> usize {
+        (self.count_1fat_sectors() as usize * self.bytes_per_sector() as usize) / size_of::<u16>()
+            - self.first_free_cluster() as usize
+    }
+
+    /// Number of sectors addressable on the disk.
+    pub fn sector_count(&self) -> u32 {
+        let sector_offset = (self.first_free_sector() - self.reserved_sector()) as u32;
+        sector_offset + self.fat_entries_count() as u32 * self.sectors_per_cluster() as u32
+    }
+
     /// Convert disk layout to buffer that Atari can understand.
     pub fn write_bios_parameter_block<W>(&self, writer: &mut W) -> error::Result<()>
     where
@@ -162,7 +192,14 @@ impl DiskLayout {
         // If I have reimplement this, I would remove this weird stuff.
         let sector_offset = self.first_free_sector() - self.reserved_sector();
 
-        sector_offset + cluster_index * sectors_per_cluster as u16
+        sector_offset + cluster_index * sectors_per_cluster
+    }
+
+    /// Convert data sector index to the index of its cluster.
+    pub fn convert_sector_to_cluster(&self, sector_index: u16) -> u16 {
+        let sector_offset = self.first_free_sector() - self.reserved_sector();
+
+        (sector_index - sector_offset) / self.sectors_per_cluster()
     }
 }
 
@@ -296,6 +333,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sector_count() {
+        assert_eq!(
+            layout!(Tos::V104, PartitionType::Gem).sector_count(),
+            0x01_04 + 32_636 * 2
+        );
+        assert_eq!(
+            layout!(Tos::V104, PartitionType::Bgm).sector_count(),
+            0x00_14 + 32_756 * 2
+        );
+    }
+
+    #[test]
+    fn test_ensure_compatible() {
+        let layout = layout!(Tos::V104, PartitionType::Bgm);
+
+        assert_eq!(
+            layout.ensure_compatible(&layout!(Tos::V104, PartitionType::Bgm)),
+            Ok(())
+        );
+        assert!(matches!(
+            layout.ensure_compatible(&layout!(Tos::V104, PartitionType::Gem)),
+            Err(SerialDiskError::IncompatibleLayout(_))
+        ));
+        assert!(matches!(
+            layout.ensure_compatible(&layout!(Tos::V100, PartitionType::Bgm)),
+            Err(SerialDiskError::IncompatibleLayout(_))
+        ));
+        assert!(matches!(
+            layout.ensure_compatible(&layout!(Tos::V104, PartitionType::Bgm, 4)),
+            Err(SerialDiskError::IncompatibleLayout(_))
+        ));
+    }
+
     #[test]
     fn test_convert_cluster_to_sector() {
         let layout = layout!(Tos::V104, PartitionType::Gem);
@@ -317,5 +388,9 @@ mod tests {
             layout.convert_cluster_to_sector(0x01_00),
             0x01_00 * 2 + 0x00_14
         );
+        assert_eq!(
+            layout.convert_sector_to_cluster(0x01_00 * 2 + 0x00_15),
+            0x01_00
+        );
     }
 }
@@ -1,9 +1,71 @@
+pub mod access_log;
+pub mod activity;
+pub mod backend;
+pub mod backup;
+pub mod boot;
+pub mod boot_profile;
+pub mod bus;
+pub mod chaos;
 pub mod checksum;
+pub mod chunk_size;
+pub mod cipher;
 pub mod config;
+pub mod debug_log;
+pub mod dev_watch;
+pub mod doctor;
 pub mod dos;
+pub mod driver;
+pub mod dump;
+pub mod encoding;
 pub mod entries;
 pub mod error;
+pub mod events;
 pub mod fat;
+#[cfg(any(test, feature = "fixture"))]
+pub mod fixture;
+pub mod floppy;
+pub mod generated;
+pub mod hash;
+pub mod heat_map;
+pub mod hooks;
+pub mod host_exec;
+pub mod http;
+pub mod idle;
+pub mod image;
+pub mod import_report;
+pub mod index;
+pub mod inflate;
+pub mod journal;
 pub mod layout;
+pub mod listener;
+pub mod mdns;
+pub mod name_key;
+pub mod overlay;
+pub mod persistence;
+pub mod prefetch;
+pub mod preflight;
+pub mod privileges;
+#[cfg(test)]
+mod prop;
+pub mod protocol;
+pub mod quota;
+pub mod reverse;
+pub mod rng;
+pub mod scrub;
+pub mod selftest;
+pub mod session_state;
+pub mod session_stats;
+pub mod shared_root;
+pub mod split;
 pub mod state_machine;
 pub mod storage;
+pub mod tools;
+pub mod tos;
+pub mod trace;
+pub mod transaction;
+pub mod transport;
+pub mod trash;
+pub mod usage;
+pub mod verify;
+pub mod websocket;
+pub mod zip;
@@ -0,0 +1,106 @@
+use std::{
+    any::Any,
+    fmt::{self, Display},
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use serde::Serialize;
+
+use crate::{
+    backend::SectorBackend,
+    error::SerialDiskError,
+    persistence::Persistence,
+    state_machine::{self, SessionConfig},
+    storage::DiskStorage,
+    transport::Transport,
+};
+
+/// Why listener thread stopped serving Atari.
+#[derive(Debug)]
+pub enum ListenerFailure {
+    /// Connection with Atari has been lost, reconnecting may fix it
+    Transport(SerialDiskError),
+    /// Any other error
+    Error(SerialDiskError),
+    /// Listener has panicked
+    Panic(String),
+}
+
+impl ListenerFailure {
+    fn from_error(error: SerialDiskError) -> Self {
+        match error {
+            SerialDiskError::Serial(_) | SerialDiskError::IO(_) => Self::Transport(error),
+            _ => Self::Error(error),
+        }
+    }
+
+    fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+
+        Self::Panic(message)
+    }
+}
+
+impl Display for ListenerFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(error) => write!(f, "connection lost: {}", error),
+            Self::Error(error) => write!(f, "{}", error),
+            Self::Panic(message) => write!(f, "panic: {}", message),
+        }
+    }
+}
+
+/// Sent by listener thread to its owner when it stops.
+///
+/// Persistence is given back so a new listener can keep using it.
+#[derive(Debug)]
+pub struct ListenerReport {
+    pub failure: ListenerFailure,
+    pub persistence: Persistence,
+}
+
+/// Serve disk from a dedicated thread and report why it stopped on `reports`.
+///
+/// With a secret in `config`, Atari must authenticate before reading or
+/// writing.
+pub fn spawn<S, B>(
+    storage: Arc<Mutex<DiskStorage<B>>>,
+    mut serial: S,
+    mut persistence: Persistence,
+    config: SessionConfig,
+    reports: Sender<ListenerReport>,
+) -> io::Result<JoinHandle<()>>
+where
+    S: Transport + Send + 'static,
+    B: SectorBackend + Serialize + Send + 'static,
+{
+    thread::Builder::new()
+        .name("listener".to_string())
+        .spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                state_machine::run_with_config(storage, &mut serial, &mut persistence, config)
+            }));
+
+            let failure = match result {
+                Ok(Ok(())) => return,
+                Ok(Err(error)) => ListenerFailure::from_error(error),
+                Err(payload) => ListenerFailure::from_panic(payload),
+            };
+
+            // Owner may already be gone if app is stopping
+            let _ = reports.send(ListenerReport {
+                failure,
+                persistence,
+            });
+        })
+}
@@ -1,50 +1,474 @@
 use std::{
+    fmt,
     fs::{self, File},
-    io::BufWriter,
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    net::TcpListener,
     path::{Path, PathBuf},
     process,
     sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
         Arc, Mutex,
     },
     thread,
     time::{Duration, Instant},
 };
 
-use ataridisk::{config::Config, error, layout::DiskLayout, storage::DiskStorage};
-use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
-use structopt::StructOpt;
+use anyhow::Context;
+use ataridisk::{
+    activity,
+    backend::{JournaledBackend, MmapBackend, SectorBackend},
+    backup,
+    boot_profile::{BootProfile, PinnedReads},
+    chaos::{Chaos, ChaosTransport},
+    cipher,
+    config::{Config, Source},
+    debug_log,
+    dev_watch::{self, DevWatch},
+    doctor, driver, dump,
+    encoding::{EncodedTransport, Encoding},
+    error,
+    events::{self, Event},
+    generated::GeneratedFile,
+    hash,
+    heat_map::HeatMap,
+    idle::{IdleBackoff, IdleConfig},
+    image,
+    import_report::ImportReport,
+    journal::{self, WriteJournal},
+    layout::DiskLayout,
+    listener::{self, ListenerFailure, ListenerReport},
+    mdns,
+    overlay::{self, OverlayConfig, OverlayPolicy},
+    persistence::Persistence,
+    preflight::{Area, Preflight},
+    privileges, protocol, scrub, selftest,
+    session_state::SessionState,
+    session_stats::SessionStats,
+    split,
+    state_machine::{self, SessionConfig},
+    storage::{DiskStorage, FormatPolicy},
+    tools,
+    trace::{self, Trace, TraceRecorder},
+    transport::{TcpKeepalive, TcpTransport, Transport},
+    trash::Trash,
+    usage,
+    websocket::EventHub,
+};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde::Serialize;
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits, TTYPort};
+use structopt::{clap::AppSettings, StructOpt};
+
+/// Exit code when an unrecoverable error stopped listener thread.
+const EXIT_LISTENER_ERROR: i32 = 2;
+/// Exit code when connection with Atari is lost and cannot be reopened.
+const EXIT_CONNECTION_LOST: i32 = 3;
+/// Exit code when listener thread panicked.
+const EXIT_LISTENER_PANIC: i32 = 4;
+/// Exit code when config or command line options are invalid.
+const EXIT_CONFIG: i32 = 5;
+/// Exit code when serial port or TCP listener cannot be opened.
+const EXIT_PORT: i32 = 6;
+/// Exit code when disk content cannot be imported or restored.
+const EXIT_IMPORT: i32 = 7;
+
+const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+const DEFAULT_DUMP: &str = "ramdisk.dump";
+const BAUD_RATE: u32 = 19200;
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+const MAX_RECONNECT: usize = 5;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
 
 #[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::SubcommandsNegateReqs)]
 struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// List available ports and close the app
     #[structopt(long)]
     list_availables: bool,
 
+    /// Print the protocol spoken with Atari driver as JSON and close the app
+    #[structopt(long)]
+    print_protocol: bool,
+
+    /// Only log errors and do not print status messages
+    #[structopt(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more details (repeat for even more)
+    #[structopt(long, short, parse(from_occurrences))]
+    verbose: u8,
+
+    /// Print newline delimited JSON events on stdout
+    #[structopt(long)]
+    events_json: bool,
+
     /// Config file to load
     #[structopt(long, short, default_value = "config.json")]
     config_path: PathBuf,
 
-    /// Port to connect with
-    #[structopt(long, short, default_value = "/dev/ttyUSB0")]
-    port: String,
+    /// Config profile of the machine to serve
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// Port to connect with (asked when several ports exist, default to /dev/ttyUSB0)
+    #[structopt(long, short)]
+    port: Option<String>,
+
+    /// Wait for Atari on this TCP address instead of a serial port, for WiFi
+    /// bridges and emulators (ex: `0.0.0.0:6502`)
+    #[structopt(long, conflicts_with = "port")]
+    tcp: Option<String>,
 
-    /// RAM disk dump filename
-    #[structopt(long, short, default_value = "ramdisk.dump")]
-    dump: String,
+    /// Do not announce TCP listener on local network with mDNS
+    #[structopt(long, requires = "tcp")]
+    no_mdns: bool,
+
+    /// Port where to serve a read only drive with Atari side tools
+    #[structopt(long)]
+    tools_port: Option<String>,
+
+    /// Inject faults on the link with Atari, to test driver retries
+    /// (ex: "drop=1000,flip=0.001,delay=200,seed=7")
+    #[structopt(long)]
+    chaos: Option<Chaos>,
+
+    /// Encode bytes exchanged with Atari, for links only passing printable
+    /// characters (raw, base16 or kermit)
+    #[structopt(long, default_value = "raw")]
+    encoding: Encoding,
+
+    /// Keep a disk folder updated with files of a host build output directory
+    /// (ex: "build/out:DEV"), can be repeated
+    #[structopt(long, number_of_values = 1)]
+    dev_watch: Vec<DevWatch>,
+
+    /// Record bytes exchanged with Atari in a trace file
+    #[structopt(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay a recorded trace against a fresh disk instead of serving Atari,
+    /// and check server answers match the recording
+    #[structopt(long)]
+    replay: Option<PathBuf>,
+
+    /// User to switch to once serial port is open (reopening port must still be allowed)
+    #[structopt(long)]
+    user: Option<String>,
+
+    /// Group to switch to once serial port is open (default to user main group)
+    #[structopt(long)]
+    group: Option<String>,
+
+    /// RAM disk dump filename [default: ramdisk.dump]
+    #[structopt(long, short)]
+    dump: Option<PathBuf>,
+
+    /// Journal file where every write command is recorded
+    #[structopt(long, short)]
+    journal: Option<PathBuf>,
+
+    /// Replay journal on top of disk content before serving it
+    #[structopt(long, requires = "journal")]
+    replay_journal: bool,
+
+    /// File where to record what Atari reads while booting, to give as
+    /// `boot_profile` in config
+    #[structopt(long)]
+    record_boot: Option<PathBuf>,
+
+    /// Share disk content read only over HTTP on this address (ex: `8080` for localhost,
+    /// `0.0.0.0:8080` with `tcp_secret` configured)
+    #[structopt(long)]
+    http: Option<String>,
+
+    /// Serve a raw disk image mapped in memory instead of a RAM disk
+    #[structopt(long, short, conflicts_with = "resume")]
+    image: Option<PathBuf>,
+
+    /// Update image from a journal of its writes (`<image>.journal`), so a crash never leaves
+    /// it half written
+    #[structopt(long, requires = "image")]
+    image_journal: bool,
+
+    /// Restore RAM disk from dump file instead of importing a path
+    #[structopt(long, short)]
+    resume: bool,
+
+    /// Start with an empty disk, to format and fill it from Atari
+    #[structopt(long, conflicts_with_all = &["resume", "load-zip", "load-path"])]
+    blank: bool,
+
+    /// ZIP archive to import as virtual disk content
+    #[structopt(long, conflicts_with = "resume")]
+    load_zip: Option<PathBuf>,
+
+    /// Other host directory merged in the disk, in a sub directory when
+    /// given (ex: "~/games:GAMES"), ignoring Atari writes to it with ":ro"
+    /// suffix, can be repeated
+    #[structopt(long = "source", number_of_values = 1, conflicts_with = "resume")]
+    sources: Vec<Source>,
 
     /// Path to import as virtual disk content
-    load_path: PathBuf,
+    load_path: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Write the Atari side driver bundled with this server
+    Driver {
+        /// Path where to write the driver
+        #[structopt(long, short, required_unless = "list")]
+        out: Option<PathBuf>,
+
+        /// Protocol version of the driver (default to the server one)
+        #[structopt(long)]
+        protocol: Option<u8>,
+
+        /// List bundled drivers and exit
+        #[structopt(long)]
+        list: bool,
+    },
+
+    /// Print serial ports, user groups and hints to join to bug reports
+    Doctor,
+
+    /// Check every file of a RAM disk dump can be read
+    Check {
+        /// Dump file to check
+        dump: PathBuf,
+
+        /// Print SHA-1 and CRC32 of each file
+        #[structopt(long)]
+        hashes: bool,
+    },
+
+    /// Check serial cable with TX and RX connected together
+    Selftest {
+        /// Number of times every test pattern is sent
+        #[structopt(long, default_value = "3")]
+        rounds: usize,
+    },
+
+    /// Print clusters used by each directory and the largest files
+    Du {
+        /// RAM disk dump, or host directory to measure as it would be imported
+        source: PathBuf,
+
+        /// Number of largest files to list
+        #[structopt(long, default_value = "10")]
+        top: usize,
+
+        /// Directory levels to print
+        #[structopt(long, default_value = "3")]
+        depth: usize,
+    },
+
+    /// Put a file deleted from Atari back in a RAM disk dump
+    Undelete {
+        /// Dump file to restore file in
+        dump: PathBuf,
+
+        /// Disk path of the file (ex: `GAMES\SAVE.DAT`)
+        path: String,
+
+        /// Trash folder (default to `trash_dir` config entry)
+        #[structopt(long)]
+        trash: Option<PathBuf>,
+    },
+
+    /// Rejoin a file imported as numbered parts from a RAM disk dump
+    Join {
+        /// Dump file holding the parts
+        dump: PathBuf,
+
+        /// Disk path of the manifest (ex: `IMAGES\BIGDISK.SPL`)
+        manifest: String,
+
+        /// Directory where to write the file (default to current one)
+        #[structopt(long, short, default_value = ".")]
+        out: PathBuf,
+    },
+
+    /// Compare a RAM disk dump with a host directory
+    Verify {
+        /// Dump file to check
+        dump: PathBuf,
+
+        /// Host directory to compare dump with
+        dir: PathBuf,
+
+        /// Do not report files only differing by modification date
+        #[structopt(long)]
+        ignore_timestamps: bool,
+    },
+
+    /// Export RAM disk dumps or host directories as a raw disk image
+    Export {
+        /// Dumps or directories to export, one partition each (C:, D:...)
+        #[structopt(required = true)]
+        sources: Vec<PathBuf>,
+
+        /// Path where to write the image
+        #[structopt(long, short)]
+        out: PathBuf,
+
+        /// Prepend an AHDI partition table, to write image to a CF/SD card
+        #[structopt(long)]
+        ahdi: bool,
+
+        /// Boot sector whose code makes first partition bootable
+        #[structopt(long)]
+        boot_sector: Option<PathBuf>,
+    },
+
+    /// Rebuild disk as it was at some moment of a session from its journal
+    Replay {
+        /// Write journal of the session (`--journal`)
+        journal: PathBuf,
+
+        /// RAM disk dump, or host directory, the session started from
+        #[structopt(long)]
+        base: PathBuf,
+
+        /// Last moment to replay writes of, as local time (ex: `2024-03-09
+        /// 17:45:00`) or UNIX timestamp [default: end of journal]
+        #[structopt(long, parse(try_from_str = parse_time))]
+        until: Option<DateTime<Utc>>,
+
+        /// Dump file where to write rebuilt disk
+        #[structopt(long, short)]
+        out: PathBuf,
+    },
+
+    /// Export sector read and write counts as CSV, or as a PNG image
+    HeatMap {
+        /// Counts file [default: heat_map_file of config]
+        file: Option<PathBuf>,
+
+        /// Path where to write counts, a PNG image when ending with `.png`
+        #[structopt(long, short)]
+        out: PathBuf,
+    },
 }
 
 impl Opt {
-    fn config(&self) -> Config {
-        fn load_config(path: &Path) -> Option<Config> {
-            let content = fs::read_to_string(path).ok()?;
-            serde_json::from_str(&content).ok()
+    fn port(&self) -> &str {
+        self.port.as_deref().unwrap_or(DEFAULT_PORT)
+    }
+
+    fn dump(&self) -> &Path {
+        self.dump
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_DUMP))
+    }
+
+    /// Config file content, default one if there is no file.
+    fn config(&self) -> anyhow::Result<Config> {
+        let content = match fs::read_to_string(&self.config_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).context(format!("cannot read {:?}", self.config_path)),
+        };
+        serde_json::from_str(&content).context(format!("invalid {:?}", self.config_path))
+    }
+
+    /// Log level to use when `RUST_LOG` is not set.
+    fn log_level(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Error;
+        }
+
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+
+    /// Tell if status messages for humans can be printed on stdout.
+    fn print_status(&self) -> bool {
+        !self.quiet && !self.events_json
+    }
+
+    fn has_content_to_import(&self) -> bool {
+        self.load_path.is_some() || self.load_zip.is_some() || !self.sources.is_empty()
+    }
+}
+
+/// Load config and apply profile selected with `--profile`.
+///
+/// Options given on command line take precedence over profile ones.
+fn configure(opt: &mut Opt) -> anyhow::Result<Config> {
+    let mut config = opt.config().context(Failure::Config)?;
+
+    if let Some(name) = &opt.profile {
+        let profile = config
+            .select_profile(name)
+            .ok_or_else(|| anyhow::anyhow!("no profile {:?} in {:?}", name, opt.config_path))
+            .context(Failure::Config)?;
+        log::info!("Using profile {:?}", name);
+
+        opt.port = opt.port.take().or(profile.port);
+        opt.dump = opt.dump.take().or(profile.dump);
+        if !opt.resume && !opt.blank {
+            opt.load_path = opt.load_path.take().or(profile.load_path);
         }
+    }
+    if !opt.resume && !opt.blank {
+        opt.sources.extend(config.sources.iter().cloned());
+        opt.sources.extend(config.mount_sources());
+    }
+
+    log::info!("Configuration: {:?}", config);
+    Ok(config)
+}
+
+/// Choose port to use when none is given.
+///
+/// Default port is used unless several ports exist and app is run from a
+/// terminal, then user is asked which one to use.
+fn pick_port() -> anyhow::Result<Option<String>> {
+    let ports: Vec<String> = serialport::available_ports()?
+        .into_iter()
+        .map(|port| port.port_name)
+        .collect();
 
-        load_config(&self.config_path).unwrap_or_default()
+    match ports.as_slice() {
+        [] => return Ok(None),
+        [port] => return Ok(Some(port.clone())),
+        _ => {}
+    }
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    println!("Several ports are available:");
+    for (index, port) in ports.iter().enumerate() {
+        println!("{}) {}", index + 1, port);
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("Port to use [1-{}]: ", ports.len());
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => anyhow::bail!("no port selected"),
+        };
+        match line.trim().parse::<usize>() {
+            Ok(choice) if (1..=ports.len()).contains(&choice) => {
+                return Ok(Some(ports[choice - 1].clone()));
+            }
+            _ => println!("Invalid choice {:?}", line.trim()),
+        }
     }
 }
 
@@ -58,7 +482,290 @@ fn print_availables() -> error::Result<()> {
     Ok(())
 }
 
-fn wait_sigterm() -> anyhow::Result<()> {
+/// Write bundled Atari driver to disk.
+fn write_driver(out: &Option<PathBuf>, protocol: Option<u8>, list: bool) -> error::Result<()> {
+    if list {
+        println!(
+            "Bundled drivers (server protocol version: {}):",
+            protocol::VERSION
+        );
+        for version in driver::bundled_versions() {
+            println!("- protocol version {}", version);
+        }
+        return Ok(());
+    }
+
+    let protocol = protocol.unwrap_or(protocol::VERSION);
+    if let Some(out) = out {
+        driver::write_driver(protocol, out)?;
+        println!(
+            "Driver for protocol version {} written to {:?}",
+            protocol, out
+        );
+    }
+
+    Ok(())
+}
+
+/// Print integrity report of a dump.
+fn check(dump_path: &Path, hashes: bool) -> anyhow::Result<()> {
+    let mut dump_reader = BufReader::new(File::open(dump_path)?);
+    let storage = dump::read_dump(&mut dump_reader)?;
+    let report = storage.hash_report()?;
+
+    if hashes {
+        for file in &report.files {
+            println!(
+                "{}  {:08x}  {:>10}  {}",
+                hash::to_hex(&file.sha1),
+                file.crc32,
+                file.size,
+                file.path
+            );
+        }
+    }
+
+    println!(
+        "{} files, {} bytes, disk SHA-1: {}",
+        report.files.len(),
+        report.files.iter().map(|f| f.size).sum::<usize>(),
+        hash::to_hex(&report.disk_sha1)
+    );
+    Ok(())
+}
+
+/// Print differences between a dump and a host directory.
+fn verify(dump_path: &Path, dir: &Path, ignore_timestamps: bool) -> anyhow::Result<()> {
+    let mut dump_reader = BufReader::new(File::open(dump_path)?);
+    let storage = dump::read_dump(&mut dump_reader)?;
+
+    let differences: Vec<_> = ataridisk::verify::compare(&storage, dir)?
+        .into_iter()
+        .filter(|d| !(ignore_timestamps && d.is_modified()))
+        .collect();
+
+    for difference in &differences {
+        println!("{}", difference);
+    }
+
+    if differences.is_empty() {
+        println!("{:?} matches {:?}", dump_path, dir);
+        Ok(())
+    } else {
+        anyhow::bail!("{} differences found", differences.len())
+    }
+}
+
+/// Write dumps or directories as a raw partition or AHDI disk image.
+fn export(
+    sources: &[PathBuf],
+    out: &Path,
+    ahdi: bool,
+    boot_code: Option<&[u8]>,
+    config: &Config,
+) -> anyhow::Result<()> {
+    if sources.len() > 1 && !ahdi {
+        anyhow::bail!("several partitions can only be exported with --ahdi");
+    }
+    let mut storages = sources
+        .iter()
+        .map(|source| load_source(source, config))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Boot files are read from first partition
+    if let Some(path) = &config.boot_profile {
+        let profile = BootProfile::load(path)?;
+        let packed = storages[0].pack_files(&profile.files)?;
+        log::info!("{} boot files laid contiguously", packed);
+    }
+
+    let mut writer = io::BufWriter::new(File::create(out)?);
+    if ahdi {
+        let storages: Vec<_> = storages.iter().collect();
+        image::write_ahdi_disk(&storages, &config.boot_sector, boot_code, &mut writer)?;
+    } else {
+        image::write_partition(&storages[0], &config.boot_sector, boot_code, &mut writer)?;
+    }
+    writer.flush()?;
+
+    println!("{} partitions exported to {:?}", storages.len(), out);
+    Ok(())
+}
+
+/// Read a dump, or import a directory as it would be served.
+fn load_source(source: &Path, config: &Config) -> anyhow::Result<DiskStorage> {
+    Ok(if source.is_dir() {
+        let mut storage = DiskStorage::new(DiskLayout::new(
+            config.tos.clone(),
+            config.partition_type.clone(),
+            config.root_directory_sectors(),
+        ));
+        storage.set_name_policy(config.name_policy);
+        storage.set_extension_map(&config.extension_map);
+        storage.set_executable_policy(config.executable_policy);
+        storage.set_split_size(config.split_size);
+        storage.set_max_depth(config.max_depth);
+        storage.import_path(source)?;
+        storage
+    } else {
+        let mut dump_reader = BufReader::new(File::open(source)?);
+        dump::read_dump(&mut dump_reader)?
+    })
+}
+
+/// Print disk usage of a dump or of a directory once imported.
+fn du(source: &Path, top: usize, depth: usize, config: &Config) -> anyhow::Result<()> {
+    let storage = load_source(source, config)?;
+
+    let report = usage::disk_usage(&storage)?;
+    let mut tree = String::new();
+    report.write_tree(&mut tree, depth)?;
+    print!("{}", tree);
+
+    println!();
+    println!("Largest files:");
+    for file in report.largest(top) {
+        println!(
+            "{:>8} clusters {:>10} bytes  {}",
+            file.clusters, file.bytes, file.path
+        );
+    }
+
+    println!();
+    println!(
+        "{} clusters used, {} free ({} bytes per cluster)",
+        report.root.clusters, report.free_clusters, report.bytes_per_cluster
+    );
+    Ok(())
+}
+
+/// Restore a trashed file in a dump.
+fn undelete(dump_path: &Path, path: &str, trash_dir: &Path) -> anyhow::Result<()> {
+    let mut dump_reader = BufReader::new(File::open(dump_path)?);
+    let mut storage = dump::read_dump(&mut dump_reader)?;
+
+    Trash::new(trash_dir)?.restore(&mut storage, path)?;
+    let compress = dump::is_compressed(dump_path)?;
+    dump::write_dump_file(dump_path, &storage, compress)?;
+    println!("{} restored in {:?}", path, dump_path);
+    Ok(())
+}
+
+fn join(dump_path: &Path, manifest: &str, out_dir: &Path) -> anyhow::Result<()> {
+    let mut dump_reader = BufReader::new(File::open(dump_path)?);
+    let storage = dump::read_dump(&mut dump_reader)?;
+
+    let (name, content) = split::join(&storage, manifest)?;
+    // Name comes from a file Atari may have changed
+    let name = Path::new(&name)
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("invalid file name {:?} in manifest", name))?;
+    let out = out_dir.join(name);
+    fs::write(&out, content)?;
+    println!("{} rejoined in {:?}", manifest, out);
+    Ok(())
+}
+
+/// Parse a local date and time, or a UNIX timestamp.
+fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(seconds) = text.parse::<i64>() {
+        return Ok(Utc.timestamp(seconds, 0));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("invalid time {:?}, expected YYYY-MM-DD HH:MM:SS", text))
+}
+
+/// Apply journal writes received up to `until` on top of `base`.
+fn replay_journal(
+    journal_path: &Path,
+    base: &Path,
+    until: Option<DateTime<Utc>>,
+    out: &Path,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let mut storage = load_source(base, config)?;
+    let mut reader = BufReader::new(File::open(journal_path)?);
+    let replayed = journal::replay_until(&mut reader, &mut storage, until)?;
+
+    if until.is_some() && replayed.writes > 0 && replayed.last_time.is_none() {
+        log::warn!(
+            "{:?} has no time marks, every write has been replayed",
+            journal_path
+        );
+    }
+    dump::write_dump_file(out, &storage, config.lz4_dump)?;
+    match replayed.last_time {
+        Some(time) => println!(
+            "{} writes replayed in {:?}, last one at {}",
+            replayed.writes,
+            out,
+            time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => println!("{} writes replayed in {:?}", replayed.writes, out),
+    }
+    Ok(())
+}
+
+fn export_heat_map(file: &Path, out: &Path) -> anyhow::Result<()> {
+    if !file.exists() {
+        anyhow::bail!(
+            "{:?} does not exist, serve Atari with heat_map_file set first",
+            file
+        );
+    }
+    let heat_map = HeatMap::load(file);
+    let is_png = out
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if is_png {
+        fs::write(out, heat_map.to_png())?;
+    } else {
+        fs::write(out, heat_map.to_csv())?;
+    }
+    println!(
+        "Counts of {} sectors written to {:?}",
+        heat_map.sectors.len(),
+        out
+    );
+    Ok(())
+}
+
+/// Send test patterns to a serial loopback and print what came back.
+fn selftest(port: &str, rounds: usize) -> anyhow::Result<()> {
+    let mut serial = open_serial(port)?;
+    serial.set_timeout(SELFTEST_TIMEOUT)?;
+
+    println!(
+        "Connect TX and RX of {} together, testing at {} bauds...",
+        port, BAUD_RATE
+    );
+    let report = selftest::run(&mut serial, rounds)?;
+    for result in &report.results {
+        println!("{}", result);
+    }
+
+    if let (Some(latency), Some(throughput)) = (report.max_latency(), report.throughput()) {
+        println!(
+            "Max latency: {} ms, throughput: {:.0} bytes/s",
+            latency.as_millis(),
+            throughput
+        );
+    }
+
+    if report.is_ok() {
+        println!("Loopback is working");
+        Ok(())
+    } else {
+        anyhow::bail!("loopback test failed, check cable and wiring")
+    }
+}
+
+/// Return a flag cleared when app is asked to stop.
+fn running_flag() -> anyhow::Result<Arc<AtomicBool>> {
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
 
@@ -66,36 +773,580 @@ fn wait_sigterm() -> anyhow::Result<()> {
         r.store(false, Ordering::SeqCst);
     })?;
 
+    Ok(running)
+}
+
+/// Open serial port, telling how to get access to it when denied.
+fn open_serial(port: &str) -> anyhow::Result<TTYPort> {
+    let opened = serialport::new(port, BAUD_RATE)
+        .parity(Parity::None)
+        .timeout(Duration::from_secs(24 * 3600))
+        .flow_control(FlowControl::None)
+        .data_bits(DataBits::Eight)
+        .stop_bits(StopBits::One)
+        .open_native();
+    let serial = match opened {
+        Err(e) if e.kind() == serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+            match doctor::permission_hint(port) {
+                Some(hint) => anyhow::bail!("{}: {} ({})", port, e, hint),
+                None => return Err(e.into()),
+            }
+        }
+        opened => opened?,
+    };
+
+    serial.clear(ClearBuffer::All)?;
+    Ok(serial)
+}
+
+/// Where Atari connects from.
+enum Endpoint {
+    /// Serial port, opened again when connection is lost
+    Serial(String),
+    /// TCP listener, accepting a new connection when connection is lost
+    Tcp {
+        listener: TcpListener,
+        keepalive: TcpKeepalive,
+        /// Secret Atari must authenticate with, if any
+        secret: Option<String>,
+        /// Key encrypting connections, if any
+        encryption_key: Option<cipher::LinkKey>,
+    },
+}
+
+impl Endpoint {
+    fn name(&self) -> String {
+        match self {
+            Self::Serial(port) => port.clone(),
+            Self::Tcp { listener, .. } => match listener.local_addr() {
+                Ok(addr) => format!("tcp://{}", addr),
+                Err(_) => "tcp".to_string(),
+            },
+        }
+    }
+
+    /// Open a link, waiting for a TCP connection until `running` is cleared.
+    fn connect(&self, running: &AtomicBool, idle: IdleConfig) -> anyhow::Result<Option<Link>> {
+        let (listener, keepalive, encryption_key) = match self {
+            Self::Serial(port) => return Ok(Some(Link::Serial(open_serial(port)?))),
+            Self::Tcp {
+                listener,
+                keepalive,
+                encryption_key,
+                ..
+            } => (listener, keepalive, encryption_key),
+        };
+
+        // Listener is non blocking, so stop signal is still noticed
+        let mut backoff = IdleBackoff::new(idle);
+        while running.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    log::info!("Atari connected from {}", peer);
+                    stream.set_nonblocking(false)?;
+                    let mut tcp = TcpTransport::new(stream, keepalive)?;
+                    if let Some(key) = encryption_key {
+                        tcp = tcp.encrypt(key)?;
+                    }
+                    return Ok(Some(Link::Tcp(tcp)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(backoff.next_delay())
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Limit of reconnections in a row, if any.
+    ///
+    /// WiFi bridges drop their connection routinely, so TCP connections are
+    /// accepted again without limit.
+    fn max_reconnect(&self) -> Option<usize> {
+        match self {
+            Self::Serial(_) => Some(MAX_RECONNECT),
+            Self::Tcp { .. } => None,
+        }
+    }
+
+    /// Secret Atari must authenticate with: serial links are physical, only
+    /// TCP ones can be reached by any device on the network.
+    fn secret(&self) -> Option<String> {
+        match self {
+            Self::Serial(_) => None,
+            Self::Tcp { secret, .. } => secret.clone(),
+        }
+    }
+
+    /// Announce TCP listener on local network.
+    fn announce(&self) {
+        let Self::Tcp { listener, .. } = self else {
+            return;
+        };
+        let announced = listener
+            .local_addr()
+            .and_then(mdns::Service::for_listener)
+            .and_then(mdns::spawn);
+        if let Err(e) = announced {
+            log::warn!("Cannot announce TCP listener with mDNS (error: {})", e);
+        }
+    }
+}
+
+/// Connection with Atari, opened from an `Endpoint`.
+enum Link {
+    Serial(TTYPort),
+    Tcp(TcpTransport),
+}
+
+impl Read for Link {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Serial(serial) => serial.read(buf),
+            Self::Tcp(tcp) => tcp.read(buf),
+        }
+    }
+}
+
+impl Write for Link {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Serial(serial) => serial.write(buf),
+            Self::Tcp(tcp) => tcp.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Serial(serial) => serial.flush(),
+            Self::Tcp(tcp) => tcp.flush(),
+        }
+    }
+}
+
+impl Transport for Link {
+    fn discard_pending(&mut self) -> error::Result<()> {
+        match self {
+            Self::Serial(serial) => serial.discard_pending(),
+            Self::Tcp(tcp) => tcp.discard_pending(),
+        }
+    }
+}
+
+/// Start listener thread on `link`, encoding bytes and injecting faults in
+/// the link if asked to. Link is served with the session config given with
+/// it.
+fn spawn_listener<B>(
+    opt: &Opt,
+    storage: Arc<Mutex<DiskStorage<B>>>,
+    (serial, session): (Link, SessionConfig),
+    persistence: Persistence,
+    reports: Sender<ListenerReport>,
+) -> io::Result<()>
+where
+    B: SectorBackend + Serialize + Send + 'static,
+{
+    let serial = EncodedTransport::new(serial, opt.encoding);
+    match opt.chaos {
+        Some(chaos) => spawn_recorded(
+            opt,
+            storage,
+            (ChaosTransport::new(serial, chaos), session),
+            persistence,
+            reports,
+        ),
+        None => spawn_recorded(opt, storage, (serial, session), persistence, reports),
+    }
+}
+
+/// Start listener thread, recording what it exchanges with Atari if asked
+/// to. Faults are recorded as seen by the server, so a trace replays the
+/// same way.
+fn spawn_recorded<S, B>(
+    opt: &Opt,
+    storage: Arc<Mutex<DiskStorage<B>>>,
+    (serial, session): (S, SessionConfig),
+    persistence: Persistence,
+    reports: Sender<ListenerReport>,
+) -> io::Result<()>
+where
+    S: Transport + Send + 'static,
+    B: SectorBackend + Serialize + Send + 'static,
+{
+    match &opt.record {
+        Some(trace_path) => listener::spawn(
+            storage,
+            TraceRecorder::append(serial, trace_path)?,
+            persistence,
+            session,
+            reports,
+        ),
+        None => listener::spawn(storage, serial, persistence, session, reports),
+    }
+    .map(|_| ())
+}
+
+/// Wait for stop signal while supervising listener thread.
+///
+/// Lost connections are reopened, any other failure stops the app
+/// and give the exit code to use.
+fn supervise<B>(
+    opt: &Opt,
+    (endpoint, session, running): (&Endpoint, &SessionConfig, &AtomicBool),
+    storage: &Arc<Mutex<DiskStorage<B>>>,
+    reports: (Sender<ListenerReport>, Receiver<ListenerReport>),
+    idle: IdleConfig,
+) -> anyhow::Result<i32>
+where
+    B: SectorBackend + Serialize + Send + 'static,
+{
+    let (sender, receiver) = reports;
+    let mut reconnect_count = 0;
+
+    // Reports wake the loop at once, delay only matters for stop signal
+    let mut backoff = IdleBackoff::new(idle);
     while running.load(Ordering::SeqCst) {
-        thread::sleep(Duration::from_millis(250));
+        let report = match receiver.recv_timeout(backoff.next_delay()) {
+            Ok(report) => report,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(EXIT_LISTENER_PANIC),
+        };
+
+        log::error!("Listener thread stopped ({})", report.failure);
+        if opt.events_json {
+            Event::Error {
+                message: report.failure.to_string(),
+            }
+            .emit();
+        }
+        match report.failure {
+            ListenerFailure::Transport(_)
+                if endpoint
+                    .max_reconnect()
+                    .is_none_or(|max| reconnect_count < max) =>
+            {
+                reconnect_count += 1;
+                thread::sleep(RECONNECT_DELAY);
+
+                // Listener starts again in waiting state, serving same disk
+                match endpoint.connect(running, idle) {
+                    Ok(Some(serial)) => {
+                        match endpoint.max_reconnect() {
+                            Some(max) => log::info!(
+                                "Reconnected to {} (attempt {}/{})",
+                                endpoint.name(),
+                                reconnect_count,
+                                max
+                            ),
+                            None => log::info!(
+                                "Reconnected to {} (attempt {})",
+                                endpoint.name(),
+                                reconnect_count
+                            ),
+                        }
+                        spawn_listener(
+                            opt,
+                            storage.clone(),
+                            (serial, session.clone()),
+                            report.persistence,
+                            sender.clone(),
+                        )?;
+                    }
+                    Ok(None) => break,
+                    Err(error) => {
+                        log::error!("Cannot reopen {} (error: {})", endpoint.name(), error);
+                        return Ok(EXIT_CONNECTION_LOST);
+                    }
+                }
+            }
+            ListenerFailure::Transport(_) => return Ok(EXIT_CONNECTION_LOST),
+            ListenerFailure::Error(_) => return Ok(EXIT_LISTENER_ERROR),
+            ListenerFailure::Panic(_) => return Ok(EXIT_LISTENER_PANIC),
+        }
     }
 
-    Ok(())
+    Ok(0)
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::init();
+/// Startup step that failed, telling which exit code to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Config,
+    Port,
+    Import,
+}
 
-    let opt = Opt::from_args();
+impl Failure {
+    fn exit_code(self) -> i32 {
+        match self {
+            Self::Config => EXIT_CONFIG,
+            Self::Port => EXIT_PORT,
+            Self::Import => EXIT_IMPORT,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Config => "invalid configuration",
+            Self::Port => "cannot open connection with Atari",
+            Self::Import => "cannot load disk content",
+        })
+    }
+}
+
+/// Check everything serving needs before starting, reporting all problems
+/// at once.
+fn preflight(opt: &Opt, config: &Config) -> anyhow::Result<()> {
+    let mut preflight = Preflight::default();
+    if !opt.has_content_to_import() && !opt.resume && !opt.blank {
+        preflight.fail(
+            Area::Options,
+            "no path to import",
+            "give one or use --resume or --blank",
+        );
+    }
+    if opt.image.is_some() && config.overlay.is_some() {
+        preflight.fail(
+            Area::Options,
+            "disk image is written in place",
+            "overlay needs a RAM disk",
+        );
+    }
+
+    // Replays do not talk to Atari
+    if opt.replay.is_none() {
+        match (&opt.tcp, &opt.port) {
+            (Some(addr), _) => preflight.check_tcp(addr),
+            (None, Some(port)) => preflight.check_port(port),
+            // Picked later among available ones
+            (None, None) => {}
+        }
+        if let Some(port) = &opt.tools_port {
+            preflight.check_port(port);
+        }
+    }
+
+    // Content of a non empty image is not imported again
+    let imports = !opt.resume && !opt.blank && opt.image.as_ref().is_none_or(|p| !p.exists());
+    if imports {
+        for path in opt
+            .load_path
+            .iter()
+            .chain(opt.sources.iter().map(|s| &s.path))
+        {
+            preflight.check_import_dir(path);
+        }
+        if let Some(zip_path) = &opt.load_zip {
+            preflight.check_readable_file(Area::Content, zip_path);
+        }
+    }
+
+    match &opt.image {
+        Some(image_path) => preflight.check_writable_file(image_path),
+        None => {
+            if opt.resume {
+                preflight.check_readable_file(Area::DiskFile, opt.dump());
+            }
+            // Base layer of an overlay is never written
+            if config.overlay.is_none() {
+                preflight.check_writable_file(opt.dump());
+            }
+        }
+    }
+    if let Some(journal_path) = &opt.journal {
+        preflight.check_writable_file(journal_path);
+    }
+
+    preflight.check_layout(&DiskLayout::new(
+        config.tos.clone(),
+        config.partition_type.clone(),
+        config.root_directory_sectors(),
+    ));
+
+    let problems = preflight.problems();
+    let failure = match problems.first().map(|problem| problem.area) {
+        None => return Ok(()),
+        Some(Area::Port) => Failure::Port,
+        Some(Area::Content) => Failure::Import,
+        Some(_) => Failure::Config,
+    };
+    let lines: Vec<String> = problems.iter().map(|p| format!("- {}", p)).collect();
+    Err(anyhow::anyhow!(
+        "{} problems found before starting:\n{}",
+        problems.len(),
+        lines.join("\n")
+    ))
+    .context(failure)
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {:?}", error);
+        let exit_code = error.downcast_ref::<Failure>().map_or(1, |f| f.exit_code());
+        process::exit(exit_code);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let mut opt = Opt::from_args();
+
+    env_logger::Builder::new()
+        .filter_level(opt.log_level())
+        .parse_env("RUST_LOG")
+        .init();
+
+    if opt.events_json {
+        events::emit_transactions();
+    }
 
     if opt.list_availables {
         print_availables()?;
         return Ok(());
     }
 
+    if opt.print_protocol {
+        let description = serde_json::to_string_pretty(&protocol::PROTOCOL)?;
+        println!("{}", description);
+        return Ok(());
+    }
+
+    match &opt.command {
+        Some(Command::Driver {
+            out,
+            protocol,
+            list,
+        }) => {
+            write_driver(out, *protocol, *list)?;
+            return Ok(());
+        }
+        Some(Command::Doctor) => {
+            print!("{}", doctor::report());
+            return Ok(());
+        }
+        Some(Command::Check { dump, hashes }) => {
+            return check(dump, *hashes);
+        }
+        Some(Command::Selftest { rounds }) => {
+            let rounds = *rounds;
+            configure(&mut opt)?;
+            if opt.port.is_none() {
+                opt.port = pick_port()?;
+            }
+            return selftest(opt.port(), rounds);
+        }
+        Some(Command::Du { source, top, depth }) => {
+            let (source, top, depth) = (source.clone(), *top, *depth);
+            let config = configure(&mut opt)?;
+            return du(&source, top, depth, &config);
+        }
+        Some(Command::Undelete { dump, path, trash }) => {
+            let (dump, path, trash) = (dump.clone(), path.clone(), trash.clone());
+            let config = configure(&mut opt)?;
+            let trash = trash
+                .or(config.trash_dir)
+                .ok_or_else(|| anyhow::anyhow!("no trash folder, give one with --trash"))?;
+            return undelete(&dump, &path, &trash);
+        }
+        Some(Command::Join {
+            dump,
+            manifest,
+            out,
+        }) => {
+            return join(dump, manifest, out);
+        }
+        Some(Command::Verify {
+            dump,
+            dir,
+            ignore_timestamps,
+        }) => {
+            return verify(dump, dir, *ignore_timestamps);
+        }
+        Some(Command::Replay {
+            journal,
+            base,
+            until,
+            out,
+        }) => {
+            let (journal, base, until, out) = (journal.clone(), base.clone(), *until, out.clone());
+            let config = configure(&mut opt)?;
+            return replay_journal(&journal, &base, until, &out, &config);
+        }
+        Some(Command::HeatMap { file, out }) => {
+            let (file, out) = (file.clone(), out.clone());
+            let config = configure(&mut opt)?;
+            let file = file.or(config.heat_map_file).ok_or_else(|| {
+                anyhow::anyhow!("no heat map file, give one or set heat_map_file in config")
+            })?;
+            return export_heat_map(&file, &out);
+        }
+        Some(Command::Export {
+            sources,
+            out,
+            ahdi,
+            boot_sector,
+        }) => {
+            let (sources, out, ahdi) = (sources.clone(), out.clone(), *ahdi);
+            let boot_code = boot_sector.as_ref().map(fs::read).transpose()?;
+            let config = configure(&mut opt)?;
+            return export(&sources, &out, ahdi, boot_code.as_deref(), &config);
+        }
+        None => {}
+    }
+
     // Load config and init serial from it
-    let config = opt.config();
-    log::info!("Configuration: {:?}", config);
+    let config = configure(&mut opt)?;
+    preflight(&opt, &config)?;
+    config.hooks.install();
 
-    let mut serial = serialport::new(&opt.port, 19200)
-        .parity(Parity::None)
-        .timeout(Duration::from_secs(24 * 3600))
-        .flow_control(FlowControl::None)
-        .data_bits(DataBits::Eight)
-        .stop_bits(StopBits::One)
-        .open_native()?;
+    if let Some(trace_path) = &opt.replay {
+        return replay(&opt, &config, trace_path);
+    }
+    if let Some(trace_path) = &opt.record {
+        // Reconnections append to the trace of this session
+        File::create(trace_path)?;
+    }
 
-    serial.clear(ClearBuffer::All)?;
+    // TCP connection is accepted once disk is ready
+    let (endpoint, serial) = match &opt.tcp {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr.as_str()).context(Failure::Port)?;
+            listener.set_nonblocking(true).context(Failure::Port)?;
+            let endpoint = Endpoint::Tcp {
+                listener,
+                keepalive: config.tcp_keepalive,
+                secret: config.tcp_secret.clone(),
+                encryption_key: config
+                    .tcp_passphrase
+                    .as_deref()
+                    .map(cipher::LinkKey::new)
+                    .transpose()
+                    .context(Failure::Port)?,
+            };
+            (endpoint, None)
+        }
+        None => {
+            if opt.port.is_none() {
+                opt.port = pick_port().context(Failure::Port)?;
+            }
+            let serial = open_serial(opt.port()).context(Failure::Port)?;
+            (
+                Endpoint::Serial(opt.port().to_string()),
+                Some(Link::Serial(serial)),
+            )
+        }
+    };
+    let tools_serial = opt
+        .tools_port
+        .as_deref()
+        .map(open_serial)
+        .transpose()
+        .context(Failure::Port)?;
+    if opt.user.is_some() || opt.group.is_some() {
+        privileges::drop_privileges(opt.user.as_deref(), opt.group.as_deref())?;
+    }
 
     // Build RAM disk + load content from real FS
     let disk_layout = DiskLayout::new(
@@ -103,42 +1354,494 @@ fn main() -> anyhow::Result<()> {
         config.partition_type.clone(),
         config.root_directory_sectors(),
     );
-    let mut storage = DiskStorage::new(disk_layout);
+
+    if let Some(tools_serial) = tools_serial {
+        serve_tools(&disk_layout, tools_serial)?;
+    }
+
+    if let Some(image_path) = &opt.image {
+        let t_start = Instant::now();
+        let link = (endpoint, serial);
+        return if opt.image_journal {
+            let backend = JournaledBackend::open(image_path, &disk_layout)?;
+            serve_image(
+                &opt,
+                &config,
+                link,
+                image_path,
+                disk_layout,
+                backend,
+                t_start,
+            )
+        } else {
+            let backend = MmapBackend::open(image_path, &disk_layout)?;
+            serve_image(
+                &opt,
+                &config,
+                link,
+                image_path,
+                disk_layout,
+                backend,
+                t_start,
+            )
+        };
+    }
 
     let t_start = Instant::now();
-    storage.import_path(&opt.load_path)?;
-    let t_load = t_start.elapsed();
+    let mut storage = ram_storage(&opt, &config, disk_layout).context(Failure::Import)?;
+    if config.compress_sectors {
+        storage.set_compression(true);
+        let (raw, stored) = storage.memory_usage();
+        log::info!("RAM disk sectors: {} bytes stored in {} bytes", raw, stored);
+    }
+
+    let dump_path = opt.dump().to_path_buf();
+    serve(
+        &opt,
+        &config,
+        (endpoint, serial),
+        storage,
+        t_start,
+        Some(dump_path),
+    )
+}
+
+/// Serve disk image opened with `backend`, importing content in it when it
+/// is empty.
+fn serve_image<B>(
+    opt: &Opt,
+    config: &Config,
+    link: (Endpoint, Option<Link>),
+    image_path: &Path,
+    disk_layout: DiskLayout,
+    backend: B,
+    t_start: Instant,
+) -> anyhow::Result<()>
+where
+    B: SectorBackend + Serialize + Send + 'static,
+{
+    let (mut storage, loaded) = DiskStorage::open_backend(disk_layout, backend)?;
+
+    if loaded {
+        log::info!("Serving existing disk image {:?}", image_path);
+    } else if opt.has_content_to_import() {
+        import_content(&mut storage, opt, config).context(Failure::Import)?;
+    } else if opt.blank {
+        log::info!("Serving blank disk image {:?}", image_path);
+    } else {
+        return Err(anyhow::anyhow!(
+            "disk image {:?} is empty and no path to import",
+            image_path
+        ))
+        .context(Failure::Import);
+    }
+
+    // Image backed disk does not need to be dumped
+    serve(opt, config, link, storage, t_start, None)
+}
+
+/// Build RAM disk: blank, imported or restored from dump.
+fn ram_storage(opt: &Opt, config: &Config, disk_layout: DiskLayout) -> anyhow::Result<DiskStorage> {
+    Ok(if opt.blank {
+        log::info!("Serving blank RAM disk");
+        DiskStorage::new(disk_layout)
+    } else if opt.has_content_to_import() && !opt.resume {
+        let mut storage = DiskStorage::new(disk_layout);
+        import_content(&mut storage, opt, config)?;
+        storage
+    } else {
+        log::info!("Restoring RAM disk from {:?}", opt.dump());
+        let mut dump_reader = BufReader::new(File::open(opt.dump())?);
+        dump::read_dump_with_layout(&mut dump_reader, &disk_layout)?
+    })
+}
+
+/// Prepare answers of the reads of a boot profile.
+fn pin_boot_reads<B>(path: &Path, storage: &DiskStorage<B>) -> anyhow::Result<PinnedReads>
+where
+    B: SectorBackend,
+{
+    let profile = BootProfile::load(path)?;
+    let pinned = PinnedReads::new(&profile, storage, state_machine::encode_frame);
+    log::info!("Pinned {} boot reads from {:?}", pinned.len(), path);
+    Ok(pinned)
+}
+
+/// Keep Atari writes in a delta layer, loading saved one if any.
+fn start_overlay<B>(overlay: &OverlayConfig, storage: &mut DiskStorage<B>) -> anyhow::Result<()>
+where
+    B: SectorBackend,
+{
+    storage.set_overlay();
+    log::info!("Overlay mode, Atari writes are kept apart from disk content");
+    if overlay.on_exit != OverlayPolicy::Save {
+        return Ok(());
+    }
+
+    let Some(delta_path) = &overlay.delta_file else {
+        anyhow::bail!("overlay save policy needs a delta_file");
+    };
+    if delta_path.exists() {
+        let count = journal::replay_file(delta_path, storage)?;
+        log::info!("Loaded {} delta sectors from {:?}", count, delta_path);
+    }
+    Ok(())
+}
+
+/// Drop, save or merge Atari writes of overlay mode.
+fn finish_overlay<B>(
+    overlay: &OverlayConfig,
+    storage: &mut DiskStorage<B>,
+    dump_path: Option<&Path>,
+    compress: bool,
+) -> anyhow::Result<()>
+where
+    B: SectorBackend + Serialize,
+{
+    let delta = storage.overlay_delta();
+    match (overlay.on_exit, &overlay.delta_file, dump_path) {
+        (OverlayPolicy::Save, Some(delta_path), _) => {
+            log::info!("Saving {} delta sectors to {:?}", delta.len(), delta_path);
+            overlay::save_delta(delta_path, &delta)?;
+        }
+        (OverlayPolicy::Merge, _, Some(dump_path)) => {
+            log::info!("Merging {} delta sectors in {:?}", delta.len(), dump_path);
+            dump::write_dump_file(dump_path, &*storage, compress)?;
+        }
+        _ => log::info!("Discarding {} delta sectors", delta.len()),
+    }
+    Ok(())
+}
+
+/// Serve a fresh RAM disk to a recorded session and compare answers.
+///
+/// Disk must be built as it was when recording (same content and config).
+fn replay(opt: &Opt, config: &Config, trace_path: &Path) -> anyhow::Result<()> {
+    let trace = Trace::open(trace_path)?;
+    let disk_layout = DiskLayout::new(
+        config.tos.clone(),
+        config.partition_type.clone(),
+        config.root_directory_sectors(),
+    );
+    let mut storage = ram_storage(opt, config, disk_layout)?;
+    apply_policies(opt, config, &mut storage);
+
+    let report = trace::replay(storage, &trace);
+    println!(
+        "Replayed {} bytes from Atari, answered {} bytes ({} recorded)",
+        report.received, report.sent, report.expected
+    );
+    match report.first_mismatch {
+        None => {
+            println!("{:?} replays identically", trace_path);
+            Ok(())
+        }
+        Some(offset) => anyhow::bail!("answers differ from recording at byte {}", offset),
+    }
+}
+
+/// Import host path and / or ZIP archive content in the virtual disk.
+fn import_content<B>(storage: &mut DiskStorage<B>, opt: &Opt, config: &Config) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    storage.set_name_policy(config.name_policy);
+    storage.set_extension_map(&config.extension_map);
+    storage.set_executable_policy(config.executable_policy);
+    storage.set_split_size(config.split_size);
+    storage.set_max_depth(config.max_depth);
+    storage.set_quotas(config.quotas.clone());
+
+    if let Some(load_path) = &opt.load_path {
+        show_import_report(opt, &storage.import_path(load_path)?);
+    }
+    for source in &opt.sources {
+        log::info!("Merging {:?} in {:?}", source.path, source.target);
+        let target = source.target.replace('/', "\\");
+        let report = storage.import_source(&source.path, &target, source.read_only)?;
+        show_import_report(opt, &report);
+    }
+    if let Some(zip_path) = &opt.load_zip {
+        log::info!("Importing ZIP archive {:?}", zip_path);
+        ataridisk::zip::import_zip(storage, zip_path)?;
+    }
+    for floppy in &config.floppy_images {
+        log::info!("Importing floppy {:?} in {}", floppy.image, floppy.target);
+        if let Err(e) = ataridisk::floppy::import_floppy(storage, &floppy.image, &floppy.target) {
+            log::warn!("Cannot import floppy {:?} (error: {})", floppy.image, e);
+        }
+    }
+    if config.generate_index {
+        ataridisk::index::add_index_file(storage)?;
+    }
+
+    Ok(())
+}
+
+fn show_import_report(opt: &Opt, report: &ImportReport) {
+    if opt.print_status() {
+        println!("{}", report);
+    }
+    if opt.events_json {
+        for entry in &report.skipped {
+            Event::from_skipped(entry).emit();
+        }
+    }
+}
+
+/// Serve tools drive from its own listener thread.
+///
+/// Tools drive is not supervised: if its connection is lost, main disk is
+/// still served.
+fn serve_tools(disk_layout: &DiskLayout, serial: TTYPort) -> anyhow::Result<()> {
+    let storage = Arc::new(Mutex::new(tools::tools_storage(disk_layout.clone())?));
+    let (sender, receiver) = mpsc::channel();
+    listener::spawn(
+        storage,
+        serial,
+        Persistence::default(),
+        SessionConfig::default(),
+        sender,
+    )?;
+
+    thread::spawn(move || {
+        if let Ok(report) = receiver.recv() {
+            log::error!("Tools drive stopped ({})", report.failure);
+        }
+    });
+    Ok(())
+}
+
+/// Set how Atari reads and writes are handled.
+fn apply_policies<B>(opt: &Opt, config: &Config, storage: &mut DiskStorage<B>)
+where
+    B: SectorBackend,
+{
+    storage.set_quotas(config.quotas.clone());
+    storage.set_strict(config.strict_entries);
+    storage.set_uninitialized_reads(config.uninitialized_reads);
+
+    // Blank disk is meant to be formatted from Atari
+    storage.set_format_policy(if opt.blank {
+        FormatPolicy::Allow
+    } else {
+        config.format_policy
+    });
+}
+
+/// Serve disk on `endpoint` until app is stopped, starting with an already
+/// opened link if any.
+fn serve<B>(
+    opt: &Opt,
+    config: &Config,
+    (endpoint, serial): (Endpoint, Option<Link>),
+    mut storage: DiskStorage<B>,
+    t_start: Instant,
+    dump_path: Option<PathBuf>,
+) -> anyhow::Result<()>
+where
+    B: SectorBackend + Serialize + Send + 'static,
+{
+    apply_policies(opt, config, &mut storage);
 
-    log::info!("Ready in {:}ms", t_load.as_millis());
+    // Dump of previous session is kept before this one replaces it
+    if let (Some(backups), Some(dump_path)) = (&config.backups, &dump_path) {
+        if backup::rotate(dump_path, backups)? {
+            log::info!(
+                "Previous dump kept as {:?}",
+                backup::generation_path(dump_path, 1, false)
+            );
+        }
+    }
+
+    // Recover writes from previous session and open journal for this one
+    let journal = match &opt.journal {
+        // Base layer is never changed
+        Some(_) if config.overlay.is_some() => {
+            log::warn!("Overlay mode, journal ignored");
+            None
+        }
+        Some(journal_path) => {
+            if opt.replay_journal && journal_path.exists() {
+                let count = journal::replay_file(journal_path, &mut storage)?;
+                log::info!("Replayed {} write commands from {:?}", count, journal_path);
+            }
+            Some(WriteJournal::open(journal_path)?)
+        }
+        None => None,
+    };
+
+    if let Some(overlay) = &config.overlay {
+        start_overlay(overlay, &mut storage)?;
+    }
+    if let Some(trash_dir) = &config.trash_dir {
+        storage.set_trash(Trash::new(trash_dir)?);
+    }
+    storage.set_generated_files(GeneratedFile::from_config(&config.generated_files))?;
+
+    log::info!("Ready in {:}ms", t_start.elapsed().as_millis());
+    if opt.events_json {
+        let (files, bytes) = storage.count_files()?;
+        Event::ImportFinished {
+            files,
+            bytes,
+            duration_ms: t_start.elapsed().as_millis(),
+        }
+        .emit();
+        events::emit_storage_events(storage.subscribe());
+    }
+
+    let persistence = match &config.overlay {
+        // Atari commits do not change base layer either
+        Some(_) => Persistence::default(),
+        None => Persistence::new(dump_path.clone(), journal)
+            .with_policy(config.write_policy)
+            .with_compression(config.lz4_dump),
+    };
 
     // Create dedicated thread and start main loop
     let storage = Arc::new(Mutex::new(storage));
+    for watch in &opt.dev_watch {
+        dev_watch::spawn(watch.clone(), storage.clone());
+    }
+    if let Some(path) = &config.debug_log {
+        debug_log::spawn(path, storage.clone())?;
+    }
+    if let Some(secs) = config.scrub_interval_secs {
+        scrub::spawn(storage.clone(), Duration::from_secs(secs.max(1)));
+    }
+    if let Some(light) = &config.activity_light {
+        match light.open() {
+            Ok(indicator) => activity::spawn(indicator),
+            Err(e) => log::warn!("Cannot open activity light {:?} (error: {})", light, e),
+        }
+    }
 
-    println!("Atari serial disk: READY.");
-    println!("Press ^C to exit.");
+    if opt.print_status() {
+        println!("Atari serial disk: READY.");
+        println!("Press ^C to exit.");
+    }
+    if opt.events_json {
+        Event::Ready {
+            port: endpoint.name(),
+        }
+        .emit();
+    }
+
+    let session_stats = config.stats_file.as_ref().map(|path| {
+        let stats = SessionStats::load(path);
+        if let Some(port_stats) = stats.ports.get(&endpoint.name()) {
+            if opt.print_status() {
+                println!("Previous sessions on {}: {}", endpoint.name(), port_stats);
+            }
+        }
+        let stats = Arc::new(Mutex::new(stats));
+        SessionStats::record_session(&stats, &endpoint.name());
+        (path, stats)
+    });
+    let heat_map = config.heat_map_file.as_ref().map(|path| {
+        let heat_map = Arc::new(Mutex::new(HeatMap::load(path)));
+        HeatMap::record_session(&heat_map);
+        (path, heat_map)
+    });
+
+    if let Some(addr) = &opt.http {
+        let hub = EventHub::default();
+        hub.follow_transactions();
+        hub.follow_storage(storage.lock().unwrap().subscribe());
+        ataridisk::http::spawn(addr, config.tcp_secret.clone(), storage.clone(), hub)?;
+    }
 
     // Start listener thread
-    {
-        let storage = storage.clone();
-        thread::Builder::new()
-            .name("listener".to_string())
-            .spawn(move || {
-                if let Err(error) = ataridisk::state_machine::run(storage, &mut serial) {
-                    log::error!("Listener thread as crash. Closing app (error: {})", error);
-                    process::exit(1);
-                }
-            })?;
+    let session = SessionConfig {
+        secret: endpoint.secret(),
+        clock: config.clock,
+        host_commands: config.host_commands.clone(),
+        reset_notice: None,
+        record_boot: opt.record_boot.clone(),
+        pinned_reads: match &config.boot_profile {
+            Some(path) => Some(Arc::new(pin_boot_reads(path, &storage.lock().unwrap())?)),
+            None => None,
+        },
+    };
+    // Only the first connection follows a restart
+    let first_session = SessionConfig {
+        reset_notice: match &config.session_file {
+            Some(path) => SessionState::restart(path, &storage.lock().unwrap().disk_layout)?,
+            None => None,
+        },
+        ..session.clone()
+    };
+    let running = running_flag()?;
+    let serial = match serial {
+        Some(serial) => Some(serial),
+        None => {
+            if !opt.no_mdns {
+                endpoint.announce();
+            }
+            log::info!("Waiting for Atari on {}", endpoint.name());
+            endpoint.connect(&running, config.idle)?
+        }
+    };
+
+    // Start listener thread and wait for stop signal, unless app was stopped
+    // before Atari connected
+    let exit_code = match serial {
+        Some(serial) => {
+            let (sender, receiver) = mpsc::channel();
+            spawn_listener(
+                opt,
+                storage.clone(),
+                (serial, first_session),
+                persistence,
+                sender.clone(),
+            )?;
+            supervise(
+                opt,
+                (&endpoint, &session, &running),
+                &storage,
+                (sender, receiver),
+                config.idle,
+            )?
+        }
+        None => 0,
+    };
+
+    if let Some((path, heat_map)) = heat_map {
+        if let Err(e) = heat_map.lock().unwrap().save(path) {
+            log::warn!("Cannot save heat map to {:?} (error: {})", path, e);
+        }
     }
 
-    // Wait for stop signal
-    wait_sigterm()?;
+    if let Some((path, stats)) = session_stats {
+        if let Err(e) = stats.lock().unwrap().save(path) {
+            log::warn!(
+                "Cannot save session statistics to {:?} (error: {})",
+                path,
+                e
+            );
+        }
+    }
 
-    // Dump disk for latter purposes
-    log::info!("Dumping RAM disk to {}", opt.dump);
-    let dump_file = File::create(opt.dump)?;
-    let dump_writer = BufWriter::new(dump_file);
-    let storage = storage.lock().unwrap();
-    bincode::serialize_into(dump_writer, &*storage)?;
+    // Dump disk for latter purposes, even if listener panicked while using it
+    let mut storage = storage.lock().unwrap_or_else(|e| e.into_inner());
+    match (&config.overlay, dump_path) {
+        (Some(overlay), dump_path) => {
+            finish_overlay(overlay, &mut storage, dump_path.as_deref(), config.lz4_dump)?;
+        }
+        (None, Some(_)) if !config.write_policy.dump_on_exit() => {
+            log::info!("RAM only disk, not dumped");
+        }
+        (None, Some(dump_path)) => {
+            log::info!("Dumping RAM disk to {:?}", dump_path);
+            dump::write_dump_file(&dump_path, &*storage, config.lz4_dump)?;
+        }
+        (None, None) => storage.flush()?,
+    }
+
+    if exit_code != 0 {
+        process::exit(exit_code);
+    }
 
     log::info!("All done. Bye !");
     Ok(())
@@ -0,0 +1,404 @@
+//! Announce the TCP transport on the local network, with multicast DNS
+//! (RFC 6762) and DNS service discovery (RFC 6763).
+//!
+//! Server is published as `<hostname>._ataridisk._tcp.local`, so emulator
+//! frontends and WiFi bridges find it without knowing its address. Records
+//! are sent at start, again when a query asks for them, and periodically for
+//! devices only listening to announcements.
+
+use std::{
+    ffi::CStr,
+    io, mem,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    os::unix::io::FromRawFd,
+    thread,
+    time::{Duration, Instant},
+};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::protocol;
+
+/// Service type of ataridisk servers.
+pub const SERVICE_TYPE: &str = "_ataridisk._tcp.local";
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+const TTL: u32 = 120;
+const FIRST_ANNOUNCE_DELAY: Duration = Duration::from_secs(1);
+const ANNOUNCE_PERIOD: Duration = Duration::from_secs(60);
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_POINTER_JUMPS: usize = 16;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records owned by this host only, replacing cached ones.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// DNS-SD records of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    /// Instance and host label, usually the host name
+    pub name: String,
+    pub addr: Ipv4Addr,
+    pub port: u16,
+}
+
+impl Service {
+    /// Service of this host reachable on TCP `listen` address.
+    pub fn for_listener(listen: SocketAddr) -> io::Result<Self> {
+        let addr = match listen {
+            SocketAddr::V4(addr) if !addr.ip().is_unspecified() => *addr.ip(),
+            SocketAddr::V4(_) => local_addr()?,
+            SocketAddr::V6(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only IPv4 listeners are announced",
+                ))
+            }
+        };
+
+        Ok(Self {
+            name: hostname()?,
+            addr,
+            port: listen.port(),
+        })
+    }
+
+    fn instance_name(&self) -> String {
+        format!("{}.{}", self.name, SERVICE_TYPE)
+    }
+
+    fn host_name(&self) -> String {
+        format!("{}.local", self.name)
+    }
+
+    /// Response packet giving every record of the service.
+    pub fn announcement(&self) -> Vec<u8> {
+        let mut packet = vec![0; HEADER_LEN];
+        BigEndian::write_u16(&mut packet[2..], FLAG_RESPONSE | FLAG_AUTHORITATIVE);
+        BigEndian::write_u16(&mut packet[6..], 4);
+
+        let instance = self.instance_name();
+        let host = self.host_name();
+
+        let mut target = Vec::new();
+        write_name(&mut target, &instance);
+        write_record(&mut packet, SERVICE_TYPE, TYPE_PTR, CLASS_IN, &target);
+
+        let mut srv = vec![0; 6];
+        BigEndian::write_u16(&mut srv[4..], self.port);
+        write_name(&mut srv, &host);
+        write_record(
+            &mut packet,
+            &instance,
+            TYPE_SRV,
+            CLASS_IN | CACHE_FLUSH,
+            &srv,
+        );
+
+        let txt = format!("proto={}", protocol::VERSION);
+        let mut txt_data = vec![txt.len() as u8];
+        txt_data.extend_from_slice(txt.as_bytes());
+        write_record(
+            &mut packet,
+            &instance,
+            TYPE_TXT,
+            CLASS_IN | CACHE_FLUSH,
+            &txt_data,
+        );
+
+        let addr = self.addr.octets();
+        write_record(&mut packet, &host, TYPE_A, CLASS_IN | CACHE_FLUSH, &addr);
+        packet
+    }
+
+    /// Check if `packet` is a query asking for one of the service records.
+    pub fn is_queried(&self, packet: &[u8]) -> bool {
+        if packet.len() < HEADER_LEN || BigEndian::read_u16(&packet[2..]) & FLAG_RESPONSE != 0 {
+            return false;
+        }
+
+        let names = [
+            (SERVICE_TYPE.to_string(), TYPE_PTR),
+            (self.instance_name(), TYPE_SRV),
+            (self.instance_name(), TYPE_TXT),
+            (self.host_name(), TYPE_A),
+        ];
+
+        let mut offset = HEADER_LEN;
+        for _ in 0..BigEndian::read_u16(&packet[4..]) {
+            let Some((name, next)) = read_name(packet, offset) else {
+                return false;
+            };
+            let Some(question) = packet.get(next..next + 4) else {
+                return false;
+            };
+            let qtype = BigEndian::read_u16(question);
+            offset = next + 4;
+
+            if names
+                .iter()
+                .any(|(n, t)| n.eq_ignore_ascii_case(&name) && (qtype == *t || qtype == TYPE_ANY))
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Announce `service` and answer queries from a background thread.
+pub fn spawn(service: Service) -> io::Result<()> {
+    let socket = multicast_socket()?;
+    log::info!(
+        "Announcing {}.{} on {}:{}",
+        service.name,
+        SERVICE_TYPE,
+        service.addr,
+        service.port
+    );
+
+    thread::Builder::new()
+        .name("mdns".to_string())
+        .spawn(move || {
+            if let Err(e) = run(&socket, &service) {
+                log::warn!("mDNS announcement stopped (error: {})", e);
+            }
+        })?;
+    Ok(())
+}
+
+fn run(socket: &UdpSocket, service: &Service) -> io::Result<()> {
+    let announcement = service.announcement();
+    let group = (MDNS_GROUP, MDNS_PORT);
+    let mut buf = [0; 9000];
+
+    // Announced twice at start, as asked by RFC 6762
+    socket.send_to(&announcement, group)?;
+    let mut next_announce = Instant::now() + FIRST_ANNOUNCE_DELAY;
+
+    loop {
+        let now = Instant::now();
+        if now >= next_announce {
+            socket.send_to(&announcement, group)?;
+            next_announce = now + ANNOUNCE_PERIOD;
+            continue;
+        }
+
+        socket.set_read_timeout(Some(next_announce - now))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) if service.is_queried(&buf[..len]) => {
+                socket.send_to(&announcement, group)?;
+            }
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn write_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(MAX_LABEL_LEN)];
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label);
+    }
+    packet.push(0);
+}
+
+fn write_record(packet: &mut Vec<u8>, name: &str, rtype: u16, class: u16, data: &[u8]) {
+    write_name(packet, name);
+    let mut fields = [0; 10];
+    BigEndian::write_u16(&mut fields[0..], rtype);
+    BigEndian::write_u16(&mut fields[2..], class);
+    BigEndian::write_u32(&mut fields[4..], TTL);
+    BigEndian::write_u16(&mut fields[8..], data.len() as u16);
+    packet.extend_from_slice(&fields);
+    packet.extend_from_slice(data);
+}
+
+/// Read name at `offset`, following compression pointers.
+///
+/// Returns name and offset of what follows it.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    for _ in 0..MAX_POINTER_JUMPS {
+        loop {
+            let len = *packet.get(offset)? as usize;
+            if len == 0 {
+                let next = end.unwrap_or(offset + 1);
+                return Some((labels.join("."), next));
+            }
+            if len & 0xC0 == 0xC0 {
+                let pointer = BigEndian::read_u16(packet.get(offset..offset + 2)?) & 0x3FFF;
+                end.get_or_insert(offset + 2);
+                offset = pointer as usize;
+                break;
+            }
+
+            let label = packet.get(offset + 1..offset + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + len;
+        }
+    }
+    None
+}
+
+/// UDP socket joined to the mDNS group, sharing port 5353 with other
+/// responders of the host (ex: avahi).
+fn multicast_socket() -> io::Result<UdpSocket> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // From now on, socket is closed on error
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+
+    let enable: libc::c_int = 1;
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        check(unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &enable as *const _ as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })?;
+    }
+
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = MDNS_PORT.to_be();
+    check(unsafe {
+        libc::bind(
+            fd,
+            &addr as *const _ as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    })?;
+
+    socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    Ok(socket)
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Short host name, as mDNS names are always in `.local`.
+fn hostname() -> io::Result<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    check(unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) })?;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+    Ok(name.split('.').next().unwrap_or_default().to_string())
+}
+
+/// Address of the interface multicast is sent from.
+fn local_addr() -> io::Result<Ipv4Addr> {
+    // Nothing is sent: connecting only picks a route
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((MDNS_GROUP, MDNS_PORT))?;
+    match socket.local_addr()? {
+        SocketAddr::V4(addr) if !addr.ip().is_unspecified() => Ok(*addr.ip()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no IPv4 address to announce",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service {
+            name: "falcon".to_string(),
+            addr: Ipv4Addr::new(192, 168, 1, 20),
+            port: 6502,
+        }
+    }
+
+    fn query(names: &[(&str, u16)]) -> Vec<u8> {
+        let mut packet = vec![0; HEADER_LEN];
+        BigEndian::write_u16(&mut packet[4..], names.len() as u16);
+        for (name, qtype) in names {
+            write_name(&mut packet, name);
+            packet.extend_from_slice(&qtype.to_be_bytes());
+            packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn test_announcement() {
+        let packet = service().announcement();
+        assert_eq!(&packet[2..4], [0x84, 0x00]);
+        assert_eq!(BigEndian::read_u16(&packet[6..]), 4);
+
+        let (name, next) = read_name(&packet, HEADER_LEN).unwrap();
+        assert_eq!(name, SERVICE_TYPE);
+        assert_eq!(BigEndian::read_u16(&packet[next..]), TYPE_PTR);
+        let (target, _) = read_name(&packet, next + 10).unwrap();
+        assert_eq!(target, "falcon._ataridisk._tcp.local");
+
+        assert!(packet.ends_with(&[0, 4, 192, 168, 1, 20]));
+        let port = 6502u16.to_be_bytes();
+        assert!(packet.windows(2).any(|w| w == port));
+    }
+
+    #[test]
+    fn test_queries() {
+        let service = service();
+        assert!(service.is_queried(&query(&[("_ataridisk._tcp.local", TYPE_PTR)])));
+        assert!(service.is_queried(&query(&[
+            ("_http._tcp.local", TYPE_PTR),
+            ("FALCON.local", TYPE_ANY),
+        ])));
+        assert!(!service.is_queried(&query(&[("falcon.local", TYPE_TXT)])));
+        assert!(!service.is_queried(&query(&[("other.local", TYPE_A)])));
+
+        // Answers are ignored
+        let mut answer = query(&[("_ataridisk._tcp.local", TYPE_PTR)]);
+        answer[2] = 0x84;
+        assert!(!service.is_queried(&answer));
+
+        // Compressed name, pointing to the first question
+        let mut packet = query(&[("_ataridisk._tcp.local", TYPE_TXT)]);
+        BigEndian::write_u16(&mut packet[4..], 2);
+        write_name(&mut packet, "falcon");
+        packet.pop();
+        packet.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        packet.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        assert!(service.is_queried(&packet));
+
+        // Pointer loop
+        let mut packet = vec![0; HEADER_LEN];
+        BigEndian::write_u16(&mut packet[4..], 1);
+        packet.extend_from_slice(&[0xC0, HEADER_LEN as u8, 0, 1, 0, 1]);
+        assert!(!service.is_queried(&packet));
+    }
+}
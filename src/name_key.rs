@@ -0,0 +1,107 @@
+//! Name equality shared by every component matching disk and host names.
+//!
+//! GEMDOS compares names ignoring case, and host names reach the disk
+//! transliterated (ex: `Späße.txt` is known by Atari as `SPASSE.TXT`). A
+//! `NameKey` is the same for all the spellings of a name, so lookups,
+//! duplicate detection and the reverse mapper agree on what is the same file.
+
+use std::fmt;
+
+/// Latin letters without an ASCII base letter once accents are removed.
+const LIGATURES: [(char, &str); 12] = [
+    ('ß', "SS"),
+    ('ẞ', "SS"),
+    ('æ', "AE"),
+    ('Æ', "AE"),
+    ('œ', "OE"),
+    ('Œ', "OE"),
+    ('ø', "O"),
+    ('Ø', "O"),
+    ('đ', "D"),
+    ('Đ', "D"),
+    ('ł', "L"),
+    ('Ł', "L"),
+];
+
+/// Normalized form of a name, or of a `\` separated path.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NameKey(String);
+
+impl NameKey {
+    pub fn new(name: &str) -> Self {
+        let mut key = String::with_capacity(name.len());
+        for c in name.chars() {
+            match c {
+                // Separate combining accents (decomposed names, ex: macOS)
+                '\u{300}'..='\u{36F}' => {}
+                c if c.is_ascii() => key.push(c.to_ascii_uppercase()),
+                c => match LIGATURES.iter().find(|(from, _)| *from == c) {
+                    Some((_, to)) => key.push_str(to),
+                    None => key.push(base_letter(c).unwrap_or(c)),
+                },
+            }
+        }
+        Self(key)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for NameKey {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl fmt::Display for NameKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Uppercase ASCII letter of an accented Latin-1 or Latin Extended-A one.
+fn base_letter(c: char) -> Option<char> {
+    let base = match c {
+        'À'..='Å' | 'à'..='å' | 'Ā'..='ą' => 'A',
+        'Ç' | 'ç' | 'Ć'..='č' => 'C',
+        'Ď' | 'ď' => 'D',
+        'È'..='Ë' | 'è'..='ë' | 'Ē'..='ě' => 'E',
+        'Ĝ'..='ģ' => 'G',
+        'Ĥ'..='ħ' => 'H',
+        'Ì'..='Ï' | 'ì'..='ï' | 'Ĩ'..='ı' => 'I',
+        'Ĵ' | 'ĵ' => 'J',
+        'Ķ' | 'ķ' => 'K',
+        'Ĺ'..='ľ' => 'L',
+        'Ñ' | 'ñ' | 'Ń'..='ň' => 'N',
+        'Ò'..='Ö' | 'ò'..='ö' | 'Ō'..='ő' => 'O',
+        'Ŕ'..='ř' => 'R',
+        'Ś'..='š' => 'S',
+        'Ţ'..='ť' => 'T',
+        'Ù'..='Ü' | 'ù'..='ü' | 'Ũ'..='ų' => 'U',
+        'Ŵ' | 'ŵ' => 'W',
+        'Ý' | 'ý' | 'ÿ' | 'Ŷ'..='Ÿ' => 'Y',
+        'Ź'..='ž' => 'Z',
+        _ => return None,
+    };
+    Some(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_key() {
+        assert_eq!(NameKey::new("readme.txt"), NameKey::new("README.TXT"));
+        assert_eq!(NameKey::new("Späße.txt"), NameKey::new("SPASSE.TXT"));
+        assert_eq!(NameKey::new("Noe\u{308}l"), NameKey::new("NOËL"));
+        assert_eq!(NameKey::new("Café\\Œuvre"), NameKey::new("CAFE\\OEUVRE"));
+        assert_eq!(NameKey::new("Łódź").as_str(), "LODZ");
+        assert_ne!(NameKey::new("DUNG.PRG"), NameKey::new("DUNG.TOS"));
+
+        // Other scripts are kept as they are
+        assert_eq!(NameKey::new("日本").as_str(), "日本");
+    }
+}
@@ -0,0 +1,88 @@
+//! Overlay mode: content served at start is an immutable base layer, what
+//! Atari writes only goes to a delta layer, for kiosk or demo setups where
+//! each run should start clean.
+//!
+//! Delta is made of the sectors Atari has changed. When server stops it is
+//! dropped, saved to a delta file loaded again on next start, or merged in
+//! the dump. Delta files use the write journal format.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{error, journal::WriteJournal};
+
+/// What to do with Atari writes when server stops.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayPolicy {
+    /// Drop them, next run serves base content again
+    #[default]
+    Discard,
+    /// Save them to the delta file, applied again on next start
+    Save,
+    /// Apply them to the base, by dumping the disk
+    Merge,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OverlayConfig {
+    /// What to do with Atari writes when server stops
+    #[serde(default)]
+    pub on_exit: OverlayPolicy,
+
+    /// File keeping Atari writes with `save` policy
+    #[serde(default)]
+    pub delta_file: Option<PathBuf>,
+}
+
+/// Write delta sectors to `path`, replacing its previous content.
+pub fn save_delta(path: &Path, delta: &[(u16, Vec<u8>)]) -> error::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    match fs::remove_file(&tmp_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let mut journal = WriteJournal::open(&tmp_path)?;
+    for (sector_index, data) in delta {
+        journal.record(*sector_index, 1, data)?;
+    }
+    journal.sync()?;
+
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::Fixture, journal, layout::DiskLayout, storage::DiskStorage};
+
+    #[test]
+    fn test_save_delta() {
+        let fixture = Fixture::empty("delta").unwrap();
+        let path = fixture.join("test.delta");
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.set_overlay();
+
+        let sector_index = storage.disk_layout.first_free_sector() + 4;
+        let data = vec![0x42; storage.disk_layout.bytes_per_sector() as usize];
+        storage
+            .write_sectors(&mut &data[..], sector_index, 1)
+            .unwrap();
+        let delta = storage.overlay_delta();
+        assert_eq!(delta, [(sector_index, data)]);
+        save_delta(&path, &delta).unwrap();
+
+        // Loaded delta is still apart from base
+        storage.discard_overlay().unwrap();
+        assert!(storage.overlay_delta().is_empty());
+        assert_eq!(journal::replay_file(&path, &mut storage).unwrap(), 1);
+        assert_eq!(storage.overlay_delta(), delta);
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{backend::SectorBackend, dump, error, journal::WriteJournal, storage::DiskStorage};
+
+/// When Atari writes are persisted to host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WritePolicy {
+    /// Every write is persisted right after it has been received: journal
+    /// is synced, and dump rewritten every `COMPACT_WRITES` writes (every
+    /// write without journal)
+    WriteThrough,
+    /// Writes are journaled as received and the dump is written when Atari
+    /// asks for a commit and when server stops
+    #[default]
+    WriteBack,
+    /// Nothing is written to host unless Atari asks for a commit
+    RamOnly,
+}
+
+impl WritePolicy {
+    /// Tell if disk must be dumped when server stops.
+    pub fn dump_on_exit(&self) -> bool {
+        !matches!(self, Self::RamOnly)
+    }
+}
+
+/// Writes journaled in write through mode before the dump is rewritten.
+pub const COMPACT_WRITES: u32 = 256;
+
+/// Targets where RAM disk content is persisted while it is served.
+#[derive(Debug, Default)]
+pub struct Persistence {
+    dump_path: Option<PathBuf>,
+    journal: Option<WriteJournal>,
+    policy: WritePolicy,
+    /// Write compressed dumps
+    compress: bool,
+    /// Writes applied since dump was last written
+    pending_writes: u32,
+}
+
+impl Persistence {
+    pub fn new(dump_path: Option<PathBuf>, journal: Option<WriteJournal>) -> Self {
+        Self {
+            dump_path,
+            journal,
+            policy: WritePolicy::default(),
+            compress: false,
+            pending_writes: 0,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: WritePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn policy(&self) -> WritePolicy {
+        self.policy
+    }
+
+    /// Tell if writes are recorded in a journal as they are received.
+    pub fn is_journaled(&self) -> bool {
+        self.journal.is_some() && self.policy != WritePolicy::RamOnly
+    }
+
+    /// Record a write command as soon as it has been received.
+    pub fn record_write(
+        &mut self,
+        sector_index: u16,
+        sector_count: u16,
+        data: &[u8],
+    ) -> error::Result<()> {
+        if self.policy == WritePolicy::RamOnly {
+            return Ok(());
+        }
+
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record(sector_index, sector_count, data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Persist disk content once a write command has been applied to it,
+    /// if policy asks for it.
+    ///
+    /// Journal already holds the write: dump is only rewritten once enough
+    /// writes have piled up in it, to keep replays short.
+    pub fn applied_write<B>(&mut self, storage: &mut DiskStorage<B>) -> error::Result<()>
+    where
+        B: SectorBackend + Serialize,
+    {
+        if self.policy != WritePolicy::WriteThrough {
+            return Ok(());
+        }
+
+        self.pending_writes += 1;
+        if self.journal.is_none() || self.pending_writes >= COMPACT_WRITES {
+            self.commit(storage)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write current disk content to stable storage.
+    ///
+    /// Journal is emptied once dump has been written, so it only holds
+    /// writes received since then.
+    pub fn commit<B>(&mut self, storage: &mut DiskStorage<B>) -> error::Result<()>
+    where
+        B: SectorBackend + Serialize,
+    {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.sync()?;
+        }
+
+        storage.flush()?;
+
+        if let Some(dump_path) = &self.dump_path {
+            log::info!("Committing RAM disk to {:?}", dump_path);
+            dump::write_dump_file(dump_path, storage, self.compress)?;
+
+            // Dump now holds every journaled write
+            if let Some(journal) = self.journal.as_mut() {
+                journal.truncate()?;
+            }
+        }
+        self.pending_writes = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout};
+
+    #[test]
+    fn test_write_policies() {
+        let fixture = Fixture::empty("policy").unwrap();
+        let dir = fixture.path();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+
+        for policy in [
+            WritePolicy::WriteThrough,
+            WritePolicy::WriteBack,
+            WritePolicy::RamOnly,
+        ] {
+            let dump_path = dir.join(format!("{:?}.dump", policy));
+            let journal_path = dir.join(format!("{:?}.journal", policy));
+            let mut persistence = Persistence::new(
+                Some(dump_path.clone()),
+                Some(WriteJournal::open(&journal_path).unwrap()),
+            )
+            .with_policy(policy);
+
+            persistence.record_write(0x100, 1, &[0; 8192]).unwrap();
+            persistence.applied_write(&mut storage).unwrap();
+            let journaled = fs::metadata(&journal_path).unwrap().len() > 0;
+            assert_eq!(journaled, policy != WritePolicy::RamOnly, "{:?}", policy);
+            persistence.commit(&mut storage).unwrap();
+            assert_eq!(fs::metadata(&journal_path).unwrap().len(), 0);
+            fs::remove_file(&dump_path).unwrap();
+
+            // Dump only written once writes pile up in write through mode
+            for _ in 1..COMPACT_WRITES {
+                persistence.applied_write(&mut storage).unwrap();
+            }
+            assert!(!dump_path.exists(), "{:?}", policy);
+            persistence.applied_write(&mut storage).unwrap();
+            assert_eq!(
+                dump_path.exists(),
+                policy == WritePolicy::WriteThrough,
+                "{:?}",
+                policy
+            );
+        }
+
+        // Without journal, only the dump holds writes
+        let dump_path = dir.join("no-journal.dump");
+        let mut persistence =
+            Persistence::new(Some(dump_path.clone()), None).with_policy(WritePolicy::WriteThrough);
+        persistence.applied_write(&mut storage).unwrap();
+        assert!(dump_path.exists());
+    }
+}
@@ -0,0 +1,172 @@
+//! Detection of sequential reads (ex: Atari loading a big file), to load next
+//! sectors from host storage before Atari asks for them, and to prepare the
+//! answer of the next read while the previous one is being sent.
+
+use crate::{backend::SectorBackend, error, storage::DiskStorage};
+
+/// Sectors kept loaded ahead of sequential reads.
+pub const PREFETCH_SECTORS: u16 = 64;
+
+/// Consecutive reads following each other before prefetching.
+const MIN_STREAK: u32 = 2;
+
+#[derive(Debug, Default)]
+pub struct SequentialReads {
+    /// Sector following previous read
+    next: Option<u16>,
+    /// Sector count of previous read
+    count: u16,
+    streak: u32,
+    /// End of sectors already asked for
+    prefetched_until: u16,
+}
+
+impl SequentialReads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a read and return the sectors worth loading ahead of it, as
+    /// first sector and count.
+    pub fn record(&mut self, index: u16, count: u16) -> Option<(u16, u16)> {
+        let end = index.saturating_add(count);
+        if self.next == Some(index) {
+            self.streak += 1;
+        } else {
+            self.streak = 1;
+            self.prefetched_until = 0;
+        }
+        self.next = Some(end);
+        self.count = count;
+
+        // Ask for more once half of the window has been read
+        if self.streak < MIN_STREAK
+            || self.prefetched_until >= end.saturating_add(PREFETCH_SECTORS / 2)
+        {
+            return None;
+        }
+
+        let start = end.max(self.prefetched_until);
+        let until = end.saturating_add(PREFETCH_SECTORS);
+        self.prefetched_until = until;
+        (start < until).then_some((start, until - start))
+    }
+
+    /// Read expected after the last ones, once they follow each other.
+    pub fn next_read(&self) -> Option<(u16, u16)> {
+        let next = self.next.filter(|_| self.streak >= MIN_STREAK)?;
+        next.checked_add(self.count).map(|_| (next, self.count))
+    }
+}
+
+/// Answer of the expected next read, with the sectors content it was built
+/// from. It is only sent if sectors still hold that content.
+#[derive(Debug, Default)]
+pub struct StagedRead {
+    staged: Option<Staged>,
+}
+
+#[derive(Debug)]
+struct Staged {
+    read: (u16, u16),
+    data: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl StagedRead {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build answer of a read, with `encode` giving the frame of some
+    /// sectors content.
+    pub fn stage<B, F>(&mut self, storage: &DiskStorage<B>, index: u16, count: u16, encode: F)
+    where
+        B: SectorBackend,
+        F: FnOnce(&[u8]) -> error::Result<Vec<u8>>,
+    {
+        let mut data = Vec::new();
+        let frame = storage
+            .read_sectors(&mut data, index, count)
+            .map_err(error::SerialDiskError::from)
+            .and_then(|_| encode(&data));
+        self.staged = match frame {
+            Ok(frame) => Some(Staged {
+                read: (index, count),
+                data,
+                frame,
+            }),
+            Err(e) => {
+                log::debug!("Cannot stage read {:#x} (error: {})", index, e);
+                None
+            }
+        };
+    }
+
+    /// Staged answer of a read, if it is the staged one and sectors still
+    /// hold `data`.
+    pub fn take(&mut self, index: u16, count: u16, data: &[u8]) -> Option<Vec<u8>> {
+        match self.staged.take() {
+            Some(staged) if staged.read == (index, count) && staged.data == data => {
+                Some(staged.frame)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut reads = SequentialReads::new();
+        assert_eq!(reads.record(0x100, 2), None);
+        assert_eq!(reads.record(0x102, 2), Some((0x104, 64)));
+
+        // Window is refilled once half of it has been read
+        let mut index = 0x104;
+        while reads.record(index, 2).is_none() {
+            index += 2;
+        }
+        assert_eq!(index, 0x104 + 32);
+        assert_eq!(reads.prefetched_until, 0x104 + 32 + 2 + 64);
+
+        // Random access does not prefetch
+        assert_eq!(reads.record(0x10, 2), None);
+        assert_eq!(reads.record(0x400, 2), None);
+    }
+
+    #[test]
+    fn test_staged_read() {
+        use crate::{layout::DiskLayout, storage::ROOT_INDEX};
+
+        let mut reads = SequentialReads::new();
+        reads.record(0x100, 4);
+        assert_eq!(reads.next_read(), None);
+        reads.record(0x104, 4);
+        assert_eq!(reads.next_read(), Some((0x108, 4)));
+
+        let mtime = chrono::Local::now().naive_local();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        // First root directory sector
+        let sector = storage.disk_layout.count_fat_sectors();
+        let mut staged = StagedRead::new();
+        staged.stage(&storage, sector, 2, |data| Ok(data.to_vec()));
+
+        // Sectors changed since answer was staged
+        storage
+            .add_virtual_file("DESK", "ACC", mtime, &[1; 64], ROOT_INDEX)
+            .unwrap();
+        let mut data = Vec::new();
+        storage.read_sectors(&mut data, sector, 2).unwrap();
+        assert_eq!(staged.take(sector, 2, &data), None);
+
+        staged.stage(&storage, sector, 2, |data| Ok(data.to_vec()));
+        assert_eq!(staged.take(sector, 1, &data), None);
+        staged.stage(&storage, sector, 2, |data| Ok(data.to_vec()));
+        assert_eq!(staged.take(sector, 2, &data), Some(data.clone()));
+        assert_eq!(staged.take(sector, 2, &data), None);
+    }
+}
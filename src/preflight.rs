@@ -0,0 +1,264 @@
+//! Checks run before serving starts, so every problem of the setup is
+//! reported at once with what to do about it, instead of the first IO error
+//! met halfway through startup.
+
+use std::{
+    ffi::CString,
+    fmt, fs,
+    io::{self, ErrorKind},
+    net::TcpListener,
+    os::unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{doctor, layout::DiskLayout};
+
+/// Directories holding UUCP lock files of serial ports.
+const LOCK_DIRS: [&str; 2] = ["/var/lock", "/run/lock"];
+
+/// What a problem is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Area {
+    Options,
+    Port,
+    /// Host content to import
+    Content,
+    /// Dump, image or journal file
+    DiskFile,
+    Layout,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub area: Area,
+    pub what: String,
+    /// What to do about it
+    pub hint: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.what, self.hint)
+    }
+}
+
+/// Problems found so far.
+#[derive(Debug, Default)]
+pub struct Preflight {
+    problems: Vec<Problem>,
+}
+
+impl Preflight {
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    pub fn fail<W, H>(&mut self, area: Area, what: W, hint: H)
+    where
+        W: Into<String>,
+        H: Into<String>,
+    {
+        self.problems.push(Problem {
+            area,
+            what: what.into(),
+            hint: hint.into(),
+        });
+    }
+
+    /// Check serial port exists, can be opened and is not used.
+    pub fn check_port(&mut self, port: &str) {
+        if let Some(pid) = lock_owner(port) {
+            let what = format!("{} is locked by process {}", port, pid);
+            self.fail(Area::Port, what, "stop the program using it (ex: minicom)");
+            return;
+        }
+
+        let opened = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(port);
+        if let Err(e) = opened {
+            let hint = match e.kind() {
+                ErrorKind::NotFound => {
+                    "check adapter is plugged, --list-availables shows ports".to_string()
+                }
+                ErrorKind::PermissionDenied => doctor::permission_hint(port).unwrap_or_else(|| {
+                    "add your user to the group owning the port (ex: dialout)".to_string()
+                }),
+                _ if e.raw_os_error() == Some(libc::EBUSY) => {
+                    "stop the program using it (ex: another server)".to_string()
+                }
+                _ => "check port name".to_string(),
+            };
+            self.fail(Area::Port, format!("cannot open {} ({})", port, e), hint);
+        }
+    }
+
+    /// Check TCP address can be listened on.
+    pub fn check_tcp(&mut self, addr: &str) {
+        if let Err(e) = TcpListener::bind(addr) {
+            let hint = match e.kind() {
+                ErrorKind::AddrInUse => "stop the program listening there, or use another port",
+                ErrorKind::PermissionDenied => "ports below 1024 need root",
+                ErrorKind::AddrNotAvailable => "use an address of this host (ex: 0.0.0.0)",
+                _ => "check address, expected <host>:<port>",
+            };
+            self.fail(
+                Area::Port,
+                format!("cannot listen on {} ({})", addr, e),
+                hint,
+            );
+        }
+    }
+
+    /// Check a host directory to import can be listed.
+    pub fn check_import_dir(&mut self, path: &Path) {
+        match fs::read_dir(path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => self.fail(
+                Area::Content,
+                format!("{:?} does not exist", path),
+                "check path to import",
+            ),
+            Err(e) => self.fail(
+                Area::Content,
+                format!("cannot list {:?} ({})", path, e),
+                access_hint(&e, "give a directory to import"),
+            ),
+        }
+    }
+
+    /// Check a host file can be read.
+    pub fn check_readable_file(&mut self, area: Area, path: &Path) {
+        if let Err(e) = fs::File::open(path) {
+            self.fail(
+                area,
+                format!("cannot read {:?} ({})", path, e),
+                access_hint(&e, "check path"),
+            );
+        }
+    }
+
+    /// Check a file can be written, or created if missing.
+    pub fn check_writable_file(&mut self, path: &Path) {
+        let result = if path.exists() {
+            fs::OpenOptions::new().write(true).open(path).map(|_| ())
+        } else {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            writable_dir(&parent)
+        };
+
+        if let Err(e) = result {
+            self.fail(
+                Area::DiskFile,
+                format!("cannot write {:?} ({})", path, e),
+                access_hint(&e, "create its directory first"),
+            );
+        }
+    }
+
+    /// Check geometry leaves room for a root directory and for files.
+    pub fn check_layout(&mut self, layout: &DiskLayout) {
+        let root_sectors = layout.root_directory_sectors() as usize;
+        let first_free_sector = layout.count_fat_sectors() as usize + root_sectors;
+        let fat_entries =
+            layout.count_1fat_sectors() as usize * layout.bytes_per_sector() as usize / 2;
+
+        if root_sectors == 0 {
+            self.fail(
+                Area::Layout,
+                "root directory has no sector",
+                "set root_directory_sectors to 1 or more",
+            );
+        } else if first_free_sector / layout.sectors_per_cluster() as usize >= fat_entries {
+            self.fail(
+                Area::Layout,
+                format!(
+                    "{} root directory sectors leave no room for files",
+                    root_sectors
+                ),
+                "lower root_directory_sectors",
+            );
+        }
+    }
+}
+
+fn access_hint(error: &io::Error, other: &str) -> String {
+    match error.kind() {
+        ErrorKind::PermissionDenied => "check user running the server can access it".to_string(),
+        _ => other.to_string(),
+    }
+}
+
+fn writable_dir(dir: &Path) -> io::Result<()> {
+    if !fs::metadata(dir)?.is_dir() {
+        return Err(io::Error::new(ErrorKind::NotFound, "not a directory"));
+    }
+    let c_dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    match unsafe { libc::access(c_dir.as_ptr(), libc::W_OK) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Process holding a UUCP lock file on `port`, if it is still running.
+fn lock_owner(port: &str) -> Option<u32> {
+    let name = Path::new(port).file_name()?.to_str()?;
+    LOCK_DIRS.iter().find_map(|dir| {
+        let content = fs::read_to_string(Path::new(dir).join(format!("LCK..{}", name))).ok()?;
+        let pid: u32 = content.trim().parse().ok()?;
+        Path::new("/proc")
+            .join(pid.to_string())
+            .exists()
+            .then_some(pid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::Fixture,
+        layout::{PartitionType, Tos},
+    };
+
+    #[test]
+    fn test_preflight() {
+        let fixture = Fixture::empty("preflight").unwrap();
+        let dir = fixture.path();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut preflight = Preflight::default();
+        preflight.check_import_dir(dir);
+        preflight.check_writable_file(&dir.join("ramdisk.dump"));
+        preflight.check_layout(&DiskLayout::default());
+        assert_eq!(preflight.problems(), []);
+
+        // Every problem is reported
+        preflight.check_port("/dev/ataridisk-missing");
+        preflight.check_tcp(&listener.local_addr().unwrap().to_string());
+        preflight.check_import_dir(&dir.join("missing"));
+        preflight.check_readable_file(Area::DiskFile, &dir.join("ramdisk.dump"));
+        preflight.check_writable_file(&dir.join("missing").join("ramdisk.dump"));
+        preflight.check_layout(&DiskLayout::new(Tos::V104, PartitionType::Bgm, 0));
+        preflight.check_layout(&DiskLayout::new(Tos::V100, PartitionType::Gem, 40000));
+        let areas: Vec<Area> = preflight.problems().iter().map(|p| p.area).collect();
+        assert_eq!(
+            areas,
+            [
+                Area::Port,
+                Area::Port,
+                Area::Content,
+                Area::DiskFile,
+                Area::DiskFile,
+                Area::Layout,
+                Area::Layout,
+            ]
+        );
+    }
+}
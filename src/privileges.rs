@@ -0,0 +1,108 @@
+//! Run as an unprivileged account once serial port is open.
+//!
+//! Server may need root to open the serial device, but has no reason to
+//! keep it while reading and writing the shared directory.
+
+use std::{ffi::CString, io};
+
+use crate::error::{self, SerialDiskError};
+
+fn c_name(name: &str) -> error::Result<CString> {
+    CString::new(name).map_err(|_| SerialDiskError::UnknownAccount(name.to_string()))
+}
+
+/// Get uid and main gid of a user name or numeric id.
+pub fn lookup_user(name: &str) -> error::Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = c_name(name)?;
+    let passwd = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if !passwd.is_null() {
+        return unsafe { Ok(((*passwd).pw_uid, (*passwd).pw_gid)) };
+    }
+
+    match name.parse::<libc::uid_t>() {
+        Ok(uid) => {
+            let passwd = unsafe { libc::getpwuid(uid) };
+            if passwd.is_null() {
+                // No main group known, keep current one
+                Ok((uid, unsafe { libc::getgid() }))
+            } else {
+                Ok((uid, unsafe { (*passwd).pw_gid }))
+            }
+        }
+        Err(_) => Err(SerialDiskError::UnknownAccount(name.to_string())),
+    }
+}
+
+/// Get gid of a group name or numeric id.
+pub fn lookup_group(name: &str) -> error::Result<libc::gid_t> {
+    let c_name = c_name(name)?;
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if !group.is_null() {
+        return unsafe { Ok((*group).gr_gid) };
+    }
+
+    name.parse()
+        .map_err(|_| SerialDiskError::UnknownAccount(name.to_string()))
+}
+
+fn check(result: libc::c_int) -> error::Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().into())
+    }
+}
+
+/// Switch to given user and / or group for the rest of the process life.
+///
+/// Group defaults to the main group of the user.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> error::Result<()> {
+    let account = user.map(lookup_user).transpose()?;
+    let gid = match group {
+        Some(group) => Some(lookup_group(group)?),
+        None => account.map(|(_, gid)| gid),
+    };
+
+    // Group must be changed first: it cannot be once user is not root anymore
+    if let Some(gid) = gid {
+        check(unsafe { libc::setgroups(1, &gid) })?;
+        check(unsafe { libc::setgid(gid) })?;
+    }
+
+    if let Some((uid, _)) = account {
+        check(unsafe { libc::setuid(uid) })?;
+
+        // Make sure root cannot be regained
+        if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::other("root privileges still available").into());
+        }
+    }
+
+    log::info!(
+        "Running as uid {} / gid {}",
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup_user("root").unwrap().0, 0);
+        assert_eq!(lookup_user("0").unwrap().0, 0);
+        assert_eq!(lookup_group("0").unwrap(), 0);
+
+        assert_eq!(
+            lookup_user("no-such-user"),
+            Err(SerialDiskError::UnknownAccount("no-such-user".into()))
+        );
+        assert_eq!(
+            lookup_group("no-such-group"),
+            Err(SerialDiskError::UnknownAccount("no-such-group".into()))
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Tiny property testing helpers.
+//!
+//! Every case gets its own seed, printed when it fails, so it can be run
+//! again alone with `ATARIDISK_PROP_SEED=<seed>`.
+//!
+//! They stand in for `proptest`, which is not a dependency of the project.
+//! Failing cases are not shrunk: the printed seed replays them as is.
+
+use std::{
+    env,
+    panic::{self, AssertUnwindSafe},
+};
+
+pub use crate::rng::Rng;
+
+/// Number of cases run by default for each property.
+pub const CASES: u64 = 64;
+
+/// Run `property` with `cases` different seeds.
+pub fn check<F>(cases: u64, mut property: F)
+where
+    F: FnMut(&mut Rng),
+{
+    let seeds: Vec<u64> = match env::var("ATARIDISK_PROP_SEED") {
+        Ok(seed) => vec![seed.parse().expect("Invalid ATARIDISK_PROP_SEED")],
+        Err(_) => (0..cases).collect(),
+    };
+
+    for seed in seeds {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| property(&mut Rng::new(seed))));
+        if let Err(payload) = result {
+            eprintln!("Property failed with ATARIDISK_PROP_SEED={}", seed);
+            panic::resume_unwind(payload);
+        }
+    }
+}
@@ -0,0 +1,502 @@
+//! Protocol spoken with Atari side driver, described as data.
+//!
+//! The state machine dispatches opcodes and sizes what it reads from these
+//! tables, and `ataridisk --print-protocol` prints them as JSON, so driver
+//! authors always get the description of the server they run.
+//!
+//! Every command starts with `MAGIC` and its opcode, then both sides send
+//! the messages of its exchange in order. Server waits for the next magic
+//! afterwards, skipping any byte before it. Values are big endian.
+
+use serde::Serialize;
+
+/// Version of the protocol spoken with Atari side driver.
+///
+/// Version 2 adds speed test, authentication, clock and host commands, reset
+/// notice, refused writes, unreadable sectors frame, and capabilities
+/// handshake with chunked frames and tagged reads.
+pub const VERSION: u8 = 2;
+
+/// Starts every command, and every notice sent by server.
+pub const MAGIC: [u8; 4] = [0x18, 0x03, 0x20, 0x06];
+
+/// Sent by server after magic sequence and before its generation (long),
+/// when it has restarted while Atari driver was running.
+pub const RESET_NOTICE: u8 = 0xFF;
+
+pub const READ_SECTORS: u8 = 0;
+pub const WRITE_SECTORS: u8 = 1;
+pub const BIOS_PARAMETER_BLOCK: u8 = 2;
+pub const COMMIT: u8 = 3;
+pub const SPEED_TEST: u8 = 4;
+pub const AUTHENTICATE: u8 = 5;
+pub const CLOCK: u8 = 6;
+pub const HOST_COMMAND: u8 = 7;
+pub const HOST_CHANGES: u8 = 8;
+pub const CAPABILITIES: u8 = 9;
+pub const TAGGED_READ: u8 = 10;
+
+/// Capability of receiving `FRAME_CHUNKED` frames.
+pub const CAP_CHUNKED_FRAMES: u16 = 0x0001;
+/// Capability of sending `TAGGED_READ` commands.
+pub const CAP_TAGGED_COMMANDS: u16 = 0x0002;
+
+/// Capabilities this server has, others stay disabled whatever the driver
+/// announces.
+pub const CAPABILITIES_SUPPORTED: u16 = CAP_CHUNKED_FRAMES | CAP_TAGGED_COMMANDS;
+
+/// Write status when data is refused (strict mode finding invalid directory
+/// entries, read only disk, quota exceeded, read only source).
+pub const WRITE_REFUSED: u8 = 0x02;
+
+/// Data flags of an uncompressed frame.
+pub const FRAME_RAW: u8 = 0x00;
+/// Data flags of an LZ4 compressed frame.
+pub const FRAME_LZ4: u8 = 0x01;
+/// Data flags of a frame sent as a sequence of `CHUNK`, only once driver
+/// announced `CAP_CHUNKED_FRAMES`.
+pub const FRAME_CHUNKED: u8 = 0x02;
+/// Data flags of a read answer for sectors never written, in `error` mode
+/// of `uninitialized_reads`. Nothing follows.
+pub const FRAME_UNREADABLE: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Byte,
+    Word,
+    Long,
+    /// Fixed count of bytes
+    Bytes(usize),
+    /// Bytes counted by an earlier field or by the disk layout
+    Sized(&'static str),
+    /// Data sent as described by `FRAME`
+    Frame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub doc: &'static str,
+}
+
+impl Field {
+    /// Size in bytes, if it does not depend on content.
+    pub fn size(&self) -> Option<usize> {
+        match self.kind {
+            Kind::Byte => Some(1),
+            Kind::Word => Some(2),
+            Kind::Long => Some(4),
+            Kind::Bytes(len) => Some(len),
+            Kind::Sized(_) | Kind::Frame => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sender {
+    Atari,
+    Server,
+}
+
+/// Fields one side sends in a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Message {
+    pub from: Sender,
+    pub fields: &'static [Field],
+    /// What happens next, when it is not the next message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub then: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Command {
+    pub opcode: u8,
+    pub name: &'static str,
+    pub doc: &'static str,
+    /// Refused until Atari authenticated, when server has a secret
+    pub requires_auth: bool,
+    pub exchange: &'static [Message],
+}
+
+impl Command {
+    /// Bytes Atari sends after the opcode before server acts, or 0 if
+    /// server speaks first.
+    pub fn header_len(&self) -> usize {
+        match self.exchange.first() {
+            Some(message) if message.from == Sender::Atari => {
+                message.fields.iter().map_while(Field::size).sum()
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Whole description, as printed by `--print-protocol`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Protocol {
+    pub version: u8,
+    pub magic: [u8; 4],
+    pub byte_order: &'static str,
+    pub frame: &'static [Field],
+    pub chunk: &'static [Field],
+    pub commands: &'static [Command],
+    pub notices: &'static [Command],
+}
+
+const fn field(name: &'static str, kind: Kind, doc: &'static str) -> Field {
+    Field { name, kind, doc }
+}
+
+const fn message(from: Sender, fields: &'static [Field]) -> Message {
+    Message {
+        from,
+        fields,
+        then: None,
+    }
+}
+
+const STATUS: Field = field("status", Kind::Byte, "1 = success, 0 = failure");
+const CRC32: Field = field("crc32", Kind::Long, "CRC32 of the uncompressed content");
+const SECTORS: [Field; 2] = [
+    field("sector_index", Kind::Word, "first sector"),
+    field("sector_count", Kind::Word, "count of sectors"),
+];
+
+/// How server sends sectors read.
+pub const FRAME: &[Field] = &[
+    field(
+        "flags",
+        Kind::Byte,
+        "0 = raw content, 1 = LZ4 block, 2 = chunks up to an empty one, \
+         0xFF = sectors never written, nothing follows",
+    ),
+    field(
+        "compressed_len",
+        Kind::Long,
+        "length of the LZ4 block, only sent when flags is 1",
+    ),
+    field(
+        "content",
+        Kind::Sized("compressed_len, or sector_count * bytes_per_sector"),
+        "sectors content",
+    ),
+    CRC32,
+];
+
+/// Part of a chunked frame, so server compresses a chunk while the previous
+/// one is sent.
+///
+/// Chunks hold whole sectors, Atari can read again only the sectors of a
+/// chunk with an invalid CRC. Their length changes along the session,
+/// following CRC errors and throughput.
+pub const CHUNK: &[Field] = &[
+    field(
+        "len",
+        Kind::Word,
+        "uncompressed length, 0 ends the frame and nothing follows",
+    ),
+    field("flags", Kind::Byte, "0 = raw content, 1 = LZ4 block"),
+    field(
+        "compressed_len",
+        Kind::Word,
+        "length of the LZ4 block, only sent when flags is 1",
+    ),
+    field("content", Kind::Sized("compressed_len, or len"), ""),
+    field("crc32", Kind::Long, "CRC32 of the uncompressed chunk"),
+];
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        opcode: READ_SECTORS,
+        name: "read_sectors",
+        doc: "read consecutive sectors",
+        requires_auth: true,
+        exchange: &[
+            message(Sender::Atari, &SECTORS),
+            message(
+                Sender::Server,
+                &[field("data", Kind::Frame, "sectors content")],
+            ),
+        ],
+    },
+    Command {
+        opcode: WRITE_SECTORS,
+        name: "write_sectors",
+        doc: "write consecutive sectors",
+        requires_auth: true,
+        exchange: &[
+            message(Sender::Atari, &SECTORS),
+            message(
+                Sender::Atari,
+                &[
+                    field(
+                        "flags",
+                        Kind::Byte,
+                        "0 = raw content, other values end the command",
+                    ),
+                    field(
+                        "data",
+                        Kind::Sized("sector_count * bytes_per_sector"),
+                        "sectors content",
+                    ),
+                    CRC32,
+                ],
+            ),
+            Message {
+                from: Sender::Server,
+                fields: &[field(
+                    "status",
+                    Kind::Byte,
+                    "1 = written, 0 = invalid CRC, 2 = refused",
+                )],
+                then: Some("on invalid CRC, Atari sends flags, data and CRC again"),
+            },
+        ],
+    },
+    Command {
+        opcode: BIOS_PARAMETER_BLOCK,
+        name: "bios_parameter_block",
+        doc: "get disk layout",
+        requires_auth: false,
+        exchange: &[message(
+            Sender::Server,
+            &[
+                field("bytes_per_sector", Kind::Word, ""),
+                field("sectors_per_cluster", Kind::Word, ""),
+                field("bytes_per_cluster", Kind::Word, ""),
+                field("root_directory_sectors", Kind::Word, ""),
+                field("fat_sectors", Kind::Word, "sectors of the first FAT"),
+                field(
+                    "second_fat_start",
+                    Kind::Word,
+                    "first sector of the second FAT",
+                ),
+                field("first_free_sector", Kind::Word, "first data sector"),
+                field("cluster_count", Kind::Word, ""),
+                field("fat_flags", Kind::Byte, "0 = 12 bits FAT"),
+                field("fat_count", Kind::Byte, ""),
+            ],
+        )],
+    },
+    Command {
+        opcode: COMMIT,
+        name: "commit",
+        doc: "persist disk content",
+        requires_auth: true,
+        exchange: &[message(Sender::Server, &[STATUS])],
+    },
+    Command {
+        opcode: SPEED_TEST,
+        name: "speed_test",
+        doc: "exchange pseudo random bytes each way to measure throughput",
+        requires_auth: false,
+        exchange: &[
+            message(
+                Sender::Atari,
+                &[
+                    field("len", Kind::Long, "at most 1 MiB, larger ones are skipped"),
+                    field("data", Kind::Sized("len"), ""),
+                    field("crc32", Kind::Long, "CRC32 of data"),
+                ],
+            ),
+            message(
+                Sender::Server,
+                &[
+                    field("status", Kind::Byte, "1 = valid CRC, 0 = invalid"),
+                    field("receive_ms", Kind::Long, "time taken to receive data"),
+                    field("data", Kind::Sized("len"), ""),
+                    field("crc32", Kind::Long, "CRC32 of data"),
+                ],
+            ),
+        ],
+    },
+    Command {
+        opcode: AUTHENTICATE,
+        name: "authenticate",
+        doc: "prove knowing the server secret",
+        requires_auth: false,
+        exchange: &[
+            message(Sender::Server, &[field("challenge", Kind::Bytes(8), "")]),
+            message(
+                Sender::Atari,
+                &[field(
+                    "answer",
+                    Kind::Bytes(20),
+                    "SHA-1 of challenge then secret",
+                )],
+            ),
+            message(
+                Sender::Server,
+                &[field("status", Kind::Byte, "1 = accepted, 0 = refused")],
+            ),
+        ],
+    },
+    Command {
+        opcode: CLOCK,
+        name: "clock",
+        doc: "get host clock",
+        requires_auth: false,
+        exchange: &[message(
+            Sender::Server,
+            &[field(
+                "clock",
+                Kind::Long,
+                "GEMDOS date in high word and time in low one, 0 if none",
+            )],
+        )],
+    },
+    Command {
+        opcode: HOST_COMMAND,
+        name: "host_command",
+        doc: "run a command configured on host",
+        requires_auth: true,
+        exchange: &[
+            message(
+                Sender::Atari,
+                &[
+                    field("name_len", Kind::Word, "longer names are skipped"),
+                    field("name", Kind::Sized("name_len"), "command name"),
+                ],
+            ),
+            message(
+                Sender::Server,
+                &[
+                    field(
+                        "status",
+                        Kind::Byte,
+                        "1 = run, 0 = unknown or failed to start",
+                    ),
+                    field("exit_code", Kind::Byte, "0xFF if none"),
+                    field("output_len", Kind::Long, ""),
+                    field("output", Kind::Sized("output_len"), ""),
+                    field("crc32", Kind::Long, "CRC32 of output"),
+                ],
+            ),
+        ],
+    },
+    Command {
+        opcode: HOST_CHANGES,
+        name: "host_changes",
+        doc: "get count of files changed from host, disk must be read again when it changes",
+        requires_auth: false,
+        exchange: &[message(Sender::Server, &[field("changes", Kind::Long, "")])],
+    },
+    Command {
+        opcode: CAPABILITIES,
+        name: "capabilities",
+        doc: "agree on optional protocol features, all disabled until then",
+        requires_auth: false,
+        exchange: &[
+            message(
+                Sender::Atari,
+                &[field(
+                    "capabilities",
+                    Kind::Word,
+                    "features driver supports, 1 = chunked frames, 2 = tagged reads",
+                )],
+            ),
+            message(
+                Sender::Server,
+                &[field(
+                    "capabilities",
+                    Kind::Word,
+                    "features both sides support, used from now on",
+                )],
+            ),
+        ],
+    },
+    Command {
+        opcode: TAGGED_READ,
+        name: "tagged_read",
+        doc: "read consecutive sectors; driver may send next command while the answer \
+              is sent, commands are answered in order",
+        requires_auth: true,
+        exchange: &[
+            message(
+                Sender::Atari,
+                &[
+                    field("tag", Kind::Byte, "chosen by driver, sent back"),
+                    SECTORS[0],
+                    SECTORS[1],
+                ],
+            ),
+            message(
+                Sender::Server,
+                &[
+                    field("tag", Kind::Byte, "tag of the read answered"),
+                    field("data", Kind::Frame, "sectors content"),
+                ],
+            ),
+        ],
+    },
+];
+
+/// Sent by server on its own, starting with `MAGIC` too.
+pub const NOTICES: &[Command] = &[Command {
+    opcode: RESET_NOTICE,
+    name: "reset_notice",
+    doc: "server restarted, Atari drops its pending command and reads disk layout again",
+    requires_auth: false,
+    exchange: &[message(
+        Sender::Server,
+        &[field("generation", Kind::Long, "count of server starts")],
+    )],
+}];
+
+pub const PROTOCOL: Protocol = Protocol {
+    version: VERSION,
+    magic: MAGIC,
+    byte_order: "big_endian",
+    frame: FRAME,
+    chunk: CHUNK,
+    commands: COMMANDS,
+    notices: NOTICES,
+};
+
+/// Command of an opcode Atari sent.
+pub fn command(opcode: u8) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.opcode == opcode)
+}
+
+/// Bytes Atari sends after the opcode of a known command.
+pub fn header_len(opcode: u8) -> usize {
+    command(opcode).map_or(0, Command::header_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::DiskLayout;
+
+    #[test]
+    fn test_commands() {
+        for (i, command) in COMMANDS.iter().enumerate() {
+            assert_eq!(command.opcode as usize, i);
+            assert_eq!(super::command(command.opcode), Some(command));
+        }
+        assert_eq!(super::command(RESET_NOTICE), None);
+
+        assert_eq!(header_len(READ_SECTORS), 4);
+        assert_eq!(header_len(WRITE_SECTORS), 4);
+        assert_eq!(header_len(SPEED_TEST), 4);
+        assert_eq!(header_len(HOST_COMMAND), 2);
+        assert_eq!(header_len(CAPABILITIES), 2);
+        assert_eq!(header_len(TAGGED_READ), 5);
+        assert_eq!(header_len(COMMIT), 0);
+    }
+
+    #[test]
+    fn test_bios_parameter_block() {
+        let mut bpb = Vec::new();
+        DiskLayout::default()
+            .write_bios_parameter_block(&mut bpb)
+            .unwrap();
+
+        let fields = command(BIOS_PARAMETER_BLOCK).unwrap().exchange[0].fields;
+        let described: Option<usize> = fields.iter().map(Field::size).sum();
+        assert_eq!(described, Some(bpb.len()));
+    }
+}
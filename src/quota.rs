@@ -0,0 +1,135 @@
+//! Limits on clusters used by sub directories.
+//!
+//! A quota covers a directory and everything below it, so a program filling
+//! a folder writable from Atari (ex: `DOWNLOADS`) cannot use the whole disk.
+
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use crate::{backend::SectorBackend, entries::FileInfo, error, storage::DiskStorage};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Quota {
+    /// Directory path from disk root (ex: `DOWNLOADS` or `GAMES\SAVES`)
+    pub path: String,
+
+    /// Number of clusters directory and its content may use
+    pub max_clusters: usize,
+}
+
+fn components(path: &str) -> impl Iterator<Item = String> + '_ {
+    path.split('\\')
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_uppercase())
+}
+
+impl Quota {
+    /// Check if a `\` separated disk path is the quota directory or is
+    /// inside of it.
+    pub fn covers(&self, path: &str) -> bool {
+        let mut path = components(path);
+        components(&self.path).all(|c| path.next() == Some(c))
+    }
+
+    /// Number of clusters used by quota directory, if it exists.
+    pub fn used_clusters<B>(&self, storage: &DiskStorage<B>) -> error::Result<Option<usize>>
+    where
+        B: SectorBackend,
+    {
+        let dir = match find_dir(storage, &self.path)? {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+
+        let mut used = 0;
+        let mut visited = HashSet::new();
+        let mut pending = vec![dir];
+        while let Some(file_info) = pending.pop() {
+            if file_info.is_deleted() || !visited.insert(file_info.cluster_index) {
+                continue;
+            }
+
+            used += storage.cluster_chain(file_info.cluster_index).len();
+            if file_info.is_dir() {
+                // Skip `.` and `..`
+                pending.extend(storage.read_dir(&file_info)?.into_iter().skip(2));
+            }
+        }
+
+        Ok(Some(used))
+    }
+}
+
+/// Find a directory from its `\` separated disk path.
+fn find_dir<B>(storage: &DiskStorage<B>, path: &str) -> error::Result<Option<FileInfo>>
+where
+    B: SectorBackend,
+{
+    let mut entries = storage.list_root_file_infos();
+    let mut found = None;
+
+    for component in components(path) {
+        let dir = entries
+            .into_iter()
+            .find(|f| f.is_dir() && !f.is_deleted() && f.filename().is_ok_and(|n| n == component));
+        match dir {
+            Some(dir) => {
+                entries = storage.read_dir(&dir)?;
+                found = Some(dir);
+            }
+            None => return Ok(None),
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::{layout::DiskLayout, storage::ROOT_INDEX};
+
+    #[test]
+    fn test_covers() {
+        let quota = Quota {
+            path: "GAMES\\SAVES".to_string(),
+            max_clusters: 10,
+        };
+        assert!(quota.covers("GAMES\\SAVES"));
+        assert!(quota.covers("games\\saves\\DOOM"));
+        assert!(!quota.covers("GAMES"));
+        assert!(!quota.covers("GAMES\\SAVES2"));
+    }
+
+    #[test]
+    fn test_used_clusters() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let bytes_per_cluster = storage.disk_layout.bytes_per_cluster() as usize;
+
+        let dir = storage
+            .add_virtual_directory("DOWNLOAD", "", mtime, ROOT_INDEX)
+            .unwrap();
+        let sub = storage
+            .add_virtual_directory("SUB", "", mtime, dir)
+            .unwrap();
+        storage
+            .add_virtual_file("BIG", "BIN", mtime, &vec![1; bytes_per_cluster + 1], sub)
+            .unwrap();
+
+        let quota = |path: &str| Quota {
+            path: path.to_string(),
+            max_clusters: 10,
+        };
+        // 2 directories + 2 clusters of file
+        assert_eq!(quota("DOWNLOAD").used_clusters(&storage).unwrap(), Some(4));
+        assert_eq!(
+            quota("DOWNLOAD\\SUB").used_clusters(&storage).unwrap(),
+            Some(3)
+        );
+        assert_eq!(quota("MISSING").used_clusters(&storage).unwrap(), None);
+    }
+}
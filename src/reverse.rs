@@ -0,0 +1,258 @@
+//! Map the disk file tree, as Atari has left it, back to host paths.
+//!
+//! Imported files and directories remember the host path they come from,
+//! keyed by their first cluster. Walking the directories then tells for
+//! every entry where it comes from and where it belongs on host:
+//!
+//! - an imported entry still named as imported belongs to its origin,
+//! - an entry renamed or created by Atari belongs to the host directory of
+//!   its parent, under its disk name.
+//!
+//! An entry keeps its origin as long as its first cluster does not change,
+//! so a new file reusing the clusters of a deleted imported one can be
+//! mistaken for it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backend::SectorBackend,
+    entries::FileInfo,
+    error,
+    name_key::NameKey,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+/// Host entry a disk entry has been imported from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub host_path: PathBuf,
+    /// Name given to the entry on disk when importing it
+    pub disk_name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MappedEntry {
+    /// `\` separated path from disk root
+    pub disk_path: String,
+    pub file_info: FileInfo,
+    /// Host entry it has been imported from
+    pub origin: Option<PathBuf>,
+    /// Where entry belongs on host, unknown outside of imported directories
+    pub host_path: Option<PathBuf>,
+}
+
+impl MappedEntry {
+    /// Entry has been created by Atari.
+    pub fn is_new(&self) -> bool {
+        self.origin.is_none()
+    }
+
+    /// Entry has been imported and now belongs elsewhere on host.
+    pub fn is_moved(&self) -> bool {
+        self.origin.is_some() && self.origin != self.host_path
+    }
+}
+
+/// Entries of the disk and the data sectors they use.
+#[derive(Debug, Clone, Default)]
+pub struct SectorMap {
+    pub entries: Vec<MappedEntry>,
+    owners: HashMap<u16, usize>,
+}
+
+impl SectorMap {
+    /// Entry using a data sector, if any.
+    pub fn owner(&self, sector_index: u16) -> Option<&MappedEntry> {
+        self.owners
+            .get(&sector_index)
+            .map(|index| &self.entries[*index])
+    }
+}
+
+pub struct ReverseMapper<'a, B> {
+    storage: &'a DiskStorage<B>,
+}
+
+impl<'a, B> ReverseMapper<'a, B>
+where
+    B: SectorBackend,
+{
+    pub fn new(storage: &'a DiskStorage<B>) -> Self {
+        Self { storage }
+    }
+
+    /// Map every entry of the disk, parents before their content.
+    pub fn entries(&self) -> error::Result<Vec<MappedEntry>> {
+        let root_host = self
+            .storage
+            .origin(ROOT_INDEX)
+            .map(|origin| origin.host_path.clone());
+
+        let mut mapped = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![(
+            String::new(),
+            root_host,
+            self.storage.list_root_file_infos(),
+        )];
+
+        while let Some((dir_path, dir_host, entries)) = pending.pop() {
+            for file_info in entries {
+                if file_info.is_deleted() || file_info.is_unused() {
+                    continue;
+                }
+                let name = file_info.filename()?;
+                if name == "." || name == ".." {
+                    continue;
+                }
+
+                let entry = self.map_entry(&dir_path, dir_host.as_deref(), name, file_info);
+                if entry.file_info.is_dir() && visited.insert(entry.file_info.cluster_index) {
+                    pending.push((
+                        entry.disk_path.clone(),
+                        entry.host_path.clone(),
+                        self.storage.read_dir(&entry.file_info)?,
+                    ));
+                }
+                mapped.push(entry);
+            }
+        }
+
+        Ok(mapped)
+    }
+
+    /// Map every data sector to the entry using it.
+    pub fn sector_map(&self) -> error::Result<SectorMap> {
+        let layout = &self.storage.disk_layout;
+        let entries = self.entries()?;
+        let mut owners = HashMap::new();
+
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.file_info.cluster_index < 2 {
+                continue;
+            }
+            for cluster_index in self.storage.cluster_chain(entry.file_info.cluster_index) {
+                let first_sector = layout.convert_cluster_to_sector(cluster_index);
+                for sector_index in first_sector..first_sector + layout.sectors_per_cluster() {
+                    owners.insert(sector_index, index);
+                }
+            }
+        }
+
+        Ok(SectorMap { entries, owners })
+    }
+
+    /// Find where a `\` separated disk path belongs on host.
+    pub fn host_path(&self, disk_path: &str) -> error::Result<Option<PathBuf>> {
+        let key = NameKey::new(disk_path.trim_matches('\\'));
+        Ok(self
+            .entries()?
+            .into_iter()
+            .find(|entry| NameKey::new(&entry.disk_path) == key)
+            .and_then(|entry| entry.host_path))
+    }
+
+    fn map_entry(
+        &self,
+        dir_path: &str,
+        dir_host: Option<&Path>,
+        name: String,
+        file_info: FileInfo,
+    ) -> MappedEntry {
+        let origin = self.storage.origin(file_info.cluster_index);
+
+        let host_path = match origin {
+            Some(origin)
+                if origin.disk_name == name
+                    && dir_host.is_none_or(|dir| origin.host_path.parent() == Some(dir)) =>
+            {
+                Some(origin.host_path.clone())
+            }
+            _ => dir_host.map(|dir| dir.join(&name)),
+        };
+
+        MappedEntry {
+            disk_path: if dir_path.is_empty() {
+                name
+            } else {
+                format!("{}\\{}", dir_path, name)
+            },
+            file_info,
+            origin: origin.map(|origin| origin.host_path.clone()),
+            host_path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout};
+
+    #[test]
+    fn test_entries() {
+        let fixture = Fixture::empty("reverse").unwrap();
+        let dir = fixture.path();
+        fs::create_dir_all(dir.join("SUB")).unwrap();
+        fs::write(dir.join("A.TXT"), b"a").unwrap();
+        fs::write(dir.join("KEEP.TXT"), b"keep").unwrap();
+        fs::write(dir.join("SUB").join("B.TXT"), b"b").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(dir).unwrap();
+        let root = dir.canonicalize().unwrap();
+
+        // Atari renames A.TXT and creates SUB\NEW.TXT
+        let sub = storage.list_root_file_infos()[2].cluster_index;
+        storage
+            .add_virtual_file("NEW", "TXT", NaiveDateTime::from_timestamp(0, 0), b"", sub)
+            .unwrap();
+        let root_sector = storage.disk_layout.count_fat_sectors();
+        let mut sector = Vec::new();
+        storage.read_sector(&mut sector, root_sector).unwrap();
+        sector[0] = b'C';
+        storage
+            .write_sectors(&mut sector.as_slice(), root_sector, 1)
+            .unwrap();
+
+        let mapper = ReverseMapper::new(&storage);
+        let entries = mapper.entries().unwrap();
+        let find = |disk_path: &str| {
+            entries
+                .iter()
+                .find(|e| e.disk_path == disk_path)
+                .unwrap()
+                .clone()
+        };
+
+        let renamed = find("C.TXT");
+        assert_eq!(renamed.origin, Some(root.join("A.TXT")));
+        assert_eq!(renamed.host_path, Some(root.join("C.TXT")));
+        assert!(renamed.is_moved());
+
+        let kept = find("SUB\\B.TXT");
+        assert_eq!(kept.host_path, Some(root.join("SUB").join("B.TXT")));
+        assert!(!kept.is_moved() && !kept.is_new());
+
+        let new = find("SUB\\NEW.TXT");
+        assert!(new.is_new());
+        assert_eq!(
+            mapper.host_path("sub\\new.txt").unwrap(),
+            Some(root.join("SUB").join("NEW.TXT"))
+        );
+
+        let map = mapper.sector_map().unwrap();
+        let first_sector = storage
+            .disk_layout
+            .convert_cluster_to_sector(kept.file_info.cluster_index);
+        assert_eq!(map.owner(first_sector).unwrap().disk_path, "SUB\\B.TXT");
+        assert!(map.owner(0).is_none());
+    }
+}
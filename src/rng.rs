@@ -0,0 +1,54 @@
+//! Seedable pseudo random numbers, for property tests and fault injection.
+//!
+//! Same seed always gives the same sequence, on every platform.
+
+/// Deterministic pseudo random generator (xorshift64*).
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is the only state xorshift cannot leave
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Random value in `0..max`.
+    pub fn below(&mut self, max: usize) -> usize {
+        assert!(max > 0, "Empty range");
+        (self.next_u64() % max as u64) as usize
+    }
+
+    /// Random value in `min..=max`.
+    pub fn between(&mut self, min: usize, max: usize) -> usize {
+        min + self.below(max - min + 1)
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    /// Random event happening with `probability` (from 0 to 1).
+    pub fn chance(&mut self, probability: f64) -> bool {
+        // 53 bits: full precision of a f64 in `0..1`
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    /// Random name made of chars valid on TOS.
+    pub fn name(&mut self, max_len: usize) -> String {
+        const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+        (0..self.between(1, max_len))
+            .map(|_| CHARS[self.below(CHARS.len())] as char)
+            .collect()
+    }
+}
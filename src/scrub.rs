@@ -0,0 +1,320 @@
+//! Background check of the disk structure while serving, so a FAT or a
+//! directory damaged by Atari (or by a bug) shows up in logs when it
+//! happens, not when the dump is checked later.
+//!
+//! Each pass walks directories and cluster chains of the live storage under
+//! its lock, from a thread with the lowest priority. Atari updates the FAT
+//! and directories with separate writes, so an issue is only logged once
+//! two passes in a row have found it.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex, TryLockError},
+    thread,
+    time::Duration,
+};
+
+use crate::{backend::SectorBackend, error, fat::ChainEnd, storage::DiskStorage};
+
+/// Delay before trying again when storage is busy.
+const BUSY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Issue {
+    /// Chain reaching a free, reserved or out of range cluster
+    BrokenChain { path: String, cluster: u16 },
+    /// Chain reaching one of its clusters again
+    LoopedChain { path: String, cluster: u16 },
+    /// Cluster used by two entries
+    CrossLinked {
+        path: String,
+        other: String,
+        cluster: u16,
+    },
+    /// File larger than its clusters
+    ShortChain {
+        path: String,
+        size: usize,
+        clusters: usize,
+    },
+    /// Allocated clusters of no entry
+    LostClusters(usize),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BrokenChain { path, cluster } => {
+                write!(f, "chain of {} is broken at cluster {:#06x}", path, cluster)
+            }
+            Self::LoopedChain { path, cluster } => {
+                write!(f, "chain of {} loops on cluster {:#06x}", path, cluster)
+            }
+            Self::CrossLinked {
+                path,
+                other,
+                cluster,
+            } => write!(f, "{} and {} share cluster {:#06x}", path, other, cluster),
+            Self::ShortChain {
+                path,
+                size,
+                clusters,
+            } => write!(f, "{} holds {} bytes in {} clusters", path, size, clusters),
+            Self::LostClusters(count) => write!(f, "{} allocated clusters are lost", count),
+        }
+    }
+}
+
+/// Walk the whole disk structure and list what is wrong with it.
+pub fn scan<B>(storage: &DiskStorage<B>) -> error::Result<Vec<Issue>>
+where
+    B: SectorBackend,
+{
+    let fat = storage.fat();
+    let cluster_size = storage.disk_layout.bytes_per_cluster() as usize;
+    let mut issues = Vec::new();
+    let mut owners: HashMap<u16, String> = HashMap::new();
+    let mut pending = vec![(String::new(), storage.list_root_file_infos())];
+
+    while let Some((dir_path, entries)) = pending.pop() {
+        for file_info in entries {
+            if file_info.is_deleted() || file_info.is_unused() {
+                continue;
+            }
+            let name = file_info.filename()?;
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = if dir_path.is_empty() {
+                name
+            } else {
+                format!("{}\\{}", dir_path, name)
+            };
+
+            // Empty files have no cluster, directories always have one
+            if file_info.cluster_index == 0 && !file_info.is_dir() {
+                if file_info.size() > 0 {
+                    issues.push(Issue::ShortChain {
+                        path,
+                        size: file_info.size(),
+                        clusters: 0,
+                    });
+                }
+                continue;
+            }
+
+            let (clusters, end) = fat.checked_chain(file_info.cluster_index);
+            match end {
+                ChainEnd::End => {}
+                ChainEnd::Broken(cluster) => issues.push(Issue::BrokenChain {
+                    path: path.clone(),
+                    cluster,
+                }),
+                ChainEnd::Loop(cluster) => issues.push(Issue::LoopedChain {
+                    path: path.clone(),
+                    cluster,
+                }),
+            }
+
+            let mut cross_linked = false;
+            for &cluster in &clusters {
+                match owners.entry(cluster) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(path.clone());
+                    }
+                    Entry::Occupied(entry) if !cross_linked => {
+                        cross_linked = true;
+                        issues.push(Issue::CrossLinked {
+                            path: path.clone(),
+                            other: entry.get().clone(),
+                            cluster,
+                        });
+                    }
+                    Entry::Occupied(_) => {}
+                }
+            }
+
+            if file_info.is_dir() {
+                // Content of a cross linked directory is walked once
+                if !cross_linked {
+                    let mut content = Vec::new();
+                    for &cluster in &clusters {
+                        content.extend(storage.read_dir_cluster(cluster)?);
+                    }
+                    pending.push((path, content));
+                }
+            } else if clusters.len() * cluster_size < file_info.size() {
+                issues.push(Issue::ShortChain {
+                    path,
+                    size: file_info.size(),
+                    clusters: clusters.len(),
+                });
+            }
+        }
+    }
+
+    let lost = fat
+        .allocated_clusters()
+        .filter(|cluster| !owners.contains_key(cluster))
+        .count();
+    if lost > 0 {
+        issues.push(Issue::LostClusters(lost));
+    }
+    Ok(issues)
+}
+
+/// Issues of the previous passes.
+#[derive(Debug, Default)]
+pub struct Scrubber {
+    previous: HashSet<Issue>,
+    reported: HashSet<Issue>,
+}
+
+impl Scrubber {
+    /// Record issues of a pass, and return the ones to log: found by this
+    /// pass and the previous one, and not logged yet.
+    pub fn update(&mut self, issues: Vec<Issue>) -> Vec<Issue> {
+        let current: HashSet<Issue> = issues.into_iter().collect();
+        let mut confirmed: Vec<Issue> = current
+            .intersection(&self.previous)
+            .filter(|issue| !self.reported.contains(*issue))
+            .cloned()
+            .collect();
+        confirmed.sort();
+
+        // Fixed issues are logged again if they come back
+        self.reported.retain(|issue| current.contains(issue));
+        self.reported.extend(confirmed.iter().cloned());
+        self.previous = current;
+        confirmed
+    }
+}
+
+/// Check `storage` every `interval` until the app stops.
+pub fn spawn<B>(storage: Arc<Mutex<DiskStorage<B>>>, interval: Duration)
+where
+    B: SectorBackend + Send + 'static,
+{
+    log::info!("Checking disk structure every {}s", interval.as_secs());
+
+    thread::spawn(move || {
+        lower_priority();
+        let mut scrubber = Scrubber::default();
+        loop {
+            thread::sleep(interval);
+
+            // Never make Atari wait behind a pass
+            let issues = loop {
+                match storage.try_lock() {
+                    Ok(storage) => break scan(&storage),
+                    Err(TryLockError::WouldBlock) => thread::sleep(BUSY_DELAY),
+                    Err(TryLockError::Poisoned(_)) => return,
+                }
+            };
+            match issues {
+                Ok(issues) => {
+                    for issue in scrubber.update(issues) {
+                        log::warn!("Disk inconsistency: {}", issue);
+                    }
+                }
+                Err(e) => log::warn!("Cannot check disk structure (error: {})", e),
+            }
+        }
+    });
+}
+
+/// Let serving threads go first on busy hosts.
+fn lower_priority() {
+    // Linux gives each thread its own priority
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        libc::setpriority(libc::PRIO_PROCESS, tid, 19);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{layout::DiskLayout, storage::ROOT_INDEX};
+
+    #[test]
+    fn test_scan() {
+        let mtime = chrono::Local::now().naive_local();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let size = storage.disk_layout.bytes_per_cluster() as usize * 3;
+        let games = storage
+            .add_virtual_directory("GAMES", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("DUNG", "PRG", mtime, &vec![1; size], games)
+            .unwrap();
+        storage
+            .add_virtual_file("EMPTY", "TXT", mtime, &[], ROOT_INDEX)
+            .unwrap();
+        assert_eq!(scan(&storage).unwrap(), []);
+
+        // Atari frees last cluster of the file, without updating its size
+        let dung = storage.find_entry("GAMES\\DUNG.PRG").unwrap().unwrap();
+        let chain = storage.cluster_chain(dung.cluster_index);
+        let mut fat = Vec::new();
+        storage.read_sectors(&mut fat, 0, 1).unwrap();
+        let set = |fat: &mut Vec<u8>, cluster: u16, value: u16| {
+            fat[cluster as usize * 2..][..2].copy_from_slice(&value.to_ne_bytes())
+        };
+        set(&mut fat, chain[2], 0);
+        set(&mut fat, chain[1], 0xFFFF);
+        storage.write_sectors(&mut fat.as_slice(), 0, 1).unwrap();
+        assert_eq!(
+            scan(&storage).unwrap(),
+            [Issue::ShortChain {
+                path: "GAMES\\DUNG.PRG".to_string(),
+                size,
+                clusters: 2,
+            }]
+        );
+
+        // Then links it back to its first cluster
+        set(&mut fat, chain[1], chain[0]);
+        storage.write_sectors(&mut fat.as_slice(), 0, 1).unwrap();
+        assert_eq!(
+            scan(&storage).unwrap(),
+            [
+                Issue::LoopedChain {
+                    path: "GAMES\\DUNG.PRG".to_string(),
+                    cluster: chain[0],
+                },
+                Issue::ShortChain {
+                    path: "GAMES\\DUNG.PRG".to_string(),
+                    size,
+                    clusters: 2,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scrubber() {
+        let lost = Issue::LostClusters(2);
+        let broken = Issue::BrokenChain {
+            path: "A.TXT".to_string(),
+            cluster: 0,
+        };
+        let mut scrubber = Scrubber::default();
+
+        // Only issues found twice in a row are logged, once
+        assert_eq!(scrubber.update(vec![lost.clone()]), []);
+        assert_eq!(
+            scrubber.update(vec![lost.clone(), broken.clone()]),
+            [Issue::LostClusters(2)]
+        );
+        assert_eq!(
+            scrubber.update(vec![lost.clone(), broken.clone()]),
+            [broken]
+        );
+        assert_eq!(scrubber.update(vec![]), []);
+        assert_eq!(scrubber.update(vec![lost.clone()]), []);
+        assert_eq!(scrubber.update(vec![lost.clone()]), [lost]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Loopback test of the serial transport.
+//!
+//! TX and RX of the cable must be connected together: every byte sent must
+//! come back unchanged. Test patterns are sent with their CRC32, so cabling
+//! problems can be found before involving the Atari.
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{checksum, error};
+
+/// Size of every test pattern.
+pub const PATTERN_LEN: usize = 512;
+
+/// Bytes sent to the loopback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Zeros,
+    Ones,
+    /// `0x55` / `0xAA` alternating bits
+    Alternating,
+    /// Every byte value in order
+    Ramp,
+    /// Pseudo random bytes
+    Noise,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 5] = [
+        Self::Zeros,
+        Self::Ones,
+        Self::Alternating,
+        Self::Ramp,
+        Self::Noise,
+    ];
+
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Zeros => vec![0x00; PATTERN_LEN],
+            Self::Ones => vec![0xFF; PATTERN_LEN],
+            Self::Alternating => (0..PATTERN_LEN)
+                .map(|i| if i % 2 == 0 { 0x55 } else { 0xAA })
+                .collect(),
+            Self::Ramp => (0..PATTERN_LEN).map(|i| i as u8).collect(),
+            Self::Noise => {
+                // Linear congruential generator, so runs can be compared
+                let mut seed: u32 = 0x2006_0318;
+                (0..PATTERN_LEN)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                        (seed >> 16) as u8
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Zeros => "zeros",
+            Self::Ones => "ones",
+            Self::Alternating => "alternating",
+            Self::Ramp => "ramp",
+            Self::Noise => "noise",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What came back from the loopback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// Some bytes have been changed on the way
+    Corrupted {
+        mismatches: usize,
+        valid_crc: bool,
+    },
+    /// Not every byte came back before timeout
+    Timeout {
+        received: usize,
+    },
+}
+
+/// Result of a single pattern sent to the loopback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternResult {
+    pub pattern: Pattern,
+    pub outcome: Outcome,
+    /// Time between first byte sent and last byte received
+    pub latency: Duration,
+}
+
+impl Display for PatternResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<12} ", self.pattern)?;
+        match &self.outcome {
+            Outcome::Ok => write!(f, "ok ({} ms)", self.latency.as_millis()),
+            Outcome::Corrupted {
+                mismatches,
+                valid_crc,
+            } => write!(
+                f,
+                "CORRUPTED ({} bytes differ, CRC {})",
+                mismatches,
+                if *valid_crc { "valid" } else { "invalid" }
+            ),
+            Outcome::Timeout { received } => write!(
+                f,
+                "TIMEOUT ({} / {} bytes received)",
+                received,
+                PATTERN_LEN + 4
+            ),
+        }
+    }
+}
+
+/// Every pattern sent to the loopback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelftestReport {
+    pub results: Vec<PatternResult>,
+}
+
+impl SelftestReport {
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|r| r.outcome == Outcome::Ok)
+    }
+
+    /// Worst latency of successful patterns.
+    pub fn max_latency(&self) -> Option<Duration> {
+        self.results
+            .iter()
+            .filter(|r| r.outcome == Outcome::Ok)
+            .map(|r| r.latency)
+            .max()
+    }
+
+    /// Throughput computed from successful patterns, in bytes per second.
+    pub fn throughput(&self) -> Option<f64> {
+        let (bytes, duration) = self
+            .results
+            .iter()
+            .filter(|r| r.outcome == Outcome::Ok)
+            .fold((0, Duration::ZERO), |(bytes, duration), r| {
+                (bytes + PATTERN_LEN + 4, duration + r.latency)
+            });
+
+        if bytes == 0 || duration.is_zero() {
+            None
+        } else {
+            Some(bytes as f64 / duration.as_secs_f64())
+        }
+    }
+}
+
+/// Send every pattern `rounds` times to the loopback.
+///
+/// Serial port must have a read timeout, an unplugged cable would block
+/// forever otherwise.
+pub fn run<S>(serial: &mut S, rounds: usize) -> error::Result<SelftestReport>
+where
+    S: Read + Write,
+{
+    let mut results = Vec::new();
+    for _ in 0..rounds {
+        for pattern in Pattern::ALL {
+            let result = send_pattern(serial, pattern)?;
+            log::info!("Loopback {}", result);
+            results.push(result);
+        }
+    }
+
+    Ok(SelftestReport { results })
+}
+
+fn send_pattern<S>(serial: &mut S, pattern: Pattern) -> error::Result<PatternResult>
+where
+    S: Read + Write,
+{
+    let mut sent = pattern.bytes();
+    checksum::write_crc32(&mut sent, &pattern.bytes())?;
+
+    let start = Instant::now();
+    serial.write_all(&sent)?;
+    serial.flush()?;
+
+    let mut received = vec![0; sent.len()];
+    let mut count = 0;
+    while count < received.len() {
+        match serial.read(&mut received[count..]) {
+            Ok(0) => break,
+            Ok(n) => count += n,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let latency = start.elapsed();
+
+    let outcome = if count < received.len() {
+        Outcome::Timeout { received: count }
+    } else if received != sent {
+        let (content, mut crc) = received.split_at(PATTERN_LEN);
+        Outcome::Corrupted {
+            mismatches: received.iter().zip(&sent).filter(|(a, b)| a != b).count(),
+            valid_crc: checksum::check_crc32(&mut crc, content)?,
+        }
+    } else {
+        Outcome::Ok
+    };
+
+    Ok(PatternResult {
+        pattern,
+        outcome,
+        latency,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Loopback changing every `corrupt_every` byte.
+    struct Loopback {
+        buffer: VecDeque<u8>,
+        corrupt_every: Option<usize>,
+        dropped: usize,
+    }
+
+    impl Loopback {
+        fn new() -> Self {
+            Self {
+                buffer: VecDeque::new(),
+                corrupt_every: None,
+                dropped: 0,
+            }
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for (i, b) in buf.iter().enumerate() {
+                match self.corrupt_every {
+                    Some(n) if i % n == 0 => self.buffer.push_back(!b),
+                    _ => self.buffer.push_back(*b),
+                }
+            }
+            // Lose last bytes
+            for _ in 0..self.dropped {
+                self.buffer.pop_back();
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.buffer.is_empty() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.buffer.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_patterns() {
+        for pattern in Pattern::ALL {
+            assert_eq!(pattern.bytes().len(), PATTERN_LEN);
+        }
+        assert_eq!(Pattern::Ramp.bytes()[..3], [0x00, 0x01, 0x02]);
+        assert_eq!(Pattern::Noise.bytes(), Pattern::Noise.bytes());
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut loopback = Loopback::new();
+        let report = run(&mut loopback, 2).unwrap();
+        assert_eq!(report.results.len(), 10);
+        assert!(report.is_ok());
+        assert!(report.max_latency().is_some());
+
+        let mut loopback = Loopback::new();
+        loopback.corrupt_every = Some(100);
+        let report = run(&mut loopback, 1).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.results[0].outcome,
+            Outcome::Corrupted {
+                mismatches: 6,
+                valid_crc: false
+            }
+        );
+
+        let mut loopback = Loopback::new();
+        loopback.dropped = 10;
+        let report = run(&mut loopback, 1).unwrap();
+        assert_eq!(
+            report.results[0].outcome,
+            Outcome::Timeout {
+                received: PATTERN_LEN + 4 - 10
+            }
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! State kept across server restarts, so a driver still running on Atari
+//! can be told to read the disk again instead of hanging on a lost answer.
+//!
+//! Each server start is a new generation. When a previous server has been
+//! started with the same state file, the new one announces its generation
+//! to Atari as soon as the link is open (see `protocol::RESET_NOTICE`).
+
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::layout::DiskLayout;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SessionState {
+    /// Incremented on each server start
+    pub generation: u32,
+    /// Layout served, Atari must reboot when it changes
+    pub disk_layout: Option<DiskLayout>,
+}
+
+impl SessionState {
+    /// State saved by previous server, if any.
+    pub fn load<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Cannot read session state {:?} (error: {})", path, e);
+                return None;
+            }
+        };
+
+        serde_json::from_str(&content)
+            .map_err(|e| log::warn!("Ignoring invalid session state {:?} (error: {})", path, e))
+            .ok()
+    }
+
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content)
+    }
+
+    /// Start a new generation serving `disk_layout`, and return it if Atari
+    /// has to be told about the restart.
+    pub fn restart<P>(path: P, disk_layout: &DiskLayout) -> io::Result<Option<u32>>
+    where
+        P: AsRef<Path>,
+    {
+        let previous = Self::load(&path);
+        let state = Self {
+            generation: previous
+                .as_ref()
+                .map_or(0, |previous| previous.generation.wrapping_add(1)),
+            disk_layout: Some(disk_layout.clone()),
+        };
+        state.save(&path)?;
+
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+        if previous.disk_layout.as_ref() != Some(disk_layout) {
+            log::warn!("Disk layout changed since previous server, Atari must reboot");
+        }
+        log::info!(
+            "Server restarted, announcing generation {} to Atari",
+            state.generation
+        );
+        Ok(Some(state.generation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::Fixture,
+        layout::{PartitionType, Tos},
+    };
+
+    #[test]
+    fn test_restart() {
+        let fixture = Fixture::empty("session").unwrap();
+        let path = fixture.join("test.session");
+        let layout = DiskLayout::default();
+
+        assert_eq!(SessionState::restart(&path, &layout).unwrap(), None);
+        assert_eq!(SessionState::restart(&path, &layout).unwrap(), Some(1));
+
+        let other = DiskLayout::new(Tos::V100, PartitionType::Gem, 8);
+        assert_eq!(SessionState::restart(&path, &other).unwrap(), Some(2));
+        assert_eq!(
+            SessionState::load(&path),
+            Some(SessionState {
+                generation: 2,
+                disk_layout: Some(other),
+            })
+        );
+
+        fs::write(&path, "{").unwrap();
+        assert_eq!(SessionState::restart(&path, &layout).unwrap(), None);
+    }
+}
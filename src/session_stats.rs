@@ -0,0 +1,175 @@
+//! Statistics of the sessions served on each port, kept across runs.
+//!
+//! They help comparing cables and adapters over time: a port with a rising
+//! CRC error rate most likely has a bad link. Only writes are counted in
+//! this rate, as Atari checks the CRC of what it reads on its side.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::{self, Notice, TransactionKind};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PortStats {
+    pub sessions: u64,
+    /// Bytes sent to Atari
+    pub bytes_read: u64,
+    /// Bytes received from Atari
+    pub bytes_written: u64,
+    /// Successful write commands
+    pub writes: u64,
+    pub crc_errors: u64,
+}
+
+impl PortStats {
+    /// Part of the written buffers received with a CRC error.
+    pub fn crc_error_rate(&self) -> f64 {
+        let received = self.writes + self.crc_errors;
+        if received == 0 {
+            0.0
+        } else {
+            self.crc_errors as f64 / received as f64
+        }
+    }
+
+    fn record(&mut self, notice: &Notice) {
+        match notice {
+            Notice::Finished(record) => match record.kind {
+                TransactionKind::Read => self.bytes_read += record.bytes as u64,
+                TransactionKind::Write if record.success => {
+                    self.bytes_written += record.bytes as u64;
+                    self.writes += 1;
+                }
+                _ => {}
+            },
+            Notice::CrcError { .. } => self.crc_errors += 1,
+            _ => {}
+        }
+    }
+}
+
+impl Display for PortStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sessions, {} bytes read, {} bytes written, {:.2}% CRC errors",
+            self.sessions,
+            self.bytes_read,
+            self.bytes_written,
+            self.crc_error_rate() * 100.0
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SessionStats {
+    /// Statistics by serial port name
+    pub ports: BTreeMap<String, PortStats>,
+}
+
+impl SessionStats {
+    /// Load statistics, starting from scratch if file is missing or invalid.
+    pub fn load<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::warn!("Cannot read session statistics {:?} (error: {})", path, e);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            log::warn!(
+                "Ignoring invalid session statistics {:?} (error: {})",
+                path,
+                e
+            );
+            Self::default()
+        })
+    }
+
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content)
+    }
+
+    /// Start a session on `port` and count its transactions.
+    pub fn record_session(stats: &Arc<Mutex<Self>>, port: &str) {
+        stats
+            .lock()
+            .unwrap()
+            .ports
+            .entry(port.to_string())
+            .or_default()
+            .sessions += 1;
+
+        let stats = stats.clone();
+        let port = port.to_string();
+        transaction::subscribe(move |notice| {
+            if let Some(port_stats) = stats.lock().unwrap().ports.get_mut(&port) {
+                port_stats.record(notice);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{fixture::Fixture, transaction::TransactionRecord};
+
+    #[test]
+    fn test_record_and_reload() {
+        let finished = |kind, bytes, success| {
+            Notice::Finished(TransactionRecord {
+                id: 1,
+                kind,
+                sectors: None,
+                bytes,
+                duration: Duration::ZERO,
+                success,
+            })
+        };
+
+        let mut port_stats = PortStats::default();
+        port_stats.record(&finished(TransactionKind::Read, 512, true));
+        port_stats.record(&finished(TransactionKind::Write, 1024, true));
+        port_stats.record(&finished(TransactionKind::Write, 1024, false));
+        port_stats.record(&Notice::CrcError {
+            id: 2,
+            sectors: None,
+        });
+        assert_eq!(
+            (port_stats.bytes_read, port_stats.bytes_written),
+            (512, 1024)
+        );
+        assert_eq!(port_stats.crc_error_rate(), 0.5);
+
+        let fixture = Fixture::empty("stats").unwrap();
+        let path = fixture.join("test.stats");
+        let mut stats = SessionStats::default();
+        stats.ports.insert("/dev/ttyUSB0".to_string(), port_stats);
+        stats.save(&path).unwrap();
+        assert_eq!(SessionStats::load(&path), stats);
+
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(SessionStats::load(&path), SessionStats::default());
+    }
+}
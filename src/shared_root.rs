@@ -0,0 +1,173 @@
+//! Host directory shared with Atari.
+//!
+//! Every host path used to import or export disk content goes through a
+//! [`SharedRoot`], so symlinks or names written by Atari (ex: `..`) can
+//! never make the server read or write outside of the shared directory.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error::{self, SerialDiskError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedRoot {
+    /// Canonical path of the shared directory
+    root: PathBuf,
+}
+
+fn outside(path: &Path) -> SerialDiskError {
+    SerialDiskError::OutsideSharedRoot(path.display().to_string())
+}
+
+impl SharedRoot {
+    /// Share an existing directory.
+    pub fn new<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let root = fs::canonicalize(path)?;
+        Ok(Self { root })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Get host path of `name` in `dir`, `name` being given by Atari.
+    ///
+    /// Name must be a single plain path component.
+    pub fn join<P>(&self, dir: P, name: &str) -> error::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let mut components = Path::new(name).components();
+        let valid = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) && !name.contains(['/', '\\', '\0']);
+
+        let path = dir.as_ref().join(name);
+        if !valid {
+            return Err(outside(&path));
+        }
+        self.resolve(&path)
+    }
+
+    /// Get real path of `path`, following symlinks, and check it is
+    /// inside shared directory.
+    ///
+    /// Path does not have to exist yet.
+    pub fn resolve<P>(&self, path: P) -> error::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        // Canonicalize the deepest existing ancestor
+        let mut existing = path;
+        let mut missing = Vec::new();
+        let resolved = loop {
+            match fs::canonicalize(existing) {
+                Ok(resolved) => break resolved,
+                // Dangling symlink: its target could be created outside
+                Err(_)
+                    if fs::symlink_metadata(existing)
+                        .is_ok_and(|metadata| metadata.file_type().is_symlink()) =>
+                {
+                    return Err(outside(path))
+                }
+                Err(_) => match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        missing.push(name);
+                        existing = parent;
+                    }
+                    _ => return Err(outside(path)),
+                },
+            }
+        };
+
+        let mut resolved = resolved;
+        for name in missing.into_iter().rev() {
+            if name == ".." || name == "." {
+                return Err(outside(path));
+            }
+            resolved.push(name);
+        }
+
+        if resolved.starts_with(&self.root) {
+            Ok(resolved)
+        } else {
+            Err(outside(path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    /// `shared` root, with a `SUB` directory, next to a `secret` directory.
+    fn shared_dirs(name: &str) -> Fixture {
+        let fixture = Fixture::empty(name).unwrap();
+        fs::create_dir_all(fixture.join("shared").join("SUB")).unwrap();
+        fs::create_dir_all(fixture.join("secret")).unwrap();
+        fixture
+    }
+
+    #[test]
+    fn test_join() {
+        let dir = shared_dirs("shared-join");
+        let root = SharedRoot::new(dir.join("shared")).unwrap();
+
+        assert_eq!(
+            root.join(root.path(), "TEST.TXT").unwrap(),
+            root.path().join("TEST.TXT")
+        );
+        assert_eq!(
+            root.join(root.path().join("SUB"), "NEW").unwrap(),
+            root.path().join("SUB").join("NEW")
+        );
+
+        for name in ["..", ".", "", "SUB/..", "..\\SECRET", "/etc", "A\0B"] {
+            assert!(
+                matches!(
+                    root.join(root.path(), name),
+                    Err(SerialDiskError::OutsideSharedRoot(_))
+                ),
+                "{:?} accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let dir = shared_dirs("shared-symlinks");
+        let root = SharedRoot::new(dir.join("shared")).unwrap();
+
+        std::os::unix::fs::symlink(dir.join("secret"), root.path().join("ESCAPE")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("SUB"), root.path().join("INSIDE")).unwrap();
+
+        assert!(root.resolve(root.path().join("ESCAPE")).is_err());
+        assert!(root.join(root.path().join("ESCAPE"), "FILE").is_err());
+
+        // Dangling symlinks, to a file or a directory not created yet
+        let new_secret = dir.join("secret").join("NEW");
+        std::os::unix::fs::symlink(&new_secret, root.path().join("DANGLING")).unwrap();
+        assert!(root.resolve(root.path().join("DANGLING")).is_err());
+        assert!(root.join(root.path(), "DANGLING").is_err());
+        assert!(root
+            .resolve(root.path().join("DANGLING").join("FILE"))
+            .is_err());
+        assert!(root
+            .resolve(root.path().join("SUB").join("..").join(".."))
+            .is_err());
+        assert_eq!(
+            root.resolve(root.path().join("INSIDE")).unwrap(),
+            root.path().join("SUB")
+        );
+    }
+}
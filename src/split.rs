@@ -0,0 +1,158 @@
+//! Host files too large to be handy on Atari, imported as numbered parts
+//! (`NAME.001`, `NAME.002`...) next to a `NAME.SPL` manifest listing them.
+//!
+//! Parts only have to be concatenated in order to get the file back, on
+//! Atari or on host (see `join`). Manifest is a CRLF text file:
+//!
+//! ```text
+//! ataridisk split file
+//! name: bigdisk.img
+//! size: 2500000
+//! part: BIGDISK.001
+//! part: BIGDISK.002
+//! ```
+
+use std::fmt::Write;
+
+use crate::{
+    backend::SectorBackend,
+    error::{self, SerialDiskError},
+    storage::DiskStorage,
+};
+
+/// Extension of manifests.
+pub const MANIFEST_EXT: &str = "SPL";
+
+/// Part extensions are 3 digits.
+pub const MAX_PARTS: usize = 999;
+
+const HEADER: &str = "ataridisk split file";
+
+macro_rules! invalid {
+    ($msg:expr) => {
+        SerialDiskError::InvalidSplit($msg.to_string())
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitManifest {
+    /// Host name of the file
+    pub name: String,
+    pub size: usize,
+    /// Disk names of the parts, in order
+    pub parts: Vec<String>,
+}
+
+impl SplitManifest {
+    /// Manifest of a `size` bytes file named `name` on host, split in
+    /// `part_size` bytes parts named from `stem`.
+    pub fn new(name: &str, stem: &str, size: usize, part_size: usize) -> error::Result<Self> {
+        let count = size.div_ceil(part_size.max(1));
+        if count > MAX_PARTS {
+            return Err(invalid!(format!(
+                "{} would be split in {} parts (max {})",
+                name, count, MAX_PARTS
+            )));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            size,
+            parts: (1..=count).map(|i| format!("{}.{:03}", stem, i)).collect(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = write!(
+            text,
+            "{}\r\nname: {}\r\nsize: {}\r\n",
+            HEADER, self.name, self.size
+        );
+        for part in &self.parts {
+            let _ = write!(text, "part: {}\r\n", part);
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> error::Result<Self> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid!("not a split file manifest"));
+        }
+
+        let mut manifest = Self {
+            name: String::new(),
+            size: 0,
+            parts: Vec::new(),
+        };
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once(": ")
+                .ok_or_else(|| invalid!(format!("invalid line {:?}", line)))?;
+            match key {
+                "name" => manifest.name = value.to_string(),
+                "size" => {
+                    manifest.size = value
+                        .parse()
+                        .map_err(|_| invalid!(format!("invalid size {:?}", value)))?
+                }
+                "part" => manifest.parts.push(value.to_string()),
+                _ => return Err(invalid!(format!("unknown key {:?}", key))),
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Get a split file back from its parts, given the `\` separated disk path
+/// of its manifest.
+pub fn join<B>(storage: &DiskStorage<B>, manifest_path: &str) -> error::Result<(String, Vec<u8>)>
+where
+    B: SectorBackend,
+{
+    let read = |path: &str| match storage.find_entry(path)? {
+        Some(file_info) if !file_info.is_dir() => storage.read_file(&file_info),
+        _ => Err(invalid!(format!("missing {}", path))),
+    };
+
+    let text = read(manifest_path)?;
+    let manifest = SplitManifest::parse(&String::from_utf8(text)?)?;
+    let dir = manifest_path.rsplit_once('\\').map(|(dir, _)| dir);
+
+    let mut content = Vec::with_capacity(manifest.size);
+    for part in &manifest.parts {
+        let path = match dir {
+            Some(dir) => format!("{}\\{}", dir, part),
+            None => part.clone(),
+        };
+        content.extend(read(&path)?);
+    }
+    if content.len() != manifest.size {
+        return Err(invalid!(format!(
+            "parts of {} hold {} bytes instead of {}",
+            manifest.name,
+            content.len(),
+            manifest.size
+        )));
+    }
+    Ok((manifest.name, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let manifest = SplitManifest::new("bigdisk.img", "BIGDISK", 2500, 1000).unwrap();
+        assert_eq!(
+            manifest.parts,
+            ["BIGDISK.001", "BIGDISK.002", "BIGDISK.003"]
+        );
+        assert_eq!(SplitManifest::parse(&manifest.to_text()).unwrap(), manifest);
+
+        assert!(SplitManifest::new("huge.img", "HUGE", 1000, 1).is_err());
+        assert!(SplitManifest::parse("name: x\r\n").is_err());
+    }
+}
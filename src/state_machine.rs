@@ -1,16 +1,50 @@
 use std::{
-    sync::{Arc, Mutex},
-    thread::sleep,
-    time::Duration,
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::{self, Display},
+    ops::Range,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use indicatif::ProgressIterator;
-use serialport::SerialPort;
 
-use crate::{checksum, error, storage::DiskStorage};
+use chrono::{Datelike, Local, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-const BUF_MAGIC_START: [u8; 4] = [0x18, 0x03, 0x20, 0x06];
+use crate::{
+    access_log::AccessLog,
+    backend::SectorBackend,
+    boot_profile::{BootRecorder, PinnedReads},
+    checksum,
+    chunk_size::ChunkSize,
+    entries::format_datetime_to_atari,
+    error,
+    hash::{constant_time_eq, Sha1},
+    host_exec::{self, HostCommands},
+    idle,
+    persistence::Persistence,
+    prefetch::{SequentialReads, StagedRead},
+    protocol::{self, MAGIC},
+    rng::Rng,
+    storage::{DiskStorage, WriteOutcome},
+    transaction::{Transaction, TransactionKind},
+    transport::Transport,
+};
+
+/// Largest buffer a speed test can exchange, to fail fast on a corrupted
+/// length instead of allocating it.
+const MAX_SPEED_TEST_LEN: usize = 1024 * 1024;
+
+/// Length of the challenge Atari hashes with the secret to authenticate.
+pub const AUTH_CHALLENGE_LEN: usize = 8;
+
+/// Buffers from this size get their CRC computed on another thread.
+///
+/// Below it, spawning the thread costs more than the CRC itself.
+const PIPELINE_MIN_LEN: usize = 16 * 1024;
 
 /*
 macro_rules! print_buffer {
@@ -23,19 +57,143 @@ macro_rules! print_buffer {
 }
 */
 
-/// Possible communication state of hard disk vs Atari
+/// Counters of the troubles met understanding Atari.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolStats {
+    /// Commands received with an unknown opcode, by opcode
+    pub unknown_commands: BTreeMap<u8, u64>,
+    /// Times the stream has been scanned for the next command
+    pub resyncs: u64,
+    /// Bytes dropped while scanning for the next command
+    pub skipped_bytes: u64,
+    /// Read, write and commit commands received before authentication
+    pub refused_commands: u64,
+}
+
+impl ProtocolStats {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Display for ProtocolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown: u64 = self.unknown_commands.values().sum();
+        write!(
+            f,
+            "{} unknown commands, {} resyncs, {} bytes skipped, {} commands refused",
+            unknown, self.resyncs, self.skipped_bytes, self.refused_commands
+        )
+    }
+}
+
+/// Clock given to Atari, which has no notion of time zone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSource {
+    /// Local time of the host
+    #[default]
+    Local,
+    Utc,
+    /// Atari is told no clock is available
+    Disabled,
+}
+
+impl ClockSource {
+    fn now(&self) -> Option<NaiveDateTime> {
+        match self {
+            Self::Local => Some(Local::now().naive_local()),
+            Self::Utc => Some(Utc::now().naive_utc()),
+            Self::Disabled => None,
+        }
+    }
+}
+
+/// Settings of the connections with Atari.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// Secret Atari must prove knowing before reading or writing sectors
+    pub secret: Option<String>,
+    pub clock: ClockSource,
+    /// Host commands Atari can run, none by default
+    pub host_commands: HostCommands,
+    /// Server generation to announce when session starts, after a restart
+    pub reset_notice: Option<u32>,
+    /// File where to record what Atari reads while booting
+    pub record_boot: Option<PathBuf>,
+    /// Answers of the reads of a boot profile, ready to send
+    pub pinned_reads: Option<Arc<PinnedReads>>,
+}
+
+/// What lasts across the commands of a connection with Atari.
 #[derive(Debug)]
+pub struct Session {
+    pub stats: ProtocolStats,
+    config: SessionConfig,
+    authenticated: bool,
+    /// Optional features agreed with driver
+    capabilities: u16,
+    chunk_size: ChunkSize,
+    rng: Rng,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(SessionConfig::default())
+    }
+}
+
+impl Session {
+    pub fn new(config: SessionConfig) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self {
+            stats: ProtocolStats::default(),
+            config,
+            authenticated: false,
+            capabilities: 0,
+            chunk_size: ChunkSize::new(),
+            rng: Rng::new(seed ^ std::process::id() as u64),
+        }
+    }
+
+    /// Check if sectors can be read and written.
+    pub fn is_authorized(&self) -> bool {
+        self.config.secret.is_none() || self.authenticated
+    }
+
+    /// Check if an optional feature has been agreed with driver.
+    pub fn has_capability(&self, capability: u16) -> bool {
+        self.capabilities & capability != 0
+    }
+}
+
+/// Answer Atari must give to `challenge`: SHA-1 of the challenge followed by
+/// the secret.
+///
+/// This keeps devices not knowing the secret away, but does not protect
+/// anything from someone listening to the link.
+pub fn auth_answer(challenge: &[u8], secret: &str) -> [u8; 20] {
+    let mut content = challenge.to_vec();
+    content.extend_from_slice(secret.as_bytes());
+    Sha1::digest(&content)
+}
+
+/// Possible communication state of hard disk vs Atari
+#[derive(Debug, Default)]
 enum SerialState {
+    #[default]
     Waiting,
+    /// Magic has been found while resynchronizing, only opcode is missing
+    ReceiveCommand,
     ReceiveReadSector,
+    ReceiveTaggedRead,
     ReceiveWriteSector,
     ReceiveData,
-}
-
-impl Default for SerialState {
-    fn default() -> Self {
-        SerialState::Waiting
-    }
+    ReceiveSpeedTest,
+    ReceiveHostCommand,
+    ReceiveCapabilities,
 }
 
 impl SerialState {
@@ -44,10 +202,17 @@ impl SerialState {
     }
 
     fn expected_buffer_len(&self) -> usize {
-        match &*self {
-            Self::Waiting => 5,
-            Self::ReceiveReadSector | Self::ReceiveWriteSector => 4,
+        match self {
+            Self::Waiting => MAGIC.len() + 1,
+            Self::ReceiveCommand => 1,
+            Self::ReceiveReadSector => protocol::header_len(protocol::READ_SECTORS),
+            Self::ReceiveTaggedRead => protocol::header_len(protocol::TAGGED_READ),
+            Self::ReceiveWriteSector => protocol::header_len(protocol::WRITE_SECTORS),
+            Self::ReceiveSpeedTest => protocol::header_len(protocol::SPEED_TEST),
+            // Data flags
             Self::ReceiveData => 1,
+            Self::ReceiveHostCommand => protocol::header_len(protocol::HOST_COMMAND),
+            Self::ReceiveCapabilities => protocol::header_len(protocol::CAPABILITIES),
         }
     }
 }
@@ -56,13 +221,51 @@ fn read_sector_infos(buffer: &[u8]) -> (u16, u16) {
     let index = ((buffer[0] as u16) << 8) + buffer[1] as u16;
     let count = ((buffer[2] as u16) << 8) + buffer[3] as u16;
 
-    log::info!("sector index={:#04x}, count={:#04x}", index, count);
     (index, count)
 }
 
-pub fn run<S>(storage: Arc<Mutex<DiskStorage>>, serial: &mut S) -> error::Result<()>
+pub fn run<S, B>(
+    storage: Arc<Mutex<DiskStorage<B>>>,
+    serial: &mut S,
+    persistence: &mut Persistence,
+) -> error::Result<()>
+where
+    S: Transport,
+    B: SectorBackend + Serialize,
+{
+    run_with_config(storage, serial, persistence, SessionConfig::default())
+}
+
+/// Serve Atari with `config`, only reading and writing sectors once it has
+/// authenticated if a secret is set.
+pub fn run_with_config<S, B>(
+    storage: Arc<Mutex<DiskStorage<B>>>,
+    serial: &mut S,
+    persistence: &mut Persistence,
+    config: SessionConfig,
+) -> error::Result<()>
 where
-    S: SerialPort,
+    S: Transport,
+    B: SectorBackend + Serialize,
+{
+    let mut session = Session::new(config);
+    let result = run_session(storage, serial, persistence, &mut session);
+    if !session.stats.is_empty() {
+        log::warn!("Protocol statistics: {}", session.stats);
+    }
+    result
+}
+
+/// Serve Atari, counting protocol troubles in `session` statistics.
+pub fn run_session<S, B>(
+    storage: Arc<Mutex<DiskStorage<B>>>,
+    serial: &mut S,
+    persistence: &mut Persistence,
+    session: &mut Session,
+) -> error::Result<()>
+where
+    S: Transport,
+    B: SectorBackend + Serialize,
 {
     let mut buffer = [0; 5];
     let mut state = SerialState::new();
@@ -70,8 +273,21 @@ where
     let mut receive_sector_index = 0;
     let mut receive_sector_count = 0;
 
+    // Read / write command waiting for its sectors or data
+    let mut transaction = None;
+    let mut was_disk_full = storage.lock().unwrap().free_cluster_count() == 0;
+    let mut access_log = AccessLog::new();
+    let mut sequential_reads = SequentialReads::new();
+    let mut staged_read = StagedRead::new();
+    let mut boot_recorder = session.config.record_boot.clone().map(BootRecorder::new);
+
+    // Driver may still wait for an answer of previous server
+    if let Some(generation) = session.config.reset_notice {
+        announce_reset(serial, generation)?;
+    }
+
     loop {
-        log::info!("State: {:?}", state);
+        log::trace!("State: {:?}", state);
 
         let l = state.expected_buffer_len();
         if l > 0 {
@@ -81,38 +297,250 @@ where
 
         state = match &state {
             // Handle waiting for Atari commands
-            SerialState::Waiting => {
+            SerialState::Waiting | SerialState::ReceiveCommand => {
+                let command = match state {
+                    SerialState::ReceiveCommand => Some(buffer[0]),
+                    _ => (buffer[..MAGIC.len()] == MAGIC).then_some(buffer[MAGIC.len()]),
+                };
+
+                if command.is_some() {
+                    idle::mark_active();
+                }
+
                 // Switch to new state
-                match (&buffer[0..4], buffer[4]) {
-                    (magic, 0) if magic == BUF_MAGIC_START => SerialState::ReceiveReadSector,
-                    (magic, 1) if magic == BUF_MAGIC_START => SerialState::ReceiveWriteSector,
-                    (magic, 2) if magic == BUF_MAGIC_START => {
+                match command {
+                    Some(opcode)
+                        if protocol::command(opcode).is_some_and(|c| c.requires_auth)
+                            && !session.is_authorized() =>
+                    {
+                        session.stats.refused_commands += 1;
+                        log::warn!("Command {:#04x} refused before authentication", opcode);
+                        clear_serial(serial)?;
+                        SerialState::Waiting
+                    }
+                    Some(protocol::READ_SECTORS) => {
+                        transaction = Some(Transaction::begin(TransactionKind::Read));
+                        SerialState::ReceiveReadSector
+                    }
+                    // Unknown command until agreed
+                    Some(protocol::TAGGED_READ)
+                        if session.has_capability(protocol::CAP_TAGGED_COMMANDS) =>
+                    {
+                        transaction = Some(Transaction::begin(TransactionKind::Read));
+                        SerialState::ReceiveTaggedRead
+                    }
+                    Some(protocol::WRITE_SECTORS) => {
+                        transaction = Some(Transaction::begin(TransactionKind::Write));
+                        SerialState::ReceiveWriteSector
+                    }
+                    Some(protocol::BIOS_PARAMETER_BLOCK) => {
                         // Send Atari disk layout
-                        log::info!("Sending atari BIOS parameter block");
+                        let mut txn = Transaction::begin(TransactionKind::BiosParameterBlock);
 
                         let storage = storage.lock().unwrap();
-                        storage.disk_layout.write_bios_parameter_block(serial)?;
+                        let mut bpb = Vec::new();
+                        storage.disk_layout.write_bios_parameter_block(&mut bpb)?;
+                        serial.write_all(&bpb)?;
+                        txn.add_bytes(bpb.len());
+                        txn.finish(true);
                         SerialState::Waiting
                     }
-                    _ => {
-                        clear_serial(serial)?;
+                    Some(protocol::COMMIT) => {
+                        // Persist disk content then acknowledge (1 = success, 0 = failure)
+                        let txn = Transaction::begin(TransactionKind::Commit);
+
+                        let mut storage = storage.lock().unwrap();
+                        match persistence.commit(&mut storage) {
+                            Ok(()) => {
+                                serial.write_u8(0x01)?;
+                                txn.finish(true);
+                            }
+                            Err(error) => {
+                                txn.warn(&format!("cannot commit RAM disk (error: {})", error));
+                                serial.write_u8(0x00)?;
+                                txn.finish(false);
+                            }
+                        }
                         SerialState::Waiting
                     }
+                    Some(protocol::SPEED_TEST) => {
+                        transaction = Some(Transaction::begin(TransactionKind::SpeedTest));
+                        SerialState::ReceiveSpeedTest
+                    }
+                    Some(protocol::AUTHENTICATE) => {
+                        let txn = Transaction::begin(TransactionKind::Auth);
+                        authenticate(serial, session, txn)?;
+                        SerialState::Waiting
+                    }
+                    Some(protocol::CLOCK) => {
+                        // Send host clock, in the format of XBIOS `Settime`
+                        let mut txn = Transaction::begin(TransactionKind::Clock);
+                        serial.write_u32::<BigEndian>(atari_clock(session.config.clock.now()))?;
+                        txn.add_bytes(4);
+                        txn.finish(true);
+                        SerialState::Waiting
+                    }
+                    Some(protocol::HOST_COMMAND) => {
+                        transaction = Some(Transaction::begin(TransactionKind::HostCommand));
+                        SerialState::ReceiveHostCommand
+                    }
+                    Some(protocol::HOST_CHANGES) => {
+                        // Send count of files changed from host, Atari
+                        // reads the disk again when it changes
+                        let mut txn = Transaction::begin(TransactionKind::HostChanges);
+                        let changes = storage.lock().unwrap().host_changes();
+                        serial.write_u32::<BigEndian>(changes)?;
+                        txn.add_bytes(4);
+                        txn.finish(true);
+                        SerialState::Waiting
+                    }
+                    Some(protocol::CAPABILITIES) => SerialState::ReceiveCapabilities,
+                    Some(opcode) => {
+                        // Opcode may be the start of next command
+                        *session.stats.unknown_commands.entry(opcode).or_default() += 1;
+                        log::warn!("Unknown command {:#04x}, looking for next one", opcode);
+                        resync(serial, &[opcode], &mut session.stats)?;
+                        SerialState::ReceiveCommand
+                    }
+                    None => {
+                        // Partial transfer or line noise: command may start
+                        // anywhere in what has been read
+                        log::warn!("Desync with atari. Looking for next command");
+                        resync(serial, &buffer, &mut session.stats)?;
+                        SerialState::ReceiveCommand
+                    }
                 }
             }
 
+            // Speed test command
+            SerialState::ReceiveSpeedTest => {
+                let len = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+                let txn = transaction
+                    .take()
+                    .unwrap_or_else(|| Transaction::begin(TransactionKind::SpeedTest));
+
+                if len > MAX_SPEED_TEST_LEN {
+                    txn.warn(&format!("speed test of {} bytes refused", len));
+                    txn.finish(false);
+                    resync(serial, &buffer[..4], &mut session.stats)?;
+                    SerialState::ReceiveCommand
+                } else {
+                    speed_test(serial, len, txn)?;
+                    SerialState::Waiting
+                }
+            }
+
+            // Host command
+            SerialState::ReceiveHostCommand => {
+                let len = u16::from_be_bytes([buffer[0], buffer[1]]) as usize;
+                let txn = transaction
+                    .take()
+                    .unwrap_or_else(|| Transaction::begin(TransactionKind::HostCommand));
+
+                if len > host_exec::MAX_NAME_LEN {
+                    txn.warn(&format!("host command name of {} bytes refused", len));
+                    txn.finish(false);
+                    resync(serial, &buffer[..2], &mut session.stats)?;
+                    SerialState::ReceiveCommand
+                } else {
+                    let mut name = vec![0; len];
+                    serial.read_exact(&mut name)?;
+                    run_host_command(serial, &session.config.host_commands, &name, txn)?;
+                    SerialState::Waiting
+                }
+            }
+
+            // Capabilities handshake
+            SerialState::ReceiveCapabilities => {
+                let mut txn = Transaction::begin(TransactionKind::Capabilities);
+                let driver = u16::from_be_bytes([buffer[0], buffer[1]]);
+                session.capabilities = driver & protocol::CAPABILITIES_SUPPORTED;
+                serial.write_u16::<BigEndian>(session.capabilities)?;
+                txn.add_bytes(2);
+                txn.event(&format!("capabilities {:#06x}", session.capabilities));
+                txn.finish(true);
+                SerialState::Waiting
+            }
+
             // Read command
-            SerialState::ReceiveReadSector => {
-                let (sector_index, sector_count) = read_sector_infos(&buffer);
+            SerialState::ReceiveReadSector | SerialState::ReceiveTaggedRead => {
+                let (tag, header) = match state {
+                    SerialState::ReceiveTaggedRead => (Some(buffer[0]), &buffer[1..]),
+                    _ => (None, &buffer[..]),
+                };
+                let (sector_index, sector_count) = read_sector_infos(header);
+                let mut txn = transaction
+                    .take()
+                    .unwrap_or_else(|| Transaction::begin(TransactionKind::Read));
+                txn.set_sectors(sector_index, sector_count);
+
+                // Next command may already be waiting, it is read once this
+                // answer is sent
+                if let Some(tag) = tag {
+                    serial.write_u8(tag)?;
+                    txn.add_bytes(1);
+                }
+
+                let mut storage = storage.lock().unwrap();
+                storage.refresh_generated(sector_index, sector_count);
+                if let Some(sector) = storage.unreadable_sector(sector_index, sector_count) {
+                    serial.write_u8(protocol::FRAME_UNREADABLE)?;
+                    txn.add_bytes(1);
+                    txn.warn(&format!(
+                        "sector {:#x} was never written, read refused",
+                        sector
+                    ));
+                    txn.finish(false);
+                } else {
+                    let mut data = Vec::with_capacity(
+                        sector_count as usize * storage.disk_layout.bytes_per_sector() as usize,
+                    );
+                    storage.read_sectors(&mut data, sector_index, sector_count)?;
+                    assert_eq!(data.capacity(), data.len(), "Out buffer not fully filled");
+                    if let Some((index, count)) =
+                        sequential_reads.record(sector_index, sector_count)
+                    {
+                        storage.prefetch(index, count);
+                    }
 
-                let storage = storage.lock().unwrap();
-                let mut data = Vec::with_capacity(
-                    sector_count as usize * storage.disk_layout.bytes_per_sector() as usize,
-                );
-                storage.read_sectors(&mut data, sector_index, sector_count)?;
-                assert_eq!(data.capacity(), data.len(), "Out buffer not fully filled");
+                    let pinned = session.config.pinned_reads.as_ref();
+                    let staged = staged_read.take(sector_index, sector_count, &data);
+                    let frame = pinned
+                        .and_then(|p| p.frame(sector_index, sector_count, &data))
+                        .or(staged.as_deref());
+                    // Both are powers of two, so chunks hold whole sectors
+                    let chunk_len = session
+                        .chunk_size
+                        .chunk_len()
+                        .max(storage.disk_layout.bytes_per_sector() as usize);
+                    let sent = match frame {
+                        Some(frame) => {
+                            write_buffer_content(serial, frame)?;
+                            frame.len()
+                        }
+                        None if session.has_capability(protocol::CAP_CHUNKED_FRAMES)
+                            && data.len() > chunk_len =>
+                        {
+                            let start = Instant::now();
+                            let sent = write_chunked_frame(serial, &data, chunk_len)?;
+                            serial.flush()?;
+                            session.chunk_size.record_frame(sent, start.elapsed());
+                            sent
+                        }
+                        None => write_buffer(serial, &data)?,
+                    };
+                    txn.add_bytes(sent);
+                    access_log.record(&storage, &txn, sector_index, sector_count);
+                    if let Some(recorder) = boot_recorder.as_mut() {
+                        recorder.record(&storage, sector_index, sector_count, Instant::now());
+                    }
+                    txn.finish(true);
 
-                write_buffer(serial, &data)?;
+                    // Atari is still receiving this answer meanwhile
+                    if let Some((index, count)) = sequential_reads.next_read() {
+                        staged_read.stage(&storage, index, count, encode_frame);
+                    }
+                }
 
                 SerialState::Waiting
             }
@@ -123,12 +551,16 @@ where
                 receive_sector_index = sector_index;
                 receive_sector_count = sector_count;
 
+                transaction
+                    .get_or_insert_with(|| Transaction::begin(TransactionKind::Write))
+                    .set_sectors(sector_index, sector_count);
+
                 SerialState::ReceiveData
             }
 
             // Waiting for Atari data
             SerialState::ReceiveData => match buffer[0] {
-                0x00 => {
+                protocol::FRAME_RAW => {
                     let mut storage = storage.lock().unwrap();
 
                     // Reserve a buffer for all the data with need to read
@@ -137,32 +569,130 @@ where
                             * receive_sector_count as usize,
                     );
 
+                    let txn = transaction
+                        .get_or_insert_with(|| Transaction::begin(TransactionKind::Write));
+
                     // Read the data from Atari over serial port
-                    log::info!("Reading data from Atari (bytes count: {})", data.capacity());
-                    for _ in (0..data.capacity()).progress() {
+                    txn.event(&format!("receiving {} bytes", data.capacity()));
+                    for _ in progress(0..data.capacity()) {
                         data.push(serial.read_u8()?);
                     }
+                    txn.add_bytes(data.len());
 
                     // Read the CRC32
                     let valid_crc = checksum::check_crc32(serial, &data)?;
-                    if valid_crc {
-                        serial.write_u8(0x01)?;
+                    let checked = if valid_crc {
+                        storage.check_write(receive_sector_index, &data)
+                    } else {
+                        Ok(())
+                    };
+                    if let Err(e) = checked {
+                        // Sending it again would not help
+                        serial.write_u8(protocol::WRITE_REFUSED)?;
+                        txn.warn(&format!("write refused ({})", e));
+                        if let Some(txn) = transaction.take() {
+                            txn.finish(false);
+                        }
+
+                        SerialState::Waiting
+                    } else if valid_crc {
+                        // Atari is only told about writes that are applied and
+                        // journaled, refused ones are not journaled. A write
+                        // that cannot be persisted is undone before it is
+                        // refused, so it is not dumped on exit either.
+                        let snapshot = storage.snapshot_write(receive_sector_index, &data)?;
+                        let applied = storage
+                            .write_sectors(
+                                &mut data.as_slice(),
+                                receive_sector_index,
+                                receive_sector_count,
+                            )
+                            .map_err(error::SerialDiskError::from)
+                            .and_then(|outcome| {
+                                if !outcome.is_refused() {
+                                    persistence.record_write(
+                                        receive_sector_index,
+                                        receive_sector_count,
+                                        &data,
+                                    )?;
+                                }
+                                Ok(outcome)
+                            });
+                        let outcome = match applied {
+                            Ok(outcome) => outcome,
+                            Err(e) => {
+                                storage.restore(&snapshot)?;
+                                serial.write_u8(protocol::WRITE_REFUSED)?;
+                                return Err(e);
+                            }
+                        };
+                        if !outcome.is_refused() {
+                            if let Err(e) = persistence.applied_write(&mut storage) {
+                                // Journal already holds the write, only the dump failed
+                                if persistence.is_journaled() {
+                                    serial.write_u8(0x01)?;
+                                } else {
+                                    storage.restore(&snapshot)?;
+                                    serial.write_u8(protocol::WRITE_REFUSED)?;
+                                }
+                                return Err(e);
+                            }
+                        }
+                        serial.write_u8(if outcome.is_refused() {
+                            protocol::WRITE_REFUSED
+                        } else {
+                            0x01
+                        })?;
 
-                        storage.write_sectors(
-                            &mut data.as_slice(),
-                            receive_sector_index,
-                            receive_sector_count,
-                        )?;
+                        // Only report disk becoming full
+                        let disk_full = storage.free_cluster_count() == 0;
+                        if let Some(txn) = transaction.take() {
+                            if outcome == WriteOutcome::Written {
+                                access_log.record(
+                                    &storage,
+                                    &txn,
+                                    receive_sector_index,
+                                    receive_sector_count,
+                                );
+                            }
+                            match outcome {
+                                WriteOutcome::Written => {}
+                                WriteOutcome::Formatted => txn.format_attempt(true),
+                                WriteOutcome::FormatRefused => txn.format_attempt(false),
+                                WriteOutcome::ReadOnly => txn.warn("read only disk, write ignored"),
+                                WriteOutcome::QuotaExceeded => {
+                                    txn.warn("directory quota exceeded, write ignored")
+                                }
+                                WriteOutcome::Protected => {
+                                    txn.warn("read only source, write ignored")
+                                }
+                            }
+                            if disk_full && !was_disk_full {
+                                txn.disk_full();
+                            }
+                            txn.finish(!matches!(
+                                outcome,
+                                WriteOutcome::ReadOnly
+                                    | WriteOutcome::QuotaExceeded
+                                    | WriteOutcome::Protected
+                            ));
+                        }
+                        was_disk_full = disk_full;
 
                         SerialState::Waiting
                     } else {
                         serial.write_u8(0x00)?;
+                        txn.crc_error();
+                        session.chunk_size.record_crc_error();
 
                         SerialState::ReceiveData
                     }
                 }
                 0x1F => unimplemented!("read data with RLE compression"),
                 _ => {
+                    if let Some(txn) = transaction.take() {
+                        txn.finish(false);
+                    }
                     clear_serial(serial)?;
                     SerialState::Waiting
                 }
@@ -171,55 +701,959 @@ where
     }
 }
 
-fn write_buffer<W>(writer: &mut W, data: &[u8]) -> error::Result<()>
+/// Size of the samples tried before compressing a large buffer.
+#[cfg(feature = "compression")]
+const COMPRESSION_SAMPLE_LEN: usize = 4096;
+
+/// Compress data to send, if it is worth it.
+///
+/// Large buffers are sampled at start, middle and end first: when none of
+/// the samples shrinks, data is most likely already packed (ex: ZIP, packed
+/// PRG) and compressing it entirely would only waste time.
+#[cfg(feature = "compression")]
+fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() >= 4 * COMPRESSION_SAMPLE_LEN {
+        let middle = (data.len() - COMPRESSION_SAMPLE_LEN) / 2;
+        let end = data.len() - COMPRESSION_SAMPLE_LEN;
+        let compressible = [0, middle, end].iter().any(|&start| {
+            let sample = &data[start..start + COMPRESSION_SAMPLE_LEN];
+            lz4_flex::compress(sample).len() < sample.len()
+        });
+        if !compressible {
+            log::debug!("Sending data uncompressed (samples do not compress)");
+            return None;
+        }
+    }
+
+    let compressed = lz4_flex::compress(data);
+    (compressed.len() < data.len()).then_some(compressed)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_data: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Iterate over a transfer, showing its progress.
+#[cfg(feature = "progress")]
+fn progress(range: Range<usize>) -> impl Iterator<Item = usize> {
+    use indicatif::ProgressIterator;
+    range.progress()
+}
+
+#[cfg(not(feature = "progress"))]
+fn progress(range: Range<usize>) -> impl Iterator<Item = usize> {
+    range
+}
+
+/// Send a buffer to Atari and return the number of bytes sent.
+///
+/// Frame header holds compressed length, so compression has to finish
+/// before sending anything. CRC of large buffers is computed meanwhile on
+/// another thread and is usually ready once content has drained to the
+/// serial port. Drivers agreeing on chunked frames get large buffers from
+/// `write_chunked_frame` instead, which does not wait.
+fn write_buffer<W>(writer: &mut W, data: &[u8]) -> error::Result<usize>
 where
     W: WriteBytesExt,
 {
-    let compressed = lz4_flex::compress(data);
+    let (sent, crc) = if data.len() >= PIPELINE_MIN_LEN {
+        thread::scope(|scope| {
+            let crc = scope.spawn(|| checksum::crc32(data));
+            let sent = write_frame_content(writer, data)?;
+            error::Result::Ok((sent, crc.join().expect("CRC thread panicked")))
+        })?
+    } else {
+        (write_frame_content(writer, data)?, checksum::crc32(data))
+    };
+
+    // Write checksum
+    writer.write_u32::<BigEndian>(crc)?;
+
+    // Flags + content + CRC32
+    Ok(1 + sent + 4)
+}
 
-    // Write flags (0 = no compression, 1 = lz4 compression)
-    let send_compressed = compressed.len() < data.len();
-    let flags = if send_compressed { 0x01 } else { 0x00 };
+/// Frame `write_buffer` sends for `data`.
+pub fn encode_frame(data: &[u8]) -> error::Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    write_buffer(&mut frame, data)?;
+    Ok(frame)
+}
+
+/// Write flags and content of a buffer, compressing it if worth it.
+fn write_frame_content<W>(writer: &mut W, data: &[u8]) -> error::Result<usize>
+where
+    W: WriteBytesExt,
+{
+    let compressed = compress(data);
+
+    let flags = if compressed.is_some() {
+        protocol::FRAME_LZ4
+    } else {
+        protocol::FRAME_RAW
+    };
     writer.write_u8(flags)?;
 
-    if send_compressed {
+    let sent = if let Some(compressed) = compressed {
         // Write data compressed
         writer.write_u32::<BigEndian>(compressed.len() as u32)?;
         write_buffer_content(writer, &compressed)?;
+        compressed.len() + 4
     } else {
         // Write data uncompressed
         write_buffer_content(writer, data)?;
-    }
+        data.len()
+    };
 
-    // Write checksum
-    checksum::write_crc32(writer, data)?;
+    Ok(sent)
+}
 
-    Ok(())
+/// Send a buffer to Atari as a chunked frame and return the number of bytes
+/// sent.
+///
+/// Chunks are compressed and checksummed on another thread, one ahead of
+/// the one being sent, so their CPU cost is hidden behind the serial link.
+fn write_chunked_frame<W>(writer: &mut W, data: &[u8], chunk_len: usize) -> error::Result<usize>
+where
+    W: WriteBytesExt,
+{
+    writer.write_u8(protocol::FRAME_CHUNKED)?;
+    let mut sent = 1;
+
+    thread::scope(|scope| {
+        let (chunks, encoded) = mpsc::sync_channel(1);
+        scope.spawn(move || {
+            for chunk in data.chunks(chunk_len) {
+                // Sending stopped, most likely on a serial error
+                if chunks.send(encode_chunk(chunk)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for chunk in encoded {
+            write_buffer_content(writer, &chunk)?;
+            sent += chunk.len();
+        }
+        error::Result::Ok(())
+    })?;
+
+    // Empty chunk ends the frame
+    writer.write_u16::<BigEndian>(0)?;
+    Ok(sent + 2)
+}
+
+/// Chunk of a chunked frame, as sent.
+fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 9);
+    chunk.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    match compress(data) {
+        Some(compressed) => {
+            chunk.push(protocol::FRAME_LZ4);
+            chunk.extend_from_slice(&(compressed.len() as u16).to_be_bytes());
+            chunk.extend_from_slice(&compressed);
+        }
+        None => {
+            chunk.push(protocol::FRAME_RAW);
+            chunk.extend_from_slice(data);
+        }
+    }
+    chunk.extend_from_slice(&checksum::crc32(data).to_be_bytes());
+    chunk
 }
 
 fn write_buffer_content<W>(writer: &mut W, data: &[u8]) -> error::Result<()>
 where
     W: WriteBytesExt,
 {
-    log::info!("Sending data (buffer size: {} bytes)", data.len());
+    log::debug!("Sending data (buffer size: {} bytes)", data.len());
 
-    for idx in (0..data.len()).progress() {
+    for idx in progress(0..data.len()) {
         writer.write_u8(data[idx])?;
     }
 
     Ok(())
 }
 
+/// Exchange `len` pseudo random bytes each way and report throughput.
+///
+/// Atari sends its bytes and their CRC. Server answers CRC status (1 =
+/// valid, 0 = invalid) and the time it took to receive them in ms as a big
+/// endian `u32`, then sends its own bytes and their CRC. Send time measured
+/// here includes OS buffering only, Atari side measure is the accurate one.
+fn speed_test<S>(serial: &mut S, len: usize, mut txn: Transaction) -> error::Result<()>
+where
+    S: Transport,
+{
+    txn.event(&format!("receiving {} bytes", len));
+    let start = Instant::now();
+    let mut received = Vec::with_capacity(len);
+    for _ in progress(0..len) {
+        received.push(serial.read_u8()?);
+    }
+    let valid_crc = checksum::check_crc32(serial, &received)?;
+    let receive_time = start.elapsed();
+    txn.add_bytes(len);
+    if !valid_crc {
+        txn.crc_error();
+    }
+
+    serial.write_u8(valid_crc as u8)?;
+    serial.write_u32::<BigEndian>(receive_time.as_millis() as u32)?;
+
+    let data = Rng::new(len as u64).bytes(len);
+    let start = Instant::now();
+    write_buffer_content(serial, &data)?;
+    checksum::write_crc32(serial, &data)?;
+    serial.flush()?;
+    let send_time = start.elapsed();
+    txn.add_bytes(len);
+
+    log::info!(
+        "Speed test: received {} bytes at {:.0} B/s ({} CRC), sent at {:.0} B/s",
+        len,
+        throughput(len, receive_time),
+        if valid_crc { "valid" } else { "invalid" },
+        throughput(len, send_time)
+    );
+    txn.finish(valid_crc);
+    Ok(())
+}
+
+/// Authenticate Atari.
+///
+/// Server sends a challenge of `AUTH_CHALLENGE_LEN` bytes, Atari answers
+/// with the 20 bytes of `auth_answer`, then server sends a status byte
+/// (1 = accepted, 0 = refused). Without secret, any answer is accepted.
+/// A refused answer ends a previous authentication.
+fn authenticate<S>(serial: &mut S, session: &mut Session, txn: Transaction) -> error::Result<()>
+where
+    S: Transport,
+{
+    let challenge = session.rng.bytes(AUTH_CHALLENGE_LEN);
+    serial.write_all(&challenge)?;
+    serial.flush()?;
+
+    let mut answer = [0; 20];
+    serial.read_exact(&mut answer)?;
+
+    let accepted = match &session.config.secret {
+        None => true,
+        Some(secret) => constant_time_eq(&auth_answer(&challenge, secret), &answer),
+    };
+    session.authenticated = accepted;
+
+    serial.write_u8(accepted as u8)?;
+    if !accepted {
+        txn.warn("wrong secret, authentication refused");
+    }
+    txn.finish(accepted);
+    Ok(())
+}
+
+/// Run host command named `name` and send its result.
+///
+/// Atari gets a status byte (1 = run, 0 = unknown command or failed to
+/// start), the exit code as a byte (`0xFF` if none), then the output length
+/// as a big endian long, the output and its CRC32.
+fn run_host_command<S>(
+    serial: &mut S,
+    commands: &HostCommands,
+    name: &[u8],
+    mut txn: Transaction,
+) -> error::Result<()>
+where
+    S: Transport,
+{
+    let name = String::from_utf8_lossy(name);
+    let (status, exit_code, output) = match host_exec::run(commands, &name) {
+        Some(Ok(result)) => {
+            let exit_code = result.exit_code.and_then(|code| u8::try_from(code).ok());
+            (0x01, exit_code.unwrap_or(0xFF), result.output)
+        }
+        Some(Err(e)) => {
+            txn.warn(&format!(
+                "cannot run host command {:?} (error: {})",
+                name, e
+            ));
+            (0x00, 0xFF, Vec::new())
+        }
+        None => {
+            txn.warn(&format!("unknown host command {:?}", name));
+            (0x00, 0xFF, Vec::new())
+        }
+    };
+
+    serial.write_all(&[status, exit_code])?;
+    serial.write_u32::<BigEndian>(output.len() as u32)?;
+    serial.write_all(&output)?;
+    checksum::write_crc32(serial, &output)?;
+    serial.flush()?;
+
+    txn.add_bytes(output.len());
+    txn.finish(status == 0x01 && exit_code == 0);
+    Ok(())
+}
+
+/// Date and time as a long, GEMDOS date in high word and time in low one.
+///
+/// Zero when there is no clock, or when it cannot be given to Atari.
+fn atari_clock(now: Option<NaiveDateTime>) -> u32 {
+    match now {
+        Some(now) if (1980..2108).contains(&now.year()) => {
+            let (time, date) = format_datetime_to_atari(now);
+            (date as u32) << 16 | time as u32
+        }
+        _ => 0,
+    }
+}
+
+fn throughput(len: usize, duration: Duration) -> f64 {
+    len as f64 / duration.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Drop bytes until the magic starting next command has been read.
+///
+/// `pending` bytes, already received, are scanned first.
+fn resync<S>(serial: &mut S, pending: &[u8], stats: &mut ProtocolStats) -> error::Result<()>
+where
+    S: Transport,
+{
+    let magic = u32::from_be_bytes(MAGIC);
+    let mut window = 0u32;
+    let mut scanned = 0u64;
+    let mut pending = pending.iter().copied();
+
+    loop {
+        let byte = match pending.next() {
+            Some(byte) => byte,
+            None => serial.read_u8()?,
+        };
+        window = (window << 8) | byte as u32;
+        scanned += 1;
+
+        if scanned >= MAGIC.len() as u64 && window == magic {
+            let skipped = scanned - MAGIC.len() as u64;
+            log::debug!("Back in sync with Atari after skipping {} bytes", skipped);
+            stats.resyncs += 1;
+            stats.skipped_bytes += skipped;
+            return Ok(());
+        }
+    }
+}
+
+/// Tell Atari driver server has restarted, so it drops its pending command
+/// and reads disk layout again.
+fn announce_reset<S>(serial: &mut S, generation: u32) -> error::Result<()>
+where
+    S: Transport,
+{
+    log::info!("Announcing server restart to Atari");
+    serial.write_all(&MAGIC)?;
+    serial.write_u8(protocol::RESET_NOTICE)?;
+    serial.write_u32::<BigEndian>(generation)?;
+    serial.flush()?;
+    Ok(())
+}
+
 fn clear_serial<S>(serial: &mut S) -> error::Result<()>
 where
-    S: SerialPort,
+    S: Transport,
 {
     log::warn!("Desync with atari. Clearing buffers and ignore command");
+    serial.discard_pending()
+}
 
-    // Give some time for new data to come
-    sleep(Duration::from_millis(500));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boot_profile::BootProfile,
+        fixture::Fixture,
+        journal::WriteJournal,
+        layout::{DiskLayout, PartitionType, Tos},
+        persistence::WritePolicy,
+        quota::Quota,
+        storage::{UninitializedReadPolicy, ROOT_INDEX},
+        transport::MemoryTransport,
+    };
 
-    // Discard everything
-    serial.clear(serialport::ClearBuffer::All)?;
-    Ok(())
+    /// Atari write command of `data` at `index`.
+    fn write_input(index: u16, data: &[u8], sector_size: usize) -> Vec<u8> {
+        let mut input = MAGIC.to_vec();
+        input.push(protocol::WRITE_SECTORS);
+        input.extend_from_slice(&index.to_be_bytes());
+        input.extend_from_slice(&((data.len() / sector_size) as u16).to_be_bytes());
+        input.push(protocol::FRAME_RAW);
+        input.extend_from_slice(data);
+        checksum::write_crc32(&mut input, data).unwrap();
+        input
+    }
+
+    fn run_with_input(input: &[u8]) -> (error::Result<()>, Vec<u8>) {
+        let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+        let mut serial = MemoryTransport::new(input);
+        let mut persistence = Persistence::new(None, None);
+
+        let result = run(storage, &mut serial, &mut persistence);
+        (result, serial.output().to_vec())
+    }
+
+    #[test]
+    fn test_bios_parameter_block() {
+        let mut input = MAGIC.to_vec();
+        input.push(2);
+        let (result, output) = run_with_input(&input);
+
+        // Stops when Atari does not send anything else
+        assert!(matches!(result, Err(error::SerialDiskError::IO(_))));
+
+        let mut bpb = Vec::new();
+        DiskLayout::default()
+            .write_bios_parameter_block(&mut bpb)
+            .unwrap();
+        assert_eq!(output, bpb);
+    }
+
+    #[test]
+    fn test_unknown_command_resync() {
+        // Unknown command, garbage, then BPB command
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[0x42, 0xAA, 0x18, 0xBB]);
+        input.extend_from_slice(&MAGIC);
+        input.push(2);
+
+        let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+        let mut serial = MemoryTransport::new(&input);
+        let mut session = Session::default();
+        let result = run_session(
+            storage,
+            &mut serial,
+            &mut Persistence::new(None, None),
+            &mut session,
+        );
+        let stats = session.stats;
+        assert!(matches!(result, Err(error::SerialDiskError::IO(_))));
+
+        let mut bpb = Vec::new();
+        DiskLayout::default()
+            .write_bios_parameter_block(&mut bpb)
+            .unwrap();
+        assert_eq!(serial.output(), bpb);
+        assert_eq!(stats.unknown_commands.get(&0x42), Some(&1));
+        assert_eq!((stats.resyncs, stats.skipped_bytes), (1, 4));
+    }
+
+    #[test]
+    fn test_speed_test() {
+        let payload = Rng::new(1).bytes(64);
+        let mut input = MAGIC.to_vec();
+        input.push(4);
+        input.extend_from_slice(&64u32.to_be_bytes());
+        input.extend_from_slice(&payload);
+        checksum::write_crc32(&mut input, &payload).unwrap();
+        let (_, output) = run_with_input(&input);
+
+        // Status, receive time, then 64 bytes and their CRC
+        assert_eq!(output[0], 0x01);
+        assert_eq!(output.len(), 1 + 4 + 64 + 4);
+        let (data, crc) = output[5..].split_at(64);
+        assert!(checksum::check_crc32(&mut &crc[..], data).unwrap());
+
+        // Corrupted length looks for next command
+        let mut input = MAGIC.to_vec();
+        input.push(4);
+        input.extend_from_slice(&u32::MAX.to_be_bytes());
+        input.extend_from_slice(&MAGIC);
+        input.push(2);
+        let (_, output) = run_with_input(&input);
+        assert_eq!(output.len(), 18);
+    }
+
+    #[test]
+    fn test_authentication() {
+        let mut challenges = Rng::new(1);
+        let first = challenges.bytes(AUTH_CHALLENGE_LEN);
+        let second = challenges.bytes(AUTH_CHALLENGE_LEN);
+
+        // Read refused, wrong answer, right answer, then read
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[0, 0x00, 0x00, 0x00, 0x01]);
+        input.extend_from_slice(&MAGIC);
+        input.push(5);
+        input.extend_from_slice(&auth_answer(&first, "wrong"));
+        input.extend_from_slice(&MAGIC);
+        input.push(5);
+        input.extend_from_slice(&auth_answer(&second, "s3cret"));
+        input.extend_from_slice(&MAGIC);
+        input.extend_from_slice(&[0, 0x00, 0x00, 0x00, 0x01]);
+
+        let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+        let mut serial = MemoryTransport::new(&input);
+        let mut session = Session::new(SessionConfig {
+            secret: Some("s3cret".to_string()),
+            ..SessionConfig::default()
+        });
+        session.rng = Rng::new(1);
+        assert!(!session.is_authorized());
+        let _ = run_session(
+            storage,
+            &mut serial,
+            &mut Persistence::new(None, None),
+            &mut session,
+        );
+
+        let output = serial.output();
+        assert_eq!(&output[..8], first.as_slice());
+        assert_eq!(output[8], 0x00);
+        assert_eq!(&output[9..17], second.as_slice());
+        assert_eq!(output[17], 0x01);
+        assert!(output.len() > 18, "sector not read once authenticated");
+        assert!(session.is_authorized());
+        assert_eq!(session.stats.refused_commands, 1);
+    }
+
+    #[test]
+    fn test_host_command() {
+        let mut input = MAGIC.to_vec();
+        input.push(7);
+        input.extend_from_slice(&5u16.to_be_bytes());
+        input.extend_from_slice(b"hello");
+        input.extend_from_slice(&MAGIC);
+        input.push(7);
+        input.extend_from_slice(&3u16.to_be_bytes());
+        input.extend_from_slice(b"pwd");
+
+        let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+        let mut serial = MemoryTransport::new(&input);
+        let mut session = Session::new(SessionConfig {
+            host_commands: [("hello".to_string(), "echo hi".to_string())].into(),
+            ..SessionConfig::default()
+        });
+        let _ = run_session(
+            storage,
+            &mut serial,
+            &mut Persistence::new(None, None),
+            &mut session,
+        );
+
+        // Status, exit code, length, output and CRC, then unknown command
+        let output = serial.output();
+        assert_eq!(
+            &output[..10],
+            [0x01, 0x00, 0, 0, 0, 4, b'h', b'i', b'\r', b'\n']
+        );
+        let mut empty_crc = Vec::new();
+        checksum::write_crc32(&mut empty_crc, &[]).unwrap();
+        assert_eq!(&output[14..20], [0x00, 0xFF, 0, 0, 0, 0]);
+        assert_eq!(&output[20..], empty_crc.as_slice());
+    }
+
+    #[test]
+    fn test_clock() {
+        let now =
+            NaiveDateTime::parse_from_str("2024-03-09 17:45:32", "%Y-%m-%d %H:%M:%S").unwrap();
+        let clock = atari_clock(Some(now));
+        assert_eq!(clock >> 16, 44 << 9 | 3 << 5 | 9);
+        assert_eq!(clock & 0xFFFF, 17 << 11 | 45 << 5 | 16);
+        assert_eq!(atari_clock(None), 0);
+
+        let mut input = MAGIC.to_vec();
+        input.push(6);
+        let (_, output) = run_with_input(&input);
+        assert_eq!(output.len(), 4);
+        assert_ne!(output, [0; 4]);
+    }
+
+    #[test]
+    fn test_host_changes() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage
+            .put_virtual_file("GAME", "PRG", mtime, b"v1", ROOT_INDEX)
+            .unwrap();
+        storage
+            .put_virtual_file("GAME", "PRG", mtime, b"v2", ROOT_INDEX)
+            .unwrap();
+
+        let mut input = MAGIC.to_vec();
+        input.push(8);
+        let mut serial = MemoryTransport::new(&input);
+        let _ = run(
+            Arc::new(Mutex::new(storage)),
+            &mut serial,
+            &mut Persistence::new(None, None),
+        );
+        assert_eq!(serial.output(), [0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_reset_notice() {
+        let mut input = MAGIC.to_vec();
+        input.push(2);
+
+        let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+        let mut serial = MemoryTransport::new(&input);
+        let mut session = Session::new(SessionConfig {
+            reset_notice: Some(3),
+            ..SessionConfig::default()
+        });
+        let _ = run_session(
+            storage,
+            &mut serial,
+            &mut Persistence::new(None, None),
+            &mut session,
+        );
+
+        // Notice comes first, then the BPB Atari asks for again
+        let output = serial.output();
+        assert_eq!(output[..4], MAGIC);
+        assert_eq!(output[4..9], [protocol::RESET_NOTICE, 0, 0, 0, 3]);
+        assert!(output.len() > 9);
+    }
+
+    #[test]
+    fn test_pinned_reads() {
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[0, 0x00, 0x00, 0x00, 0x01]);
+        input.extend_from_slice(&MAGIC);
+        input.extend_from_slice(&[0, 0x00, 0x01, 0x00, 0x01]);
+
+        let storage = DiskStorage::new(DiskLayout::default());
+        let profile = BootProfile {
+            reads: vec![(0, 1)],
+            files: Vec::new(),
+        };
+        let pinned = PinnedReads::new(&profile, &storage, |_| Ok(b"pinned".to_vec()));
+        let mut second = Vec::new();
+        storage.read_sectors(&mut second, 1, 1).unwrap();
+        let mut serial = MemoryTransport::new(&input);
+        let mut session = Session::new(SessionConfig {
+            pinned_reads: Some(Arc::new(pinned)),
+            ..SessionConfig::default()
+        });
+        let _ = run_session(
+            Arc::new(Mutex::new(storage)),
+            &mut serial,
+            &mut Persistence::new(None, None),
+            &mut session,
+        );
+
+        // Only first read is pinned
+        let output = serial.output();
+        assert_eq!(output[..6], *b"pinned");
+        assert_eq!(output[6..], encode_frame(&second).unwrap());
+    }
+
+    #[test]
+    fn test_unreadable_sector() {
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.set_uninitialized_reads(UninitializedReadPolicy::Error);
+        let sector = storage.disk_layout.first_free_sector().to_be_bytes();
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[0, sector[0], sector[1], 0x00, 0x01]);
+        input.extend_from_slice(&MAGIC);
+        input.extend_from_slice(&[0, 0x00, 0x00, 0x00, 0x01]);
+
+        let mut first_fat = Vec::new();
+        storage.read_sectors(&mut first_fat, 0, 1).unwrap();
+        let mut serial = MemoryTransport::new(&input);
+        let _ = run(
+            Arc::new(Mutex::new(storage)),
+            &mut serial,
+            &mut Persistence::new(None, None),
+        );
+
+        // Session goes on after a refused read
+        let output = serial.output();
+        assert_eq!(output[0], protocol::FRAME_UNREADABLE);
+        assert_eq!(output[1..], encode_frame(&first_fat).unwrap());
+    }
+
+    #[test]
+    fn test_quota_write_refused() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Gem, 4));
+        let dir = storage
+            .add_virtual_directory("DOWNLOAD", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("FILE", "BIN", mtime, b"content", dir)
+            .unwrap();
+        storage.set_quotas(vec![Quota {
+            path: "DOWNLOAD".to_string(),
+            max_clusters: 2,
+        }]);
+
+        // Atari extends the file with a free cluster
+        let file = &storage
+            .read_dir(&storage.list_root_file_infos()[0])
+            .unwrap()[2];
+        let (last, next) = (file.cluster_index as usize, 0x10);
+        let mut fat = Vec::new();
+        storage.read_sectors(&mut fat, 0, 1).unwrap();
+        let original = fat.clone();
+        fat[last * 2..last * 2 + 2].copy_from_slice(&(next as u16).to_ne_bytes());
+        fat[next * 2..next * 2 + 2].copy_from_slice(&[0xFF, 0xFF]);
+
+        let sector_size = storage.disk_layout.bytes_per_sector() as usize;
+        let storage = Arc::new(Mutex::new(storage));
+        let mut serial = MemoryTransport::new(&write_input(0, &fat, sector_size));
+        let _ = run(
+            storage.clone(),
+            &mut serial,
+            &mut Persistence::new(None, None),
+        );
+
+        assert_eq!(serial.output(), [protocol::WRITE_REFUSED]);
+        let mut current = Vec::new();
+        storage
+            .lock()
+            .unwrap()
+            .read_sectors(&mut current, 0, 1)
+            .unwrap();
+        assert_eq!(current, original);
+    }
+
+    #[test]
+    fn test_unpersisted_write_refused() {
+        let fixture = Fixture::empty("unpersisted-write").unwrap();
+        // Dump cannot be written in a missing directory
+        let dump_path = fixture.join("missing").join("ramdisk.dump");
+        let layout = DiskLayout::default();
+        let sector_size = layout.bytes_per_sector() as usize;
+        let index = layout.first_free_sector() + 10;
+        let data = vec![0xAA; sector_size];
+
+        for journaled in [false, true] {
+            let journal =
+                journaled.then(|| WriteJournal::open(fixture.join("ramdisk.journal")).unwrap());
+            let mut persistence = Persistence::new(Some(dump_path.clone()), journal)
+                .with_policy(WritePolicy::WriteThrough);
+            let storage = Arc::new(Mutex::new(DiskStorage::new(layout.clone())));
+            let mut serial = MemoryTransport::new(&write_input(index, &data, sector_size));
+            assert!(run(storage.clone(), &mut serial, &mut persistence).is_err());
+
+            // Journaled write is kept, others are undone
+            let mut current = Vec::new();
+            storage
+                .lock()
+                .unwrap()
+                .read_sectors(&mut current, index, 1)
+                .unwrap();
+            if journaled {
+                assert_eq!(serial.output(), [0x01]);
+                assert_eq!(current, data);
+            } else {
+                assert_eq!(serial.output(), [protocol::WRITE_REFUSED]);
+                assert_ne!(current, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_only_write_refused() {
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.set_read_only(true);
+        let sector_size = storage.disk_layout.bytes_per_sector() as usize;
+        let data = vec![0xAA; sector_size];
+        let storage = Arc::new(Mutex::new(storage));
+        let mut serial = MemoryTransport::new(&write_input(0, &data, sector_size));
+        let _ = run(
+            storage.clone(),
+            &mut serial,
+            &mut Persistence::new(None, None),
+        );
+
+        assert_eq!(serial.output(), [protocol::WRITE_REFUSED]);
+        let mut current = Vec::new();
+        storage
+            .lock()
+            .unwrap()
+            .read_sectors(&mut current, 0, 1)
+            .unwrap();
+        assert_ne!(current, data);
+    }
+
+    #[test]
+    fn test_misaligned_command() {
+        // Leftover of a partial transfer before BPB command
+        for garbage in 1..8 {
+            let mut input = vec![0x18; garbage];
+            input.extend_from_slice(&MAGIC);
+            input.push(2);
+
+            let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+            let mut serial = MemoryTransport::new(&input);
+            let mut session = Session::default();
+            let _ = run_session(
+                storage,
+                &mut serial,
+                &mut Persistence::new(None, None),
+                &mut session,
+            );
+            let stats = session.stats;
+
+            assert!(!serial.output().is_empty(), "{} bytes of garbage", garbage);
+            assert_eq!(stats.skipped_bytes, garbage as u64);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_read_sector() {
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[0, 0x00, 0x00, 0x00, 0x01]);
+        let (_, output) = run_with_input(&input);
+
+        // Empty sector is compressed: flags + length + content + CRC
+        assert_eq!(output[0], 0x01);
+        let length = u32::from_be_bytes([output[1], output[2], output[3], output[4]]) as usize;
+        assert_eq!(output.len(), 1 + 4 + length + 4);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compress_samples() {
+        let mut rng = Rng::new(3);
+        let packed = rng.bytes(8 * COMPRESSION_SAMPLE_LEN);
+        assert_eq!(compress(&packed), None);
+
+        // Only the end of the buffer compresses
+        let mut image = packed.clone();
+        image.extend_from_slice(&[0; COMPRESSION_SAMPLE_LEN]);
+        assert!(compress(&image).is_some());
+
+        assert!(compress(&[0; 512]).is_some());
+        assert_eq!(compress(&packed[..512]), None);
+    }
+
+    /// Content of a chunked frame, checking CRC of its chunks.
+    fn decode_chunked_frame(mut frame: &[u8]) -> Vec<u8> {
+        assert_eq!(frame.read_u8().unwrap(), protocol::FRAME_CHUNKED);
+        let mut data = Vec::new();
+        loop {
+            let len = frame.read_u16::<BigEndian>().unwrap() as usize;
+            if len == 0 {
+                assert!(frame.is_empty());
+                return data;
+            }
+            let chunk = match frame.read_u8().unwrap() {
+                protocol::FRAME_RAW => {
+                    let (chunk, rest) = frame.split_at(len);
+                    frame = rest;
+                    chunk.to_vec()
+                }
+                #[cfg(feature = "compression")]
+                protocol::FRAME_LZ4 => {
+                    let compressed_len = frame.read_u16::<BigEndian>().unwrap() as usize;
+                    let (compressed, rest) = frame.split_at(compressed_len);
+                    frame = rest;
+                    lz4_flex::decompress(compressed, len).unwrap()
+                }
+                flags => panic!("Unexpected chunk flags {}", flags),
+            };
+            assert!(checksum::check_crc32(&mut frame, &chunk).unwrap());
+            data.extend_from_slice(&chunk);
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[protocol::CAPABILITIES, 0xFF, 0xFF]);
+        input.extend_from_slice(&MAGIC);
+        input.extend_from_slice(&[0, 0x00, 0x00, 0x00, 0x20]);
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let content = Rng::new(5).bytes(12 * 1024);
+        storage
+            .add_virtual_file("DATA", "BIN", mtime, &content, ROOT_INDEX)
+            .unwrap();
+        let mut expected = Vec::new();
+        storage.read_sectors(&mut expected, 0, 0x20).unwrap();
+
+        let mut serial = MemoryTransport::new(&input);
+        let _ = run(
+            Arc::new(Mutex::new(storage)),
+            &mut serial,
+            &mut Persistence::new(None, None),
+        );
+
+        // Only supported capabilities are agreed
+        let output = serial.output();
+        assert_eq!(output[..2], protocol::CAPABILITIES_SUPPORTED.to_be_bytes());
+        assert_eq!(decode_chunked_frame(&output[2..]), expected);
+    }
+
+    #[test]
+    fn test_tagged_reads() {
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        let storage = DiskStorage::new(DiskLayout::default());
+        storage.read_sectors(&mut first, 0, 1).unwrap();
+        storage.read_sectors(&mut second, 1, 1).unwrap();
+
+        // Refused until agreed
+        let mut input = MAGIC.to_vec();
+        input.extend_from_slice(&[protocol::TAGGED_READ, 7, 0x00, 0x00, 0x00, 0x01]);
+        input.extend_from_slice(&MAGIC);
+        input.extend_from_slice(&[protocol::CAPABILITIES, 0x00, 0x02]);
+        // Second read sent before first answer
+        for (tag, sector) in [(7, 0), (8, 1)] {
+            input.extend_from_slice(&MAGIC);
+            input.extend_from_slice(&[protocol::TAGGED_READ, tag, 0x00, sector, 0x00, 0x01]);
+        }
+
+        let mut serial = MemoryTransport::new(&input);
+        let mut session = Session::default();
+        let _ = run_session(
+            Arc::new(Mutex::new(storage)),
+            &mut serial,
+            &mut Persistence::new(None, None),
+            &mut session,
+        );
+        assert_eq!(session.stats.unknown_commands[&protocol::TAGGED_READ], 1);
+
+        let mut expected = protocol::CAP_TAGGED_COMMANDS.to_be_bytes().to_vec();
+        expected.push(7);
+        expected.extend_from_slice(&encode_frame(&first).unwrap());
+        expected.push(8);
+        expected.extend_from_slice(&encode_frame(&second).unwrap());
+        assert_eq!(serial.output(), expected);
+    }
+
+    #[test]
+    fn test_chunked_frame() {
+        let mut rng = Rng::new(11);
+        let mut data = rng.bytes(3 * 4096);
+        data.extend_from_slice(&[0; 2048]);
+
+        let mut output = Vec::new();
+        let sent = write_chunked_frame(&mut output, &data, 4096).unwrap();
+        assert_eq!(sent, output.len());
+        assert_eq!(decode_chunked_frame(&output), data);
+    }
+
+    #[test]
+    fn test_write_buffer_pipelined() {
+        let mut rng = Rng::new(7);
+        for len in [PIPELINE_MIN_LEN - 1, PIPELINE_MIN_LEN, 4 * PIPELINE_MIN_LEN] {
+            let data: Vec<u8> = (0..len)
+                .map(|i| (rng.next_u64() as u8) & (i as u8))
+                .collect();
+
+            let mut output = Vec::new();
+            let sent = write_buffer(&mut output, &data).unwrap();
+            assert_eq!(sent, output.len());
+
+            let (content, crc) = output.split_at(output.len() - 4);
+            let mut expected = Vec::new();
+            write_frame_content(&mut expected, &data).unwrap();
+            assert_eq!(content, expected);
+            assert!(checksum::check_crc32(&mut &crc[..], &data).unwrap());
+        }
+    }
 }
@@ -1,15 +1,36 @@
-use std::{collections::HashMap, fmt::Debug, fs, io, mem, path::Path};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Debug,
+    fs, io, mem,
+    ops::Range,
+    path::Path,
+    sync::mpsc,
+    time::Instant,
+};
 
+use chrono::{Local, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    entries::{DirectoryContent, FileInfo},
+    backend::{MemoryBackend, SectorBackend},
+    bus::{EventBus, StorageEvent},
+    dos,
+    entries::{DirectoryContent, EntryMetadata, FileInfo},
     error::{self, SerialDiskError},
-    fat::FileAllocationTable,
+    fat::{ClusterChain, FileAllocationTable},
+    generated::GeneratedFile,
+    import_report::{ImportReport, SkipReason},
     layout::DiskLayout,
+    name_key::NameKey,
+    quota::Quota,
+    reverse::Origin,
+    shared_root::SharedRoot,
+    split::{self, SplitManifest},
+    tos::{self, ExecutablePolicy, NamePolicy},
+    trash::Trash,
 };
 
-const ROOT_INDEX: u16 = 0;
+pub const ROOT_INDEX: u16 = 0;
 
 macro_rules! extract_cluster {
     ($reader:expr, $disk_layout:expr) => {{
@@ -25,14 +46,72 @@ macro_rules! table_size {
     };
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-enum DiskBloc {
-    Data(Vec<u8>),
-    Entries(DirectoryContent),
+/// Empty table of a directory sector, partition sectors always hold some.
+fn empty_table(disk_layout: &DiskLayout) -> DirectoryContent {
+    DirectoryContent::new(table_size!(disk_layout)).expect("sector too small for directory entries")
+}
+
+/// What to do when Atari formats the disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormatPolicy {
+    /// Ignore the write and keep disk content
+    #[default]
+    Refuse,
+    /// Clear disk content
+    Allow,
+}
+
+/// What Atari gets when reading a data sector never written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UninitializedReadPolicy {
+    /// Zeros, silently
+    Zeros,
+    /// Zeros, logging a warning
+    #[default]
+    Warn,
+    /// An error answer, see `unreadable_sector`
+    Error,
+    /// `0xE5` bytes, as in sectors of a freshly formatted floppy
+    FillE5,
+}
+
+/// Sectors content kept before a write, to undo it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorSnapshot {
+    index: u16,
+    content: Vec<u8>,
+}
+
+/// What a sectors write has done to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Written,
+    /// Write was a format attempt, disk has been cleared
+    Formatted,
+    /// Write was a format attempt, it has been ignored
+    FormatRefused,
+    /// Disk is read only, write has been ignored
+    ReadOnly,
+    /// Write would make a directory use more than its quota, it has been
+    /// ignored
+    QuotaExceeded,
+    /// Write would change content imported from a read only source, it has
+    /// been ignored
+    Protected,
+}
+
+impl WriteOutcome {
+    /// Tell if write has been ignored, so Atari must not trust its cached
+    /// FAT and directories any more.
+    pub fn is_refused(&self) -> bool {
+        matches!(self, Self::ReadOnly | Self::QuotaExceeded | Self::Protected)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct DiskStorage {
+pub struct DiskStorage<B = MemoryBackend> {
     /// Contains disk layout information and bytes mapping
     pub disk_layout: DiskLayout,
 
@@ -43,29 +122,430 @@ pub struct DiskStorage {
     fat: FileAllocationTable,
 
     /// Bloc of data stored on disk
-    sector_data: HashMap<u16, DiskBloc>,
+    sector_data: B,
+
+    /// How to handle imported names TOS does not accept
+    #[serde(skip)]
+    name_policy: NamePolicy,
+    /// Disk extension of host files, by lowercase host extension
+    #[serde(skip)]
+    extension_map: HashMap<String, String>,
+    /// How to handle imported programs TOS cannot run
+    #[serde(skip)]
+    executable_policy: ExecutablePolicy,
+    /// Size of the parts of larger imported files
+    #[serde(skip)]
+    split_size: Option<usize>,
+    /// Deepest directory level created when importing
+    #[serde(skip)]
+    max_depth: Option<usize>,
+
+    /// How to handle Atari formatting the disk
+    #[serde(skip)]
+    format_policy: FormatPolicy,
+    /// What reads of sectors never written give
+    #[serde(skip)]
+    uninitialized_reads: UninitializedReadPolicy,
+
+    /// FAT as it was before Atari emptied it, until next write
+    #[serde(skip)]
+    emptied_fat: Option<FileAllocationTable>,
+
+    /// Ignore every write from Atari
+    #[serde(skip)]
+    read_only: bool,
+
+    /// Refuse writes of directory entries GEMDOS would not write
+    #[serde(skip)]
+    strict: bool,
+
+    /// Where to keep content of files deleted by Atari
+    #[serde(skip)]
+    trash: Option<Trash>,
+
+    /// FAT as it was before last FAT write, to read chains Atari has
+    /// freed before marking their file as deleted
+    #[serde(skip)]
+    last_fat: Option<FileAllocationTable>,
+
+    /// Limits on clusters used by sub directories
+    #[serde(skip)]
+    quotas: Vec<Quota>,
+
+    /// Sectors changed since last `clear_dirty`
+    #[serde(skip)]
+    dirty: BTreeSet<u16>,
+
+    /// Subscribers to disk changes
+    #[serde(skip)]
+    events: EventBus,
+
+    /// Host entries imported files and directories come from, by first
+    /// cluster
+    #[serde(skip)]
+    origins: HashMap<u16, Origin>,
+
+    /// Number of files changed from host while serving
+    #[serde(skip)]
+    host_changes: u32,
+
+    /// Clusters imported from read only sources, Atari cannot change them
+    #[serde(skip)]
+    protected: BTreeSet<u16>,
+
+    /// In overlay mode, content sectors had before Atari first wrote them
+    #[serde(skip)]
+    overlay_base: Option<BTreeMap<u16, Vec<u8>>>,
+
+    /// Files generated by host commands, with their directory first cluster
+    #[serde(skip)]
+    generated: Vec<(u16, GeneratedFile)>,
 }
 
 impl DiskStorage {
+    /// Create an empty RAM disk.
     pub fn new(disk_layout: DiskLayout) -> Self {
+        Self::with_backend(disk_layout, MemoryBackend::default())
+    }
+
+    /// Keep RAM disk sectors compressed, see `MemoryBackend::set_compression`.
+    pub fn set_compression(&mut self, compress: bool) {
+        self.sector_data.set_compression(compress);
+    }
+
+    /// Sector bytes of the RAM disk and bytes used to store them.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        self.sector_data.memory_usage()
+    }
+}
+
+impl<B> DiskStorage<B>
+where
+    B: SectorBackend,
+{
+    /// Create an empty disk storing its sectors in given backend.
+    pub fn with_backend(disk_layout: DiskLayout, backend: B) -> Self {
         // Init buffers
-        let fat = FileAllocationTable::new(
-            ((disk_layout.count_1fat_sectors() as usize * disk_layout.bytes_per_sector() as usize)
-                / mem::size_of::<u16>())
-                - disk_layout.first_free_cluster() as usize,
-        );
+        let fat = FileAllocationTable::new(disk_layout.fat_entries_count());
 
-        let root_entries = vec![
-            DirectoryContent::new(table_size!(disk_layout));
-            disk_layout.root_directory_sectors() as usize
-        ];
+        let root_entries =
+            vec![empty_table(&disk_layout); disk_layout.root_directory_sectors() as usize];
 
         // Create struct
         Self {
             disk_layout,
             root_entries,
             fat,
-            sector_data: HashMap::new(),
+            sector_data: backend,
+            name_policy: NamePolicy::default(),
+            extension_map: HashMap::new(),
+            executable_policy: ExecutablePolicy::default(),
+            split_size: None,
+            max_depth: None,
+            format_policy: FormatPolicy::default(),
+            uninitialized_reads: UninitializedReadPolicy::default(),
+            emptied_fat: None,
+            read_only: false,
+            strict: false,
+            trash: None,
+            last_fat: None,
+            quotas: Vec::new(),
+            dirty: BTreeSet::new(),
+            events: EventBus::default(),
+            origins: HashMap::new(),
+            host_changes: 0,
+            protected: BTreeSet::new(),
+            overlay_base: None,
+            generated: Vec::new(),
+        }
+    }
+
+    /// Change how imported names TOS does not accept are handled.
+    pub fn set_name_policy(&mut self, name_policy: NamePolicy) {
+        self.name_policy = name_policy;
+    }
+
+    /// Replace extensions of imported files, from host extension (ex:
+    /// `tosprg`, ignoring case) to the one TOS expects (ex: `PRG`).
+    pub fn set_extension_map(&mut self, extension_map: &BTreeMap<String, String>) {
+        self.extension_map = extension_map
+            .iter()
+            .map(|(host, disk)| {
+                let disk: String = disk.trim_start_matches('.').chars().take(3).collect();
+                (
+                    host.trim_start_matches('.').to_lowercase(),
+                    disk.to_uppercase(),
+                )
+            })
+            .collect();
+    }
+
+    pub fn set_executable_policy(&mut self, executable_policy: ExecutablePolicy) {
+        self.executable_policy = executable_policy;
+    }
+
+    /// Import host files larger than `split_size` bytes as numbered parts.
+    pub fn set_split_size(&mut self, split_size: Option<usize>) {
+        self.split_size = split_size;
+    }
+
+    /// Create imported directories down to `max_depth` levels from disk
+    /// root, files of deeper ones are imported in their ancestor at this
+    /// level.
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Name and extension to give to a file, checking programs have an
+    /// extension TOS can run.
+    pub fn executable_components(
+        &self,
+        name: String,
+        ext: String,
+        content: &[u8],
+    ) -> (String, String) {
+        if self.executable_policy == ExecutablePolicy::Ignore
+            || !tos::is_misnamed_program(&ext, content)
+        {
+            return (name, ext);
+        }
+
+        let full_name = if ext.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", name, ext)
+        };
+        if self.executable_policy == ExecutablePolicy::Warn {
+            log::warn!("{} is a program but TOS will not run it", full_name);
+            return (name, ext);
+        }
+        log::warn!("{} is a program, renaming it to {}.PRG", full_name, name);
+        (name, "PRG".to_string())
+    }
+
+    /// DOS name and extension of a host file, extension remapped.
+    pub fn file_components<P>(&self, path: P) -> error::Result<(String, String)>
+    where
+        P: AsRef<Path>,
+    {
+        let (name, ext) = dos::as_valid_file_components(&path)?;
+        let mapped = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.extension_map.get(&ext.to_lowercase()));
+        Ok(match mapped {
+            Some(mapped) => (name, mapped.clone()),
+            None => (name, ext),
+        })
+    }
+
+    /// Change how Atari formatting the disk is handled.
+    pub fn set_format_policy(&mut self, format_policy: FormatPolicy) {
+        self.format_policy = format_policy;
+    }
+
+    pub fn set_uninitialized_reads(&mut self, policy: UninitializedReadPolicy) {
+        self.uninitialized_reads = policy;
+    }
+
+    /// First sector of a read never written, when Atari must get an error
+    /// for it. Other readers (ex: exports) get zeros.
+    pub fn unreadable_sector(&self, index: u16, count: u16) -> Option<u16> {
+        if self.uninitialized_reads != UninitializedReadPolicy::Error {
+            return None;
+        }
+        let first_data_sector = index.max(self.disk_layout.first_free_sector());
+        (first_data_sector..index.saturating_add(count))
+            .find(|&sector| matches!(self.sector_data.sector(sector), Ok(None)))
+    }
+
+    /// Make Atari writes ignored, or accepted again.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Make `check_write` validate directory entries written by Atari.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// In strict mode, check names, first clusters and sizes of the
+    /// directory entries a write changes, so garbage is refused instead of
+    /// breaking extraction later.
+    pub fn check_write(&self, index: u16, data: &[u8]) -> error::Result<()> {
+        if !self.strict {
+            return Ok(());
+        }
+
+        let layout = &self.disk_layout;
+        let bytes_per_sector = layout.bytes_per_sector() as usize;
+        let entry_size = mem::size_of::<FileInfo>();
+        let root_sectors = layout.count_fat_sectors()..layout.first_free_sector();
+        let end = index as usize + data.len() / bytes_per_sector;
+        let directory_sectors = if end > root_sectors.end as usize {
+            self.directory_sectors()
+        } else {
+            HashMap::new()
+        };
+
+        for (sector, sector_index) in data.chunks(bytes_per_sector).zip(index..) {
+            if !root_sectors.contains(&sector_index)
+                && !directory_sectors.contains_key(&sector_index)
+            {
+                continue;
+            }
+
+            // Entries already there are not Atari's doing
+            let mut current = Vec::new();
+            self.read_sector(&mut current, sector_index)?;
+            for (old, new) in current.chunks(entry_size).zip(sector.chunks(entry_size)) {
+                if old != new {
+                    FileInfo::try_from_reader(&mut &new[..])?.check(
+                        self.fat.cluster_count(),
+                        layout.bytes_per_cluster() as usize,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Limit clusters used by sub directories, for imports and Atari writes.
+    pub fn set_quotas(&mut self, quotas: Vec<Quota>) {
+        self.quotas = quotas;
+    }
+
+    /// Keep content of files deleted by Atari in `trash`.
+    pub fn set_trash(&mut self, trash: Trash) {
+        self.trash = Some(trash);
+    }
+
+    /// Sectors changed by Atari writes or imports since disk creation or
+    /// last [`clear_dirty`](Self::clear_dirty).
+    pub fn dirty_sectors(&self) -> &BTreeSet<u16> {
+        &self.dirty
+    }
+
+    /// Forget changed sectors, once they have been saved somewhere else.
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Host entry an imported file or directory starting at `cluster_index`
+    /// comes from. Disk root maps to the directory imported at root.
+    pub fn origin(&self, cluster_index: u16) -> Option<&Origin> {
+        self.origins.get(&cluster_index)
+    }
+
+    /// Get every change made to the disk from now on.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<StorageEvent> {
+        self.events.subscribe()
+    }
+
+    /// Mark FAT sectors holding entry of `cluster_index`, in both FAT.
+    fn mark_fat_dirty(&mut self, cluster_index: u16) {
+        let sector_index = (cluster_index as usize * mem::size_of::<u16>()
+            / self.disk_layout.bytes_per_sector() as usize) as u16;
+        self.dirty.insert(sector_index);
+        self.dirty
+            .insert(self.disk_layout.count_1fat_sectors() + sector_index);
+    }
+
+    fn mark_fat_sectors_dirty(&mut self) {
+        self.dirty.extend(0..self.disk_layout.count_fat_sectors());
+    }
+
+    /// Open a disk previously stored in a persistent backend.
+    ///
+    /// Also return if disk has been loaded from backend or if an empty
+    /// one has been created since backend does not contain a disk yet.
+    pub fn open_backend(disk_layout: DiskLayout, backend: B) -> io::Result<(Self, bool)> {
+        let mut storage = Self::with_backend(disk_layout, backend);
+        let bytes_per_sector = storage.disk_layout.bytes_per_sector() as usize;
+
+        // Load FAT
+        let mut fat_raw = Vec::new();
+        for index in 0..storage.disk_layout.count_1fat_sectors() {
+            match storage.sector_data.sector(index)? {
+                Some(data) => fat_raw.extend_from_slice(&data),
+                None => return Ok((storage, false)),
+            }
+        }
+
+        let fat_bytes = storage.fat.as_raw().len();
+        let mut fat = FileAllocationTable::new(storage.disk_layout.fat_entries_count());
+        fat.merge_data(&mut &fat_raw[..fat_bytes], 0, fat_bytes)?;
+        if fat.as_raw()[..4] != storage.fat.as_raw()[..4] {
+            // Reserved clusters are not set: this is an empty backend
+            return Ok((storage, false));
+        }
+        storage.fat = fat;
+
+        // Load root directory
+        let count = table_size!(storage.disk_layout);
+        for i in 0..storage.disk_layout.root_directory_sectors() {
+            let index = storage.disk_layout.count_fat_sectors() + i;
+            let data = storage
+                .sector_data
+                .sector(index)?
+                .map(|data| data.to_vec())
+                .unwrap_or_else(|| vec![0; bytes_per_sector]);
+
+            storage.root_entries[i as usize] =
+                DirectoryContent::try_from_reader(&mut data.as_slice(), count)?;
+        }
+
+        Ok((storage, true))
+    }
+
+    /// Make sure disk content reached stable storage.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sync_metadata();
+        self.sector_data.flush()
+    }
+
+    /// Copy FAT and root directory to the backend if it is persistent.
+    fn sync_metadata(&mut self) {
+        if !self.sector_data.is_persistent() {
+            return;
+        }
+
+        let bytes_per_sector = self.disk_layout.bytes_per_sector() as usize;
+        let mut fat_raw = self.fat.as_raw().to_vec();
+        fat_raw.resize(
+            self.disk_layout.count_1fat_sectors() as usize * bytes_per_sector,
+            0,
+        );
+
+        for (i, data) in fat_raw.chunks(bytes_per_sector).enumerate() {
+            // Both FAT are stored with the same content
+            self.sector_data.set_sector(i as u16, data.to_vec());
+            self.sector_data.set_sector(
+                self.disk_layout.count_1fat_sectors() + i as u16,
+                data.to_vec(),
+            );
+        }
+
+        for (i, entries) in self.root_entries.iter().enumerate() {
+            self.sector_data.set_sector(
+                self.disk_layout.count_fat_sectors() + i as u16,
+                entries.as_raw().to_vec(),
+            );
+        }
+    }
+
+    /// Load sectors from host storage ahead of Atari reading them.
+    pub fn prefetch(&self, index: u16, count: u16) {
+        // Only data sectors may come from host storage
+        let first = index.max(self.disk_layout.first_free_sector());
+        let end = index.saturating_add(count);
+        if first < end {
+            self.sector_data.prefetch(first, end - first);
         }
     }
 
@@ -80,14 +560,530 @@ impl DiskStorage {
         Ok(())
     }
 
-    pub fn write_sectors<R>(&mut self, reader: &mut R, index: u16, count: u16) -> io::Result<()>
+    /// Keep sectors a write of `data` at `index` may change: written ones,
+    /// or the whole disk for a format attempt.
+    pub fn snapshot_write(&self, index: u16, data: &[u8]) -> io::Result<SectorSnapshot> {
+        let bytes_per_sector = self.disk_layout.bytes_per_sector() as usize;
+        let (index, count) = if self.is_format(index, data) {
+            (
+                0,
+                self.disk_layout.sector_count().min(u16::MAX as u32) as u16,
+            )
+        } else {
+            (index, data.len().div_ceil(bytes_per_sector) as u16)
+        };
+
+        let mut content = Vec::with_capacity(count as usize * bytes_per_sector);
+        self.read_sectors(&mut content, index, count)?;
+        Ok(SectorSnapshot { index, content })
+    }
+
+    /// Put back sectors kept by `snapshot_write`, without the checks of
+    /// `write_sectors`.
+    pub fn restore(&mut self, snapshot: &SectorSnapshot) -> io::Result<()> {
+        let bytes_per_sector = self.disk_layout.bytes_per_sector() as usize;
+        for (i, sector) in snapshot.content.chunks(bytes_per_sector).enumerate() {
+            self.write_sector(&mut &sector[..], snapshot.index + i as u16)?;
+        }
+        self.sync_metadata();
+        Ok(())
+    }
+
+    /// Write sectors sent by Atari.
+    ///
+    /// A write blanking the root directory of a non empty disk is a format
+    /// attempt: it is either fully applied or fully ignored depending on
+    /// format policy. Formatting tools writing the FAT before the root
+    /// directory are detected as well, emptied FAT is restored if format is
+    /// refused.
+    pub fn write_sectors<R>(
+        &mut self,
+        reader: &mut R,
+        index: u16,
+        count: u16,
+    ) -> io::Result<WriteOutcome>
     where
         R: io::Read,
     {
-        for i in 0..count {
-            self.write_sector(reader, index + i)?;
+        let bytes_per_sector = self.disk_layout.bytes_per_sector() as usize;
+        let mut data = vec![0; count as usize * bytes_per_sector];
+        reader.read_exact(&mut data)?;
+
+        if self.read_only {
+            log::warn!("Disk is read only, write ignored");
+            return Ok(WriteOutcome::ReadOnly);
+        }
+
+        let emptied_fat = self.emptied_fat.take();
+        let mut outcome = WriteOutcome::Written;
+        if self.is_format(index, &data) {
+            match self.format_policy {
+                FormatPolicy::Refuse => {
+                    log::warn!("Atari tried to format the disk, write ignored");
+                    if let Some(fat) = emptied_fat {
+                        self.fat = fat;
+                        self.mark_fat_sectors_dirty();
+                        self.sync_metadata();
+                    }
+                    return Ok(WriteOutcome::FormatRefused);
+                }
+                FormatPolicy::Allow => {
+                    log::warn!("Atari is formatting the disk, clearing its content");
+                    self.clear();
+                    outcome = WriteOutcome::Formatted;
+                }
+            }
+        }
+
+        // Directory sectors are mapped once, for all the checks of this write
+        let needs_entries =
+            !self.protected.is_empty() || self.trash.is_some() || self.events.has_subscribers();
+        let directory_sectors = if needs_entries || !self.quotas.is_empty() {
+            self.directory_sectors()
+        } else {
+            HashMap::new()
+        };
+        let changed_entries = if needs_entries {
+            self.changed_entries(index, &data, &directory_sectors)
+        } else {
+            Vec::new()
+        };
+
+        if self.changes_protected(index, &data, &changed_entries) {
+            log::warn!("Write would change a read only source, write ignored");
+            return Ok(WriteOutcome::Protected);
+        }
+
+        let deleted = self.deleted_files(&changed_entries);
+
+        // Keep replaced sectors to revert a write going over a quota
+        let quota_check = if self.changes_allocation(index, count, &directory_sectors) {
+            let mut replaced = Vec::with_capacity(data.len());
+            self.read_sectors(&mut replaced, index, count)?;
+            let replaced = SectorSnapshot {
+                index,
+                content: replaced,
+            };
+            Some((replaced, self.quota_usage(), self.dirty.clone()))
+        } else {
+            None
+        };
+
+        // Keep FAT if this write empties it, root directory may come next
+        let writes_fat = index < self.disk_layout.count_fat_sectors();
+        let previous_fat = (writes_fat && (self.trash.is_some() || !self.fat.is_empty()))
+            .then(|| self.fat.clone());
+
+        self.keep_overlay_base(index..index.saturating_add(count));
+        for (i, sector) in data.chunks(bytes_per_sector).enumerate() {
+            self.write_sector(&mut &sector[..], index + i as u16)?;
+        }
+
+        if let Some((replaced, usage, dirty)) = quota_check {
+            if let Some(quota) = self.grown_over_quota(&usage) {
+                log::warn!("Quota of {} exceeded, write ignored", quota.path);
+                self.restore(&replaced)?;
+                self.dirty = dirty;
+                return Ok(WriteOutcome::QuotaExceeded);
+            }
+        }
+
+        if writes_fat && self.trash.is_some() {
+            self.last_fat = previous_fat.clone();
+        }
+        if self.fat.is_empty() && !self.is_root_blank() {
+            self.emptied_fat = previous_fat;
+        }
+
+        if let Some(trash) = &self.trash {
+            for (path, content) in deleted {
+                if let Err(error) = trash.keep(&path, &content) {
+                    log::warn!("Cannot keep deleted file {} (error: {})", path, error);
+                }
+            }
+        }
+
+        if self.events.has_subscribers() {
+            self.events
+                .emit(StorageEvent::SectorWritten { index, count });
+            for (dir, old, new) in changed_entries {
+                self.emit_entry_change(dir, Some(&old), &new);
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Tell subscribers about a directory entry being added or changed.
+    fn emit_entry_change(&mut self, dir: u16, old: Option<&FileInfo>, new: &FileInfo) {
+        let deleted = new.is_deleted() || new.is_unused();
+        let name = match old.filter(|_| deleted).unwrap_or(new).filename() {
+            Ok(name) if name != "." && name != ".." => name,
+            _ => return,
+        };
+        let path = match self.entry_path(dir, name) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+
+        if !deleted && !new.is_dir() {
+            self.events.emit(StorageEvent::FileWritten {
+                path: path.clone(),
+                size: new.size(),
+            });
+        }
+        self.events
+            .emit(StorageEvent::DirEntryChanged { path, deleted });
+    }
+
+    /// Path of entry `name` of directory starting at `dir` cluster.
+    fn entry_path(&self, dir: u16, name: String) -> error::Result<String> {
+        Ok(match dir {
+            ROOT_INDEX => name,
+            _ => format!("{}\\{}", self.directory_path(dir)?, name),
+        })
+    }
+
+    /// Add to `error` the path of entry `name.ext` of directory `dir`.
+    fn entry_error(
+        &self,
+        error: SerialDiskError,
+        dir: u16,
+        (name, ext): (&str, &str),
+    ) -> SerialDiskError {
+        let name = match ext {
+            "" => name.to_string(),
+            ext => format!("{}.{}", name, ext),
+        };
+        match self.entry_path(dir, name) {
+            Ok(path) => error.with_path(path),
+            Err(_) => error,
+        }
+    }
+
+    /// Check if a write changes FAT entries or sectors of protected clusters,
+    /// or directory entries pointing to them (`changed_entries` of the write).
+    fn changes_protected(
+        &self,
+        index: u16,
+        data: &[u8],
+        changed_entries: &[(u16, FileInfo, FileInfo)],
+    ) -> bool {
+        if self.protected.is_empty() {
+            return false;
+        }
+
+        let layout = &self.disk_layout;
+        let bytes_per_sector = layout.bytes_per_sector() as usize;
+        let entry_size = mem::size_of::<u16>();
+        for (sector, sector_index) in data.chunks(bytes_per_sector).zip(index..) {
+            let mut current = Vec::new();
+            if self.read_sector(&mut current, sector_index).is_err() || current == sector {
+                continue;
+            }
+
+            if sector_index < layout.count_fat_sectors() {
+                let first_entry = (sector_index % layout.count_1fat_sectors()) as usize
+                    * bytes_per_sector
+                    / entry_size;
+                let changed = current
+                    .chunks(entry_size)
+                    .zip(sector.chunks(entry_size))
+                    .enumerate()
+                    .filter(|(_, (old, new))| old != new)
+                    .any(|(i, _)| self.protected.contains(&((first_entry + i) as u16)));
+                if changed {
+                    return true;
+                }
+            } else if sector_index >= layout.first_free_sector()
+                && self
+                    .protected
+                    .contains(&layout.convert_sector_to_cluster(sector_index))
+            {
+                return true;
+            }
+        }
+
+        changed_entries.iter().any(|(_, old, _)| {
+            !old.is_deleted() && !old.is_unused() && self.protected.contains(&old.cluster_index)
+        })
+    }
+
+    /// Check if quotas are set and a write may change clusters used by
+    /// directories (FAT or directory sectors).
+    fn changes_allocation(
+        &self,
+        index: u16,
+        count: u16,
+        directory_sectors: &HashMap<u16, u16>,
+    ) -> bool {
+        if self.quotas.is_empty() {
+            return false;
+        }
+        if index < self.disk_layout.first_free_sector() {
+            return true;
+        }
+
+        (index..index.saturating_add(count)).any(|i| directory_sectors.contains_key(&i))
+    }
+
+    /// Clusters used by every quota directory.
+    fn quota_usage(&self) -> Vec<usize> {
+        self.quotas
+            .iter()
+            .map(|quota| quota.used_clusters(self).ok().flatten().unwrap_or_default())
+            .collect()
+    }
+
+    /// Find a quota exceeded by a write, ignoring directories that were
+    /// already over their quota and have not grown.
+    fn grown_over_quota(&self, usage_before: &[usize]) -> Option<&Quota> {
+        self.quotas
+            .iter()
+            .zip(self.quota_usage())
+            .zip(usage_before)
+            .find(|((quota, after), before)| *after > quota.max_clusters && after > *before)
+            .map(|((quota, _), _)| quota)
+    }
+
+    /// Check a new file of `size` bytes in `parent_index` fits in quotas.
+    fn check_quotas(&self, parent_index: u16, size: usize) -> error::Result<()> {
+        if self.quotas.is_empty() {
+            return Ok(());
+        }
+
+        let parent_path = self.directory_path(parent_index)?;
+        let needed = size
+            .div_ceil(self.disk_layout.bytes_per_cluster() as usize)
+            .max(1);
+
+        for quota in self.quotas.iter().filter(|q| q.covers(&parent_path)) {
+            let used = quota.used_clusters(self)?.unwrap_or_default();
+            if used + needed > quota.max_clusters {
+                return Err(SerialDiskError::QuotaExceeded(quota.path.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find files a write marks as deleted and read their content, when
+    /// a trash is set.
+    fn deleted_files(
+        &self,
+        changed_entries: &[(u16, FileInfo, FileInfo)],
+    ) -> Vec<(String, Vec<u8>)> {
+        if self.trash.is_none() {
+            return Vec::new();
+        }
+
+        let mut deleted = Vec::new();
+        for (dir, old, new) in changed_entries {
+            if old.is_dir() || old.is_deleted() || old.cluster_index < 2 || !new.is_deleted() {
+                continue;
+            }
+
+            match self.deleted_file(*dir, old) {
+                Ok(file) => deleted.push(file),
+                Err(error) => log::warn!("Cannot read deleted file (error: {})", error),
+            }
+        }
+
+        deleted
+    }
+
+    /// Find directory entries a write changes, with the first cluster of
+    /// their directory, their current and their new value.
+    fn changed_entries(
+        &self,
+        index: u16,
+        data: &[u8],
+        directory_sectors: &HashMap<u16, u16>,
+    ) -> Vec<(u16, FileInfo, FileInfo)> {
+        let layout = &self.disk_layout;
+        let entry_size = mem::size_of::<FileInfo>();
+        let mut changed = Vec::new();
+
+        let sectors = data.chunks(layout.bytes_per_sector() as usize).zip(index..);
+        for (sector, sector_index) in sectors {
+            let dir = if (layout.count_fat_sectors()..layout.first_free_sector())
+                .contains(&sector_index)
+            {
+                ROOT_INDEX
+            } else {
+                match directory_sectors.get(&sector_index) {
+                    Some(dir) => *dir,
+                    None => continue,
+                }
+            };
+
+            let mut current = Vec::new();
+            if self.read_sector(&mut current, sector_index).is_err() {
+                continue;
+            }
+
+            for (old, new) in current.chunks(entry_size).zip(sector.chunks(entry_size)) {
+                if old == new {
+                    continue;
+                }
+                if let (Ok(old), Ok(new)) = (
+                    FileInfo::try_from_reader(&mut &old[..]),
+                    FileInfo::try_from_reader(&mut &new[..]),
+                ) {
+                    changed.push((dir, old, new));
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Path and content of a file Atari is deleting.
+    fn deleted_file(&self, dir: u16, file_info: &FileInfo) -> error::Result<(String, Vec<u8>)> {
+        let path = self.entry_path(dir, file_info.filename()?)?;
+
+        // Atari may have freed clusters of the file already
+        let fat = match &self.last_fat {
+            Some(last_fat) if self.fat.is_free(file_info.cluster_index) => last_fat,
+            _ => &self.fat,
+        };
+        Ok((path, self.read_chain(fat, file_info)?))
+    }
+
+    /// Map every directory sector to the first cluster of its directory.
+    fn directory_sectors(&self) -> HashMap<u16, u16> {
+        let mut sectors = HashMap::new();
+        let mut pending = self.list_root_file_infos();
+
+        while let Some(file_info) = pending.pop() {
+            let first_sector = self
+                .disk_layout
+                .convert_cluster_to_sector(file_info.cluster_index);
+            if !file_info.is_dir()
+                || file_info.is_deleted()
+                || file_info.cluster_index < 2
+                || sectors.contains_key(&first_sector)
+            {
+                continue;
+            }
+
+            for cluster_index in self.fat.list_chain(file_info.cluster_index) {
+                let sector_index = self.disk_layout.convert_cluster_to_sector(cluster_index);
+                for i in 0..self.disk_layout.sectors_per_cluster() {
+                    sectors.insert(sector_index + i, file_info.cluster_index);
+                }
+            }
+
+            // Skip `.` and `..`
+            if let Ok(entries) = self.read_dir(&file_info) {
+                pending.extend(entries.into_iter().skip(2));
+            }
+        }
+
+        sectors
+    }
+
+    /// Check if write would blank the root directory of a non empty disk.
+    fn is_format(&self, index: u16, data: &[u8]) -> bool {
+        let layout = &self.disk_layout;
+        let first_root = layout.count_fat_sectors();
+        let bytes_per_sector = layout.bytes_per_sector() as usize;
+        let count = (data.len() / bytes_per_sector) as u16;
+
+        if index > first_root || index + count <= first_root || self.is_root_blank() {
+            return false;
+        }
+
+        data.chunks(bytes_per_sector)
+            .zip(index..)
+            .take_while(|(_, sector_index)| *sector_index < layout.first_free_sector())
+            .all(|(sector, sector_index)| {
+                if sector_index % layout.count_1fat_sectors() == 0 && sector_index < first_root {
+                    // Formatter may set media descriptor in reserved entries
+                    sector[4..].iter().all(|b| *b == 0)
+                } else {
+                    sector.iter().all(|b| *b == 0)
+                }
+            })
+    }
+
+    fn is_root_blank(&self) -> bool {
+        self.root_entries
+            .iter()
+            .all(|entries| entries.as_raw().iter().all(|b| *b == 0))
+    }
+
+    /// Remove every file and directory from the disk.
+    ///
+    /// Data sectors are kept, as they are no longer referenced.
+    fn clear(&mut self) {
+        self.keep_overlay_base(0..self.disk_layout.first_free_sector());
+        self.fat = FileAllocationTable::new(self.disk_layout.fat_entries_count());
+        self.protected.clear();
+        for entries in &mut self.root_entries {
+            *entries = empty_table(&self.disk_layout);
+        }
+        self.dirty.extend(0..self.disk_layout.first_free_sector());
+        self.sync_metadata();
+    }
+
+    /// Keep Atari writes apart from current content, which becomes the base
+    /// layer.
+    pub fn set_overlay(&mut self) {
+        self.overlay_base = Some(BTreeMap::new());
+    }
+
+    /// Sectors of second FAT are the ones of first FAT.
+    fn overlay_sector(&self, index: u16) -> u16 {
+        let layout = &self.disk_layout;
+        if index < layout.count_fat_sectors() {
+            index % layout.count_1fat_sectors()
+        } else {
+            index
         }
+    }
+
+    /// Keep base content of sectors not written yet, in overlay mode.
+    fn keep_overlay_base(&mut self, sectors: Range<u16>) {
+        let Some(mut base) = self.overlay_base.take() else {
+            return;
+        };
+        for sector_index in sectors.map(|i| self.overlay_sector(i)) {
+            if base.contains_key(&sector_index) {
+                continue;
+            }
+            let mut content = Vec::new();
+            if self.read_sector(&mut content, sector_index).is_ok() {
+                base.insert(sector_index, content);
+            }
+        }
+        self.overlay_base = Some(base);
+    }
+
+    /// Sectors Atari has changed in overlay mode, with their content.
+    pub fn overlay_delta(&self) -> Vec<(u16, Vec<u8>)> {
+        let Some(base) = &self.overlay_base else {
+            return Vec::new();
+        };
+        base.iter()
+            .filter_map(|(&sector_index, base_content)| {
+                let mut content = Vec::new();
+                self.read_sector(&mut content, sector_index).ok()?;
+                (&content != base_content).then_some((sector_index, content))
+            })
+            .collect()
+    }
 
+    /// Give back base content of every sector Atari has written.
+    pub fn discard_overlay(&mut self) -> io::Result<()> {
+        let Some(base) = self.overlay_base.replace(BTreeMap::new()) else {
+            return Ok(());
+        };
+        for (sector_index, content) in base {
+            self.write_sector(&mut &content[..], sector_index)?;
+            self.dirty.insert(sector_index);
+        }
+        self.sync_metadata();
         Ok(())
     }
 
@@ -115,14 +1111,23 @@ impl DiskStorage {
         // Read buffer differently depending of sector location
         if index < self.disk_layout.count_fat_sectors() {
             log::debug!("Writing FAT: {:#04x}", index);
-            self.write_fat_sector(reader, index)
+            self.write_fat_sector(reader, index)?;
+
+            // Both FAT are read from the same table
+            let count_1fat_sectors = self.disk_layout.count_1fat_sectors();
+            self.dirty.insert(index % count_1fat_sectors);
+            self.dirty
+                .insert(count_1fat_sectors + index % count_1fat_sectors);
         } else if index < self.disk_layout.first_free_sector() {
             log::debug!("Writing root sector: {:#04x}", index);
-            self.write_root_sector(reader, index)
+            self.write_root_sector(reader, index)?;
+            self.dirty.insert(index);
         } else {
             log::debug!("Writing data: {:#04x}", index);
-            self.write_data_sector(reader, index)
+            self.write_data_sector(reader, index)?;
+            self.dirty.insert(index);
         }
+        Ok(())
     }
 
     fn read_fat_sector<W>(&self, writer: &mut W, sector_index: u16) -> io::Result<()>
@@ -147,6 +1152,12 @@ impl DiskStorage {
 
         let idx_end = idx_start + bytes_per_sector;
 
+        // Last sector is padded: table does not cover reserved clusters
+        if idx_end > buf.len() {
+            let mut sector = buf[idx_start.min(buf.len())..].to_vec();
+            sector.resize(bytes_per_sector, 0);
+            return writer.write_all(&sector);
+        }
         writer.write_all(&buf[idx_start..idx_end])
     }
 
@@ -169,7 +1180,10 @@ impl DiskStorage {
             sector_index as usize
         } * bytes_per_sector;
 
-        self.fat.merge_data(reader, idx_start, bytes_per_sector)
+        self.fat
+            .merge_data(reader, idx_start / mem::size_of::<u16>(), bytes_per_sector)?;
+        self.sync_metadata();
+        Ok(())
     }
 
     fn read_root_sector<W>(&self, writer: &mut W, sector_index: u16) -> io::Result<()>
@@ -196,148 +1210,1049 @@ impl DiskStorage {
             "Out of range sector"
         );
 
-        let count = table_size!(self.disk_layout);
-        let bloc = DirectoryContent::try_from_reader(reader, count)?;
-        let real_sector_index =
-            sector_index as usize - self.disk_layout.count_fat_sectors() as usize;
+        let count = table_size!(self.disk_layout);
+        let bloc = DirectoryContent::try_from_reader(reader, count)?;
+        let real_sector_index =
+            sector_index as usize - self.disk_layout.count_fat_sectors() as usize;
+
+        self.root_entries[real_sector_index] = bloc;
+        self.sync_metadata();
+        Ok(())
+    }
+
+    fn read_data_sector<W>(&self, writer: &mut W, sector_index: u16) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        match self.sector_data.sector(sector_index)? {
+            Some(data) => writer.write_all(&data),
+            None => {
+                let fill = match self.uninitialized_reads {
+                    UninitializedReadPolicy::Warn => {
+                        log::warn!("Reading uninitialized sector, fallback to empty data bloc");
+                        0x00
+                    }
+                    UninitializedReadPolicy::Zeros | UninitializedReadPolicy::Error => 0x00,
+                    UninitializedReadPolicy::FillE5 => 0xE5,
+                };
+                let data = vec![fill; self.disk_layout.bytes_per_sector() as usize];
+                writer.write_all(&data)
+            }
+        }
+    }
+
+    fn write_data_sector<R>(&mut self, reader: &mut R, sector_index: u16) -> io::Result<()>
+    where
+        R: io::Read,
+    {
+        let data = extract_cluster!(reader, self.disk_layout);
+        self.sector_data.set_sector(sector_index, data);
+
+        Ok(())
+    }
+
+    /// Import content of a host directory at disk root.
+    ///
+    /// Entries which cannot be imported are skipped and listed in returned
+    /// report.
+    pub fn import_path<P>(&mut self, path: P) -> error::Result<ImportReport>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let report = self.import_sub_path(path, ROOT_INDEX)?;
+        self.sync_metadata();
+        Ok(report)
+    }
+
+    pub fn import_sub_path<P>(&mut self, path: P, parent_index: u16) -> error::Result<ImportReport>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let root = SharedRoot::new(&path)?;
+        let free_clusters = self.free_cluster_count();
+        let mut report = ImportReport::default();
+        // New Atari files at root belong to the first source imported there
+        if parent_index == ROOT_INDEX && !self.origins.contains_key(&ROOT_INDEX) {
+            self.set_origin(ROOT_INDEX, root.path(), String::new());
+        }
+        // Directory may already hold content of other sources
+        self.import_shared_dir(&root, root.path(), parent_index, &mut report, true, false)?;
+        report.clusters = free_clusters - self.free_cluster_count();
+        Ok(report)
+    }
+
+    /// Import content of a host directory in `target` directory (`\`
+    /// separated path from root, created when missing), merged with what is
+    /// already there.
+    ///
+    /// Directories existing on both sides are merged, files already on disk
+    /// are kept and host ones are skipped.
+    ///
+    /// When `read_only`, files and directories added by the import (and
+    /// `target` if it is created) cannot be changed by Atari. Directories
+    /// merged with other sources stay writable.
+    pub fn import_source<P>(
+        &mut self,
+        path: P,
+        target: &str,
+        read_only: bool,
+    ) -> error::Result<ImportReport>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let mtime = Local::now().naive_local();
+        let target_exists = target.is_empty() || self.find_entry(target)?.is_some();
+        let known: HashSet<u16> = self.origins.keys().copied().collect();
+        let parent_index = self.find_or_add_virtual_path(target, mtime)?;
+        let report = self.import_sub_path(path, parent_index)?;
+
+        if read_only {
+            let mut added: Vec<u16> = self
+                .origins
+                .keys()
+                .filter(|cluster_index| !known.contains(cluster_index))
+                .copied()
+                .collect();
+            if !target_exists {
+                added.push(parent_index);
+            }
+            for cluster_index in added.into_iter().filter(|c| *c >= 2) {
+                let chain = self.fat.list_chain(cluster_index);
+                self.protected.extend(chain);
+            }
+        }
+
+        self.sync_metadata();
+        Ok(report)
+    }
+
+    /// Import content of `dir`, ignoring everything outside of `root`.
+    ///
+    /// When `merging`, entries may already exist in parent directory. When
+    /// `flattening`, `dir` is too deep and its files are renamed as needed
+    /// to fit in parent directory next to the ones already there.
+    fn import_shared_dir(
+        &mut self,
+        root: &SharedRoot,
+        dir: &Path,
+        parent_index: u16,
+        report: &mut ImportReport,
+        merging: bool,
+        flattening: bool,
+    ) -> error::Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(dir)?
+            // Filter invalid read dir result
+            .filter_map(|r| r.ok())
+            // Skip hidden files
+            .filter(|e| !e.file_name().to_str().unwrap().starts_with('.'))
+            // Filter missing file type entry
+            .filter_map(|e| {
+                if let Ok(ft) = e.file_type() {
+                    Some((ft, e.path()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // Host listing order is file system dependent, sort it so importing
+        // the same tree always builds the same disk
+        entries.sort_by(|(_, a), (_, b)| a.file_name().cmp(&b.file_name()));
+
+        let parent_path = self.directory_path(parent_index)?;
+        let too_deep = self.max_depth.is_some_and(|max_depth| {
+            parent_path.split('\\').filter(|c| !c.is_empty()).count() >= max_depth
+        });
+
+        let mut existing = HashMap::new();
+        if merging {
+            for entry in self.list_dir(parent_index)? {
+                if let Ok(name) = entry.filename() {
+                    existing.insert(NameKey::new(&name), entry);
+                }
+            }
+        }
+
+        for (file_type, path) in entries {
+            // Follow symlinks as long as they stay in shared directory
+            let file_type = if file_type.is_symlink() {
+                match self.resolve_symlink(root, dir, &path) {
+                    Ok(file_type) => file_type,
+                    Err(reason) => {
+                        report.skip(path, reason);
+                        continue;
+                    }
+                }
+            } else {
+                file_type
+            };
+
+            if !file_type.is_dir() && !file_type.is_file() {
+                report.skip(path, SkipReason::UnsupportedType);
+                continue;
+            }
+
+            if file_type.is_dir() && too_deep {
+                if let Err(e) =
+                    self.import_shared_dir(root, &path, parent_index, report, false, true)
+                {
+                    report.skip_error(path, &e);
+                }
+                continue;
+            }
+
+            let components = if file_type.is_dir() {
+                dos::as_valid_file_components(&path)
+            } else {
+                self.file_components(&path)
+            };
+            let name = match components
+                .and_then(|(name, ext)| self.checked_name(&name, &ext, parent_index))
+                .and_then(|name| {
+                    if flattening {
+                        self.free_name(name, parent_index)
+                    } else {
+                        Ok(name)
+                    }
+                }) {
+                Ok(name) => name,
+                Err(e) => {
+                    report.skip_error(path, &e);
+                    continue;
+                }
+            };
+
+            let disk_name = FileInfo::from_static_dir_info(&name.0, &name.1, 0).filename()?;
+            if let Some(entry) = existing.get(&NameKey::new(&disk_name)) {
+                if file_type.is_dir() && entry.is_dir() {
+                    let cluster_index = entry.cluster_index;
+                    if let Err(e) =
+                        self.import_shared_dir(root, &path, cluster_index, report, true, false)
+                    {
+                        report.skip_error(path, &e);
+                    }
+                } else {
+                    report.skip(path, SkipReason::AlreadyExists);
+                }
+                continue;
+            }
+
+            if file_type.is_dir() {
+                match self.add_named_directory(root, &path, name, parent_index, report) {
+                    Ok(()) => report.directories += 1,
+                    Err(e) => report.skip_error(path, &e),
+                }
+            } else {
+                match self.add_named_file(&path, name, parent_index) {
+                    Ok(size) => {
+                        report.files += 1;
+                        report.bytes += size;
+                        if flattening {
+                            let disk_path = if parent_path.is_empty() {
+                                disk_name
+                            } else {
+                                format!("{}\\{}", parent_path, disk_name)
+                            };
+                            report.flatten(path, disk_path);
+                        }
+                    }
+                    Err(e) => report.skip_error(path, &e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get type of symlink target, refusing links to a parent directory.
+    fn resolve_symlink(
+        &self,
+        root: &SharedRoot,
+        dir: &Path,
+        path: &Path,
+    ) -> Result<fs::FileType, SkipReason> {
+        let target = root.resolve(path).map_err(|e| SkipReason::from(&e))?;
+        let file_type = fs::metadata(&target)
+            .map_err(|e| SkipReason::Error(e.to_string()))?
+            .file_type();
+
+        if file_type.is_dir()
+            && root
+                .resolve(dir)
+                .is_ok_and(|resolved| resolved.starts_with(&target))
+        {
+            return Err(SkipReason::SymlinkLoop);
+        }
+        Ok(file_type)
+    }
+
+    /// Import a host directory, and its content, in a sub directory.
+    pub fn add_directory<P>(
+        &mut self,
+        path: P,
+        parent_cluster_index: u16,
+    ) -> error::Result<ImportReport>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let root = SharedRoot::new(&path)?;
+        let free_clusters = self.free_cluster_count();
+        let mut report = ImportReport::default();
+        self.add_shared_directory(&root, path.as_ref(), parent_cluster_index, &mut report)?;
+        report.directories += 1;
+        report.clusters = free_clusters - self.free_cluster_count();
+        Ok(report)
+    }
+
+    fn add_shared_directory(
+        &mut self,
+        root: &SharedRoot,
+        path: &Path,
+        parent_cluster_index: u16,
+        report: &mut ImportReport,
+    ) -> error::Result<()> {
+        log::debug!(
+            "Adding directory: {:?} (parent {:#04x})",
+            path,
+            parent_cluster_index
+        );
+
+        let (name, ext) = dos::as_valid_file_components(path)?;
+        let name = self.checked_name(&name, &ext, parent_cluster_index)?;
+        self.add_named_directory(root, path, name, parent_cluster_index, report)
+    }
+
+    /// Import a host directory under its checked disk name.
+    fn add_named_directory(
+        &mut self,
+        root: &SharedRoot,
+        path: &Path,
+        (name, ext): (String, String),
+        parent_cluster_index: u16,
+        report: &mut ImportReport,
+    ) -> error::Result<()> {
+        // Create new entry in FAT
+        let entry_cluster_index = self.create_directory(parent_cluster_index)?;
+
+        // Add entry for this folder
+        let metadata = EntryMetadata::from_host(path)?;
+        let file_info = FileInfo::from_metadata(&name, &ext, &metadata, entry_cluster_index);
+        self.set_origin(entry_cluster_index, path, file_info.filename()?);
+        self.add_storage_entry(file_info, parent_cluster_index)?;
+
+        // Import folder content
+        self.import_shared_dir(root, path, entry_cluster_index, report, false, false)?;
+
+        Ok(())
+    }
+
+    /// Add a directory which does not exist on host FS and return its cluster index.
+    pub fn add_virtual_directory(
+        &mut self,
+        filename: &str,
+        extension: &str,
+        mtime: NaiveDateTime,
+        parent_cluster_index: u16,
+    ) -> error::Result<u16> {
+        log::debug!(
+            "Adding virtual directory: {}.{} (parent {:#04x})",
+            filename,
+            extension,
+            parent_cluster_index
+        );
+
+        let (filename, extension) = self.checked_name(filename, extension, parent_cluster_index)?;
+        let entry_cluster_index = self.create_directory(parent_cluster_index)?;
+
+        let metadata = EntryMetadata {
+            is_dir: true,
+            modified: mtime,
+            size: 0,
+        };
+        let file_info =
+            FileInfo::from_metadata(&filename, &extension, &metadata, entry_cluster_index);
+        self.add_storage_entry(file_info, parent_cluster_index)?;
+        self.sync_metadata();
+
+        Ok(entry_cluster_index)
+    }
+
+    /// Get cluster index of a directory, creating it if it does not exist yet.
+    ///
+    /// Names are compared the way TOS does, ignoring case.
+    pub fn find_or_add_virtual_directory(
+        &mut self,
+        filename: &str,
+        extension: &str,
+        mtime: NaiveDateTime,
+        parent_cluster_index: u16,
+    ) -> error::Result<u16> {
+        let expected =
+            NameKey::new(&FileInfo::from_static_dir_info(filename, extension, 0).filename()?);
+
+        let siblings = self.list_dir(parent_cluster_index)?;
+        for sibling in siblings.iter().filter(|f| f.is_dir()) {
+            if NameKey::new(&sibling.filename()?) == expected {
+                return Ok(sibling.cluster_index);
+            }
+        }
+
+        self.add_virtual_directory(filename, extension, mtime, parent_cluster_index)
+    }
+
+    /// Entries in use of directory starting at `cluster_index`.
+    fn list_dir(&self, cluster_index: u16) -> error::Result<Vec<FileInfo>> {
+        let entries = if cluster_index == ROOT_INDEX {
+            self.list_root_file_infos()
+        } else {
+            self.read_dir(&FileInfo::from_static_dir_info("", "", cluster_index))?
+        };
+        Ok(entries
+            .into_iter()
+            .filter(|e| !e.is_deleted() && !e.is_unused())
+            .collect())
+    }
+
+    /// Get cluster index of a `\` separated directory path, creating missing
+    /// directories.
+    pub fn find_or_add_virtual_path(
+        &mut self,
+        disk_path: &str,
+        mtime: NaiveDateTime,
+    ) -> error::Result<u16> {
+        let mut index = ROOT_INDEX;
+        for component in disk_path.split('\\').filter(|c| !c.is_empty()) {
+            let (name, ext) = dos::as_valid_file_components(component)?;
+            index = self.find_or_add_virtual_directory(&name, &ext, mtime, index)?;
+        }
+        Ok(index)
+    }
+
+    /// Reserve a cluster for a new directory and add `.` and `..` in it.
+    fn create_directory(&mut self, parent_cluster_index: u16) -> error::Result<u16> {
+        let entry_cluster_index = self.reserve_cluster()?;
+
+        self.add_storage_entry(
+            FileInfo::from_static_dir_info(".", "", entry_cluster_index),
+            entry_cluster_index,
+        )?;
+        self.add_storage_entry(
+            FileInfo::from_static_dir_info("..", "", parent_cluster_index),
+            entry_cluster_index,
+        )?;
+
+        Ok(entry_cluster_index)
+    }
+
+    /// Import a host file and return its size.
+    pub fn add_file<P>(&mut self, path: P, parent_index: u16) -> error::Result<usize>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        log::debug!("Adding file: {:?} (parent: {:#04x})", path, parent_index);
+
+        let (name, ext) = self.file_components(&path)?;
+        let name = self.checked_name(&name, &ext, parent_index)?;
+        self.add_named_file(path.as_ref(), name, parent_index)
+    }
+
+    /// Import a host file under its checked disk name and return its size.
+    fn add_named_file(
+        &mut self,
+        path: &Path,
+        (name, ext): (String, String),
+        parent_index: u16,
+    ) -> error::Result<usize> {
+        // Store content of the file in blocks
+        let content = fs::read(path)?;
+        if let Some(part_size) = self.split_size.filter(|size| content.len() > *size) {
+            self.add_split_file(path, &name, &content, part_size, parent_index)?;
+            return Ok(content.len());
+        }
+        let (name, ext) = self.executable_components(name, ext, &content);
+        let first_cluster_block_index = self
+            .check_quotas(parent_index, content.len())
+            .and_then(|_| self.store_content(&content))
+            .map_err(|e| self.entry_error(e, parent_index, (&name, &ext)))?;
+
+        // Add to entry table
+        let metadata = EntryMetadata::from_host(path)?;
+        let file_info = FileInfo::from_metadata(&name, &ext, &metadata, first_cluster_block_index);
+        self.set_origin(first_cluster_block_index, path, file_info.filename()?);
+        self.add_storage_entry(file_info, parent_index)
+            .map_err(|e| self.entry_error(e, parent_index, (&name, &ext)))?;
+
+        Ok(content.len())
+    }
+
+    /// Import a host file as numbered parts and their manifest.
+    ///
+    /// Parts are not linked to the host file, so they are seen as created
+    /// by Atari when mapping disk back to host.
+    fn add_split_file(
+        &mut self,
+        path: &Path,
+        stem: &str,
+        content: &[u8],
+        part_size: usize,
+        parent_index: u16,
+    ) -> error::Result<()> {
+        let host_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let manifest = SplitManifest::new(&host_name, stem, content.len(), part_size)?;
+        let mtime = EntryMetadata::from_host(path)?.modified;
+        log::info!("Splitting {:?} in {} parts", path, manifest.parts.len());
+
+        for (part, chunk) in manifest.parts.iter().zip(content.chunks(part_size)) {
+            let (_, ext) = part.rsplit_once('.').unwrap_or_default();
+            self.add_virtual_file(stem, ext, mtime, chunk, parent_index)?;
+        }
+        let text = manifest.to_text();
+        self.add_virtual_file(
+            stem,
+            split::MANIFEST_EXT,
+            mtime,
+            text.as_bytes(),
+            parent_index,
+        )
+    }
+
+    /// Add a file which does not exist on host FS.
+    pub fn add_virtual_file(
+        &mut self,
+        filename: &str,
+        extension: &str,
+        mtime: NaiveDateTime,
+        content: &[u8],
+        parent_index: u16,
+    ) -> error::Result<()> {
+        log::debug!(
+            "Adding virtual file: {}.{} (parent: {:#04x})",
+            filename,
+            extension,
+            parent_index
+        );
+
+        let (filename, extension) = self.checked_name(filename, extension, parent_index)?;
+        let first_cluster_block_index = self
+            .check_quotas(parent_index, content.len())
+            .and_then(|_| self.store_content(content))
+            .map_err(|e| self.entry_error(e, parent_index, (&filename, &extension)))?;
+
+        self.add_storage_entry(
+            FileInfo::from_static_file_info(
+                &filename,
+                &extension,
+                mtime,
+                first_cluster_block_index,
+                content.len() as u32,
+            ),
+            parent_index,
+        )
+        .map_err(|e| self.entry_error(e, parent_index, (&filename, &extension)))?;
+        self.sync_metadata();
+        Ok(())
+    }
+
+    /// Replace content of a file changed from host, adding it if it does not
+    /// exist yet.
+    pub fn put_virtual_file(
+        &mut self,
+        filename: &str,
+        extension: &str,
+        mtime: NaiveDateTime,
+        content: &[u8],
+        parent_index: u16,
+    ) -> error::Result<()> {
+        self.host_changes = self.host_changes.wrapping_add(1);
+        self.replace_virtual_file(filename, extension, mtime, content, parent_index)
+    }
+
+    /// Replace content of a file, adding it if it does not exist yet.
+    ///
+    /// Entry is rewritten in place, so repeated updates do not fill the
+    /// directory with deleted entries. Clusters of the file are reused when
+    /// new content fits, so the FAT Atari has cached stays valid.
+    fn replace_virtual_file(
+        &mut self,
+        filename: &str,
+        extension: &str,
+        mtime: NaiveDateTime,
+        content: &[u8],
+        parent_index: u16,
+    ) -> error::Result<()> {
+        let name = FileInfo::from_static_file_info(filename, extension, mtime, 0, 0).filename()?;
+        let Some(existing) = self.find_file(parent_index, &name)? else {
+            return self.add_virtual_file(filename, extension, mtime, content, parent_index);
+        };
+
+        log::debug!(
+            "Updating virtual file: {} (parent: {:#04x})",
+            name,
+            parent_index
+        );
+
+        if self.overwrite_chain(existing.cluster_index, content) {
+            self.change_entry(parent_index, &name, |entry| {
+                entry.set_size(content.len() as u32);
+                entry.set_modified(mtime);
+            })?;
+            self.sync_metadata();
+            return Ok(());
+        }
+
+        // New content is stored first, a full disk keeps the old one
+        self.check_quotas(parent_index, content.len())?;
+        let first_cluster_block_index = self.store_content(content)?;
+        self.change_entry(parent_index, &name, |entry| {
+            entry.cluster_index = first_cluster_block_index;
+            entry.set_size(content.len() as u32);
+            entry.set_modified(mtime);
+        })?;
+        self.free_chain(existing.cluster_index);
+        self.sync_metadata();
+        Ok(())
+    }
+
+    /// Delete file `filename` of directory `parent_index`, and return if it
+    /// existed.
+    pub fn remove_file(&mut self, filename: &str, parent_index: u16) -> error::Result<bool> {
+        let Some(removed) = self.change_entry(parent_index, filename, FileInfo::mark_deleted)?
+        else {
+            return Ok(false);
+        };
+
+        log::debug!(
+            "Removing file: {} (parent: {:#04x})",
+            filename,
+            parent_index
+        );
+        self.host_changes = self.host_changes.wrapping_add(1);
+        self.free_chain(removed.cluster_index);
+        self.sync_metadata();
+        Ok(true)
+    }
+
+    /// Write `content` over the chain starting at `cluster_index` if it is
+    /// long enough, and free clusters left unused.
+    fn overwrite_chain(&mut self, cluster_index: u16, content: &[u8]) -> bool {
+        if cluster_index < 2 {
+            return false;
+        }
+
+        let bytes_per_sector = self.disk_layout.bytes_per_sector() as usize;
+        let sectors_per_cluster = self.disk_layout.sectors_per_cluster();
+        let needed = content
+            .len()
+            .div_ceil(self.disk_layout.bytes_per_cluster() as usize)
+            .max(1);
+        let chain = self.fat.list_chain(cluster_index);
+        if chain.len() < needed {
+            return false;
+        }
+
+        let sectors = chain[..needed].iter().flat_map(|&cluster_index| {
+            let first_sector = self.disk_layout.convert_cluster_to_sector(cluster_index);
+            first_sector..first_sector + sectors_per_cluster
+        });
+        let sectors: Vec<u16> = sectors.collect();
+        for (&sector_index, chunk) in sectors.iter().zip(content.chunks(bytes_per_sector)) {
+            let mut chunk_stored = chunk.to_vec();
+            chunk_stored.resize(bytes_per_sector, 0);
+            self.sector_data.set_sector(sector_index, chunk_stored);
+            self.dirty.insert(sector_index);
+        }
+
+        for freed in self.fat.truncate_chain(cluster_index, needed) {
+            self.protected.remove(&freed);
+            self.mark_fat_dirty(freed);
+        }
+        if needed < chain.len() {
+            self.mark_fat_dirty(chain[needed - 1]);
+        }
+        true
+    }
+
+    /// Serve files generated by host commands, running each one now.
+    pub fn set_generated_files(&mut self, files: Vec<GeneratedFile>) -> error::Result<()> {
+        let now = Instant::now();
+        let mtime = Local::now().naive_local();
+
+        for mut file in files {
+            let (dir_path, _) = file.split_path();
+            let parent_index = self.find_or_add_virtual_path(dir_path, mtime)?;
+            self.generate(parent_index, &mut file, now)?;
+            self.generated.push((parent_index, file));
+        }
+        Ok(())
+    }
+
+    /// Generate again files whose directory is read from sectors
+    /// `index..index + count`.
+    pub fn refresh_generated(&mut self, index: u16, count: u16) {
+        let now = Instant::now();
+        let sectors = index..index.saturating_add(count);
+
+        let mut generated = mem::take(&mut self.generated);
+        for (parent_index, file) in &mut generated {
+            if !file.is_due(now)
+                || !self
+                    .entry_sectors(*parent_index)
+                    .iter()
+                    .any(|sector_index| sectors.contains(sector_index))
+            {
+                continue;
+            }
+            if let Err(e) = self.generate(*parent_index, file, now) {
+                log::warn!("Cannot generate {} (error: {})", file.disk_path, e);
+            }
+        }
+        self.generated = generated;
+    }
+
+    fn generate(
+        &mut self,
+        parent_index: u16,
+        file: &mut GeneratedFile,
+        now: Instant,
+    ) -> error::Result<()> {
+        let Some(content) = file.generate(now) else {
+            return Ok(());
+        };
+        let (_, filename) = file.split_path();
+        let (name, ext) = dos::as_valid_file_components(filename)?;
+        let mtime = Local::now().naive_local();
+        self.replace_virtual_file(&name, &ext, mtime, &content, parent_index)
+    }
+
+    /// Number of files changed with `put_virtual_file` or `remove_file`, so
+    /// Atari can notice it has to read the disk again.
+    pub fn host_changes(&self) -> u32 {
+        self.host_changes
+    }
+
+    /// Move files of `disk_paths` (`\` separated) to clusters following each
+    /// other, in this order, and return how many have been moved.
+    ///
+    /// Missing files are skipped. Nothing moves when the disk has no free run
+    /// long enough for all of them.
+    pub fn pack_files(&mut self, disk_paths: &[String]) -> error::Result<usize> {
+        let mut files: Vec<(u16, String, FileInfo)> = Vec::new();
+        for disk_path in disk_paths {
+            let (dir_path, filename) = disk_path.rsplit_once('\\').unwrap_or(("", disk_path));
+            let dir = match self.find_entry(dir_path)? {
+                Some(entry) if entry.is_dir() => entry.cluster_index,
+                Some(_) => continue,
+                None if dir_path.is_empty() => ROOT_INDEX,
+                None => continue,
+            };
+            match self.find_file(dir, filename)? {
+                Some(entry)
+                    if entry.cluster_index >= 2
+                        && files
+                            .iter()
+                            .all(|(.., f)| f.cluster_index != entry.cluster_index) =>
+                {
+                    files.push((dir, filename.to_string(), entry));
+                }
+                _ => continue,
+            }
+        }
+
+        let mut contents = Vec::with_capacity(files.len());
+        for (.., entry) in &files {
+            contents.push(self.read_file(entry)?);
+        }
+        let cluster_len = self.disk_layout.bytes_per_cluster() as usize;
+        let lens: Vec<usize> = contents
+            .iter()
+            .map(|content| content.len().div_ceil(cluster_len).max(1))
+            .collect();
+
+        let previous_fat = self.fat.clone();
+        for (.., entry) in &files {
+            self.fat.free_chain(entry.cluster_index);
+        }
+        let Some(firsts) = self.fat.reserve_runs(&lens) else {
+            log::warn!(
+                "No free run of {} clusters, files not packed",
+                lens.iter().sum::<usize>()
+            );
+            self.fat = previous_fat;
+            return Ok(0);
+        };
+
+        // Origins move with files, taken first as new clusters may be old
+        // ones of other files
+        let origins: Vec<_> = files
+            .iter()
+            .map(|(.., entry)| self.origins.remove(&entry.cluster_index))
+            .collect();
+        for (((dir, filename, _), content), (first, origin)) in files
+            .iter()
+            .zip(&contents)
+            .zip(firsts.into_iter().zip(origins))
+        {
+            self.overwrite_chain(first, content);
+            self.change_entry(*dir, filename, |entry| entry.cluster_index = first)?;
+            if let Some(origin) = origin {
+                self.origins.insert(first, origin);
+            }
+        }
 
-        self.root_entries[real_sector_index] = bloc;
-        Ok(())
+        self.mark_fat_sectors_dirty();
+        self.sync_metadata();
+        Ok(files.len())
     }
 
-    fn read_data_sector<W>(&self, writer: &mut W, sector_index: u16) -> io::Result<()>
-    where
-        W: io::Write,
-    {
-        match self.sector_data.get(&sector_index) {
-            Some(DiskBloc::Data(data)) => writer.write_all(data),
-            Some(DiskBloc::Entries(entries)) => writer.write_all(entries.as_raw()),
-            None => {
-                log::warn!("Reading uninitialized sector, fallback to empty data bloc");
-                let data = vec![0; self.disk_layout.bytes_per_sector() as usize];
-                writer.write_all(&data)
+    /// File named `filename` in directory `parent_index`, comparing names by
+    /// `NameKey`.
+    fn find_file(&self, parent_index: u16, filename: &str) -> error::Result<Option<FileInfo>> {
+        for sector_index in self.entry_sectors(parent_index) {
+            if let Some(entry) = self.entry_table(sector_index)?.find_file_mut(filename) {
+                return Ok(Some(entry.clone()));
             }
         }
+        Ok(None)
     }
 
-    fn write_data_sector<R>(&mut self, reader: &mut R, sector_index: u16) -> io::Result<()>
+    /// Apply `change` to file `filename` of directory `dir`, and return the
+    /// entry as it was.
+    fn change_entry<F>(
+        &mut self,
+        dir: u16,
+        filename: &str,
+        change: F,
+    ) -> error::Result<Option<FileInfo>>
     where
-        R: io::Read,
+        F: FnOnce(&mut FileInfo),
     {
-        let data = extract_cluster!(reader, self.disk_layout);
-        self.sector_data.insert(sector_index, DiskBloc::Data(data));
+        for sector_index in self.entry_sectors(dir) {
+            let mut table = self.entry_table(sector_index)?;
+            let Some(entry) = table.find_file_mut(filename) else {
+                continue;
+            };
+
+            let old = entry.clone();
+            change(entry);
+            let new = entry.clone();
+
+            if dir == ROOT_INDEX {
+                let root_index = sector_index - self.disk_layout.count_fat_sectors();
+                self.root_entries[root_index as usize] = table;
+            } else {
+                self.sector_data
+                    .set_sector(sector_index, table.as_raw().to_vec());
+            }
+            self.dirty.insert(sector_index);
+            self.emit_entry_change(dir, Some(&old), &new);
+            return Ok(Some(old));
+        }
+        Ok(None)
+    }
 
-        Ok(())
+    /// Sectors holding entries of directory starting at `dir` cluster.
+    fn entry_sectors(&self, dir: u16) -> Vec<u16> {
+        if dir == ROOT_INDEX {
+            let first_sector = self.disk_layout.count_fat_sectors();
+            return (first_sector..first_sector + self.disk_layout.root_directory_sectors())
+                .collect();
+        }
+
+        let sectors_per_cluster = self.disk_layout.sectors_per_cluster();
+        self.fat
+            .chain(dir)
+            .flat_map(|cluster_index| {
+                let first_sector = self.disk_layout.convert_cluster_to_sector(cluster_index);
+                first_sector..first_sector + sectors_per_cluster
+            })
+            .collect()
     }
 
-    pub fn import_path<P>(&mut self, path: P) -> error::Result<()>
-    where
-        P: AsRef<Path> + Debug,
-    {
-        self.import_sub_path(path, ROOT_INDEX)
+    fn entry_table(&self, sector_index: u16) -> error::Result<DirectoryContent> {
+        let mut data = Vec::with_capacity(self.disk_layout.bytes_per_sector() as usize);
+        self.read_sector(&mut data, sector_index)?;
+        DirectoryContent::try_from_reader(&mut data.as_slice(), table_size!(self.disk_layout))
+            .map_err(|e| SerialDiskError::from(e).at_sector(sector_index))
     }
 
-    pub fn import_sub_path<P>(&mut self, path: P, parent_index: u16) -> error::Result<()>
-    where
-        P: AsRef<Path> + Debug,
-    {
-        for (file_type, path) in fs::read_dir(path)?
-            .into_iter()
-            // Filter invalid read dir result
-            .filter_map(|r| r.ok())
-            // Skip hidden files
-            .filter(|e| !e.file_name().to_str().unwrap().starts_with('.'))
-            // Filter missing file type entry
-            .filter_map(|e| {
-                if let Ok(ft) = e.file_type() {
-                    Some((ft, e.path()))
-                } else {
-                    None
-                }
-            })
-        {
-            if file_type.is_dir() {
-                if let Err(e) = self.add_directory(&path, parent_index) {
-                    log::warn!("Cannot add {:?} (error: {})", path, e);
-                }
-            } else if file_type.is_file() {
-                if let Err(e) = self.add_file(&path, parent_index) {
-                    log::warn!("Cannot add {:?} (error: {})", path, e);
-                }
+    fn free_chain(&mut self, cluster_index: u16) {
+        if cluster_index < 2 {
+            return;
+        }
+        for freed in self.fat.free_chain(cluster_index) {
+            self.protected.remove(&freed);
+            self.mark_fat_dirty(freed);
+        }
+        self.origins.remove(&cluster_index);
+    }
+
+    /// Name not used yet in a directory, ending with `~N` when needed.
+    pub(crate) fn free_name(
+        &self,
+        (name, ext): (String, String),
+        parent_index: u16,
+    ) -> error::Result<(String, String)> {
+        let used: HashSet<NameKey> = self
+            .list_dir(parent_index)?
+            .iter()
+            .filter_map(|entry| entry.filename().ok())
+            .map(|name| NameKey::new(&name))
+            .collect();
+        let is_free = |name: &str| {
+            let full_name = if ext.is_empty() {
+                name.to_string()
             } else {
-                log::warn!("Skipping: {:?} (unhandled file type)", path);
-            }
+                format!("{}.{}", name, ext)
+            };
+            !used.contains(&NameKey::new(&full_name))
+        };
+        if is_free(&name) {
+            return Ok((name, ext));
         }
 
-        Ok(())
+        let name = (1..)
+            .map(|n| {
+                let suffix = format!("~{}", n);
+                let stem: String = name.chars().take(8 - suffix.len()).collect();
+                stem + &suffix
+            })
+            .find(|name| is_free(name))
+            .expect("Some name is free");
+        Ok((name, ext))
     }
 
-    pub fn add_directory<P>(&mut self, path: P, parent_cluster_index: u16) -> error::Result<()>
-    where
-        P: AsRef<Path> + Debug,
-    {
-        log::debug!(
-            "Adding directory: {:?} (parent {:#04x})",
-            path,
-            parent_cluster_index
-        );
-
-        // Create new entry in FAT
-        let entry_cluster_index = self
-            .fat
-            .reserve_cluster()
-            .ok_or(SerialDiskError::DiskFull)?;
+    /// Check name against TOS constraints and return the one to use.
+    fn checked_name(
+        &self,
+        filename: &str,
+        extension: &str,
+        parent_index: u16,
+    ) -> error::Result<(String, String)> {
+        let parent_path = self.directory_path(parent_index)?;
+        let issues = tos::check_name(&parent_path, filename, extension);
+        if issues.is_empty() {
+            return Ok((filename.to_string(), extension.to_string()));
+        }
 
-        // Add entry for this folder
-        self.add_storage_entry(
-            FileInfo::try_from_path_and_index(&path, entry_cluster_index)?,
-            parent_cluster_index,
-        )?;
+        let full_name = if extension.is_empty() {
+            format!("{}\\{}", parent_path, filename)
+        } else {
+            format!("{}\\{}.{}", parent_path, filename, extension)
+        };
+        let reasons: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+
+        match self.name_policy {
+            NamePolicy::Warn => {
+                log::warn!(
+                    "{} may not be usable by TOS: {}",
+                    full_name,
+                    reasons.join(", ")
+                );
+                Ok((filename.to_string(), extension.to_string()))
+            }
+            NamePolicy::Rename => {
+                let (new_name, new_ext) = tos::sanitize_name(filename, extension);
+                if !tos::check_name(&parent_path, &new_name, &new_ext).is_empty() {
+                    return Err(SerialDiskError::RejectedName(format!(
+                        "{}: {}",
+                        full_name,
+                        reasons.join(", ")
+                    )));
+                }
 
-        // Add . and .. in new folder
-        self.add_storage_entry(
-            FileInfo::from_static_dir_info(".", "", entry_cluster_index),
-            entry_cluster_index,
-        )?;
-        self.add_storage_entry(
-            FileInfo::from_static_dir_info("..", "", parent_cluster_index),
-            entry_cluster_index,
-        )?;
+                log::warn!("Renaming {} to {}.{}", full_name, new_name, new_ext);
+                Ok((new_name, new_ext))
+            }
+            NamePolicy::Skip => Err(SerialDiskError::RejectedName(format!(
+                "{}: {}",
+                full_name,
+                reasons.join(", ")
+            ))),
+        }
+    }
 
-        // Import folder content
-        self.import_sub_path(path, entry_cluster_index)?;
+    /// Find the entry at a `\` separated path from disk root, comparing
+    /// names by `NameKey`.
+    pub fn find_entry(&self, disk_path: &str) -> error::Result<Option<FileInfo>> {
+        let mut entries = self.list_root_file_infos();
+        let mut components = disk_path
+            .split('\\')
+            .filter(|c| !c.is_empty())
+            .map(NameKey::new)
+            .peekable();
+
+        while let Some(component) = components.next() {
+            let mut found = None;
+            for entry in entries {
+                if entry.is_deleted() || entry.is_unused() {
+                    continue;
+                }
+                if NameKey::new(&entry.filename()?) == component {
+                    found = Some(entry);
+                    break;
+                }
+            }
 
-        Ok(())
+            match found {
+                Some(entry) if components.peek().is_none() => return Ok(Some(entry)),
+                Some(entry) if entry.is_dir() => entries = self.read_dir(&entry)?,
+                _ => return Ok(None),
+            }
+        }
+        Ok(None)
     }
 
-    pub fn add_file<P>(&mut self, path: P, parent_index: u16) -> error::Result<()>
-    where
-        P: AsRef<Path> + Debug,
-    {
-        log::debug!("Adding file: {:?} (parent: {:#04x})", path, parent_index);
+    /// Build `\` separated path of a directory from disk root.
+    pub fn directory_path(&self, cluster_index: u16) -> error::Result<String> {
+        let mut components = Vec::new();
+        let mut current = cluster_index;
+
+        while current != ROOT_INDEX {
+            let entries = self.read_dir(&FileInfo::from_static_dir_info("", "", current))?;
+            let parent = entries
+                .iter()
+                .find(|f| f.is_dir() && f.filename().map(|n| n == "..").unwrap_or(false))
+                .map(|f| f.cluster_index)
+                .ok_or(SerialDiskError::InvalidAttr)?;
+
+            let siblings = if parent == ROOT_INDEX {
+                self.list_root_file_infos()
+            } else {
+                self.read_dir(&FileInfo::from_static_dir_info("", "", parent))?
+            };
+            let name = siblings
+                .iter()
+                .filter(|f| f.is_dir() && f.cluster_index == current)
+                .filter_map(|f| f.filename().ok())
+                .find(|n| n != "." && n != "..")
+                .ok_or(SerialDiskError::InvalidAttr)?;
+
+            components.push(name);
+            if components.len() > self.fat.as_raw().len() {
+                // Corrupted directory tree
+                return Err(SerialDiskError::InvalidAttr);
+            }
+            current = parent;
+        }
+
+        components.reverse();
+        Ok(components.join("\\"))
+    }
 
+    /// Store content in a new cluster chain and return its first cluster.
+    fn store_content(&mut self, content: &[u8]) -> error::Result<u16> {
         // Create some alias
         let bytes_per_sector = self.disk_layout.bytes_per_sector() as usize;
         let sectors_per_cluster = self.disk_layout.sectors_per_cluster() as usize;
 
         // Create first block for data
-        let first_cluster_block_index = self
-            .fat
-            .reserve_cluster()
-            .ok_or(SerialDiskError::DiskFull)?;
+        let first_cluster_block_index = self.reserve_cluster()?;
 
         let mut current_cluster_block_index = first_cluster_block_index;
 
-        // Store content of the file in blocks
-        let content = fs::read(&path)?;
-
         for (index, chunk) in content.chunks(bytes_per_sector).enumerate() {
             // Check if we have to extend block chain
             if index > 0 && index % sectors_per_cluster == 0 {
-                current_cluster_block_index = self
-                    .fat
-                    .extend_cluster(current_cluster_block_index)
-                    .ok_or(SerialDiskError::DiskFull)?;
+                current_cluster_block_index = self.extend_cluster(current_cluster_block_index)?;
             }
 
             // Compute sector index
@@ -350,30 +2265,60 @@ impl DiskStorage {
             let mut chunk_stored = chunk.to_vec();
             chunk_stored.resize(bytes_per_sector, 0);
             self.sector_data
-                .insert(current_sector_index, DiskBloc::Data(chunk_stored));
+                .set_sector(current_sector_index, chunk_stored);
+            self.dirty.insert(current_sector_index);
         }
 
-        // Add to entry table
-        self.add_storage_entry(
-            FileInfo::try_from_path_and_index(&path, first_cluster_block_index)?,
-            parent_index,
-        )?;
+        Ok(first_cluster_block_index)
+    }
 
-        Ok(())
+    fn set_origin(&mut self, cluster_index: u16, host_path: &Path, disk_name: String) {
+        self.origins.insert(
+            cluster_index,
+            Origin {
+                host_path: host_path.to_path_buf(),
+                disk_name,
+            },
+        );
+    }
+
+    fn reserve_cluster(&mut self) -> error::Result<u16> {
+        let cluster_index = self
+            .fat
+            .reserve_cluster()
+            .ok_or(SerialDiskError::DiskFull)?;
+        self.mark_fat_dirty(cluster_index);
+        Ok(cluster_index)
+    }
+
+    fn extend_cluster(&mut self, existing_index: u16) -> error::Result<u16> {
+        let cluster_index = self
+            .fat
+            .extend_cluster(existing_index)
+            .ok_or(SerialDiskError::DiskFull)?;
+        self.mark_fat_dirty(existing_index);
+        self.mark_fat_dirty(cluster_index);
+        Ok(cluster_index)
     }
 
     fn add_storage_entry(&mut self, entry: FileInfo, cluster_index: u16) -> error::Result<()> {
-        if cluster_index == ROOT_INDEX {
-            for i in 0..self.disk_layout.root_directory_sectors() as usize {
-                if self.root_entries[i].push(entry.clone()).is_ok() {
-                    return Ok(());
-                }
-            }
+        let added = self.events.has_subscribers().then(|| entry.clone());
 
-            Err(SerialDiskError::FolderFull)
+        if cluster_index == ROOT_INDEX {
+            let pushed = (0..self.disk_layout.root_directory_sectors() as usize)
+                .find(|i| self.root_entries[*i].push(entry.clone()).is_ok())
+                .ok_or(SerialDiskError::FolderFull.in_folder(ROOT_INDEX))?;
+            self.dirty
+                .insert(self.disk_layout.count_fat_sectors() + pushed as u16);
         } else {
             self.add_storage_sub_entry(entry, cluster_index)
+                .map_err(|e| e.in_folder(cluster_index))?;
+        }
+
+        if let Some(entry) = added {
+            self.emit_entry_change(cluster_index, None, &entry);
         }
+        Ok(())
     }
 
     fn add_storage_sub_entry(&mut self, entry: FileInfo, cluster_index: u16) -> error::Result<()> {
@@ -392,11 +2337,11 @@ impl DiskStorage {
         }
 
         // Still folder full ...
-        // So we have no choice that getting a new cluster for this !
-        let next_cluster = self
-            .fat
-            .extend_cluster(cluster_index)
-            .ok_or(SerialDiskError::DiskFull)?;
+        // So continue in next cluster, or get a new one if this is the last
+        let next_cluster = match self.fat.chain(cluster_index).nth(1) {
+            Some(next_cluster) => next_cluster,
+            None => self.extend_cluster(cluster_index)?,
+        };
         self.add_storage_sub_entry(entry, next_cluster)
     }
 
@@ -407,41 +2352,75 @@ impl DiskStorage {
     ) -> error::Result<()> {
         let table_size = table_size!(self.disk_layout);
 
-        let bloc = self
-            .sector_data
-            .entry(sector_index)
-            .or_insert_with(|| DiskBloc::Entries(DirectoryContent::new(table_size)));
+        // Re-interpret data as StorageTable
+        let mut table = match self.sector_data.sector(sector_index)? {
+            Some(data) => DirectoryContent::try_from_reader(&mut &data[..], table_size)?,
+            None => DirectoryContent::new(table_size)?,
+        };
+        table.push(entry)?;
 
-        match bloc {
-            DiskBloc::Entries(table) => table.push(entry),
-            DiskBloc::Data(data) => {
-                // Re-interpret data as StorageTable
-                let mut table =
-                    DirectoryContent::try_from_reader(&mut data.as_slice(), table_size)?;
-                table.push(entry)?;
+        // Update stored bloc
+        self.sector_data
+            .set_sector(sector_index, table.as_raw().to_vec());
+        self.dirty.insert(sector_index);
 
-                // Update stored bloc
-                self.sector_data
-                    .insert(sector_index, DiskBloc::Entries(table));
+        Ok(())
+    }
+
+    /// Number of clusters which can still be used by new files.
+    pub fn free_cluster_count(&self) -> usize {
+        self.fat.free_cluster_count()
+    }
+
+    /// Count files stored on disk and their total size.
+    pub fn count_files(&self) -> error::Result<(usize, usize)> {
+        let mut pending = self.list_root_file_infos();
+        let mut count = 0;
+        let mut size = 0;
 
-                Ok(())
+        while let Some(file_info) = pending.pop() {
+            if file_info.is_dir() {
+                // Skip `.` and `..`
+                pending.extend(self.read_dir(&file_info)?.into_iter().skip(2));
+            } else {
+                count += 1;
+                size += file_info.size();
             }
         }
+
+        Ok((count, size))
     }
 
     pub fn list_root_file_infos(&self) -> Vec<FileInfo> {
         self.root_entries
             .iter()
-            .map(|dir| dir.as_vec())
-            .flatten()
+            .flat_map(|dir| dir.as_vec())
             .collect()
     }
 
+    pub fn fat(&self) -> &FileAllocationTable {
+        &self.fat
+    }
+
+    /// Clusters used by a chain, starting with `cluster_index`.
+    pub fn cluster_chain(&self, cluster_index: u16) -> Vec<u16> {
+        self.fat.list_chain(cluster_index)
+    }
+
     pub fn read_file(&self, file_info: &FileInfo) -> error::Result<Vec<u8>> {
+        self.read_chain(&self.fat, file_info)
+    }
+
+    /// Read file content following its cluster chain in `fat`.
+    fn read_chain(
+        &self,
+        fat: &FileAllocationTable,
+        file_info: &FileInfo,
+    ) -> error::Result<Vec<u8>> {
         assert!(!file_info.is_dir(), "Cannot read dir as a file");
 
         // Get clusters and reserve some space to dump file
-        let cluster_indexes = self.fat.list_chain(file_info.cluster_index);
+        let cluster_indexes = fat.list_chain(file_info.cluster_index);
         let mut content = Vec::with_capacity(
             cluster_indexes.len() * self.disk_layout.bytes_per_cluster() as usize,
         );
@@ -463,25 +2442,829 @@ impl DiskStorage {
         assert!(file_info.is_dir(), "Cannot read file as a dir");
 
         let mut entries = Vec::new();
+        for cluster_index in self.dir_chain(file_info) {
+            entries.extend(self.read_dir_cluster(cluster_index)?);
+        }
+        Ok(entries)
+    }
+
+    /// Clusters of a directory, only the first one starts with `.` and `..`.
+    ///
+    /// A file (ex: entry corrupted by Atari) has none.
+    pub fn dir_chain(&self, file_info: &FileInfo) -> ClusterChain<'_> {
+        // Cluster 0 is never part of a chain
+        let start = if file_info.is_dir() {
+            file_info.cluster_index
+        } else {
+            0
+        };
+        self.fat.chain(start)
+    }
+
+    /// Entries stored in a single directory cluster.
+    pub fn read_dir_cluster(&self, cluster_index: u16) -> error::Result<Vec<FileInfo>> {
         let table_size = table_size!(self.disk_layout);
+        let first_sector = self.disk_layout.convert_cluster_to_sector(cluster_index);
 
-        macro_rules! read_entries {
-            ($sector_index:expr) => {{
-                let mut data = Vec::with_capacity(self.disk_layout.bytes_per_sector() as usize);
-                self.read_sector(&mut data, $sector_index)?;
+        let mut entries = Vec::new();
+        for sector_index in first_sector..first_sector + self.disk_layout.sectors_per_cluster() {
+            let mut data = Vec::with_capacity(self.disk_layout.bytes_per_sector() as usize);
+            self.read_sector(&mut data, sector_index)?;
 
-                let content = DirectoryContent::try_from_reader(&mut data.as_slice(), table_size)?;
-                content.as_vec()
-            }};
+            let content = DirectoryContent::try_from_reader(&mut data.as_slice(), table_size)?;
+            entries.extend(content.as_vec());
         }
+        Ok(entries)
+    }
+}
 
-        for cluster_index in self.fat.list_chain(file_info.cluster_index) {
-            let sector_index = self.disk_layout.convert_cluster_to_sector(cluster_index);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::Fixture,
+        import_report::{FlattenedEntry, SkippedEntry},
+        layout::{PartitionType, Tos},
+        prop::{self, Rng},
+    };
+
+    fn random_layout(rng: &mut Rng) -> DiskLayout {
+        let tos = if rng.bool() { Tos::V100 } else { Tos::V104 };
+        let partition_type = if rng.bool() {
+            PartitionType::Gem
+        } else {
+            PartitionType::Bgm
+        };
+        DiskLayout::new(tos, partition_type, rng.between(1, 8) as u16)
+    }
 
-            entries.extend(read_entries!(sector_index));
-            entries.extend(read_entries!(sector_index + 1));
+    fn random_storage(rng: &mut Rng) -> DiskStorage {
+        let layout = random_layout(rng);
+        let max_size = 3 * layout.bytes_per_cluster() as usize;
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+
+        let mut storage = DiskStorage::new(layout);
+        let mut dirs = vec![ROOT_INDEX];
+        for i in 0..rng.below(12) {
+            let parent = dirs[rng.below(dirs.len())];
+            if rng.below(4) == 0 {
+                let dir = storage
+                    .add_virtual_directory(&format!("D{}", i), "", mtime, parent)
+                    .unwrap();
+                dirs.push(dir);
+            } else {
+                let size = rng.between(1, max_size);
+                let content = rng.bytes(size);
+                storage
+                    .add_virtual_file(&format!("F{}", i), "BIN", mtime, &content, parent)
+                    .unwrap();
+            }
         }
+        storage
+    }
 
-        Ok(entries)
+    fn read(storage: &DiskStorage, index: u16, count: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+        storage.read_sectors(&mut data, index, count).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_data_sectors_round_trip() {
+        prop::check(prop::CASES, |rng| {
+            let mut storage = random_storage(rng);
+            let layout = storage.disk_layout.clone();
+
+            let first = layout.first_free_sector() as usize;
+            let count = rng.between(1, 4);
+            let index = rng.between(first, layout.sector_count() as usize - count) as u16;
+            let data = rng.bytes(count * layout.bytes_per_sector() as usize);
+
+            storage
+                .write_sectors(&mut data.as_slice(), index, count as u16)
+                .unwrap();
+            assert_eq!(read(&storage, index, count as u16), data);
+        });
+    }
+
+    #[test]
+    fn test_fat_sectors_round_trip() {
+        prop::check(prop::CASES / 4, |rng| {
+            let mut storage = DiskStorage::new(random_layout(rng));
+            let layout = storage.disk_layout.clone();
+            let bytes_per_sector = layout.bytes_per_sector() as usize;
+            let fat_sectors = layout.count_1fat_sectors();
+
+            // Write to 1st or 2nd FAT, both target the same table
+            let offset = if rng.bool() { 0 } else { fat_sectors };
+            let mut data = rng.bytes(fat_sectors as usize * bytes_per_sector);
+            for (i, sector) in data.chunks(bytes_per_sector).enumerate() {
+                storage
+                    .write_sectors(&mut &sector[..], offset + i as u16, 1)
+                    .unwrap();
+            }
+
+            // Padding of last sector is not part of the table
+            data[layout.fat_entries_count() * 2..].fill(0);
+            assert_eq!(read(&storage, 0, fat_sectors), data);
+            assert_eq!(read(&storage, fat_sectors, fat_sectors), data);
+        });
+    }
+
+    #[test]
+    fn test_disk_copy_round_trip() {
+        prop::check(prop::CASES / 4, |rng| {
+            let storage = random_storage(rng);
+            let layout = storage.disk_layout.clone();
+            let mut copy = DiskStorage::new(layout.clone());
+
+            // Copy every used sector, as a disk copy tool would do on Atari
+            let used_clusters = layout.fat_entries_count() - 2 - storage.free_cluster_count();
+            let last_used = layout.convert_cluster_to_sector(2 + used_clusters as u16);
+            for index in 0..last_used {
+                let data = read(&storage, index, 1);
+                copy.write_sectors(&mut data.as_slice(), index, 1).unwrap();
+            }
+
+            for index in 0..layout.first_free_sector() {
+                assert_eq!(read(&copy, index, 1), read(&storage, index, 1));
+            }
+            assert_eq!(copy.hash_report().unwrap(), storage.hash_report().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_dirty_sectors() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let layout = storage.disk_layout.clone();
+        let count_1fat_sectors = layout.count_1fat_sectors();
+        let first_data = layout.first_free_sector();
+        assert!(storage.dirty_sectors().is_empty());
+
+        // One cluster of data, first root sector, and first sector of both FAT
+        storage
+            .add_virtual_file("FILE", "TXT", mtime, b"content", ROOT_INDEX)
+            .unwrap();
+        let dirty: Vec<u16> = storage.dirty_sectors().iter().copied().collect();
+        assert_eq!(
+            dirty,
+            [
+                0,
+                count_1fat_sectors,
+                layout.count_fat_sectors(),
+                first_data
+            ]
+        );
+
+        storage.clear_dirty();
+        let sector = vec![1; layout.bytes_per_sector() as usize];
+        storage
+            .write_sectors(&mut sector.as_slice(), first_data + 5, 1)
+            .unwrap();
+        let fat = read(&storage, count_1fat_sectors, 1);
+        storage
+            .write_sectors(&mut fat.as_slice(), count_1fat_sectors, 1)
+            .unwrap();
+        let dirty: Vec<u16> = storage.dirty_sectors().iter().copied().collect();
+        assert_eq!(dirty, [0, count_1fat_sectors, first_data + 5]);
+    }
+
+    #[test]
+    fn test_storage_events() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let events = storage.subscribe();
+
+        let games = storage
+            .add_virtual_directory("GAMES", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("SAVE", "DAT", mtime, b"level 1", games)
+            .unwrap();
+        let received: Vec<StorageEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            [
+                StorageEvent::DirEntryChanged {
+                    path: "GAMES".to_string(),
+                    deleted: false
+                },
+                StorageEvent::FileWritten {
+                    path: "GAMES\\SAVE.DAT".to_string(),
+                    size: 7
+                },
+                StorageEvent::DirEntryChanged {
+                    path: "GAMES\\SAVE.DAT".to_string(),
+                    deleted: false
+                },
+            ]
+        );
+
+        // Atari deletes the file
+        let sector_index = storage.disk_layout.convert_cluster_to_sector(games);
+        let mut sector = read(&storage, sector_index, 1);
+        sector[2 * mem::size_of::<FileInfo>()] = 0xE5;
+        storage
+            .write_sectors(&mut sector.as_slice(), sector_index, 1)
+            .unwrap();
+        let received: Vec<StorageEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            [
+                StorageEvent::SectorWritten {
+                    index: sector_index,
+                    count: 1
+                },
+                StorageEvent::DirEntryChanged {
+                    path: "GAMES\\SAVE.DAT".to_string(),
+                    deleted: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_changes() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let free = storage.free_cluster_count();
+
+        let build = storage
+            .add_virtual_directory("BUILD", "", mtime, ROOT_INDEX)
+            .unwrap();
+        for (dir, size) in [(ROOT_INDEX, 100), (build, 3000), (build, 10)] {
+            storage
+                .put_virtual_file("GAME", "PRG", mtime, &vec![7; size], dir)
+                .unwrap();
+        }
+        assert_eq!(storage.host_changes(), 3);
+        assert_eq!(storage.count_files().unwrap().0, 2);
+        assert_eq!(storage.free_cluster_count(), free - 3);
+
+        let updated = storage.find_entry("BUILD\\GAME.PRG").unwrap().unwrap();
+        assert_eq!(storage.read_file(&updated).unwrap(), [7; 10]);
+
+        assert!(storage.remove_file("game.prg", ROOT_INDEX).unwrap());
+        assert!(!storage.remove_file("GAME.PRG", ROOT_INDEX).unwrap());
+        assert!(!storage.remove_file("BUILD", ROOT_INDEX).unwrap());
+        assert!(storage.find_entry("GAME.PRG").unwrap().is_none());
+        assert_eq!(storage.free_cluster_count(), free - 2);
+        assert_eq!(storage.host_changes(), 4);
+    }
+
+    #[test]
+    fn test_generated_files() {
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage
+            .set_generated_files(vec![GeneratedFile::new("INFO\\NEWS.TXT", "echo news")])
+            .unwrap();
+
+        let news = storage.find_entry("INFO\\NEWS.TXT").unwrap().unwrap();
+        assert_eq!(storage.read_file(&news).unwrap(), b"news\n");
+        assert_eq!(storage.host_changes(), 0);
+
+        // Clusters are reused by shorter content
+        let info = storage.find_entry("INFO").unwrap().unwrap();
+        storage
+            .replace_virtual_file(
+                "NEWS",
+                "TXT",
+                NaiveDateTime::from_timestamp(0, 0),
+                b"-",
+                info.cluster_index,
+            )
+            .unwrap();
+        let updated = storage.find_entry("INFO\\NEWS.TXT").unwrap().unwrap();
+        assert_eq!(updated.cluster_index, news.cluster_index);
+        assert_eq!(storage.read_file(&updated).unwrap(), b"-");
+    }
+
+    #[test]
+    fn test_import_sources() {
+        let fixture = Fixture::empty("sources").unwrap();
+        let dir = fixture.path();
+        let (library, work) = (dir.join("library"), dir.join("work"));
+        fs::create_dir_all(library.join("GAMES")).unwrap();
+        fs::create_dir_all(work.join("GAMES")).unwrap();
+        fs::write(library.join("GAMES").join("DUNG.PRG"), b"dungeon").unwrap();
+        fs::write(library.join("README.TXT"), b"library").unwrap();
+        fs::write(work.join("GAMES").join("MINE.PRG"), b"mine").unwrap();
+        fs::write(work.join("README.TXT"), b"work").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(&library).unwrap();
+        let report = storage.import_source(&work, "", false).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(
+            report.skipped,
+            [SkippedEntry {
+                path: work.join("README.TXT"),
+                reason: SkipReason::AlreadyExists,
+            }]
+        );
+
+        // Directories are merged, first source wins
+        assert!(storage.find_entry("GAMES\\DUNG.PRG").unwrap().is_some());
+        assert!(storage.find_entry("GAMES\\MINE.PRG").unwrap().is_some());
+        let readme = storage.find_entry("README.TXT").unwrap().unwrap();
+        assert_eq!(storage.read_file(&readme).unwrap(), b"library");
+        assert_eq!(
+            storage.origin(ROOT_INDEX).unwrap().host_path,
+            library.canonicalize().unwrap()
+        );
+
+        storage.import_source(&work, "WORK\\SRC", false).unwrap();
+        assert!(storage
+            .find_entry("WORK\\SRC\\GAMES\\MINE.PRG")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_split_files() {
+        let fixture = Fixture::empty("split").unwrap();
+        let dir = fixture.path();
+        let _ = fs::remove_dir_all(dir.join("IMAGES"));
+        fs::create_dir_all(dir.join("IMAGES")).unwrap();
+        let content: Vec<u8> = (0..2500).map(|i| i as u8).collect();
+        fs::write(dir.join("IMAGES").join("bigdisk.img"), &content).unwrap();
+        fs::write(dir.join("IMAGES").join("small.img"), b"small").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.set_split_size(Some(1000));
+        let report = storage.import_path(dir).unwrap();
+        assert_eq!(report.files, 2);
+
+        let part = storage.find_entry("IMAGES\\BIGDISK.003").unwrap().unwrap();
+        assert_eq!(part.size(), 500);
+        assert!(storage.find_entry("IMAGES\\BIGDISK.IMG").unwrap().is_none());
+        assert!(storage.find_entry("IMAGES\\SMALL.IMG").unwrap().is_some());
+        assert_eq!(
+            split::join(&storage, "IMAGES\\BIGDISK.SPL").unwrap(),
+            ("bigdisk.img".to_string(), content)
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let fixture = Fixture::empty("depth").unwrap();
+        let dir = fixture.path();
+        let deep = dir.join("repo").join("src").join("main");
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(dir.join("repo").join("readme.txt"), b"repo").unwrap();
+        fs::write(dir.join("repo").join("src").join("readme.txt"), b"src").unwrap();
+        fs::write(deep.join("main.c"), b"main").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.set_max_depth(Some(1));
+        let report = storage.import_path(dir).unwrap();
+        assert_eq!((report.files, report.directories), (3, 1));
+        assert_eq!(
+            report.flattened,
+            [
+                FlattenedEntry {
+                    path: deep.join("main.c"),
+                    disk_path: "repo\\main.c".to_string(),
+                },
+                FlattenedEntry {
+                    path: dir.join("repo").join("src").join("readme.txt"),
+                    disk_path: "repo\\readme~1.txt".to_string(),
+                },
+            ]
+        );
+        assert!(storage.find_entry("REPO\\SRC").unwrap().is_none());
+        let entry = storage.find_entry("REPO\\README~1.TXT").unwrap().unwrap();
+        assert_eq!(storage.read_file(&entry).unwrap(), b"src");
+    }
+
+    #[test]
+    fn test_executable_policy() {
+        let fixture = Fixture::empty("exec").unwrap();
+        let dir = fixture.path();
+        fs::write(dir.join("DEMO.BIN"), [0x60, 0x1A, 0, 0]).unwrap();
+        fs::write(dir.join("GAME"), [0x60, 0x1A, 0, 0]).unwrap();
+        fs::write(dir.join("DATA.BIN"), b"data").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.set_executable_policy(ExecutablePolicy::Rename);
+        storage.import_path(dir).unwrap();
+
+        assert!(storage.find_entry("DEMO.PRG").unwrap().is_some());
+        assert!(storage.find_entry("GAME.PRG").unwrap().is_some());
+        assert!(storage.find_entry("DATA.BIN").unwrap().is_some());
+
+        storage.set_executable_policy(ExecutablePolicy::Warn);
+        let (name, ext) = storage.executable_components("X".into(), "BIN".into(), &[0x60, 0x1A]);
+        assert_eq!((name.as_str(), ext.as_str()), ("X", "BIN"));
+    }
+
+    #[test]
+    fn test_extension_map() {
+        let fixture = Fixture::empty("ext").unwrap();
+        let dir = fixture.path();
+        fs::create_dir_all(dir.join("TOOLS.TOSPRG")).unwrap();
+        fs::write(dir.join("GAME.TosPrg"), b"game").unwrap();
+        fs::write(dir.join("NOTES.TXT"), b"notes").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let extension_map = [(".tosprg".to_string(), "prg".to_string())].into();
+        storage.set_extension_map(&extension_map);
+        storage.import_path(dir).unwrap();
+
+        assert!(storage.find_entry("GAME.PRG").unwrap().is_some());
+        assert!(storage.find_entry("NOTES.TXT").unwrap().is_some());
+        // Directories keep their name
+        assert!(storage.find_entry("TOOLS.TOS").unwrap().unwrap().is_dir());
+    }
+
+    #[test]
+    fn test_uninitialized_reads() {
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage
+            .add_virtual_file("DUNG", "PRG", NaiveDateTime::from_timestamp(0, 0), b"x", 0)
+            .unwrap();
+        let layout = storage.disk_layout.clone();
+        let entry = storage.find_entry("DUNG.PRG").unwrap().unwrap();
+        let written = layout.convert_cluster_to_sector(entry.cluster_index);
+        let unwritten = written + 4 * layout.sectors_per_cluster();
+        let len = layout.bytes_per_sector() as usize;
+
+        assert_eq!(read(&storage, unwritten, 1), vec![0; len]);
+        assert_eq!(storage.unreadable_sector(unwritten, 1), None);
+        storage.set_uninitialized_reads(UninitializedReadPolicy::FillE5);
+        assert_eq!(read(&storage, unwritten, 1), vec![0xE5; len]);
+
+        // Only reads of unwritten data sectors are refused
+        storage.set_uninitialized_reads(UninitializedReadPolicy::Error);
+        assert_eq!(
+            storage.unreadable_sector(0, layout.first_free_sector()),
+            None
+        );
+        assert_eq!(storage.unreadable_sector(written, 1), None);
+        assert_eq!(storage.unreadable_sector(unwritten, 2), Some(unwritten));
+        assert_eq!(read(&storage, unwritten, 1), vec![0; len]);
+    }
+
+    #[test]
+    fn test_strict_entries() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let games = storage
+            .add_virtual_directory("GAMES", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("DUNG", "PRG", mtime, b"dungeon", games)
+            .unwrap();
+        let root = storage.disk_layout.count_fat_sectors();
+        let entry = storage.find_entry("GAMES").unwrap().unwrap();
+        let games_sector = storage
+            .disk_layout
+            .convert_cluster_to_sector(entry.cluster_index);
+
+        // Name with a `*`, first cluster out of FAT, file larger than disk
+        let mut bad_name = read(&storage, root, 1);
+        bad_name[1] = b'*';
+        let mut bad_cluster = read(&storage, games_sector, 1);
+        bad_cluster[2 * 32 + 26..][..2].copy_from_slice(&0xFFF0u16.to_ne_bytes());
+        let mut bad_size = read(&storage, games_sector, 1);
+        bad_size[2 * 32 + 28..][..4].copy_from_slice(&u32::MAX.to_ne_bytes());
+
+        for (data, sector) in [
+            (&bad_name, root),
+            (&bad_cluster, games_sector),
+            (&bad_size, games_sector),
+        ] {
+            assert!(storage.check_write(sector, data).is_ok());
+            storage.set_strict(true);
+            assert!(matches!(
+                storage.check_write(sector, data),
+                Err(SerialDiskError::InvalidEntry(_))
+            ));
+            storage.set_strict(false);
+        }
+
+        // Valid and unchanged entries are accepted
+        storage.set_strict(true);
+        let mut renamed = read(&storage, root, 1);
+        renamed[..5].copy_from_slice(b"PLAY ");
+        assert!(storage.check_write(root, &renamed).is_ok());
+        storage
+            .write_sectors(&mut &bad_size[..], games_sector, 1)
+            .unwrap();
+        assert!(storage.check_write(games_sector, &bad_size).is_ok());
+    }
+
+    #[test]
+    fn test_read_only_sources() {
+        let fixture = Fixture::empty("read-only").unwrap();
+        let dir = fixture.path();
+        let (library, work) = (dir.join("library"), dir.join("work"));
+        fs::create_dir_all(library.join("DEMOS")).unwrap();
+        fs::create_dir_all(&work).unwrap();
+        fs::write(library.join("DEMOS").join("DEMO.PRG"), b"demo").unwrap();
+        fs::write(work.join("NOTES.TXT"), b"notes").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_source(&library, "LIB", true).unwrap();
+        storage.import_source(&work, "", false).unwrap();
+        let layout = storage.disk_layout.clone();
+        let bytes_per_sector = layout.bytes_per_sector() as usize;
+        let sector_of = |storage: &DiskStorage, path: &str| {
+            let entry = storage.find_entry(path).unwrap().unwrap();
+            layout.convert_cluster_to_sector(entry.cluster_index)
+        };
+
+        // File and directory content
+        for path in ["LIB\\DEMOS\\DEMO.PRG", "LIB\\DEMOS", "LIB"] {
+            let sector = sector_of(&storage, path);
+            let data = vec![0xAA; bytes_per_sector];
+            let outcome = storage.write_sectors(&mut &data[..], sector, 1).unwrap();
+            assert_eq!(outcome, WriteOutcome::Protected, "{}", path);
+        }
+        let demo = storage.find_entry("LIB\\DEMOS\\DEMO.PRG").unwrap().unwrap();
+        assert_eq!(storage.read_file(&demo).unwrap(), b"demo");
+
+        // Same content can be written again
+        let sector = sector_of(&storage, "LIB\\DEMOS\\DEMO.PRG");
+        let data = read(&storage, sector, 1);
+        let outcome = storage.write_sectors(&mut &data[..], sector, 1).unwrap();
+        assert_eq!(outcome, WriteOutcome::Written);
+
+        // Freeing clusters in FAT, or deleting entry at root
+        let mut fat = read(&storage, 0, 1);
+        let entry = demo.cluster_index as usize * 2;
+        fat[entry..entry + 2].copy_from_slice(&[0, 0]);
+        let outcome = storage.write_sectors(&mut &fat[..], 0, 1).unwrap();
+        assert_eq!(outcome, WriteOutcome::Protected);
+        let root_sector = layout.count_fat_sectors();
+        let mut root = read(&storage, root_sector, 1);
+        root[0] = 0xE5;
+        let outcome = storage
+            .write_sectors(&mut &root[..], root_sector, 1)
+            .unwrap();
+        assert_eq!(outcome, WriteOutcome::Protected);
+        assert!(storage.find_entry("LIB").unwrap().is_some());
+
+        // Writable source
+        let sector = sector_of(&storage, "NOTES.TXT");
+        let data = vec![b'x'; bytes_per_sector];
+        let outcome = storage.write_sectors(&mut &data[..], sector, 1).unwrap();
+        assert_eq!(outcome, WriteOutcome::Written);
+    }
+
+    #[test]
+    fn test_pack_files() {
+        let mtime = Local::now().naive_local();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let cluster_len = storage.disk_layout.bytes_per_cluster() as usize;
+        let auto = storage
+            .add_virtual_directory("AUTO", "", mtime, ROOT_INDEX)
+            .unwrap();
+        for (name, len) in [("A", cluster_len + 1), ("B", 10), ("C", 20)] {
+            let content = vec![name.as_bytes()[0]; len];
+            storage
+                .add_virtual_file(name, "PRG", mtime, &content, auto)
+                .unwrap();
+        }
+
+        let paths = ["AUTO\\C.PRG", "AUTO\\MISSING.PRG", "AUTO\\A.PRG"].map(String::from);
+        assert_eq!(storage.pack_files(&paths).unwrap(), 2);
+
+        let c = storage.find_entry("AUTO\\C.PRG").unwrap().unwrap();
+        let a = storage.find_entry("AUTO\\A.PRG").unwrap().unwrap();
+        assert_eq!(storage.cluster_chain(c.cluster_index), [c.cluster_index]);
+        assert_eq!(
+            storage.cluster_chain(a.cluster_index),
+            [c.cluster_index + 1, c.cluster_index + 2]
+        );
+        assert_eq!(storage.read_file(&a).unwrap(), vec![b'A'; cluster_len + 1]);
+        assert_eq!(storage.read_file(&c).unwrap(), vec![b'C'; 20]);
+    }
+
+    #[test]
+    fn test_format_attempt() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage
+            .add_virtual_file("KEEP", "TXT", mtime, b"content", ROOT_INDEX)
+            .unwrap();
+        let layout = storage.disk_layout.clone();
+        let fat_sectors = layout.count_fat_sectors();
+        let metadata_sectors = layout.first_free_sector();
+        let original = read(&storage, 0, metadata_sectors);
+
+        // FAT and root written blank at once
+        let mut blank = vec![0; metadata_sectors as usize * layout.bytes_per_sector() as usize];
+        blank[..4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]);
+        let outcome = storage
+            .write_sectors(&mut blank.as_slice(), 0, metadata_sectors)
+            .unwrap();
+        assert_eq!(outcome, WriteOutcome::FormatRefused);
+        assert_eq!(read(&storage, 0, metadata_sectors), original);
+
+        // FAT written before root: emptied FAT is restored
+        let (fat, root) = blank.split_at(fat_sectors as usize * layout.bytes_per_sector() as usize);
+        let outcome = storage
+            .write_sectors(&mut &fat[..], 0, fat_sectors)
+            .unwrap();
+        assert_eq!(outcome, WriteOutcome::Written);
+        let outcome = storage
+            .write_sectors(&mut &root[..], fat_sectors, metadata_sectors - fat_sectors)
+            .unwrap();
+        assert_eq!(outcome, WriteOutcome::FormatRefused);
+        assert_eq!(read(&storage, 0, metadata_sectors), original);
+
+        storage.set_format_policy(FormatPolicy::Allow);
+        let outcome = storage
+            .write_sectors(&mut blank.as_slice(), 0, metadata_sectors)
+            .unwrap();
+        assert_eq!(outcome, WriteOutcome::Formatted);
+        assert_eq!(storage.count_files().unwrap(), (0, 0));
+        assert!(storage.fat.is_empty());
+
+        // Empty disk has nothing to protect
+        storage.set_format_policy(FormatPolicy::Refuse);
+        let outcome = storage
+            .write_sectors(&mut blank.as_slice(), 0, metadata_sectors)
+            .unwrap();
+        assert_eq!(outcome, WriteOutcome::Written);
+    }
+
+    #[test]
+    fn test_trash_deleted_files() {
+        let fixture = Fixture::empty("deleted").unwrap();
+        let dir = fixture.path();
+
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Gem, 4));
+        storage
+            .add_virtual_file("ROOT", "TXT", mtime, b"root file", ROOT_INDEX)
+            .unwrap();
+        let sub = storage
+            .add_virtual_directory("SUB", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("NESTED", "DAT", mtime, b"nested file", sub)
+            .unwrap();
+        storage.set_trash(Trash::new(dir).unwrap());
+
+        let layout = storage.disk_layout.clone();
+        let entry_size = mem::size_of::<FileInfo>();
+
+        // Atari frees FAT chain first, then marks root entry as deleted
+        let mut fat = read(&storage, 0, 1);
+        let root_cluster = storage.list_root_file_infos()[0].cluster_index as usize;
+        fat[root_cluster * 2..root_cluster * 2 + 2].fill(0);
+        storage.write_sectors(&mut fat.as_slice(), 0, 1).unwrap();
+
+        let first_root = layout.count_fat_sectors();
+        let mut root = read(&storage, first_root, 1);
+        root[0] = 0xE5;
+        storage
+            .write_sectors(&mut root.as_slice(), first_root, 1)
+            .unwrap();
+        assert_eq!(fs::read(dir.join("ROOT.TXT")).unwrap(), b"root file");
+
+        // Entries of sub directories are found too
+        let sub_sector = layout.convert_cluster_to_sector(sub);
+        let mut entries = read(&storage, sub_sector, 1);
+        entries[2 * entry_size] = 0xE5;
+        storage
+            .write_sectors(&mut entries.as_slice(), sub_sector, 1)
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.join("SUB").join("NESTED.DAT")).unwrap(),
+            b"nested file"
+        );
+    }
+
+    #[test]
+    fn test_error_context() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Gem, 1));
+        let error = (0..)
+            .find_map(|i| {
+                storage
+                    .add_virtual_file(&format!("F{}", i), "TXT", mtime, b"content", ROOT_INDEX)
+                    .err()
+            })
+            .unwrap();
+
+        assert_eq!(error.root(), &SerialDiskError::FolderFull);
+        assert_eq!(error.context().unwrap().parent_cluster, Some(ROOT_INDEX));
+        let path = error.context().unwrap().path.clone().unwrap();
+        assert!(path.starts_with('F') && path.ends_with(".TXT"));
+    }
+
+    #[test]
+    fn test_quotas() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::new(Tos::V104, PartitionType::Gem, 4));
+        let dir = storage
+            .add_virtual_directory("DOWNLOAD", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("FILE", "BIN", mtime, b"content", dir)
+            .unwrap();
+        storage.set_quotas(vec![Quota {
+            path: "DOWNLOAD".to_string(),
+            max_clusters: 2,
+        }]);
+
+        assert_eq!(
+            storage.add_virtual_file("MORE", "BIN", mtime, b"more", dir),
+            Err(SerialDiskError::QuotaExceeded("DOWNLOAD".to_string())
+                .with_path("DOWNLOAD\\MORE.BIN".to_string()))
+        );
+        storage
+            .add_virtual_file("OUTSIDE", "BIN", mtime, b"outside", ROOT_INDEX)
+            .unwrap();
+
+        // Atari extends the file with a free cluster
+        let file = &storage
+            .read_dir(&storage.list_root_file_infos()[0])
+            .unwrap()[2];
+        let (last, next) = (file.cluster_index as usize, 0x10);
+        let original = read(&storage, 0, 1);
+        let mut fat = original.clone();
+        fat[last * 2..last * 2 + 2].copy_from_slice(&(next as u16).to_ne_bytes());
+        fat[next * 2..next * 2 + 2].copy_from_slice(&[0xFF, 0xFF]);
+
+        let outcome = storage.write_sectors(&mut fat.as_slice(), 0, 1).unwrap();
+        assert_eq!(outcome, WriteOutcome::QuotaExceeded);
+        assert_eq!(read(&storage, 0, 1), original);
+    }
+
+    #[test]
+    fn test_import_is_deterministic() {
+        let fixture = Fixture::empty("order").unwrap();
+        let dir = fixture.path();
+        fs::create_dir_all(dir.join("B_DIR")).unwrap();
+        for name in ["ZETA.TXT", "ALPHA.TXT", "MID.TXT", "B_DIR/INNER.TXT"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let dump = || {
+            let mut storage = DiskStorage::new(DiskLayout::default());
+            storage.import_path(dir).unwrap();
+            let names: Vec<String> = storage
+                .list_root_file_infos()
+                .iter()
+                .map(|f| f.filename().unwrap())
+                .collect();
+
+            let mut sectors = Vec::new();
+            let count = storage.disk_layout.first_free_sector() + 16;
+            storage.read_sectors(&mut sectors, 0, count).unwrap();
+            (names, sectors)
+        };
+
+        let (names, sectors) = dump();
+        assert_eq!(names, ["ALPHA.TXT", "B_DIR", "MID.TXT", "ZETA.TXT"]);
+        assert_eq!(dump().1, sectors);
+    }
+
+    #[test]
+    fn test_import_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let fixture = Fixture::empty("import").unwrap();
+        let dir = fixture.path();
+        fs::create_dir_all(dir.join("shared").join("SUB")).unwrap();
+        fs::write(dir.join("shared").join("SUB").join("FILE.TXT"), b"inside").unwrap();
+        fs::write(dir.join("SECRET.TXT"), b"outside").unwrap();
+
+        let shared = dir.join("shared");
+        symlink(shared.join("SUB").join("FILE.TXT"), shared.join("LINK.TXT")).unwrap();
+        symlink(dir.join("SECRET.TXT"), shared.join("ESCAPE.TXT")).unwrap();
+        symlink(&shared, shared.join("SUB").join("LOOP")).unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let import = storage.import_path(&shared).unwrap();
+        assert_eq!((import.files, import.directories, import.bytes), (2, 1, 12));
+        assert_eq!(import.clusters, 3);
+        let mut skipped: Vec<_> = import
+            .skipped
+            .iter()
+            .map(|s| {
+                (
+                    s.path.file_name().unwrap().to_str().unwrap(),
+                    s.reason.kind(),
+                )
+            })
+            .collect();
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            [
+                ("ESCAPE.TXT", "outside_shared_root"),
+                ("LOOP", "symlink_loop")
+            ]
+        );
+
+        let report = storage.hash_report().unwrap();
+        let paths: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["LINK.TXT", "SUB\\FILE.TXT"]);
     }
 }
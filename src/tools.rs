@@ -0,0 +1,65 @@
+//! Read only drive with the Atari side tooling.
+//!
+//! It is served on its own port, next to the main disk, and contains the
+//! bundled drivers and every file of the `tools/` folder embedded at build
+//! time (test programs, docs...).
+
+use chrono::Local;
+
+use crate::{
+    driver, error,
+    layout::DiskLayout,
+    protocol,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+include!(concat!(env!("OUT_DIR"), "/tools.rs"));
+
+const DRIVER_NAME: &str = "SERDISK";
+const DRIVERS_DIR: &str = "DRIVERS";
+
+/// Build the tools drive.
+pub fn tools_storage(disk_layout: DiskLayout) -> error::Result<DiskStorage> {
+    let mtime = Local::now().naive_local();
+    let mut storage = DiskStorage::new(disk_layout);
+
+    if let Some(content) = driver::find(protocol::VERSION) {
+        storage.add_virtual_file(DRIVER_NAME, "PRG", mtime, content, ROOT_INDEX)?;
+    }
+
+    let versions = driver::bundled_versions();
+    if !versions.is_empty() {
+        let dir = storage.add_virtual_directory(DRIVERS_DIR, "", mtime, ROOT_INDEX)?;
+        for version in versions {
+            let content = driver::find(version).expect("Listed driver is bundled");
+            storage.add_virtual_file(&format!("V{}", version), "PRG", mtime, content, dir)?;
+        }
+    }
+
+    for (name, content) in TOOLS {
+        let (filename, extension) = name.split_once('.').unwrap_or((name, ""));
+        storage.add_virtual_file(filename, extension, mtime, content, ROOT_INDEX)?;
+    }
+
+    storage.set_read_only(true);
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{PartitionType, Tos};
+
+    #[test]
+    fn test_tools_storage() {
+        let storage = tools_storage(DiskLayout::new(Tos::V104, PartitionType::Gem, 4)).unwrap();
+        let names: Vec<String> = storage
+            .list_root_file_infos()
+            .iter()
+            .map(|f| f.filename().unwrap())
+            .collect();
+
+        assert!(names.contains(&"README.TXT".to_string()));
+        assert!(storage.is_read_only());
+    }
+}
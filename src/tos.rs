@@ -0,0 +1,181 @@
+//! File name constraints enforced by TOS / GEMDOS.
+
+use serde::Deserialize;
+
+/// GEMDOS paths are stored in 128 bytes buffers (drive letter and
+/// terminating NUL included).
+pub const MAX_PATH_LEN: usize = 128;
+
+/// Length of the `C:\` prefix and NUL terminator taken from path buffers.
+const PATH_OVERHEAD: usize = 4;
+
+/// Device names GEMDOS open instead of files.
+const RESERVED_NAMES: [&str; 4] = ["AUX", "CON", "NUL", "PRN"];
+
+/// Chars allowed in file names beside letters and digits.
+const ALLOWED_SYMBOLS: &str = "_!@#$%^&()-{}~'`";
+
+/// First bytes of GEMDOS programs (`bra.s` over the header).
+pub const PRG_MAGIC: [u8; 2] = [0x60, 0x1A];
+
+/// Extensions of files TOS and GEM can run (desk accessories included).
+const RUNNABLE_EXTENSIONS: [&str; 8] = ["PRG", "TOS", "TTP", "APP", "GTP", "ACC", "PRX", "ACX"];
+
+/// What to do with files TOS cannot handle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamePolicy {
+    /// Import file anyway and log a warning
+    #[default]
+    Warn,
+    /// Do not import file
+    Skip,
+    /// Import file with a name TOS accepts
+    Rename,
+}
+
+/// What to do with programs which do not have a runnable extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutablePolicy {
+    /// Import file as it is
+    #[default]
+    Ignore,
+    /// Import file as it is and log a warning
+    Warn,
+    /// Import file with `PRG` extension
+    Rename,
+}
+
+/// Tell if content is a GEMDOS program while its extension cannot run it.
+pub fn is_misnamed_program(extension: &str, content: &[u8]) -> bool {
+    content.starts_with(&PRG_MAGIC)
+        && !RUNNABLE_EXTENSIONS
+            .iter()
+            .any(|runnable| runnable.eq_ignore_ascii_case(extension))
+}
+
+/// Reason why a file name is not accepted by TOS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameIssue {
+    ReservedName,
+    InvalidChars,
+    PathTooLong(usize),
+}
+
+impl std::fmt::Display for NameIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReservedName => write!(f, "reserved device name"),
+            Self::InvalidChars => write!(f, "chars not allowed by TOS"),
+            Self::PathTooLong(len) => {
+                write!(f, "path is {} chars long (max {})", len, MAX_PATH_LEN)
+            }
+        }
+    }
+}
+
+/// Char TOS accepts in file names and extensions.
+pub fn is_valid_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ALLOWED_SYMBOLS.contains(c)
+}
+
+/// Length of a file path on the Atari side.
+fn path_len(parent_path: &str, name: &str, extension: &str) -> usize {
+    let mut len = parent_path.len() + name.len() + PATH_OVERHEAD;
+    if !parent_path.is_empty() {
+        len += 1;
+    }
+    if !extension.is_empty() {
+        len += extension.len() + 1;
+    }
+    len
+}
+
+/// List every TOS constraint a file breaks.
+///
+/// `parent_path` is the `\` separated path of the parent directory from disk root.
+pub fn check_name(parent_path: &str, name: &str, extension: &str) -> Vec<NameIssue> {
+    let mut issues = Vec::new();
+
+    if RESERVED_NAMES.contains(&name.to_ascii_uppercase().as_str()) {
+        issues.push(NameIssue::ReservedName);
+    }
+    if name.is_empty() || !name.chars().chain(extension.chars()).all(is_valid_char) {
+        issues.push(NameIssue::InvalidChars);
+    }
+
+    let len = path_len(parent_path, name, extension);
+    if len > MAX_PATH_LEN {
+        issues.push(NameIssue::PathTooLong(len));
+    }
+
+    issues
+}
+
+/// Build a name TOS accepts from an invalid one.
+///
+/// Path length cannot be fixed by renaming a single file.
+pub fn sanitize_name(name: &str, extension: &str) -> (String, String) {
+    let replace = |s: &str| -> String {
+        s.chars()
+            .map(|c| if is_valid_char(c) { c } else { '_' })
+            .collect()
+    };
+
+    let mut name = replace(name);
+    if name.is_empty() {
+        name.push('_');
+    }
+    if RESERVED_NAMES.contains(&name.to_ascii_uppercase().as_str()) {
+        name.push('_');
+    }
+
+    (name, replace(extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert_eq!(check_name("", "README", "TXT"), vec![]);
+        assert_eq!(check_name("GAMES", "DUNG_1", "PRG"), vec![]);
+        assert_eq!(check_name("", "aux", ""), vec![NameIssue::ReservedName]);
+        assert_eq!(check_name("", "A+B", "TXT"), vec![NameIssue::InvalidChars]);
+        assert_eq!(check_name("", "A B", ""), vec![NameIssue::InvalidChars]);
+        assert_eq!(check_name("", "FILE", "T;T"), vec![NameIssue::InvalidChars]);
+        assert_eq!(check_name("", "", "TXT"), vec![NameIssue::InvalidChars]);
+
+        let deep = vec!["FOLDER00"; 13].join("\\");
+        assert_eq!(
+            check_name(&deep, "FILE", "TXT"),
+            vec![NameIssue::PathTooLong(129)]
+        );
+    }
+
+    #[test]
+    fn test_misnamed_program() {
+        let program = [0x60, 0x1A, 0x00, 0x00];
+        assert!(is_misnamed_program("", &program));
+        assert!(is_misnamed_program("BIN", &program));
+        assert!(!is_misnamed_program("prg", &program));
+        assert!(!is_misnamed_program("ACC", &program));
+        assert!(!is_misnamed_program("BIN", b"data"));
+        assert!(!is_misnamed_program("BIN", &[0x60]));
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("CON", ""), ("CON_".into(), "".into()));
+        assert_eq!(
+            sanitize_name("A+B C", "T;T"),
+            ("A_B_C".into(), "T_T".into())
+        );
+        assert_eq!(sanitize_name("", "TXT"), ("_".into(), "TXT".into()));
+
+        let (name, ext) = sanitize_name("PRN", "[X]");
+        assert_eq!(check_name("", &name, &ext), vec![]);
+    }
+}
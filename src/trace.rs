@@ -0,0 +1,267 @@
+//! Record of the bytes exchanged with Atari, replayed for offline debugging.
+//!
+//! A trace is a sequence of chunks, each made of a direction byte (`<` for
+//! bytes received from Atari, `>` for bytes sent to it), a big endian `u32`
+//! length and the bytes themselves. Replaying feeds received bytes to the
+//! state machine serving a fresh disk, and checks it answers the same bytes.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::Serialize;
+
+use crate::{
+    backend::SectorBackend,
+    error::{self, SerialDiskError},
+    persistence::Persistence,
+    state_machine,
+    storage::DiskStorage,
+    transport::{MemoryTransport, Transport},
+};
+
+const FROM_ATARI: u8 = b'<';
+const TO_ATARI: u8 = b'>';
+
+/// Bytes exchanged with Atari, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    /// Direction and content of each chunk
+    pub chunks: Vec<(u8, Vec<u8>)>,
+}
+
+impl Trace {
+    pub fn read_from<R>(reader: &mut R) -> error::Result<Self>
+    where
+        R: Read,
+    {
+        let mut chunks = Vec::new();
+        loop {
+            let direction = match reader.read_u8() {
+                Ok(direction) => direction,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            if direction != FROM_ATARI && direction != TO_ATARI {
+                return Err(SerialDiskError::InvalidTrace(format!(
+                    "unknown direction {:#04x}",
+                    direction
+                )));
+            }
+
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            let mut data = vec![0; len];
+            reader.read_exact(&mut data)?;
+            chunks.push((direction, data));
+        }
+
+        Ok(Self { chunks })
+    }
+
+    pub fn open<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::read_from(&mut io::BufReader::new(File::open(path)?))
+    }
+
+    fn concat(&self, direction: u8) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|(d, _)| *d == direction)
+            .flat_map(|(_, data)| data.iter().copied())
+            .collect()
+    }
+
+    /// Every byte received from Atari.
+    pub fn received(&self) -> Vec<u8> {
+        self.concat(FROM_ATARI)
+    }
+
+    /// Every byte sent to Atari.
+    pub fn sent(&self) -> Vec<u8> {
+        self.concat(TO_ATARI)
+    }
+}
+
+/// Transport writing everything going through it to a trace file.
+#[derive(Debug)]
+pub struct TraceRecorder<T> {
+    inner: T,
+    writer: BufWriter<File>,
+    direction: u8,
+    pending: Vec<u8>,
+}
+
+impl<T> TraceRecorder<T> {
+    /// Record to the end of `path`, so reconnections keep a single trace.
+    pub fn append<P>(inner: T, path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner,
+            writer: BufWriter::new(file),
+            direction: FROM_ATARI,
+            pending: Vec::new(),
+        })
+    }
+
+    fn record(&mut self, direction: u8, data: &[u8]) -> io::Result<()> {
+        if direction != self.direction {
+            self.write_pending()?;
+            self.direction = direction;
+        }
+        self.pending.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Write chunk in progress. Done each time the link turns around, so the
+    /// trace is usable even if server is killed.
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.writer.write_u8(self.direction)?;
+        self.writer
+            .write_u32::<BigEndian>(self.pending.len() as u32)?;
+        self.writer.write_all(&self.pending)?;
+        self.writer.flush()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<T> Drop for TraceRecorder<T> {
+    fn drop(&mut self) {
+        if let Err(error) = self.write_pending() {
+            log::warn!("Cannot write end of trace (error: {})", error);
+        }
+    }
+}
+
+impl<T> Read for TraceRecorder<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.record(FROM_ATARI, &buf[..count])?;
+        Ok(count)
+    }
+}
+
+impl<T> Write for TraceRecorder<T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.record(TO_ATARI, &buf[..count])?;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T> Transport for TraceRecorder<T>
+where
+    T: Transport,
+{
+    fn discard_pending(&mut self) -> error::Result<()> {
+        self.inner.discard_pending()
+    }
+}
+
+/// How a replayed session compares to its recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub received: usize,
+    pub expected: usize,
+    pub sent: usize,
+    /// Offset of the first byte sent differing from the recording
+    pub first_mismatch: Option<usize>,
+}
+
+impl ReplayReport {
+    pub fn matches(&self) -> bool {
+        self.first_mismatch.is_none()
+    }
+}
+
+/// Serve `storage` to the bytes Atari sent in `trace` and compare answers.
+pub fn replay<B>(storage: DiskStorage<B>, trace: &Trace) -> ReplayReport
+where
+    B: SectorBackend + Serialize,
+{
+    let received = trace.received();
+    let expected = trace.sent();
+
+    let mut serial = MemoryTransport::new(&received);
+    let mut persistence = Persistence::new(None, None);
+    // Stops once every received byte has been used
+    if let Err(error) =
+        state_machine::run(Arc::new(Mutex::new(storage)), &mut serial, &mut persistence)
+    {
+        log::debug!("Replay stopped: {}", error);
+    }
+
+    let sent = serial.output();
+    let first_mismatch = sent
+        .iter()
+        .zip(&expected)
+        .position(|(a, b)| a != b)
+        .or_else(|| (sent.len() != expected.len()).then(|| sent.len().min(expected.len())));
+
+    ReplayReport {
+        received: received.len(),
+        expected: expected.len(),
+        sent: sent.len(),
+        first_mismatch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::Fixture,
+        layout::{DiskLayout, PartitionType, Tos},
+    };
+
+    #[test]
+    fn test_record_and_replay() {
+        let fixture = Fixture::empty("trace").unwrap();
+        let path = fixture.join("test.trace");
+
+        // Atari asks for BPB, then first FAT sector
+        let mut input = vec![0x18, 0x03, 0x20, 0x06, 2];
+        input.extend_from_slice(&[0x18, 0x03, 0x20, 0x06, 0, 0x00, 0x00, 0x00, 0x01]);
+        {
+            let mut serial = TraceRecorder::append(MemoryTransport::new(&input), &path).unwrap();
+            let storage = Arc::new(Mutex::new(DiskStorage::new(DiskLayout::default())));
+            let _ = state_machine::run(storage, &mut serial, &mut Persistence::new(None, None));
+        }
+
+        let trace = Trace::open(&path).unwrap();
+        assert_eq!(trace.received(), input);
+        assert_eq!(trace.chunks[0].0, FROM_ATARI);
+        assert!(trace.chunks.len() >= 4);
+
+        let report = replay(DiskStorage::new(DiskLayout::default()), &trace);
+        assert!(report.matches(), "{:?}", report);
+        assert_eq!(report.sent, report.expected);
+
+        // Different disk layout: different BPB
+        let layout = DiskLayout::new(Tos::V104, PartitionType::Gem, 8);
+        let report = replay(DiskStorage::new(layout), &trace);
+        assert!(!report.matches());
+    }
+}
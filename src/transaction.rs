@@ -0,0 +1,297 @@
+//! Structured logs of the commands served to Atari.
+//!
+//! Each command is a transaction with a unique id. Its fields are logged as
+//! `key=value` pairs under the `ataridisk::transaction` target, so every log
+//! of a transfer can be found with `txn=<id>`. Finished transactions and
+//! notable issues are also given to subscribers (ex: metrics, hooks).
+//!
+//! Fields go through `log` rather than `tracing` spans: `tracing` is not a
+//! dependency of the project, and subscribers already play the part span
+//! layers would for metrics and hooks.
+
+use std::{
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const LOG_TARGET: &str = "ataridisk::transaction";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+type Subscriber = Box<dyn Fn(&Notice) + Send>;
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// Call `subscriber` on every notice.
+pub fn subscribe<F>(subscriber: F)
+where
+    F: Fn(&Notice) + Send + 'static,
+{
+    SUBSCRIBERS.lock().unwrap().push(Box::new(subscriber));
+}
+
+/// Check if anyone gets notices, to skip computing them otherwise.
+pub fn has_subscribers() -> bool {
+    !SUBSCRIBERS.lock().unwrap().is_empty()
+}
+
+fn notify(notice: &Notice) {
+    for subscriber in SUBSCRIBERS.lock().unwrap().iter() {
+        subscriber(notice);
+    }
+}
+
+/// What subscribers are told about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    /// Transaction has started
+    Started { id: u64, kind: TransactionKind },
+    /// Transaction is finished
+    Finished(TransactionRecord),
+    /// Data received from Atari does not match its CRC
+    CrcError {
+        id: u64,
+        sectors: Option<(u16, u16)>,
+    },
+    /// Atari has used every free cluster of the disk
+    DiskFull { id: u64 },
+    /// Atari has tried to format the disk
+    FormatAttempt { id: u64, allowed: bool },
+    /// Atari has read or written some files
+    FileAccess {
+        id: u64,
+        kind: TransactionKind,
+        paths: Vec<String>,
+    },
+}
+
+/// Command sent by Atari.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Read,
+    Write,
+    BiosParameterBlock,
+    Commit,
+    SpeedTest,
+    Auth,
+    Clock,
+    HostCommand,
+    HostChanges,
+    Capabilities,
+}
+
+impl Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::BiosParameterBlock => "bpb",
+            Self::Commit => "commit",
+            Self::SpeedTest => "speed_test",
+            Self::Auth => "auth",
+            Self::Clock => "clock",
+            Self::HostCommand => "host_command",
+            Self::HostChanges => "host_changes",
+            Self::Capabilities => "capabilities",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Transaction in progress.
+#[derive(Debug)]
+pub struct Transaction {
+    id: u64,
+    kind: TransactionKind,
+    sectors: Option<(u16, u16)>,
+    bytes: usize,
+    start: Instant,
+}
+
+impl Transaction {
+    pub fn begin(kind: TransactionKind) -> Self {
+        let transaction = Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            kind,
+            sectors: None,
+            bytes: 0,
+            start: Instant::now(),
+        };
+        transaction.event("started");
+        if has_subscribers() {
+            notify(&Notice::Started {
+                id: transaction.id,
+                kind,
+            });
+        }
+        transaction
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    /// Set range of sectors the transaction is about.
+    pub fn set_sectors(&mut self, index: u16, count: u16) {
+        self.sectors = Some((index, count));
+        self.event("sectors received");
+    }
+
+    /// Count bytes transferred with Atari.
+    pub fn add_bytes(&mut self, count: usize) {
+        self.bytes += count;
+    }
+
+    /// Log a step of the transaction.
+    pub fn event(&self, message: &str) {
+        log::debug!(target: LOG_TARGET, "{} {}", self.fields(), message);
+    }
+
+    /// Log a step that went wrong but does not stop the transaction.
+    pub fn warn(&self, message: &str) {
+        log::warn!(target: LOG_TARGET, "{} {}", self.fields(), message);
+    }
+
+    /// Report data received does not match its CRC.
+    pub fn crc_error(&self) {
+        self.warn(match self.kind {
+            TransactionKind::Write => "invalid CRC, waiting for data again",
+            _ => "invalid CRC",
+        });
+        notify(&Notice::CrcError {
+            id: self.id,
+            sectors: self.sectors,
+        });
+    }
+
+    /// Report this transaction has filled the disk.
+    pub fn disk_full(&self) {
+        self.warn("no free cluster left on disk");
+        notify(&Notice::DiskFull { id: self.id });
+    }
+
+    /// Report this transaction is a format attempt.
+    pub fn format_attempt(&self, allowed: bool) {
+        if allowed {
+            self.warn("disk formatted by Atari");
+        } else {
+            self.warn("format refused, disk content kept");
+        }
+        notify(&Notice::FormatAttempt {
+            id: self.id,
+            allowed,
+        });
+    }
+
+    /// Report files read or written by this transaction.
+    pub fn file_access(&self, paths: Vec<String>) {
+        notify(&Notice::FileAccess {
+            id: self.id,
+            kind: self.kind,
+            paths,
+        });
+    }
+
+    fn fields(&self) -> String {
+        let mut fields = format!("txn={} kind={}", self.id, self.kind);
+        if let Some((index, count)) = self.sectors {
+            fields.push_str(&format!(" sector={:#06x} count={}", index, count));
+        }
+        fields
+    }
+
+    /// Log transaction result and notify subscribers.
+    pub fn finish(self, success: bool) -> TransactionRecord {
+        let record = TransactionRecord {
+            id: self.id,
+            kind: self.kind,
+            sectors: self.sectors,
+            bytes: self.bytes,
+            duration: self.start.elapsed(),
+            success,
+        };
+
+        if success {
+            log::info!(target: LOG_TARGET, "{}", record);
+        } else {
+            log::error!(target: LOG_TARGET, "{}", record);
+        }
+
+        notify(&Notice::Finished(record.clone()));
+        record
+    }
+}
+
+/// Finished transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionRecord {
+    pub id: u64,
+    pub kind: TransactionKind,
+    /// First sector index and sector count
+    pub sectors: Option<(u16, u16)>,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub success: bool,
+}
+
+impl Display for TransactionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "txn={} kind={}", self.id, self.kind)?;
+        if let Some((index, count)) = self.sectors {
+            write!(f, " sector={:#06x} count={}", index, count)?;
+        }
+        write!(
+            f,
+            " bytes={} duration_ms={} status={}",
+            self.bytes,
+            self.duration.as_millis(),
+            if self.success { "ok" } else { "failed" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_transaction() {
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        {
+            let finished = finished.clone();
+            subscribe(move |notice| {
+                if let Notice::Finished(record) = notice {
+                    finished.lock().unwrap().push(record.id);
+                }
+            });
+        }
+
+        let first = Transaction::begin(TransactionKind::Read);
+        let mut second = Transaction::begin(TransactionKind::Write);
+        assert!(second.id() > first.id());
+
+        second.set_sectors(0x10, 2);
+        second.add_bytes(512);
+        second.add_bytes(512);
+        let record = second.finish(true);
+
+        assert_eq!(record.kind, TransactionKind::Write);
+        assert_eq!(record.sectors, Some((0x10, 2)));
+        assert_eq!(record.bytes, 1024);
+        assert!(record.to_string().starts_with(&format!(
+            "txn={} kind=write sector=0x0010 count=2 bytes=1024",
+            record.id
+        )));
+        assert!(record.to_string().ends_with("status=ok"));
+        assert!(finished.lock().unwrap().contains(&record.id));
+    }
+}
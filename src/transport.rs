@@ -0,0 +1,298 @@
+//! Links used to talk with Atari.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    mem,
+    net::{SocketAddr, TcpStream},
+    os::unix::io::AsRawFd,
+    thread::sleep,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serialport::SerialPort;
+
+use crate::{
+    cipher::{self, KeyStream, LinkKey},
+    error,
+};
+
+/// Byte stream connected to Atari.
+pub trait Transport: Read + Write {
+    /// Give some time for pending data to come, then discard it.
+    ///
+    /// Used to get back in sync with Atari after an unexpected command.
+    fn discard_pending(&mut self) -> error::Result<()>;
+}
+
+impl<S> Transport for S
+where
+    S: SerialPort,
+{
+    fn discard_pending(&mut self) -> error::Result<()> {
+        sleep(Duration::from_millis(500));
+        self.clear(serialport::ClearBuffer::All)?;
+        Ok(())
+    }
+}
+
+/// TCP keepalive probes, to notice a WiFi bridge gone without closing its
+/// connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TcpKeepalive {
+    pub enabled: bool,
+    /// Idle time before first probe
+    pub idle_secs: u32,
+    /// Time between probes
+    pub interval_secs: u32,
+    /// Unanswered probes before connection is dropped
+    pub probes: u32,
+}
+
+impl Default for TcpKeepalive {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_secs: 10,
+            interval_secs: 5,
+            probes: 3,
+        }
+    }
+}
+
+// Idle time option is named differently on Apple systems
+#[cfg(not(target_vendor = "apple"))]
+const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPIDLE;
+#[cfg(target_vendor = "apple")]
+const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPALIVE;
+
+impl TcpKeepalive {
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let fd = stream.as_raw_fd();
+        set_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, self.enabled as _)?;
+        if self.enabled {
+            set_option(fd, libc::IPPROTO_TCP, TCP_KEEPIDLE, self.idle_secs as _)?;
+            set_option(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_KEEPINTVL,
+                self.interval_secs as _,
+            )?;
+            set_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, self.probes as _)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_option(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Link with Atari through a TCP connection, from a WiFi to serial bridge
+/// or an emulator.
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+    cipher: Option<Box<Cipher>>,
+}
+
+#[derive(Debug)]
+struct Cipher {
+    receive: KeyStream,
+    send: KeyStream,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream, keepalive: &TcpKeepalive) -> io::Result<Self> {
+        // Commands are small and answered one at a time
+        stream.set_nodelay(true)?;
+        keepalive.apply(&stream)?;
+        Ok(Self {
+            stream,
+            cipher: None,
+        })
+    }
+
+    /// Encrypt everything exchanged from now on with `key`, sending its salt
+    /// and the session nonce to Atari first.
+    pub fn encrypt(mut self, key: &LinkKey) -> io::Result<Self> {
+        let nonce = cipher::session_nonce()?;
+        let mut handshake = key.salt.to_vec();
+        handshake.extend_from_slice(&nonce);
+        self.stream.write_all(&handshake)?;
+        self.cipher = Some(Box::new(Cipher {
+            receive: KeyStream::new(&key.key, cipher::FROM_ATARI, &nonce),
+            send: KeyStream::new(&key.key, cipher::TO_ATARI, &nonce),
+        }));
+        Ok(self)
+    }
+
+    /// Decrypt what has just been read in `buf`.
+    fn received(&mut self, buf: &mut [u8]) {
+        if let Some(cipher) = &mut self.cipher {
+            cipher.receive.apply(buf);
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stream.read(buf)?;
+        self.received(&mut buf[..len]);
+        Ok(len)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(cipher) = &mut self.cipher else {
+            return self.stream.write(buf);
+        };
+
+        // Key stream has moved on: everything must be written
+        let mut encrypted = buf.to_vec();
+        cipher.send.apply(&mut encrypted);
+        self.stream.write_all(&encrypted)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn discard_pending(&mut self) -> error::Result<()> {
+        sleep(Duration::from_millis(500));
+
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0; 512];
+        let drained = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+                // Discarded bytes still move the key stream on
+                Ok(len) => self.received(&mut buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        Ok(drained?)
+    }
+}
+
+/// Transport reading from a fixed byte stream, for tests and fuzzing.
+///
+/// Reading after the end of input fails with `UnexpectedEof`.
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl MemoryTransport {
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Everything written to Atari so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.is_empty() && !buf.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn discard_pending(&mut self) -> error::Result<()> {
+        // No more data will come: keep what is left so it is still parsed
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_encrypted_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let link_key = LinkKey::new("secret").unwrap();
+        let mut server = TcpTransport::new(stream, &TcpKeepalive::default())
+            .unwrap()
+            .encrypt(&link_key)
+            .unwrap();
+
+        // Atari derives key with the salt it receives
+        let mut salt = [0; cipher::SALT_LEN];
+        client.read_exact(&mut salt).unwrap();
+        let key = cipher::derive_key("secret", &salt);
+        let mut nonce = [0; cipher::SESSION_NONCE_LEN];
+        client.read_exact(&mut nonce).unwrap();
+        let mut to_server = KeyStream::new(&key, cipher::FROM_ATARI, &nonce);
+        let mut from_server = KeyStream::new(&key, cipher::TO_ATARI, &nonce);
+
+        let mut request = b"hello".to_vec();
+        to_server.apply(&mut request);
+        client.write_all(&request).unwrap();
+        let mut received = [0; 5];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"hello");
+
+        server.write_all(b"world").unwrap();
+        let mut answer = [0; 5];
+        client.read_exact(&mut answer).unwrap();
+        assert_ne!(&answer, b"world");
+        from_server.apply(&mut answer);
+        assert_eq!(&answer, b"world");
+    }
+}
@@ -0,0 +1,133 @@
+//! Host copy of files deleted from Atari.
+//!
+//! Content of a file is copied to the trash folder when Atari marks its
+//! entry as deleted, under its disk path (ex: `GAMES\SAVE.DAT` is kept as
+//! `<trash>/GAMES/SAVE.DAT`). Only the last deleted version of a path is
+//! kept. Trashed files can be put back in a dump with `ataridisk undelete`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDateTime};
+
+use crate::{
+    backend::SectorBackend,
+    error::{self, SerialDiskError},
+    shared_root::SharedRoot,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+#[derive(Debug, Clone)]
+pub struct Trash {
+    root: SharedRoot,
+}
+
+impl Trash {
+    /// Open trash folder, creating it if needed.
+    pub fn new<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(&path)?;
+        Ok(Self {
+            root: SharedRoot::new(path)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// Host path of a `\` separated disk path.
+    fn host_path(&self, disk_path: &str, create_dirs: bool) -> error::Result<PathBuf> {
+        let mut components: Vec<&str> = disk_path.split('\\').filter(|c| !c.is_empty()).collect();
+        let name = components
+            .pop()
+            .ok_or_else(|| SerialDiskError::OutsideSharedRoot(disk_path.to_string()))?;
+
+        let mut dir = self.root.path().to_path_buf();
+        for component in components {
+            dir = self.root.join(&dir, component)?;
+            if create_dirs {
+                fs::create_dir_all(&dir)?;
+            }
+        }
+        self.root.join(&dir, name)
+    }
+
+    /// Keep content of a file deleted from disk and return where it is.
+    pub fn keep(&self, disk_path: &str, content: &[u8]) -> error::Result<PathBuf> {
+        let path = self.host_path(disk_path, true)?;
+        fs::write(&path, content)?;
+        log::info!("Deleted file {} kept in {:?}", disk_path, path);
+        Ok(path)
+    }
+
+    /// Put a trashed file back on disk at its original path.
+    ///
+    /// Missing parent directories are created. File is removed from trash
+    /// once restored.
+    pub fn restore<B>(&self, storage: &mut DiskStorage<B>, disk_path: &str) -> error::Result<()>
+    where
+        B: SectorBackend,
+    {
+        let path = self.host_path(disk_path, false)?;
+        let content = fs::read(&path)?;
+        let mtime = Local::now().naive_local();
+
+        let mut components: Vec<&str> = disk_path.split('\\').filter(|c| !c.is_empty()).collect();
+        let name = components.pop().unwrap_or_default();
+
+        let mut parent = ROOT_INDEX;
+        for component in components {
+            let (filename, extension) = split_name(component);
+            parent = storage.find_or_add_virtual_directory(filename, extension, mtime, parent)?;
+        }
+
+        let (filename, extension) = split_name(name);
+        storage.add_virtual_file(filename, extension, file_mtime(&path), &content, parent)?;
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
+
+fn split_name(name: &str) -> (&str, &str) {
+    name.split_once('.').unwrap_or((name, ""))
+}
+
+fn file_mtime(path: &Path) -> NaiveDateTime {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|mtime| chrono::DateTime::<Local>::from(mtime).naive_local())
+        .unwrap_or_else(|_| Local::now().naive_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::Fixture, layout::DiskLayout};
+
+    #[test]
+    fn test_keep_and_restore() {
+        let fixture = Fixture::empty("trash").unwrap();
+        let dir = fixture.path();
+        let trash = Trash::new(dir).unwrap();
+
+        let path = trash.keep("GAMES\\SAVE.DAT", b"level 3").unwrap();
+        assert_eq!(path, trash.path().join("GAMES").join("SAVE.DAT"));
+        assert!(trash.keep("..\\ESCAPE.TXT", b"").is_err());
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        trash.restore(&mut storage, "GAMES\\SAVE.DAT").unwrap();
+        assert!(!path.exists());
+
+        let games = storage.list_root_file_infos();
+        assert_eq!(games[0].filename().unwrap(), "GAMES");
+        let files = storage.read_dir(&games[0]).unwrap();
+        assert_eq!(files[2].filename().unwrap(), "SAVE.DAT");
+        assert_eq!(storage.read_file(&files[2]).unwrap(), b"level 3");
+    }
+}
@@ -0,0 +1,201 @@
+//! Disk usage per directory, computed from FAT and directory entries.
+
+use std::fmt::{self, Display};
+
+use crate::{
+    backend::SectorBackend,
+    entries::FileInfo,
+    error,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+/// Deeper trees can only come from directory loops of corrupted disks.
+const MAX_DEPTH: usize = 64;
+
+/// Clusters used by a directory and everything below it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirUsage {
+    pub name: String,
+    /// Clusters of the directory entries and of its content
+    pub clusters: usize,
+    /// Size of files in directory and sub directories
+    pub bytes: usize,
+    pub children: Vec<DirUsage>,
+}
+
+/// Clusters used by a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileUsage {
+    pub path: String,
+    pub bytes: usize,
+    pub clusters: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageReport {
+    pub root: DirUsage,
+    /// Every file, largest first
+    pub files: Vec<FileUsage>,
+    pub bytes_per_cluster: usize,
+    pub free_clusters: usize,
+}
+
+impl UsageReport {
+    pub fn largest(&self, count: usize) -> &[FileUsage] {
+        &self.files[..count.min(self.files.len())]
+    }
+
+    /// Write directory tree, down to `max_depth` levels.
+    pub fn write_tree(&self, f: &mut dyn fmt::Write, max_depth: usize) -> fmt::Result {
+        write_dir(f, &self.root, 0, max_depth, self.bytes_per_cluster)
+    }
+}
+
+impl Display for UsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_tree(f, usize::MAX)
+    }
+}
+
+fn write_dir(
+    f: &mut dyn fmt::Write,
+    dir: &DirUsage,
+    depth: usize,
+    max_depth: usize,
+    bytes_per_cluster: usize,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "{:>8} clusters {:>10} KB  {}{}",
+        dir.clusters,
+        dir.clusters * bytes_per_cluster / 1024,
+        "  ".repeat(depth),
+        if depth == 0 { "\\" } else { &dir.name }
+    )?;
+
+    if depth < max_depth {
+        for child in &dir.children {
+            write_dir(f, child, depth + 1, max_depth, bytes_per_cluster)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compute usage of every directory and file of the disk.
+pub fn disk_usage<B>(storage: &DiskStorage<B>) -> error::Result<UsageReport>
+where
+    B: SectorBackend,
+{
+    let mut files = Vec::new();
+    // Root directory has its own sectors, outside of data clusters
+    let root = walk(storage, "", storage.list_root_file_infos(), &mut files, 0)?;
+    files.sort_by(|a, b| b.clusters.cmp(&a.clusters).then(a.path.cmp(&b.path)));
+
+    Ok(UsageReport {
+        root,
+        files,
+        bytes_per_cluster: storage.disk_layout.bytes_per_cluster() as usize,
+        free_clusters: storage.free_cluster_count(),
+    })
+}
+
+fn walk<B>(
+    storage: &DiskStorage<B>,
+    path: &str,
+    entries: Vec<FileInfo>,
+    files: &mut Vec<FileUsage>,
+    depth: usize,
+) -> error::Result<DirUsage>
+where
+    B: SectorBackend,
+{
+    let mut usage = DirUsage {
+        name: path.rsplit('\\').next().unwrap_or_default().to_string(),
+        clusters: 0,
+        bytes: 0,
+        children: Vec::new(),
+    };
+
+    for file_info in entries.into_iter().filter(|f| !f.is_deleted()) {
+        let name = file_info.filename()?;
+        if name == "." || name == ".." {
+            continue;
+        }
+        let file_path = if path.is_empty() {
+            name
+        } else {
+            format!("{}\\{}", path, name)
+        };
+
+        let clusters = match file_info.cluster_index {
+            ROOT_INDEX => 0,
+            cluster_index => storage.cluster_chain(cluster_index).len(),
+        };
+
+        if file_info.is_dir() {
+            if depth >= MAX_DEPTH {
+                log::warn!("Skipping {} (too deep)", file_path);
+                continue;
+            }
+            let mut child = walk(
+                storage,
+                &file_path,
+                storage.read_dir(&file_info)?,
+                files,
+                depth + 1,
+            )?;
+            child.clusters += clusters;
+            usage.clusters += child.clusters;
+            usage.bytes += child.bytes;
+            usage.children.push(child);
+        } else {
+            usage.clusters += clusters;
+            usage.bytes += file_info.size();
+            files.push(FileUsage {
+                path: file_path,
+                bytes: file_info.size(),
+                clusters,
+            });
+        }
+    }
+
+    usage
+        .children
+        .sort_by(|a, b| b.clusters.cmp(&a.clusters).then(a.name.cmp(&b.name)));
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::layout::DiskLayout;
+
+    #[test]
+    fn test_disk_usage() {
+        let mtime = NaiveDateTime::from_timestamp(0, 0);
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let bytes_per_cluster = storage.disk_layout.bytes_per_cluster() as usize;
+
+        storage
+            .add_virtual_file("SMALL", "TXT", mtime, b"small", ROOT_INDEX)
+            .unwrap();
+        let games = storage
+            .add_virtual_directory("GAMES", "", mtime, ROOT_INDEX)
+            .unwrap();
+        storage
+            .add_virtual_file("BIG", "ST", mtime, &vec![0; 2 * bytes_per_cluster], games)
+            .unwrap();
+
+        let report = disk_usage(&storage).unwrap();
+        assert_eq!(report.root.clusters, 4);
+        assert_eq!(report.root.bytes, 2 * bytes_per_cluster + 5);
+        assert_eq!(report.root.children[0].name, "GAMES");
+        assert_eq!(report.root.children[0].clusters, 3);
+
+        let largest: Vec<&str> = report.largest(1).iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(largest, ["GAMES\\BIG.ST"]);
+        assert!(report.to_string().contains("  GAMES"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{backend::SectorBackend, dos, entries::FileInfo, error, storage::DiskStorage};
+
+/// What is known about a file to compare it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Summary {
+    is_dir: bool,
+    size: usize,
+    crc32: u32,
+    modified: Option<NaiveDateTime>,
+}
+
+/// Difference found between disk content and host directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    OnlyOnDisk(String),
+    OnlyOnHost(String),
+    Kind(String),
+    Size {
+        path: String,
+        disk: usize,
+        host: usize,
+    },
+    Content(String),
+    Modified {
+        path: String,
+        disk: Option<NaiveDateTime>,
+        host: Option<NaiveDateTime>,
+    },
+}
+
+impl Difference {
+    /// Tell if difference is only about timestamps.
+    pub fn is_modified(&self) -> bool {
+        matches!(self, Self::Modified { .. })
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OnlyOnDisk(path) => write!(f, "{}: only on disk", path),
+            Self::OnlyOnHost(path) => write!(f, "{}: only on host", path),
+            Self::Kind(path) => write!(f, "{}: file on one side, directory on the other", path),
+            Self::Size { path, disk, host } => {
+                write!(f, "{}: size differs (disk {}, host {})", path, disk, host)
+            }
+            Self::Content(path) => write!(f, "{}: content differs", path),
+            Self::Modified { path, disk, host } => write!(
+                f,
+                "{}: modification date differs (disk {:?}, host {:?})",
+                path, disk, host
+            ),
+        }
+    }
+}
+
+fn crc32(content: &[u8]) -> u32 {
+    let mut crc = crc_any::CRC::crc32();
+    crc.digest(content);
+    crc.get_crc() as u32
+}
+
+/// Compare disk content with a host directory.
+///
+/// Host names are converted to 8.3 the same way they are when imported,
+/// so this works with both imported and extracted directories.
+pub fn compare<B, P>(storage: &DiskStorage<B>, dir: P) -> error::Result<Vec<Difference>>
+where
+    B: SectorBackend,
+    P: AsRef<Path>,
+{
+    let mut disk_files = BTreeMap::new();
+    walk_disk(
+        storage,
+        &storage.list_root_file_infos(),
+        "",
+        &mut disk_files,
+    )?;
+
+    let mut host_files = BTreeMap::new();
+    walk_host(dir.as_ref(), "", &mut host_files)?;
+
+    let mut differences = Vec::new();
+
+    for (path, disk) in &disk_files {
+        let host = match host_files.get(path) {
+            Some(host) => host,
+            None => {
+                differences.push(Difference::OnlyOnDisk(path.clone()));
+                continue;
+            }
+        };
+
+        if disk.is_dir != host.is_dir {
+            differences.push(Difference::Kind(path.clone()));
+        } else if disk.size != host.size {
+            differences.push(Difference::Size {
+                path: path.clone(),
+                disk: disk.size,
+                host: host.size,
+            });
+        } else if disk.crc32 != host.crc32 {
+            differences.push(Difference::Content(path.clone()));
+        } else if !disk.is_dir && disk.modified != host.modified {
+            differences.push(Difference::Modified {
+                path: path.clone(),
+                disk: disk.modified,
+                host: host.modified,
+            });
+        }
+    }
+
+    for path in host_files.keys() {
+        if !disk_files.contains_key(path) {
+            differences.push(Difference::OnlyOnHost(path.clone()));
+        }
+    }
+
+    Ok(differences)
+}
+
+fn walk_disk<B>(
+    storage: &DiskStorage<B>,
+    file_infos: &[FileInfo],
+    prefix: &str,
+    summaries: &mut BTreeMap<String, Summary>,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    for file_info in file_infos {
+        let path = format!("{}{}", prefix, file_info.filename()?);
+
+        if file_info.is_dir() {
+            // Skip `.` and `..`
+            let children: Vec<FileInfo> =
+                storage.read_dir(file_info)?.into_iter().skip(2).collect();
+            walk_disk(storage, &children, &format!("{}/", path), summaries)?;
+            summaries.insert(path, dir_summary());
+        } else {
+            let content = storage.read_file(file_info)?;
+            summaries.insert(
+                path,
+                Summary {
+                    is_dir: false,
+                    size: content.len(),
+                    crc32: crc32(&content),
+                    modified: file_info.modified(),
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn walk_host(
+    dir: &Path,
+    prefix: &str,
+    summaries: &mut BTreeMap<String, Summary>,
+) -> error::Result<()> {
+    for entry in fs::read_dir(dir)?.filter_map(|r| r.ok()) {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let (name, ext) = dos::as_valid_file_components(&path)?;
+        let name = if ext.is_empty() {
+            format!("{}{}", prefix, name)
+        } else {
+            format!("{}{}.{}", prefix, name, ext)
+        };
+
+        if path.is_dir() {
+            walk_host(&path, &format!("{}/", name), summaries)?;
+            summaries.insert(name, dir_summary());
+        } else {
+            let content = fs::read(&path)?;
+            summaries.insert(
+                name,
+                Summary {
+                    is_dir: false,
+                    size: content.len(),
+                    crc32: crc32(&content),
+                    modified: FileInfo::try_from_path_and_index(&path, 0)?.modified(),
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn dir_summary() -> Summary {
+    Summary {
+        is_dir: true,
+        size: 0,
+        crc32: 0,
+        modified: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{
+        dump,
+        fixture::Fixture,
+        layout::DiskLayout,
+        prop::{self, Rng},
+        storage::ROOT_INDEX,
+    };
+
+    /// Create a random host tree with valid 8.3 names.
+    fn random_tree(rng: &mut Rng, dir: &Path, depth: usize) {
+        let mut names = HashSet::new();
+        for _ in 0..rng.below(6) {
+            // Prefix avoids names reserved by TOS
+            let mut name = format!("H{}", rng.name(7));
+            if rng.bool() {
+                name = format!("{}.{}", name, rng.name(3));
+            }
+            if !names.insert(name.clone()) {
+                continue;
+            }
+
+            let path = dir.join(&name);
+            if depth > 0 && rng.below(3) == 0 {
+                fs::create_dir(&path).unwrap();
+                random_tree(rng, &path, depth - 1);
+            } else {
+                let size = rng.below(3000);
+                fs::write(&path, rng.bytes(size)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_content() {
+        let sample = Fixture::sample("verify").unwrap();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        storage.import_path(sample.path()).unwrap();
+
+        assert_eq!(compare(&storage, sample.path()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_differences() {
+        let fixture = Fixture::empty("verify-host").unwrap();
+        let dir = fixture.path();
+        fs::create_dir_all(dir.join("SUB")).unwrap();
+        fs::write(dir.join("TEST.TXT"), b"other content").unwrap();
+        fs::write(dir.join("SUB").join("HOST.TXT"), b"host").unwrap();
+
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        let sample = Fixture::sample("verify-differences").unwrap();
+        storage.import_path(sample.path()).unwrap();
+        let mtime = FileInfo::try_from_path_and_index(sample.join("TEST.TXT"), 0)
+            .unwrap()
+            .modified()
+            .unwrap();
+        storage
+            .add_virtual_file("DISK", "TXT", mtime, b"disk", ROOT_INDEX)
+            .unwrap();
+
+        let differences = compare(&storage, dir).unwrap();
+        assert_eq!(
+            differences,
+            vec![
+                Difference::OnlyOnDisk("DISK.TXT".into()),
+                Difference::Size {
+                    path: "TEST.TXT".into(),
+                    disk: 20,
+                    host: 13
+                },
+                Difference::OnlyOnHost("SUB".into()),
+                Difference::OnlyOnHost("SUB/HOST.TXT".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_export_round_trip() {
+        prop::check(prop::CASES / 4, |rng| {
+            let fixture = Fixture::empty(&format!("prop-{}", rng.next_u64())).unwrap();
+            let dir = fixture.path();
+            random_tree(rng, dir, 2);
+
+            let mut storage = DiskStorage::new(DiskLayout::default());
+            storage.import_path(dir).unwrap();
+            assert_eq!(compare(&storage, dir).unwrap(), vec![]);
+
+            // Disk restored from a dump still matches
+            let mut raw = Vec::new();
+            dump::write_dump(&mut raw, &storage).unwrap();
+            let restored = dump::read_dump(&mut raw.as_slice()).unwrap();
+            assert_eq!(compare(&restored, dir).unwrap(), vec![]);
+        });
+    }
+}
@@ -0,0 +1,154 @@
+//! Live stream of server events to web browsers (RFC 6455).
+//!
+//! Only what a dashboard needs is implemented: the opening handshake and
+//! unfragmented text frames from server to browser. Frames sent by browsers
+//! are never read, a closed connection is noticed on next write.
+
+use std::{
+    io::{self, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{bus::StorageEvent, events::Event, hash::Sha1, transaction};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_TEXT: u8 = 0x01;
+const FINAL_FRAME: u8 = 0x80;
+
+/// `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key.trim(), ACCEPT_GUID).as_bytes());
+    base64(&digest)
+}
+
+/// Write `text` as a single, unmasked, text frame.
+pub fn write_text_frame<W>(writer: &mut W, text: &str) -> io::Result<()>
+where
+    W: Write,
+{
+    let payload = text.as_bytes();
+    let mut header = vec![FINAL_FRAME | OPCODE_TEXT];
+    match payload.len() {
+        len if len < 126 => header.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Events as JSON, given to every connected browser.
+#[derive(Debug, Clone, Default)]
+pub struct EventHub {
+    clients: Arc<Mutex<Vec<Sender<String>>>>,
+}
+
+impl EventHub {
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        self.clients.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: &Event) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let json = event.to_json();
+        clients.retain(|client| client.send(json.clone()).is_ok());
+    }
+
+    /// Publish every transaction served to Atari.
+    pub fn follow_transactions(&self) {
+        let hub = self.clone();
+        transaction::subscribe(move |notice| {
+            if let Some(event) = Event::from_notice(notice) {
+                hub.publish(&event);
+            }
+        });
+    }
+
+    /// Publish every disk change received from `receiver`.
+    pub fn follow_storage(&self, receiver: Receiver<StorageEvent>) {
+        let hub = self.clone();
+        thread::spawn(move || {
+            for event in receiver.iter().filter_map(|e| Event::from_storage(&e)) {
+                hub.publish(&event);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // Example of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_frames_and_hub() {
+        let mut frame = Vec::new();
+        write_text_frame(&mut frame, "hi").unwrap();
+        assert_eq!(frame, [0x81, 2, b'h', b'i']);
+
+        let long = "x".repeat(300);
+        let mut frame = Vec::new();
+        write_text_frame(&mut frame, &long).unwrap();
+        assert_eq!(&frame[..4], [0x81, 126, 0x01, 0x2C]);
+        assert_eq!(frame.len(), 4 + 300);
+
+        let hub = EventHub::default();
+        let client = hub.subscribe();
+        let event = Event::DiskFull { txn: 3 };
+        hub.publish(&event);
+        assert_eq!(client.try_recv(), Ok(event.to_json()));
+
+        drop(client);
+        hub.publish(&event);
+        assert!(hub.clients.lock().unwrap().is_empty());
+    }
+}
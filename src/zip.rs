@@ -0,0 +1,451 @@
+use std::{fs, path::Path};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use chrono::NaiveDate;
+
+use crate::{
+    backend::SectorBackend,
+    dos,
+    entries::parse_datetime_from_atari,
+    error::{self, SerialDiskError},
+    inflate,
+    storage::{DiskStorage, ROOT_INDEX},
+};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4B50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4B50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4B50;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+macro_rules! invalid {
+    ($msg:expr) => {
+        SerialDiskError::InvalidArchive(format!("zip: {}", $msg))
+    };
+}
+
+/// File or directory stored in a ZIP archive.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    /// Path inside archive (`/` separated)
+    pub name: String,
+    method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    size: usize,
+    /// DOS modification time
+    time: u16,
+    /// DOS modification date
+    date: u16,
+    local_header_offset: usize,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// ZIP archive fully loaded in memory.
+#[derive(Debug)]
+pub struct ZipArchive {
+    data: Vec<u8>,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipArchive {
+    pub fn open<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> error::Result<Self> {
+        let eocd_offset = find_end_of_central_directory(&data)?;
+
+        let mut eocd = &data[eocd_offset + 10..];
+        let entry_count = eocd.read_u16::<LittleEndian>()? as usize;
+        let _central_directory_size = eocd.read_u32::<LittleEndian>()?;
+        let central_directory_offset = eocd.read_u32::<LittleEndian>()? as usize;
+
+        let mut entries = Vec::with_capacity(entry_count);
+        let mut reader = data
+            .get(central_directory_offset..)
+            .ok_or_else(|| invalid!("central directory out of range"))?;
+
+        for _ in 0..entry_count {
+            if reader.read_u32::<LittleEndian>()? != CENTRAL_HEADER_SIGNATURE {
+                return Err(invalid!("invalid central directory header"));
+            }
+
+            let _version_made_by = reader.read_u16::<LittleEndian>()?;
+            let _version_needed = reader.read_u16::<LittleEndian>()?;
+            let flags = reader.read_u16::<LittleEndian>()?;
+            let method = reader.read_u16::<LittleEndian>()?;
+            let time = reader.read_u16::<LittleEndian>()?;
+            let date = reader.read_u16::<LittleEndian>()?;
+            let crc32 = reader.read_u32::<LittleEndian>()?;
+            let compressed_size = reader.read_u32::<LittleEndian>()? as usize;
+            let size = reader.read_u32::<LittleEndian>()? as usize;
+            let name_len = reader.read_u16::<LittleEndian>()? as usize;
+            let extra_len = reader.read_u16::<LittleEndian>()? as usize;
+            let comment_len = reader.read_u16::<LittleEndian>()? as usize;
+            let _disk_number = reader.read_u16::<LittleEndian>()?;
+            let _internal_attr = reader.read_u16::<LittleEndian>()?;
+            let _external_attr = reader.read_u32::<LittleEndian>()?;
+            let local_header_offset = reader.read_u32::<LittleEndian>()? as usize;
+
+            if reader.len() < name_len + extra_len + comment_len {
+                return Err(invalid!("truncated central directory"));
+            }
+            let name = String::from_utf8_lossy(&reader[..name_len]).to_string();
+            reader = &reader[name_len + extra_len + comment_len..];
+
+            if flags & 0x0001 != 0 {
+                log::warn!("Skipping encrypted archive entry: {}", name);
+                continue;
+            }
+
+            entries.push(ZipEntry {
+                name,
+                method,
+                crc32,
+                compressed_size,
+                size,
+                time,
+                date,
+                local_header_offset,
+            });
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Read and check content of an archive entry.
+    pub fn read(&self, entry: &ZipEntry) -> error::Result<Vec<u8>> {
+        let mut header = self
+            .data
+            .get(entry.local_header_offset..)
+            .ok_or_else(|| invalid!("local header out of range"))?;
+        if header.read_u32::<LittleEndian>()? != LOCAL_HEADER_SIGNATURE || header.len() < 26 {
+            return Err(invalid!("invalid local header"));
+        }
+
+        let name_len = (&header[22..24]).read_u16::<LittleEndian>()? as usize;
+        let extra_len = (&header[24..26]).read_u16::<LittleEndian>()? as usize;
+        // Sizes come from the archive, they may overflow on 32 bits targets
+        let data_offset = entry.local_header_offset + 30 + name_len + extra_len;
+        let compressed = data_offset
+            .checked_add(entry.compressed_size)
+            .and_then(|data_end| self.data.get(data_offset..data_end))
+            .ok_or_else(|| invalid!("entry data out of range"))?;
+
+        let content = match entry.method {
+            METHOD_STORED => compressed.to_vec(),
+            // Stop as soon as content is larger than announced (zip bomb)
+            METHOD_DEFLATED => inflate::inflate(compressed, entry.size)?,
+            method => {
+                return Err(invalid!(format!(
+                    "unsupported compression method {} for {}",
+                    method, entry.name
+                )))
+            }
+        };
+
+        let mut crc = crc_any::CRC::crc32();
+        crc.digest(&content);
+        if content.len() != entry.size || crc.get_crc() as u32 != entry.crc32 {
+            return Err(invalid!(format!("corrupted entry {}", entry.name)));
+        }
+
+        Ok(content)
+    }
+}
+
+fn find_end_of_central_directory(data: &[u8]) -> error::Result<usize> {
+    if data.len() < END_OF_CENTRAL_DIRECTORY_SIZE {
+        return Err(invalid!("file too small"));
+    }
+
+    // End of central directory may be followed by a comment of at most 64K
+    let min_offset = data
+        .len()
+        .saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE + u16::MAX as usize);
+    (min_offset..=data.len() - END_OF_CENTRAL_DIRECTORY_SIZE)
+        .rev()
+        .find(|offset| {
+            data[*offset..*offset + 4] == END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes()
+        })
+        .ok_or_else(|| invalid!("end of central directory not found"))
+}
+
+/// Import ZIP archive content at disk root.
+///
+/// Directory structure is kept and names are converted to 8.3 the same way
+/// host files are.
+pub fn import_zip<P, B>(storage: &mut DiskStorage<B>, path: P) -> error::Result<()>
+where
+    P: AsRef<Path>,
+    B: SectorBackend,
+{
+    let archive = ZipArchive::open(path)?;
+    import_archive(storage, &archive)
+}
+
+pub fn import_archive<B>(storage: &mut DiskStorage<B>, archive: &ZipArchive) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    for entry in archive.entries() {
+        if let Err(e) = import_entry(storage, archive, entry) {
+            log::warn!("Cannot add {:?} from archive (error: {})", entry.name, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn import_entry<B>(
+    storage: &mut DiskStorage<B>,
+    archive: &ZipArchive,
+    entry: &ZipEntry,
+) -> error::Result<()>
+where
+    B: SectorBackend,
+{
+    let components: Vec<&str> = entry.name.split('/').filter(|c| !c.is_empty()).collect();
+
+    // Skip hidden files, as done when importing host folders
+    if components.iter().any(|c| c.starts_with('.')) {
+        log::debug!("Skipping hidden archive entry: {}", entry.name);
+        return Ok(());
+    }
+
+    let (dir_components, filename) = match components.split_last() {
+        Some((filename, dir_components)) if !entry.is_dir() => (dir_components, Some(*filename)),
+        _ => (&components[..], None),
+    };
+
+    // DOS epoch, used when entries have no valid date
+    let default_mtime = NaiveDate::from_ymd(1980, 1, 1).and_hms(0, 0, 0);
+    let mtime = parse_datetime_from_atari(entry.time, entry.date).unwrap_or(default_mtime);
+
+    // Create missing parent directories, merging the ones with same 8.3 name
+    let mut parent_index = ROOT_INDEX;
+    for component in dir_components {
+        let (name, ext) = dos::as_valid_file_components(component)?;
+        parent_index = storage.find_or_add_virtual_directory(&name, &ext, mtime, parent_index)?;
+    }
+
+    if let Some(filename) = filename {
+        let (name, ext) = storage.file_components(filename)?;
+        let content = archive.read(entry)?;
+        let (name, ext) = storage.executable_components(name, ext, &content);
+        // Several archive names may shorten to the same 8.3 one
+        let (name, ext) = storage.free_name((name, ext), parent_index)?;
+        storage.add_virtual_file(&name, &ext, mtime, &content, parent_index)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+    use crate::layout::DiskLayout;
+
+    /// Build an archive of stored entries, all dated with given DOS date.
+    fn stored_zip(files: &[(&str, &[u8])], date: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central = Vec::new();
+        for (name, content) in files {
+            let mut crc = crc_any::CRC::crc32();
+            crc.digest(content);
+            let crc = crc.get_crc() as u32;
+            let offset = data.len() as u32;
+
+            for (header, signature) in [
+                (&mut data, LOCAL_HEADER_SIGNATURE),
+                (&mut central, CENTRAL_HEADER_SIGNATURE),
+            ] {
+                let is_central = signature == CENTRAL_HEADER_SIGNATURE;
+                header.write_u32::<LittleEndian>(signature).unwrap();
+                if is_central {
+                    header.write_u16::<LittleEndian>(20).unwrap();
+                }
+                header.write_u16::<LittleEndian>(20).unwrap();
+                header.write_u16::<LittleEndian>(0).unwrap();
+                header.write_u16::<LittleEndian>(METHOD_STORED).unwrap();
+                header.write_u16::<LittleEndian>(0).unwrap();
+                header.write_u16::<LittleEndian>(date).unwrap();
+                header.write_u32::<LittleEndian>(crc).unwrap();
+                header
+                    .write_u32::<LittleEndian>(content.len() as u32)
+                    .unwrap();
+                header
+                    .write_u32::<LittleEndian>(content.len() as u32)
+                    .unwrap();
+                header.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+                header.write_u16::<LittleEndian>(0).unwrap();
+                if is_central {
+                    header.extend_from_slice(&[0; 10]);
+                    header.write_u32::<LittleEndian>(offset).unwrap();
+                }
+                header.extend_from_slice(name.as_bytes());
+            }
+            data.extend_from_slice(content);
+        }
+
+        let central_offset = data.len() as u32;
+        data.extend_from_slice(&central);
+        data.write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+            .unwrap();
+        data.extend_from_slice(&[0; 4]);
+        data.write_u16::<LittleEndian>(files.len() as u16).unwrap();
+        data.write_u16::<LittleEndian>(files.len() as u16).unwrap();
+        data.write_u32::<LittleEndian>(central.len() as u32)
+            .unwrap();
+        data.write_u32::<LittleEndian>(central_offset).unwrap();
+        data.write_u16::<LittleEndian>(0).unwrap();
+        data
+    }
+
+    /// Archive containing `GAMES/`, `GAMES/DUNG.PRG` (deflated) and `README.TXT` (stored).
+    const TEST_ZIP: [u8; 341] = [
+        0x50, 0x4B, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x73, 0x01, 0x53, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00,
+        0x47, 0x41, 0x4D, 0x45, 0x53, 0x2F, 0x50, 0x4B, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0xC0, 0x73, 0x01, 0x53, 0x71, 0x9F, 0x06, 0x88, 0x14, 0x00, 0x00, 0x00, 0x78, 0x00,
+        0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x47, 0x41, 0x4D, 0x45, 0x53, 0x2F, 0x44, 0x55, 0x4E,
+        0x47, 0x2E, 0x50, 0x52, 0x47, 0x4B, 0x29, 0xCD, 0x4B, 0x4F, 0xCD, 0xCF, 0x53, 0xC8, 0x4D,
+        0x2C, 0x2E, 0x49, 0x2D, 0x52, 0x48, 0xA1, 0x17, 0x17, 0x00, 0x50, 0x4B, 0x03, 0x04, 0x14,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x73, 0x01, 0x53, 0xA1, 0x5D, 0x3F, 0x96, 0x0B, 0x00,
+        0x00, 0x00, 0x0B, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x00, 0x00, 0x52, 0x45, 0x41, 0x44, 0x4D,
+        0x45, 0x2E, 0x54, 0x58, 0x54, 0x48, 0x65, 0x6C, 0x6C, 0x6F, 0x20, 0x41, 0x74, 0x61, 0x72,
+        0x69, 0x50, 0x4B, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x73,
+        0x01, 0x53, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x47, 0x41, 0x4D, 0x45, 0x53, 0x2F, 0x50, 0x4B, 0x01, 0x02, 0x14, 0x03, 0x14,
+        0x00, 0x00, 0x00, 0x08, 0x00, 0xC0, 0x73, 0x01, 0x53, 0x71, 0x9F, 0x06, 0x88, 0x14, 0x00,
+        0x00, 0x00, 0x78, 0x00, 0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x01, 0x24, 0x00, 0x00, 0x00, 0x47, 0x41, 0x4D, 0x45, 0x53, 0x2F,
+        0x44, 0x55, 0x4E, 0x47, 0x2E, 0x50, 0x52, 0x47, 0x50, 0x4B, 0x01, 0x02, 0x14, 0x03, 0x14,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x73, 0x01, 0x53, 0xA1, 0x5D, 0x3F, 0x96, 0x0B, 0x00,
+        0x00, 0x00, 0x0B, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x01, 0x64, 0x00, 0x00, 0x00, 0x52, 0x45, 0x41, 0x44, 0x4D, 0x45,
+        0x2E, 0x54, 0x58, 0x54, 0x50, 0x4B, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x03,
+        0x00, 0xA8, 0x00, 0x00, 0x00, 0x97, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_read_archive() {
+        let archive = ZipArchive::from_bytes(TEST_ZIP.to_vec()).unwrap();
+
+        let names: Vec<&str> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["GAMES/", "GAMES/DUNG.PRG", "README.TXT"]);
+
+        assert!(archive.entries()[0].is_dir());
+        assert_eq!(
+            archive.read(&archive.entries()[1]).unwrap(),
+            b"dungeon master ".repeat(8)
+        );
+        assert_eq!(archive.read(&archive.entries()[2]).unwrap(), b"Hello Atari");
+    }
+
+    #[test]
+    fn test_invalid_archive() {
+        assert!(ZipArchive::from_bytes(vec![]).is_err());
+        assert!(ZipArchive::from_bytes(vec![0; 100]).is_err());
+
+        // Corrupt stored content
+        let mut data = TEST_ZIP.to_vec();
+        let offset = data.windows(11).position(|w| w == b"Hello Atari").unwrap();
+        data[offset] = b'J';
+        let archive = ZipArchive::from_bytes(data).unwrap();
+        assert!(archive.read(&archive.entries()[2]).is_err());
+
+        // Deflated content larger than announced, or data out of range
+        let archive = ZipArchive::from_bytes(TEST_ZIP.to_vec()).unwrap();
+        let mut entry = archive.entries()[1].clone();
+        entry.size = 16;
+        assert!(archive.read(&entry).is_err());
+        entry.compressed_size = usize::MAX;
+        assert!(archive.read(&entry).is_err());
+    }
+
+    #[test]
+    fn test_import_archive() {
+        let archive = ZipArchive::from_bytes(TEST_ZIP.to_vec()).unwrap();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        import_archive(&mut storage, &archive).unwrap();
+
+        let root = storage.list_root_file_infos();
+        let names: Vec<String> = root.iter().map(|f| f.filename().unwrap()).collect();
+        assert_eq!(names, ["GAMES", "README.TXT"]);
+        assert!(root[0].is_dir());
+        assert_eq!(storage.read_file(&root[1]).unwrap(), b"Hello Atari");
+
+        let games = storage.read_dir(&root[0]).unwrap();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[2].filename().unwrap(), "DUNG.PRG");
+        assert_eq!(
+            storage.read_file(&games[2]).unwrap(),
+            b"dungeon master ".repeat(8)
+        );
+    }
+
+    #[test]
+    fn test_import_duplicate_names() {
+        // 1990-01-01
+        let data = stored_zip(
+            &[
+                ("games/a.txt", b"first"),
+                ("GAMES/b.txt", b"second"),
+                ("SCREENSHOT1.PNG", b"one"),
+                ("SCREENSHOT2.PNG", b"two"),
+            ],
+            (10 << 9) | (1 << 5) | 1,
+        );
+        let archive = ZipArchive::from_bytes(data).unwrap();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        import_archive(&mut storage, &archive).unwrap();
+
+        let root = storage.list_root_file_infos();
+        let names: Vec<String> = root.iter().map(|f| f.filename().unwrap()).collect();
+        assert_eq!(names, ["games", "SCREENSH.PNG", "SCREEN~1.PNG"]);
+        assert_eq!(storage.read_file(&root[1]).unwrap(), b"one");
+        assert_eq!(storage.read_file(&root[2]).unwrap(), b"two");
+
+        let games = storage.read_dir(&root[0]).unwrap();
+        let names: Vec<String> = games[2..].iter().map(|f| f.filename().unwrap()).collect();
+        assert_eq!(names, ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn test_import_without_date() {
+        let archive = ZipArchive::from_bytes(stored_zip(&[("NODATE.TXT", b"text")], 0)).unwrap();
+        let mut storage = DiskStorage::new(DiskLayout::default());
+        import_archive(&mut storage, &archive).unwrap();
+
+        let root = storage.list_root_file_infos();
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].filename().unwrap(), "NODATE.TXT");
+        assert_eq!(
+            root[0].modified(),
+            Some(NaiveDate::from_ymd(1980, 1, 1).and_hms(0, 0, 0))
+        );
+    }
+}
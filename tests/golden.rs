@@ -0,0 +1,144 @@
+//! Byte-for-byte compatibility tests of the serial protocol.
+//!
+//! Every file of `tests/data/golden` is an exchange with Atari:
+//!
+//! - `! layout <json>` sets the disk layout (default one otherwise),
+//! - `! requires <feature>` skips the exchange when a feature is disabled,
+//! - `> <hex bytes>` lines are sent by Atari,
+//! - `< <hex bytes>` lines are expected answers from server,
+//! - `#` starts a comment.
+//!
+//! Run with `ATARIDISK_UPDATE_GOLDEN=1` to rewrite expected answers from
+//! current implementation, then review the diff before committing it.
+
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use ataridisk::{
+    layout::DiskLayout, persistence::Persistence, state_machine, storage::DiskStorage,
+    transport::MemoryTransport,
+};
+
+const GOLDEN_DIR: &str = "tests/data/golden";
+const BYTES_PER_LINE: usize = 32;
+
+struct Exchange {
+    layout: DiskLayout,
+    sent: Vec<u8>,
+    expected: Vec<u8>,
+    /// Cargo features needed to get expected answers
+    features: Vec<String>,
+    /// Every line but expected answers
+    header: Vec<String>,
+}
+
+fn has_feature(feature: &str) -> bool {
+    match feature {
+        "compression" => cfg!(feature = "compression"),
+        _ => panic!("Unknown feature {:?}", feature),
+    }
+}
+
+fn parse_hex(line: &str) -> Vec<u8> {
+    line.split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).expect("Invalid hex byte"))
+        .collect()
+}
+
+fn parse_exchange(content: &str) -> Exchange {
+    let mut exchange = Exchange {
+        layout: DiskLayout::default(),
+        sent: Vec::new(),
+        expected: Vec::new(),
+        features: Vec::new(),
+        header: Vec::new(),
+    };
+
+    for line in content.lines() {
+        if let Some(hex) = line.strip_prefix('<') {
+            exchange.expected.extend(parse_hex(hex));
+            continue;
+        }
+
+        if let Some(json) = line.strip_prefix("! layout") {
+            exchange.layout = serde_json::from_str(json).expect("Invalid layout");
+        } else if let Some(feature) = line.strip_prefix("! requires") {
+            exchange.features.push(feature.trim().to_string());
+        } else if let Some(hex) = line.strip_prefix('>') {
+            exchange.sent.extend(parse_hex(hex));
+        } else {
+            assert!(
+                line.trim().is_empty() || line.starts_with('#'),
+                "Invalid golden line: {:?}",
+                line
+            );
+        }
+        exchange.header.push(line.to_string());
+    }
+
+    exchange
+}
+
+fn format_answer(header: &[String], answer: &[u8]) -> String {
+    let mut content = String::new();
+    for line in header {
+        writeln!(content, "{}", line).unwrap();
+    }
+    for chunk in answer.chunks(BYTES_PER_LINE) {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(content, "< {}", hex.join(" ")).unwrap();
+    }
+    content
+}
+
+fn serve(exchange: &Exchange) -> Vec<u8> {
+    let storage = Arc::new(Mutex::new(DiskStorage::new(exchange.layout.clone())));
+    let mut serial = MemoryTransport::new(&exchange.sent);
+    let mut persistence = Persistence::new(None, None);
+
+    // Server stops when Atari has nothing more to send
+    let result = state_machine::run(storage, &mut serial, &mut persistence);
+    assert!(
+        result.is_err(),
+        "Server is expected to stop at end of input"
+    );
+
+    serial.output().to_vec()
+}
+
+#[test]
+fn test_golden_exchanges() {
+    let update = env::var_os("ATARIDISK_UPDATE_GOLDEN").is_some();
+
+    let mut paths: Vec<_> = fs::read_dir(GOLDEN_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No golden file found");
+
+    for path in paths {
+        let exchange = parse_exchange(&fs::read_to_string(&path).unwrap());
+        if !exchange.features.iter().all(|f| has_feature(f)) {
+            continue;
+        }
+        let answer = serve(&exchange);
+
+        if update {
+            fs::write(&path, format_answer(&exchange.header, &answer)).unwrap();
+        } else {
+            assert_eq!(
+                answer,
+                exchange.expected,
+                "Answer differs for {}",
+                Path::new(&path).display()
+            );
+        }
+    }
+}